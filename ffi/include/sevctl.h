@@ -0,0 +1,103 @@
+/* SPDX-License-Identifier: Apache-2.0 */
+
+#ifndef SEVCTL_H
+#define SEVCTL_H
+
+/* Generated by cbindgen from src/lib.rs; do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Describes the last failure on the calling thread, or returns null if there
+// was none. The string remains valid until the next failure on this thread.
+const char *sevctl_last_error(void);
+
+// Releases a buffer returned by sevctl.
+//
+// # Safety
+//
+// `data` must be null or a buffer of `len` bytes returned by sevctl, which is
+// not used afterwards.
+void sevctl_free(uint8_t *data, size_t len);
+
+// Exports the platform's SEV certificate chain, or with `full` the SEV and CA
+// chains, as `sevctl export` writes them.
+//
+// # Safety
+//
+// `chain` and `chain_len` must be valid for writes.
+int sevctl_chain_export(bool full, uint8_t **chain, size_t *chain_len);
+
+// Verifies a SEV certificate chain against a CA chain, returning 1 if it is
+// valid and 0 if it is not.
+//
+// Without `sev`, the platform's own chain is verified; `oca` replaces the
+// chain's OCA certificate, and without `ca` the built-in AMD chain is used.
+//
+// # Safety
+//
+// Each buffer must be null or point to as many readable bytes as its length.
+int sevctl_chain_verify(const uint8_t *sev,
+                        size_t sev_len,
+                        const uint8_t *oca,
+                        size_t oca_len,
+                        const uint8_t *ca,
+                        size_t ca_len);
+
+// Computes the launch measurement LAUNCH_MEASURE returns for a launch digest,
+// e.g. to check the one reported by the platform.
+//
+// # Safety
+//
+// `digest` and `measurement` must point to 32 bytes, `mnonce` and `tik` to 16.
+int sevctl_measurement_compute(uint8_t api_major,
+                               uint8_t api_minor,
+                               uint8_t build_id,
+                               uint32_t policy,
+                               const uint8_t *digest,
+                               const uint8_t *mnonce,
+                               const uint8_t *tik,
+                               uint8_t *measurement);
+
+// Computes the launch digest from the arguments of `sevctl measurement build`
+// (e.g. `--firmware`, `--kernel`, `--num-cpus`), and the launch measurement if
+// they include `--tik`. `measured` is set to whether `measurement` was written.
+//
+// # Safety
+//
+// `args` must point to `nargs` NUL-terminated strings, `digest` and
+// `measurement` to 32 writable bytes, and `measured` must be valid for writes.
+int sevctl_measurement_build(const char *const *args,
+                             size_t nargs,
+                             uint8_t *digest,
+                             uint8_t *measurement,
+                             bool *measured);
+
+// Packages a launch secret for LAUNCH_SECRET, encrypted with the TEK and bound
+// to the launch measurement with the TIK, returning the packet header and the
+// encrypted secret.
+//
+// # Safety
+//
+// `tek` and `tik` must point to 16 bytes, `measurement` to 32, `secret` to
+// `secret_len`, and the outputs must be valid for writes.
+int sevctl_secret_package(const uint8_t *tek,
+                          const uint8_t *tik,
+                          const uint8_t *measurement,
+                          const uint8_t *secret,
+                          size_t secret_len,
+                          uint8_t **header,
+                          size_t *header_len,
+                          uint8_t **data,
+                          size_t *data_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SEVCTL_H */
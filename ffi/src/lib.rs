@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! C bindings for sevctl's core workflows: exporting and verifying certificate
+//! chains, computing launch measurements and packaging launch secrets.
+//!
+//! Functions return 0 on success and -1 on failure, after which
+//! `sevctl_last_error()` describes the failure. Buffers returned by sevctl must
+//! be released with `sevctl_free()`.
+//!
+//! The declarations are in `include/sevctl.h`, generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/sevctl.h`.
+
+#![deny(clippy::all)]
+
+use codicon::Decoder;
+use sev::certs::{ca, sev as certs};
+use sevctl::error::{Contextual, Result};
+use sevctl::measurement;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// Runs a binding, recording its error (or panic, which must not unwind into C).
+fn run(binding: impl FnOnce() -> Result<c_int>) -> c_int {
+    let message = match catch_unwind(AssertUnwindSafe(binding)) {
+        Ok(Ok(ret)) => return ret,
+        Ok(Err(e)) => {
+            let mut message = e.to_string();
+            let mut err: &(dyn std::error::Error + 'static) = &e;
+            while let Some(cause) = err.source() {
+                message += &format!(": {}", cause);
+                err = cause;
+            }
+            message
+        }
+        Err(_) => "sevctl panicked".to_string(),
+    };
+
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    -1
+}
+
+fn null(what: &str) -> sevctl::error::Context {
+    sevctl::error::Context::new(
+        &format!("{} must not be null", what),
+        Box::<Error>::new(ErrorKind::InvalidInput.into()),
+    )
+}
+
+/// Borrows a buffer passed by the caller, `None` if it is null.
+///
+/// # Safety
+///
+/// `data` must be null or point to `len` readable bytes.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match data.is_null() {
+        true => None,
+        false => Some(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Hands a buffer over to the caller, who releases it with `sevctl_free()`.
+///
+/// # Safety
+///
+/// `data` and `len` must be valid for writes.
+unsafe fn output(buf: Vec<u8>, data: *mut *mut u8, len: *mut usize) {
+    let buf = Box::into_raw(buf.into_boxed_slice());
+    *len = (*buf).len();
+    *data = buf as *mut u8;
+}
+
+/// Describes the last failure on the calling thread, or returns null if there
+/// was none. The string remains valid until the next failure on this thread.
+#[no_mangle]
+pub extern "C" fn sevctl_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// Releases a buffer returned by sevctl.
+///
+/// # Safety
+///
+/// `data` must be null or a buffer of `len` bytes returned by sevctl, which is
+/// not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sevctl_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Exports the platform's SEV certificate chain, or with `full` the SEV and CA
+/// chains, as `sevctl export` writes them.
+///
+/// # Safety
+///
+/// `chain` and `chain_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sevctl_chain_export(
+    full: bool,
+    chain: *mut *mut u8,
+    chain_len: *mut usize,
+) -> c_int {
+    run(|| {
+        if chain.is_null() || chain_len.is_null() {
+            return Err(null("chain"));
+        }
+
+        output(sevctl::export::encode(full)?, chain, chain_len);
+        Ok(0)
+    })
+}
+
+/// Verifies a SEV certificate chain against a CA chain, returning 1 if it is
+/// valid and 0 if it is not.
+///
+/// Without `sev`, the platform's own chain is verified; `oca` replaces the
+/// chain's OCA certificate, and without `ca` the built-in AMD chain is used.
+///
+/// # Safety
+///
+/// Each buffer must be null or point to as many readable bytes as its length.
+#[no_mangle]
+pub unsafe extern "C" fn sevctl_chain_verify(
+    sev: *const u8,
+    sev_len: usize,
+    oca: *const u8,
+    oca_len: usize,
+    ca: *const u8,
+    ca_len: usize,
+) -> c_int {
+    run(|| {
+        let mut schain = match input(sev, sev_len) {
+            Some(mut buf) => {
+                certs::Chain::decode(&mut buf, ()).context("unable to decode SEV chain")?
+            }
+            None => sevctl::chain()?,
+        };
+        if let Some(mut buf) = input(oca, oca_len) {
+            schain.oca =
+                certs::Certificate::decode(&mut buf, ()).context("unable to decode OCA")?;
+        }
+        let cchain = match input(ca, ca_len) {
+            Some(mut buf) => {
+                ca::Chain::decode(&mut buf, ()).context("unable to decode CA chain")?
+            }
+            None => sevctl::ca_chain_builtin(&schain)?,
+        };
+
+        Ok(sevctl::verify::chains(&schain, &cchain, true) as c_int)
+    })
+}
+
+/// Computes the launch measurement LAUNCH_MEASURE returns for a launch digest,
+/// e.g. to check the one reported by the platform.
+///
+/// # Safety
+///
+/// `digest` and `measurement` must point to 32 bytes, `mnonce` and `tik` to 16.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn sevctl_measurement_compute(
+    api_major: u8,
+    api_minor: u8,
+    build_id: u8,
+    policy: u32,
+    digest: *const u8,
+    mnonce: *const u8,
+    tik: *const u8,
+    measurement: *mut u8,
+) -> c_int {
+    run(|| {
+        if digest.is_null() || mnonce.is_null() || tik.is_null() || measurement.is_null() {
+            return Err(null("digest, mnonce, tik and measurement"));
+        }
+
+        let context = measurement::Context {
+            build: sev::Build {
+                version: sev::Version {
+                    major: api_major,
+                    minor: api_minor,
+                },
+                build: build_id,
+            },
+            policy,
+            digest: *(digest as *const [u8; 32]),
+            mnonce: *(mnonce as *const [u8; 16]),
+        };
+
+        let computed = context.measure(std::slice::from_raw_parts(tik, 16))?;
+        std::ptr::copy_nonoverlapping(computed.as_ptr(), measurement, computed.len());
+        Ok(0)
+    })
+}
+
+/// Computes the launch digest from the arguments of `sevctl measurement build`
+/// (e.g. `--firmware`, `--kernel`, `--num-cpus`), and the launch measurement if
+/// they include `--tik`. `measured` is set to whether `measurement` was written.
+///
+/// # Safety
+///
+/// `args` must point to `nargs` NUL-terminated strings, `digest` and
+/// `measurement` to 32 writable bytes, and `measured` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sevctl_measurement_build(
+    args: *const *const c_char,
+    nargs: usize,
+    digest: *mut u8,
+    measurement: *mut u8,
+    measured: *mut bool,
+) -> c_int {
+    run(|| {
+        if (args.is_null() && nargs > 0) || digest.is_null() || measurement.is_null() {
+            return Err(null("args, digest and measurement"));
+        }
+        if measured.is_null() {
+            return Err(null("measured"));
+        }
+
+        let args = match nargs {
+            0 => Vec::new(),
+            n => std::slice::from_raw_parts(args, n)
+                .iter()
+                .map(|&arg| match arg.is_null() {
+                    true => Err(null("argument")),
+                    false => Ok(CStr::from_ptr(arg).to_string_lossy().into_owned()),
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        let (computed, launch) = measurement::build_from_args(args)?;
+        std::ptr::copy_nonoverlapping(computed.as_ptr(), digest, computed.len());
+        if let Some(launch) = launch {
+            std::ptr::copy_nonoverlapping(launch.as_ptr(), measurement, launch.len());
+        }
+        *measured = launch.is_some();
+        Ok(0)
+    })
+}
+
+/// Packages a launch secret for LAUNCH_SECRET, encrypted with the TEK and bound
+/// to the launch measurement with the TIK, returning the packet header and the
+/// encrypted secret.
+///
+/// # Safety
+///
+/// `tek` and `tik` must point to 16 bytes, `measurement` to 32, `secret` to
+/// `secret_len`, and the outputs must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn sevctl_secret_package(
+    tek: *const u8,
+    tik: *const u8,
+    measurement: *const u8,
+    secret: *const u8,
+    secret_len: usize,
+    header: *mut *mut u8,
+    header_len: *mut usize,
+    data: *mut *mut u8,
+    data_len: *mut usize,
+) -> c_int {
+    run(|| {
+        let (tek, tik, measurement) = match (input(tek, 16), input(tik, 16), input(measurement, 32))
+        {
+            (Some(tek), Some(tik), Some(measurement)) => (tek, tik, measurement),
+            _ => return Err(null("tek, tik and measurement")),
+        };
+        let secret = input(secret, secret_len).ok_or_else(|| null("secret"))?;
+        if header.is_null() || header_len.is_null() || data.is_null() || data_len.is_null() {
+            return Err(null("header and data"));
+        }
+
+        let (packet_header, encrypted) = sevctl::secret::build(tek, tik, measurement, secret)?;
+        output(packet_header, header, header_len);
+        output(encrypted, data, data_len);
+        Ok(0)
+    })
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Python bindings for sevctl's guest owner workflows: exporting and verifying
+//! certificate chains, computing launch measurements, creating launch sessions
+//! and packaging launch secrets.
+//!
+//! Build the `sevctl` module with `maturin build` (or `maturin develop`) in this
+//! directory. Failures raise `sevctl.SevctlError`, and malformed arguments
+//! `ValueError`. Certificates, keys and measurements are `bytes`.
+
+#![deny(clippy::all)]
+
+use codicon::Decoder;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use sev::certs::{ca, sev as certs};
+use sevctl::error::Contextual;
+use sevctl::{measurement, session};
+
+use std::convert::TryFrom;
+
+create_exception!(
+    sevctl,
+    SevctlError,
+    PyException,
+    "A failed sevctl operation, described with its causes."
+);
+
+fn failed(e: sevctl::error::Context) -> PyErr {
+    let mut message = e.to_string();
+    let mut err: &(dyn std::error::Error + 'static) = &e;
+    while let Some(cause) = err.source() {
+        message += &format!(": {}", cause);
+        err = cause;
+    }
+
+    SevctlError::new_err(message)
+}
+
+/// Checks the length of a fixed-size argument.
+fn sized<const N: usize>(value: &[u8], name: &str) -> PyResult<[u8; N]> {
+    <[u8; N]>::try_from(value)
+        .map_err(|_| PyValueError::new_err(format!("{} must be {} bytes", name, N)))
+}
+
+fn bytes(py: Python<'_>, buf: &[u8]) -> PyObject {
+    PyBytes::new(py, buf).into()
+}
+
+/// Exports the platform's SEV certificate chain, or with `full` the SEV and CA
+/// chains, as `sevctl export` writes them.
+#[pyfunction]
+#[pyo3(signature = (full = false))]
+fn export_chain(py: Python<'_>, full: bool) -> PyResult<PyObject> {
+    let chain = py
+        .allow_threads(|| sevctl::export::encode(full))
+        .map_err(failed)?;
+    Ok(bytes(py, &chain))
+}
+
+/// Verifies a SEV certificate chain against a CA chain, returning whether it is
+/// valid.
+///
+/// Without `sev`, the platform's own chain is verified; `oca` replaces the
+/// chain's OCA certificate, and without `ca` the built-in AMD chain is used.
+#[pyfunction]
+#[pyo3(signature = (sev = None, oca = None, ca = None))]
+fn verify_chain(
+    py: Python<'_>,
+    sev: Option<&[u8]>,
+    oca: Option<&[u8]>,
+    ca: Option<&[u8]>,
+) -> PyResult<bool> {
+    let mut schain = match sev {
+        Some(mut buf) => certs::Chain::decode(&mut buf, ()).context("unable to decode SEV chain"),
+        None => py.allow_threads(sevctl::chain),
+    }
+    .map_err(failed)?;
+    if let Some(mut buf) = oca {
+        schain.oca = certs::Certificate::decode(&mut buf, ())
+            .context("unable to decode OCA")
+            .map_err(failed)?;
+    }
+    let cchain = match ca {
+        Some(mut buf) => ca::Chain::decode(&mut buf, ()).context("unable to decode CA chain"),
+        None => sevctl::ca_chain_builtin(&schain),
+    }
+    .map_err(failed)?;
+
+    Ok(sevctl::verify::chains(&schain, &cchain, true))
+}
+
+/// Computes the launch measurement LAUNCH_MEASURE returns for a 32-byte launch
+/// digest, a 16-byte nonce and the 16-byte TIK, e.g. to check the one reported
+/// by the platform.
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+fn compute_measurement(
+    py: Python<'_>,
+    api_major: u8,
+    api_minor: u8,
+    build_id: u8,
+    policy: u32,
+    digest: &[u8],
+    mnonce: &[u8],
+    tik: &[u8],
+) -> PyResult<PyObject> {
+    let context = measurement::Context {
+        build: sev::Build {
+            version: sev::Version {
+                major: api_major,
+                minor: api_minor,
+            },
+            build: build_id,
+        },
+        policy,
+        digest: sized(digest, "digest")?,
+        mnonce: sized(mnonce, "mnonce")?,
+    };
+
+    let tik = sized::<16>(tik, "tik")?;
+    Ok(bytes(py, &context.measure(&tik).map_err(failed)?))
+}
+
+/// Computes the launch digest from the arguments of `sevctl measurement build`
+/// (e.g. `["--firmware", "OVMF.fd", "--num-cpus", "4"]`), returning it with the
+/// launch measurement if they include `--tik`, or else None.
+#[pyfunction]
+fn build_measurement(py: Python<'_>, args: Vec<String>) -> PyResult<(PyObject, Option<PyObject>)> {
+    let (digest, launch) = py
+        .allow_threads(|| measurement::build_from_args(args))
+        .map_err(failed)?;
+    Ok((bytes(py, &digest), launch.map(|m| bytes(py, &m))))
+}
+
+/// Creates the launch session for a platform's certificate chain (optionally
+/// followed by its CA chain, which must be one built into sevctl) and a policy
+/// such as `"0x5"`, returning a dict of the `godh` certificate, the `session`
+/// buffer and the `tek` and `tik` keys.
+#[pyfunction]
+fn create_session(py: Python<'_>, chain: &[u8], policy: &str) -> PyResult<PyObject> {
+    let pdh = session::target_pdh(chain, sevctl::roots_builtin()).map_err(failed)?;
+    let policy = session::sev_policy(policy).map_err(failed)?;
+    let artifacts = session::Artifacts::generate(&pdh, policy).map_err(failed)?;
+
+    let session = PyDict::new(py);
+    session.set_item("godh", bytes(py, &artifacts.godh))?;
+    session.set_item("session", bytes(py, &artifacts.session))?;
+    session.set_item("tek", bytes(py, &artifacts.tek))?;
+    session.set_item("tik", bytes(py, &artifacts.tik))?;
+    Ok(session.into())
+}
+
+/// Packages a launch secret for LAUNCH_SECRET, encrypted with the TEK and bound
+/// to the launch measurement with the TIK, returning the packet header and the
+/// encrypted secret.
+#[pyfunction]
+fn package_secret(
+    py: Python<'_>,
+    tek: &[u8],
+    tik: &[u8],
+    measurement: &[u8],
+    secret: &[u8],
+) -> PyResult<(PyObject, PyObject)> {
+    let tek = sized::<16>(tek, "tek")?;
+    let tik = sized::<16>(tik, "tik")?;
+    let measurement = sized::<32>(measurement, "measurement")?;
+
+    let (header, encrypted) =
+        sevctl::secret::build(&tek, &tik, &measurement, secret).map_err(failed)?;
+    Ok((bytes(py, &header), bytes(py, &encrypted)))
+}
+
+#[pymodule]
+#[pyo3(name = "sevctl")]
+fn module(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add("__version__", sevctl::VERSION)?;
+    m.add("SevctlError", py.get_type::<SevctlError>())?;
+    m.add_function(wrap_pyfunction!(export_chain, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_chain, m)?)?;
+    m.add_function(wrap_pyfunction!(compute_measurement, m)?)?;
+    m.add_function(wrap_pyfunction!(build_measurement, m)?)?;
+    m.add_function(wrap_pyfunction!(create_session, m)?)?;
+    m.add_function(wrap_pyfunction!(package_secret, m)?)?;
+    Ok(())
+}
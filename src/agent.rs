@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A guest agent answering attestation report requests over vsock, and its host side.
+//!
+//! Each connection carries one request line, a JSON object with the hex-encoded
+//! `report-data` to bind into the report, answered by one line holding either the
+//! base64-encoded `report` or an `error`.
+
+use super::*;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+
+pub fn cmd(vsock: bool, port: u32) -> Result<()> {
+    if !vsock {
+        return Err(error::Context::new(
+            "a transport is required: --vsock",
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let listener = vsock::listen(port)?;
+    tracing::info!(port, "listening on vsock");
+
+    loop {
+        let (conn, cid) = listener.accept()?;
+        if let Err(e) = answer(conn) {
+            eprintln!("request from CID {} failed: {}", cid, e);
+        } else {
+            tracing::info!(cid, "sent a report");
+        }
+    }
+}
+
+fn answer(conn: File) -> Result<()> {
+    let mut request = String::new();
+    BufReader::new(&conn)
+        .read_line(&mut request)
+        .context("unable to read request")?;
+
+    let response = match report_for(&request) {
+        Ok(report) => json!({ "report": base64::encode(report.as_bytes()) }),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+
+    writeln!(&conn, "{}", response).context("unable to send response")
+}
+
+fn report_for(request: &str) -> Result<report::Report> {
+    let request: Value = serde_json::from_str(request).context("invalid request")?;
+    let data = match request["report-data"].as_str() {
+        Some(data) => unhex(data).context("invalid report data")?,
+        None => Vec::new(),
+    };
+
+    report::request(&report::data(&data)?)
+}
+
+/// Requests a report from the agent in the guest with the given CID.
+pub fn guest_report(
+    cid: u32,
+    port: u32,
+    data: Option<String>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let data = match data {
+        Some(data) => report::data(&unhex(&data).context("invalid --report-data")?)?,
+        None => [0u8; 64],
+    };
+
+    let conn = vsock::connect(cid, port)?;
+    writeln!(&conn, "{}", json!({ "report-data": hex(&data) }))
+        .context("unable to send request")?;
+
+    let mut response = String::new();
+    BufReader::new(&conn)
+        .read_line(&mut response)
+        .context("unable to read response")?;
+    let response: Value = serde_json::from_str(&response).context("invalid agent response")?;
+
+    if let Some(error) = response["error"].as_str() {
+        return Err(error::Context::new(
+            &format!("agent failed to produce a report: {}", error),
+            Box::<Error>::new(ErrorKind::Other.into()),
+        ));
+    }
+
+    let report = response["report"]
+        .as_str()
+        .and_then(|r| base64::decode(r).ok())
+        .ok_or_else(|| {
+            error::Context::new(
+                "agent response holds no report",
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            )
+        })?;
+    let report = report::Report::new(report)?;
+
+    match output {
+        Some(path) => session::write(&path, report.as_bytes(), 0o644),
+        None => {
+            output::print(&report::Details(report.document()));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,521 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Appraisal of SEV-SNP attestation reports: the signature, checked up to a trusted
+//! ARK, and the rules of an appraisal policy file, each with its own verdict.
+//!
+//! ```toml
+//! # The guest policy may set no other flags, e.g. not DEBUG.
+//! allowed-policy-flags = ["SMT"]
+//! measurements = ["4f0d2c..."]
+//! id-key-digests = ["a1b2c3..."]
+//!
+//! # The reported TCB must be at least this, component by component.
+//! [min-tcb]
+//! bootloader = 3
+//! tee = 0
+//! snp = 8
+//! microcode = 115
+//! ```
+
+use super::*;
+use openssl::x509::X509;
+use schema::{CheckResult, CheckState};
+use serde::Deserialize;
+
+/// An appraisal policy; every rule is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Appraisal {
+    /// The minimum SVN of each TCB component.
+    #[serde(default)]
+    pub min_tcb: MinTcb,
+
+    /// The guest policy flags that may be set (see `sevctl policy explain --snp`).
+    pub allowed_policy_flags: Option<Vec<String>>,
+
+    /// The launch measurements accepted, in hex.
+    pub measurements: Option<Vec<String>>,
+
+    /// The ID key digests accepted, in hex.
+    pub id_key_digests: Option<Vec<String>>,
+}
+
+/// The minimum SVN of each TCB component; components not given are not checked.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinTcb {
+    pub bootloader: Option<u8>,
+    pub tee: Option<u8>,
+    pub snp: Option<u8>,
+    pub microcode: Option<u8>,
+}
+
+impl Appraisal {
+    pub fn load(path: &Path) -> Result<Self> {
+        tracing::debug!(path = %path.display(), "reading file");
+        let text =
+            std::fs::read_to_string(path).context(format!("unable to read {}", path.display()))?;
+        let appraisal: Self = toml::from_str(&text)
+            .context(format!("invalid appraisal policy {}", path.display()))?;
+
+        // Catch typos in the policy rather than failing every report.
+        for name in appraisal.allowed_policy_flags.iter().flatten() {
+            if policy::snp_flag(name).is_none() {
+                return Err(error::Context::new(
+                    &format!("invalid appraisal policy {}", path.display()),
+                    Box::new(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("unknown guest policy flag {}", name),
+                    )),
+                ));
+            }
+        }
+        let digests = appraisal.measurements.iter().flatten();
+        for digest in digests.chain(appraisal.id_key_digests.iter().flatten()) {
+            unhex(digest).context(format!("invalid digest {} in {}", digest, path.display()))?;
+        }
+
+        Ok(appraisal)
+    }
+
+    /// Applies the rules to a report.
+    pub fn appraise(&self, report: &schema::AttestationReport) -> Vec<CheckResult> {
+        let mut results = Vec::new();
+
+        let reported = &report.reported_tcb;
+        for (id, min, svn) in &[
+            (
+                "tcb.bootloader",
+                self.min_tcb.bootloader,
+                reported.bootloader,
+            ),
+            ("tcb.tee", self.min_tcb.tee, reported.tee),
+            ("tcb.snp", self.min_tcb.snp, reported.snp),
+            ("tcb.microcode", self.min_tcb.microcode, reported.microcode),
+        ] {
+            if let Some(min) = min {
+                results.push(result(
+                    id,
+                    &format!("Reported {} SVN at least {}", &id[4..], min),
+                    match svn >= min {
+                        true => Ok(svn.to_string()),
+                        false => Err(format!("{} is below {}", svn, min)),
+                    },
+                ));
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_policy_flags {
+            let set = policy::snp_flags(report.policy);
+            let denied: Vec<_> = set
+                .iter()
+                // Bit 17 must always be set.
+                .filter(|flag| **flag != "RESERVED")
+                .filter(|flag| !allowed.iter().any(|a| a.eq_ignore_ascii_case(flag)))
+                .map(|flag| flag.to_string())
+                .collect();
+
+            let outcome = match (denied.is_empty(), policy::snp_reserved(report.policy)) {
+                (true, 0) => Ok(policy::describe_snp(report.policy)),
+                (true, reserved) => Err(format!("reserved bits {:#x} are set", reserved)),
+                (false, _) => Err(format!("{} not allowed", denied.join(", "))),
+            };
+            results.push(result(
+                "policy.flags",
+                "Guest policy flags allowed",
+                outcome,
+            ));
+        }
+
+        if let Some(measurements) = &self.measurements {
+            results.push(result(
+                "measurement",
+                "Launch measurement expected",
+                one_of(&report.measurement, measurements),
+            ));
+        }
+
+        if let Some(digests) = &self.id_key_digests {
+            results.push(result(
+                "id-key-digest",
+                "ID key digest expected",
+                one_of(&report.id_key_digest, digests),
+            ));
+        }
+
+        results
+    }
+}
+
+/// The AMD ARKs an SEV-SNP certificate chain must end in.
+#[derive(StructOpt, Default)]
+pub struct TrustedArks {
+    #[structopt(
+        long = "ark",
+        number_of_values = 1,
+        parse(from_os_str),
+        help = "AMD ARK (PEM or DER) to trust, in addition to those set with snp-arks"
+    )]
+    arks: Vec<PathBuf>,
+}
+
+impl TrustedArks {
+    /// The ARKs given and those of the configuration, at least one.
+    pub fn load(&self) -> Result<Vec<X509>> {
+        let configured = config::get().snp_arks.unwrap_or_default();
+        let mut arks = Vec::new();
+        for path in self.arks.iter().chain(configured.iter()) {
+            tracing::debug!(path = %path.display(), "reading file");
+            let data = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+            let ark = X509::from_pem(&data)
+                .or_else(|_| X509::from_der(&data))
+                .context(format!("unable to decode {}", path.display()))?;
+            arks.push(ark);
+        }
+
+        if arks.is_empty() {
+            return Err(error::Context::new(
+                "no AMD ARK is trusted",
+                Box::new(Error::new(
+                    ErrorKind::NotFound,
+                    "give the ARK of the processor generation with --ark, or set snp-arks",
+                )),
+            ));
+        }
+        Ok(arks)
+    }
+}
+
+/// Checks that the VCEK chains up to one of the trusted `arks` and belongs to the
+/// chip and TCB of the report, then that the report is signed by the VCEK.
+pub fn signature(
+    report: &report::Report,
+    vcek: &X509,
+    chain: &[X509],
+    arks: &[X509],
+) -> Vec<CheckResult> {
+    let fields = report.document();
+    let chain_outcome = vcek_chain(vcek, chain).and_then(|_| trusted(&chain[1], arks));
+    let identity_outcome = identity(vcek, &fields.chip_id, &fields.reported_tcb);
+    let trusted = chain_outcome.is_ok() && identity_outcome.is_ok();
+
+    let mut results = vec![
+        result(
+            "vcek.chain",
+            "VCEK signed by the ASK and a trusted ARK",
+            chain_outcome,
+        ),
+        result(
+            "vcek.identity",
+            "VCEK issued for the chip and TCB of the report",
+            identity_outcome,
+        ),
+    ];
+    results.push(match trusted {
+        true => result(
+            "report.signature",
+            "Report signed by the VCEK",
+            match report.verify(vcek) {
+                Ok(true) => Ok(String::new()),
+                Ok(false) => Err("invalid signature".to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+        ),
+        false => CheckResult {
+            id: "report.signature".into(),
+            name: "Report signed by the VCEK".into(),
+            state: CheckState::Skip,
+            details: Some("requires vcek.chain and vcek.identity".into()),
+        },
+    });
+    results
+}
+
+/// Checks that the VCEK is signed by the ASK and the ASK by the self-signed ARK.
+///
+/// Anyone can make such a chain; [`signature`] also requires a trusted ARK.
+pub fn vcek_chain(vcek: &X509, chain: &[X509]) -> std::result::Result<String, String> {
+    match chain {
+        [ask, ark] => {
+            let signed = |cert: &X509, signer: &X509| {
+                signer
+                    .public_key()
+                    .and_then(|key| cert.verify(&key))
+                    .unwrap_or(false)
+            };
+            match (signed(ark, ark), signed(ask, ark), signed(vcek, ask)) {
+                (true, true, true) => Ok(String::new()),
+                (false, _, _) => Err("the ARK is not self-signed".to_string()),
+                (_, false, _) => Err("the ASK is not signed by the ARK".to_string()),
+                (_, _, false) => Err("the VCEK is not signed by the ASK".to_string()),
+            }
+        }
+        _ => Err(format!(
+            "expected the ASK and ARK, found {} certificates",
+            chain.len()
+        )),
+    }
+}
+
+/// Checks that `ark` has the key of one of the trusted `arks`.
+fn trusted(ark: &X509, arks: &[X509]) -> std::result::Result<String, String> {
+    let key = ark.public_key().map_err(|e| e.to_string())?;
+    let same = |trusted: &X509| {
+        trusted
+            .public_key()
+            .map_or(false, |trusted| trusted.public_eq(&key))
+    };
+    match arks.iter().any(same) {
+        true => Ok(String::new()),
+        false => Err("the ARK is not a trusted one (see --ark)".to_string()),
+    }
+}
+
+/// The VCEK extension holding the chip ID (hwID).
+const HWID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x04];
+
+/// The VCEK extensions holding the SVN of each TCB component (blSPL, teeSPL,
+/// snpSPL and ucodeSPL).
+const SPLS: &[(&str, &[u8])] = &[
+    (
+        "bootloader",
+        &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x03, 0x01],
+    ),
+    (
+        "tee",
+        &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x03, 0x02],
+    ),
+    (
+        "snp",
+        &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x03, 0x03],
+    ),
+    (
+        "microcode",
+        &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x9c, 0x78, 0x01, 0x03, 0x08],
+    ),
+];
+
+/// Checks the VCEK's hwID and TCB extensions against the report's chip ID and
+/// reported TCB, which the KDS derives the VCEK from.
+fn identity(vcek: &X509, chip_id: &[u8], tcb: &schema::Tcb) -> std::result::Result<String, String> {
+    let der = vcek.to_der().map_err(|e| e.to_string())?;
+    let mut details = Vec::new();
+
+    match (extension(&der, HWID), chip_id.iter().all(|b| *b == 0)) {
+        (_, true) => details.push("the chip ID is masked in the report".to_string()),
+        (None, false) => return Err("the VCEK has no hwID".to_string()),
+        (Some(hwid), false) => {
+            // The hwID is the bare chip ID, or an OCTET STRING of it.
+            let hwid = match hwid {
+                [0x04, 0x40, id @ ..] if id.len() == 64 => id,
+                hwid => hwid,
+            };
+            if hwid != chip_id {
+                return Err(format!("the VCEK is for chip {}", hex(hwid)));
+            }
+        }
+    }
+
+    for ((name, oid), svn) in SPLS
+        .iter()
+        .zip(&[tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode])
+    {
+        // An INTEGER of one byte, or two if the high bit is set.
+        let spl = match extension(&der, oid) {
+            Some([0x02, 0x01, spl]) if *spl < 0x80 => *spl,
+            Some([0x02, 0x02, 0x00, spl]) if *spl >= 0x80 => *spl,
+            _ => return Err(format!("the VCEK has no valid {} SVN", name)),
+        };
+        if spl != *svn {
+            return Err(format!(
+                "the VCEK is for {} SVN {}, the report's is {}",
+                name, spl, svn
+            ));
+        }
+    }
+
+    Ok(details.join(", "))
+}
+
+/// The value of the extension `oid` of a DER certificate, if present.
+///
+/// Extensions are the only place the OIDs of the AMD arc occur in a VCEK.
+fn extension<'a>(der: &'a [u8], oid: &[u8]) -> Option<&'a [u8]> {
+    // The OID, an optional critical flag, then the value as an OCTET STRING.
+    let mut tag = vec![0x06, oid.len() as u8];
+    tag.extend_from_slice(oid);
+    let at = der.windows(tag.len()).position(|w| w == &tag[..])? + tag.len();
+    let mut rest = &der[at..];
+    if let [0x01, 0x01, _, tail @ ..] = rest {
+        rest = tail;
+    }
+
+    let (len, rest) = match rest {
+        [0x04, len, rest @ ..] if *len < 0x80 => (*len as usize, rest),
+        [0x04, 0x81, len, rest @ ..] => (*len as usize, rest),
+        [0x04, 0x82, hi, lo, rest @ ..] => (u16::from_be_bytes([*hi, *lo]) as usize, rest),
+        _ => return None,
+    };
+    rest.get(..len)
+}
+
+/// Checks that the report carries the expected report data.
+pub fn report_data(report: &schema::AttestationReport, expected: &[u8; 64]) -> CheckResult {
+    result(
+        "report-data",
+        "Report data expected",
+        match report.report_data == expected[..] {
+            true => Ok(String::new()),
+            false => Err(format!("{} is not expected", hex(&report.report_data))),
+        },
+    )
+}
+
+/// Prints the verdicts, failing if any rule failed.
+pub fn report(checks: Vec<CheckResult>) -> Result<()> {
+    let failed = checks
+        .iter()
+        .filter(|c| c.state == CheckState::Fail)
+        .count();
+    let total = checks.len();
+    output::print(&schema::Checks { checks });
+
+    match failed {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            "the attestation report failed appraisal",
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} of {} rules failed", n, total),
+            )),
+        )),
+    }
+}
+
+fn one_of(value: &[u8], expected: &[String]) -> std::result::Result<String, String> {
+    match expected
+        .iter()
+        .any(|e| unhex(e).map_or(false, |e| e == value))
+    {
+        true => Ok(hex(value)),
+        false => Err(format!("{} is not expected", hex(value))),
+    }
+}
+
+pub fn result(id: &str, name: &str, outcome: std::result::Result<String, String>) -> CheckResult {
+    let (state, details) = match outcome {
+        Ok(details) => (CheckState::Pass, details),
+        Err(details) => (CheckState::Fail, details),
+    };
+
+    CheckResult {
+        id: id.to_string(),
+        name: name.to_string(),
+        state,
+        details: Some(details).filter(|d| !d.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::{X509Extension, X509NameBuilder};
+
+    fn key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    /// A certificate for `key` named `cn`, signed by `signer` and with the
+    /// extensions given as (OID, DER value in hex).
+    fn cert(cn: &str, key: &PKey<Private>, signer: &PKey<Private>, exts: &[(&str, &str)]) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        cert.set_serial_number(&serial).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        for (oid, value) in exts {
+            #[allow(deprecated)]
+            let ext = X509Extension::new(None, None, oid, &format!("DER:{}", value)).unwrap();
+            cert.append_extension(ext).unwrap();
+        }
+        cert.sign(signer, MessageDigest::sha384()).unwrap();
+        cert.build()
+    }
+
+    #[test]
+    fn forged_ark() {
+        let (ark_key, ask_key, vcek_key) = (key(), key(), key());
+        let ark = cert("ARK-Milan", &ark_key, &ark_key, &[]);
+        let ask = cert("SEV-Milan", &ask_key, &ark_key, &[]);
+        let vcek = cert("SEV-VCEK", &vcek_key, &ask_key, &[]);
+        let chain = [ask, ark.clone()];
+        assert!(vcek_chain(&vcek, &chain).is_ok());
+        assert!(trusted(&chain[1], &[ark]).is_ok());
+
+        // A chain of the attacker's own, well-formed but ending in another ARK.
+        let forged = key();
+        let forged_ark = cert("ARK-Milan", &forged, &forged, &[]);
+        assert!(trusted(&forged_ark, &chain[1..]).is_err());
+        assert!(trusted(&chain[1], &[]).is_err());
+    }
+
+    #[test]
+    fn vcek_identity() {
+        let chip_id: Vec<u8> = (0..64).collect();
+        let tcb = |bootloader, microcode| schema::Tcb {
+            bootloader,
+            tee: 0,
+            _reserved: vec![0; 4],
+            snp: 8,
+            microcode,
+        };
+
+        let key = key();
+        let hwid: Vec<String> = chip_id.iter().map(|b| format!("{:02x}", b)).collect();
+        let hwid = format!("04:40:{}", hwid.join(":"));
+        let vcek = cert(
+            "SEV-VCEK",
+            &key,
+            &key,
+            &[
+                ("1.3.6.1.4.1.3704.1.3.1", "02:01:03"),
+                ("1.3.6.1.4.1.3704.1.3.2", "02:01:00"),
+                ("1.3.6.1.4.1.3704.1.3.3", "02:01:08"),
+                ("1.3.6.1.4.1.3704.1.3.8", "02:02:00:d1"),
+                ("1.3.6.1.4.1.3704.1.4", &hwid),
+            ],
+        );
+
+        assert!(identity(&vcek, &chip_id, &tcb(3, 0xd1)).is_ok());
+        assert!(identity(&vcek, &chip_id, &tcb(2, 0xd1)).is_err());
+        assert!(identity(&vcek, &chip_id, &tcb(3, 0xd0)).is_err());
+
+        let mut other = chip_id.clone();
+        other[0] ^= 1;
+        assert!(identity(&vcek, &other, &tcb(3, 0xd1)).is_err());
+
+        // A masked chip ID cannot be compared, but the TCB still is.
+        assert!(identity(&vcek, &[0; 64], &tcb(3, 0xd1)).is_ok());
+        assert!(identity(&vcek, &[0; 64], &tcb(4, 0xd1)).is_err());
+
+        let bare = cert("SEV-VCEK", &key, &key, &[]);
+        assert!(identity(&bare, &chip_id, &tcb(3, 0xd1)).is_err());
+    }
+}
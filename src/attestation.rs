@@ -0,0 +1,303 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Attestation reports of running SEV and SEV-ES guests.
+//!
+//! Before SEV-SNP, a guest owner can ask for a report of a running guest with
+//! the firmware's ATTESTATION command, which the VMM issues through
+//! KVM_SEV_GET_ATTESTATION_REPORT (QEMU's `query-sev-attestation-report`). The
+//! report binds the owner's nonce to the guest's launch digest and policy, and is
+//! signed by the platform's PEK, which the owner checks up to the ARK.
+
+use super::*;
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{hash, MessageDigest};
+use schema::{CheckResult, CheckState};
+
+/// The size of a report.
+const REPORT_SIZE: usize = 0xd0;
+
+/// The size of the signed part of a report, before the signature.
+const SIGNED_SIZE: usize = 0x40;
+
+/// The size of each little-endian component of the signature.
+const COMPONENT_SIZE: usize = 72;
+
+/// The key usage of the PEK.
+const PEK_USAGE: u32 = 0x1002;
+
+/// ECDSA with SHA-256.
+const ECDSA_SHA256: u32 = 0x2;
+
+#[derive(StructOpt)]
+pub enum Attestation {
+    #[structopt(about = "Request an attestation report of a running SEV guest through QEMU")]
+    Request {
+        #[structopt(long, parse(from_os_str), help = "QMP socket of the guest's QEMU")]
+        qmp: PathBuf,
+
+        #[structopt(long, help = "16-byte nonce to bind to the report, in hex")]
+        mnonce: String,
+
+        #[structopt(parse(from_os_str), help = "File to write the raw report to")]
+        destination: PathBuf,
+    },
+
+    #[structopt(
+        about = "Verify the PEK signature, nonce and contents of an SEV attestation report"
+    )]
+    Verify {
+        #[structopt(
+            long,
+            required_unless = "nonce-id",
+            conflicts_with = "nonce-id",
+            help = "Nonce the report must bind, in hex"
+        )]
+        mnonce: Option<String>,
+
+        #[structopt(
+            long,
+            help = "ID of the stored nonce the report must bind (see nonce new --store)"
+        )]
+        nonce_id: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Certificate chain of the platform, as written by export (or - for stdin)"
+        )]
+        chain: PathBuf,
+
+        #[structopt(long, help = "Launch digest the guest must have, in hex")]
+        launch_digest: Option<String>,
+
+        #[structopt(
+            long,
+            help = "Guest policy the guest must have, as a number or flag names"
+        )]
+        policy: Option<String>,
+
+        #[structopt(parse(from_os_str), help = "Raw attestation report")]
+        report: PathBuf,
+
+        #[structopt(flatten)]
+        roots: session::Roots,
+    },
+}
+
+pub fn cmd(attestation: Attestation) -> Result<()> {
+    match attestation {
+        Attestation::Request {
+            qmp,
+            mnonce,
+            destination,
+        } => {
+            let mnonce: [u8; 16] = encoding::arg(encoding::Form::Hex, &mnonce, "mnonce")?;
+            let mut qmp = qmp::Qmp::connect(&qmp)?;
+            let report = Report::new(qmp.query_sev_attestation_report(&mnonce)?)?;
+
+            session::write(&destination, &report.0, 0o644)?;
+            output::print(&report.document());
+            Ok(())
+        }
+
+        Attestation::Verify {
+            mnonce,
+            nonce_id,
+            chain,
+            launch_digest,
+            policy,
+            report,
+            roots,
+        } => {
+            let mnonce = mnonce
+                .map(|n| encoding::arg::<[u8; 16]>(encoding::Form::Hex, &n, "mnonce"))
+                .transpose()?;
+            let stored = nonce_id.as_deref().map(nonce::Stored::load).transpose()?;
+            let launch_digest = launch_digest
+                .map(|d| encoding::arg::<[u8; 32]>(encoding::Form::Hex, &d, "launch digest"))
+                .transpose()?;
+            let policy = policy.as_deref().map(session::sev_policy).transpose()?;
+
+            let chain = session::decode_chain(
+                &session::read_chain(Some(chain), None, None)?,
+                roots.load()?,
+            )?;
+
+            tracing::debug!(path = %report.display(), "reading file");
+            let bytes =
+                std::fs::read(&report).context(format!("unable to read {}", report.display()))?;
+            let report = Report::new(bytes)?;
+
+            let mut results = report.signature(&chain);
+            results.extend(mnonce.map(|mnonce| report.mnonce(&mnonce)));
+            results.extend(launch_digest.map(|digest| report.launch_digest(&digest)));
+            results.extend(policy.map(|policy| report.policy(policy)));
+            if let Some(stored) = stored {
+                let check = stored.check(&report.0[0x00..0x10]);
+                stored.consume(&check)?;
+                results.push(check);
+            }
+            appraisal::report(results)
+        }
+    }
+}
+
+/// An SEV attestation report.
+struct Report(Vec<u8>);
+
+impl Report {
+    fn new(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < REPORT_SIZE {
+            return Err(error::Context::new(
+                &format!(
+                    "attestation report is {} bytes, expected {}",
+                    bytes.len(),
+                    REPORT_SIZE
+                ),
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+
+        Ok(Self(bytes))
+    }
+
+    fn u32(&self, off: usize) -> u32 {
+        let mut v = [0u8; 4];
+        v.copy_from_slice(&self.0[off..off + 4]);
+        u32::from_le_bytes(v)
+    }
+
+    fn document(&self) -> schema::SevAttestationReport {
+        schema::SevAttestationReport {
+            mnonce: hex(&self.0[0x00..0x10]),
+            launch_digest: hex(&self.0[0x10..0x30]),
+            policy: self.u32(0x30),
+            sig_usage: self.u32(0x34),
+            sig_algo: self.u32(0x38),
+        }
+    }
+
+    /// Checks the PEK up to the ARK, then its signature of the report.
+    fn signature(&self, chain: &Chain) -> Vec<CheckResult> {
+        let checked = verify::check(&chain.sev, &chain.ca);
+        let broken: Vec<String> = checked
+            .signatures
+            .iter()
+            .filter(|s| !s.valid || s.self_signature_valid == Some(false))
+            .map(|s| {
+                let signer = s.signer.split(' ').next().unwrap_or_default();
+                format!("the {} signature of the {} is invalid", signer, s.signee)
+            })
+            .collect();
+
+        let mut results = vec![appraisal::result(
+            "pek.chain",
+            "PEK certified up to the ARK",
+            match broken.is_empty() {
+                true => Ok(String::new()),
+                false => Err(broken.join(", ")),
+            },
+        )];
+        results.push(match broken.is_empty() {
+            true => appraisal::result(
+                "report.signature",
+                "Report signed by the PEK",
+                self.verify(&chain.sev.pek),
+            ),
+            false => CheckResult {
+                id: "report.signature".into(),
+                name: "Report signed by the PEK".into(),
+                state: CheckState::Skip,
+                details: Some("requires pek.chain".into()),
+            },
+        });
+        results
+    }
+
+    /// Checks the ECDSA signature of the report with the PEK.
+    fn verify(&self, pek: &sev::Certificate) -> std::result::Result<String, String> {
+        let (usage, algo) = (self.u32(0x34), self.u32(0x38));
+        if usage != PEK_USAGE || algo != ECDSA_SHA256 {
+            return Err(format!(
+                "signed with key usage {:#x} and algorithm {:#x}, expected the PEK and ECDSA",
+                usage, algo
+            ));
+        }
+
+        let component = |off: usize| {
+            let mut be = self.0[off..off + COMPONENT_SIZE].to_vec();
+            be.reverse();
+            BigNum::from_slice(&be)
+        };
+        let verified = (|| -> Result<bool> {
+            let sig = EcdsaSig::from_private_components(
+                component(SIGNED_SIZE).context("invalid report signature")?,
+                component(SIGNED_SIZE + COMPONENT_SIZE).context("invalid report signature")?,
+            )
+            .context("invalid report signature")?;
+            let key = session::public_key(pek, "PEK", session::ECDSA)?
+                .ec_key()
+                .context("invalid PEK public key")?;
+            let digest = hash(MessageDigest::sha256(), &self.0[..SIGNED_SIZE])
+                .context("unable to hash the report")?;
+            sig.verify(&digest, &key)
+                .context("unable to check the report signature")
+        })();
+
+        match verified {
+            Ok(true) => Ok(String::new()),
+            Ok(false) => Err("invalid signature".to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn mnonce(&self, expected: &[u8; 16]) -> CheckResult {
+        let mnonce = &self.0[0x00..0x10];
+        appraisal::result(
+            "mnonce",
+            "Nonce expected",
+            match mnonce == expected {
+                true => Ok(String::new()),
+                false => Err(format!("{} is not expected", hex(mnonce))),
+            },
+        )
+    }
+
+    fn launch_digest(&self, expected: &[u8; 32]) -> CheckResult {
+        let digest = &self.0[0x10..0x30];
+        appraisal::result(
+            "launch-digest",
+            "Launch digest expected",
+            match digest == expected {
+                true => Ok(hex(digest)),
+                false => Err(format!("{} is not expected", hex(digest))),
+            },
+        )
+    }
+
+    fn policy(&self, expected: u32) -> CheckResult {
+        let policy = self.u32(0x30);
+        appraisal::result(
+            "policy",
+            "Guest policy expected",
+            match policy == expected {
+                true => Ok(format!("{:#x}", policy)),
+                false => Err(format!("{:#x} is not {:#x}", policy, expected)),
+            },
+        )
+    }
+}
+
+impl output::Document for schema::SevAttestationReport {
+    fn table(&self) -> String {
+        let signer = match (self.sig_usage, self.sig_algo) {
+            (PEK_USAGE, ECDSA_SHA256) => "PEK, ECDSA with SHA-256".to_string(),
+            (usage, algo) => format!("key usage {:#x}, algorithm {:#x}", usage, algo),
+        };
+        format!(
+            "mnonce: {}\nlaunch digest: {}\npolicy: {:#x}\nsigned by: {}\n",
+            self.mnonce, self.launch_digest, self.policy, signer
+        )
+    }
+}
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The audit log of the commands changing the platform.
+//!
+//! Every run of such a command, and every such firmware command the helper issues
+//! for others, is recorded once it completes: when, by whom, with what arguments
+//! and with what outcome, including the status code of a firmware command the
+//! firmware rejected. Entries go to syslog (and so the journal) unless the
+//! `audit-log` setting names a file, to which they are appended as JSON lines, or
+//! is `off`.
+
+use super::*;
+use serde_json::Value;
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where entries go.
+pub enum Log {
+    Off,
+    Syslog,
+    File { path: PathBuf, file: File },
+}
+
+impl Log {
+    /// Opens the configured log, before the command runs so that a log that
+    /// cannot be written stops it.
+    pub fn open() -> Result<Self> {
+        match config::get().audit_log.as_deref() {
+            None | Some("syslog") => Ok(Log::Syslog),
+            Some("off") => Ok(Log::Off),
+            Some(path) => {
+                let path = PathBuf::from(path);
+                let file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o640)
+                    .open(&path)
+                    .context(format!("unable to open the audit log {}", path.display()))?;
+                Ok(Log::File { path, file })
+            }
+        }
+    }
+
+    pub fn record(&mut self, entry: &schema::AuditEntry) -> Result<()> {
+        let line = serde_json::to_string(&schema::Versioned::new(entry)).unwrap();
+
+        match self {
+            Log::Off => Ok(()),
+            Log::Syslog => {
+                // JSON escapes NUL characters.
+                let line = CString::new(line).unwrap();
+
+                // SAFETY: the identifier is static and the format takes one string.
+                unsafe {
+                    libc::openlog(
+                        b"sevctl\0".as_ptr() as *const libc::c_char,
+                        libc::LOG_PID,
+                        libc::LOG_AUTHPRIV,
+                    );
+                    libc::syslog(
+                        libc::LOG_NOTICE,
+                        b"%s\0".as_ptr() as *const libc::c_char,
+                        line.as_ptr(),
+                    );
+                }
+                Ok(())
+            }
+            // A single write, so that concurrent runs do not interleave.
+            Log::File { path, file } => file
+                .write_all(format!("{}\n", line).as_bytes())
+                .context(format!("unable to write the audit log {}", path.display())),
+        }
+    }
+}
+
+/// The entry for a command this process ran.
+pub fn command(command: &str, result: &Result<()>) -> schema::AuditEntry {
+    // SAFETY: getuid and getpid cannot fail.
+    let (uid, pid) = unsafe { (libc::getuid(), libc::getpid()) };
+
+    let (error, firmware_status) = match result {
+        Ok(()) => (None, None),
+        Err(err) => {
+            let report = err.report();
+            let mut message = report.message;
+            for cause in report.causes {
+                message += &format!(": {}", cause);
+            }
+            (Some(message), report.firmware_status)
+        }
+    };
+
+    entry(
+        command,
+        uid,
+        pid as u32,
+        std::env::args().collect(),
+        error,
+        firmware_status,
+    )
+}
+
+/// The entry for a firmware command the helper issued for process `pid` of `uid`.
+pub fn request(
+    uid: u32,
+    pid: u32,
+    request: &Value,
+    result: &std::io::Result<Value>,
+) -> schema::AuditEntry {
+    let response = helper::response(result);
+    entry(
+        request["command"].as_str().unwrap_or_default(),
+        uid,
+        pid,
+        vec![request.to_string()],
+        response["error"].as_str().map(String::from),
+        response["status"].as_u64().map(|status| status as u32),
+    )
+}
+
+fn entry(
+    command: &str,
+    uid: u32,
+    pid: u32,
+    args: Vec<String>,
+    error: Option<String>,
+    firmware_status: Option<u32>,
+) -> schema::AuditEntry {
+    schema::AuditEntry {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        uid,
+        user: user(uid),
+        pid,
+        command: command.to_string(),
+        args,
+        ok: error.is_none(),
+        error,
+        firmware_status,
+    }
+}
+
+/// The name of a user.
+fn user(uid: u32) -> Option<String> {
+    // SAFETY: passwd is plain data that getpwuid_r fills in.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut found = std::ptr::null_mut();
+
+    // SAFETY: the buffers outlive the call and the strings read from them.
+    unsafe {
+        let ret = libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found);
+        if ret != 0 || found.is_null() {
+            return None;
+        }
+        CStr::from_ptr(pwd.pw_name).to_str().ok().map(String::from)
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Latency of the firmware commands that only read the platform state.
+//!
+//! The LAUNCH commands are not measured: the kernel only issues them for KVM
+//! guests, through their VM, and each launch holds a guest ASID.
+
+use super::*;
+use device::Device;
+use std::time::Instant;
+
+/// The commands measured unless told otherwise.
+pub const COMMANDS: &[&str] = &[
+    "PLATFORM_STATUS",
+    "PDH_CERT_EXPORT",
+    "GET_ID",
+    "SNP_PLATFORM_STATUS",
+];
+
+#[derive(StructOpt)]
+pub struct BenchArgs {
+    #[structopt(
+        short = "n",
+        long,
+        default_value = "100",
+        help = "Times to issue each command"
+    )]
+    iterations: u32,
+
+    #[structopt(
+        long,
+        possible_values = COMMANDS,
+        number_of_values = 1,
+        help = "Command to measure, all of them unless given (repeatable)"
+    )]
+    command: Vec<String>,
+}
+
+pub fn cmd(args: BenchArgs) -> Result<()> {
+    if args.iterations == 0 {
+        return Err(error::Context::new(
+            "--iterations must be at least one",
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let commands: Vec<&str> = match args.command.is_empty() {
+        true => COMMANDS.to_vec(),
+        false => args.command.iter().map(String::as_str).collect(),
+    };
+
+    let mut fw = firmware()?;
+    let results: Vec<_> = commands
+        .iter()
+        .map(|command| measure(&mut *fw, command, args.iterations))
+        .collect();
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    let total = results.len();
+    output::print(&schema::Benchmark {
+        iterations: args.iterations,
+        commands: results,
+    });
+
+    match failed == total {
+        false => Ok(()),
+        true => Err(error::Context::new(
+            "unable to issue any firmware command",
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} of {} commands failed", failed, total),
+            )),
+        )),
+    }
+}
+
+/// Issues a command once to check that the platform supports it, then times it.
+fn measure(fw: &mut dyn Device, command: &str, iterations: u32) -> schema::CommandLatency {
+    let mut issue = || -> std::io::Result<()> {
+        match command {
+            "PLATFORM_STATUS" => fw.platform_status().map(|_| ()),
+            "PDH_CERT_EXPORT" => fw.pdh_cert_export().map(|_| ()),
+            "GET_ID" => fw.get_identifier().map(|_| ()),
+            "SNP_PLATFORM_STATUS" => fw.snp_platform_status().map(|_| ()),
+            _ => unreachable!("unknown command {}", command),
+        }
+    };
+
+    let _span = tracing::info_span!("firmware", command).entered();
+    tracing::info!(iterations, "measuring firmware command");
+
+    let mut samples = Vec::with_capacity(iterations as usize);
+    let mut outcome = issue();
+    for _ in 0..iterations {
+        if outcome.is_err() {
+            break;
+        }
+        let start = Instant::now();
+        outcome = issue();
+        samples.push(start.elapsed().as_micros() as u64);
+    }
+
+    schema::CommandLatency {
+        command: command.to_string(),
+        latency: match &outcome {
+            Ok(()) => Some(latency(samples)),
+            Err(_) => None,
+        },
+        error: outcome.err().map(|e| {
+            tracing::error!(error = %e);
+            e.to_string()
+        }),
+    }
+}
+
+fn latency(mut samples: Vec<u64>) -> schema::Latency {
+    samples.sort_unstable();
+
+    // The nearest-rank percentile.
+    let percentile = |p: usize| samples[(samples.len() * p + 99) / 100 - 1];
+    schema::Latency {
+        min_us: samples[0],
+        mean_us: samples.iter().sum::<u64>() / samples.len() as u64,
+        p50_us: percentile(50),
+        p90_us: percentile(90),
+        p99_us: percentile(99),
+        max_us: samples[samples.len() - 1],
+    }
+}
+
+impl output::Document for schema::Benchmark {
+    fn table(&self) -> String {
+        let mut out = format!(
+            "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}\n",
+            "command (µs)", "min", "mean", "p50", "p90", "p99", "max"
+        );
+        for result in &self.commands {
+            match (&result.latency, &result.error) {
+                (Some(l), _) => {
+                    out += &format!(
+                        "{:<20} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}\n",
+                        result.command, l.min_us, l.mean_us, l.p50_us, l.p90_us, l.p99_us, l.max_us
+                    )
+                }
+                (None, error) => {
+                    out += &format!(
+                        "{:<20} {}\n",
+                        result.command,
+                        error.as_deref().unwrap_or("failed")
+                    )
+                }
+            }
+        }
+        out += &format!("{} iterations each\n", self.iterations);
+        out
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The support bundle attached to bug reports.
+//!
+//! Everything is gathered independently, so that a host that is far from ready
+//! (no SEV device, no kvm_amd) still yields a bundle saying so. Only public
+//! information is gathered: the certificate chain is the one `export` writes, and
+//! neither the chip ID, nor the guests, nor the owner's keys are included. Kernel
+//! messages are limited to the SEV-related ones, with the host name masked.
+
+use super::*;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory the files are in, in the tarball.
+const PREFIX: &str = "sevctl-bundle";
+
+/// The kernel modules whose parameters are gathered.
+const MODULES: &[&str] = &["kvm_amd", "ccp"];
+
+/// The components involved in SEV, which prefix their kernel messages with their
+/// name and a colon or a space. Other messages are kept if they mention SEV.
+const SUBSYSTEMS: &[&str] = &[
+    "ccp", "sev", "kvm", "kvm_amd", "SVM", "AMD-Vi", "iommu", "psp", "RMP",
+];
+
+/// A file of the bundle, and how to gather it.
+struct Item {
+    name: &'static str,
+    gather: fn() -> Result<Vec<u8>>,
+}
+
+const ITEMS: &[Item] = &[
+    Item {
+        name: "ok.json",
+        gather: || Ok(json(&ok::run(&ok::checks()))),
+    },
+    Item {
+        name: "snp-ok.json",
+        gather: || Ok(json(&ok::run(&ok::snp_checks()))),
+    },
+    Item {
+        name: "show-version.json",
+        gather: || {
+            let status = platform_status()?;
+            Ok(json(&schema::FirmwareVersion {
+                api_major: status.build.version.major,
+                api_minor: status.build.version.minor,
+                build_id: status.build.build,
+            }))
+        },
+    },
+    Item {
+        name: "show-flags.json",
+        gather: || {
+            let status = platform_status()?;
+            Ok(json(&schema::PlatformFlags {
+                owned: status.flags.contains(::sev::firmware::Flags::OWNED),
+                es: status
+                    .flags
+                    .contains(::sev::firmware::Flags::ENCRYPTED_STATE),
+            }))
+        },
+    },
+    Item {
+        name: "show-guests.json",
+        gather: || {
+            Ok(json(&schema::Guests {
+                guests: platform_status()?.guests,
+            }))
+        },
+    },
+    Item {
+        name: "kernel.txt",
+        gather: || read("/proc/version"),
+    },
+    Item {
+        name: "module-params.txt",
+        gather: module_params,
+    },
+    Item {
+        name: "dmesg.txt",
+        gather: dmesg,
+    },
+    Item {
+        name: "chain.cert",
+        gather: || export::encode(false),
+    },
+];
+
+pub fn cmd(destination: PathBuf) -> Result<()> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut tar = Vec::new();
+    let mut files = Vec::new();
+    for item in ITEMS {
+        let _span = tracing::debug_span!("gather", file = item.name).entered();
+        match (item.gather)() {
+            Ok(data) => {
+                append(&mut tar, item.name, &data, mtime);
+                files.push(schema::BundleFile {
+                    name: item.name.to_string(),
+                    error: None,
+                });
+            }
+            Err(err) => {
+                let report = err.report();
+                let error = std::iter::once(report.message)
+                    .chain(report.causes)
+                    .collect::<Vec<_>>()
+                    .join(": ");
+                tracing::debug!(%error, "unable to gather");
+                files.push(schema::BundleFile {
+                    name: item.name.to_string(),
+                    error: Some(error),
+                });
+            }
+        }
+    }
+
+    let bundle = schema::ReportBundle {
+        sevctl_version: VERSION.to_string(),
+        created: mtime,
+        files,
+    };
+    append(&mut tar, "manifest.json", &json(&bundle), mtime);
+
+    // The end of the archive: two empty blocks.
+    tar.extend_from_slice(&[0u8; 1024]);
+
+    tracing::debug!(path = %destination.display(), "writing file");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(&destination)
+        .and_then(|mut file| file.write_all(&tar))
+        .context(format!("unable to write {}", destination.display()))?;
+
+    output::print(&bundle);
+    Ok(())
+}
+
+/// A document as it would be printed with `--output json`.
+fn json<T: serde::Serialize>(document: &T) -> Vec<u8> {
+    let mut json = schema::Versioned::new(document).to_string_pretty();
+    json.push('\n');
+    json.into_bytes()
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    tracing::debug!(path, "reading file");
+    std::fs::read(path).context(format!("unable to read {}", path))
+}
+
+/// The parameters of the modules in `MODULES`, one `module.name=value` per line.
+fn module_params() -> Result<Vec<u8>> {
+    let mut out = String::new();
+    for module in MODULES {
+        let dir = Path::new("/sys/module").join(module).join("parameters");
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                out += &format!("# {} is not loaded\n", module);
+                continue;
+            }
+        };
+
+        let mut params: Vec<(String, String)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let value = std::fs::read_to_string(entry.path())
+                    .map(|v| v.trim().to_string())
+                    .unwrap_or_else(|e| format!("<{}>", e));
+                (entry.file_name().to_string_lossy().into_owned(), value)
+            })
+            .collect();
+        params.sort();
+
+        for (name, value) in params {
+            out += &format!("{}.{}={}\n", module, name, value);
+        }
+    }
+    Ok(out.into_bytes())
+}
+
+/// The SEV-related kernel messages, with the host name masked.
+fn dmesg() -> Result<Vec<u8>> {
+    tracing::debug!(path = "/dev/kmsg", "reading file");
+    let kmsg = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/kmsg")
+        .context("unable to read the kernel messages (are you root?)")?;
+
+    let hostname = platform::hostname();
+    let mut out = String::new();
+    let mut record = vec![0u8; 8192];
+    loop {
+        // Each read returns one record: "priority,sequence,microseconds,flags;message".
+        let n = match std::io::Read::read(&mut &kmsg, &mut record) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            // The record was overwritten while reading; the next one follows.
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(e) => return Err(e).context("unable to read the kernel messages"),
+        };
+
+        let text = String::from_utf8_lossy(&record[..n]);
+        let (header, message) = match text.split_once(';') {
+            Some(split) => split,
+            None => continue,
+        };
+        let message = message.lines().next().unwrap_or_default();
+        let relevant = message.contains("SEV")
+            || SUBSYSTEMS.iter().any(|s| {
+                message.starts_with(s)
+                    && matches!(message[s.len()..].chars().next(), Some(':' | ' '))
+            });
+        if !relevant {
+            continue;
+        }
+
+        let micros: u64 = header
+            .split(',')
+            .nth(2)
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0);
+        let message = match &hostname {
+            Some(hostname) => mask(message, hostname),
+            None => message.to_string(),
+        };
+        out += &format!(
+            "[{:>5}.{:06}] {}\n",
+            micros / 1_000_000,
+            micros % 1_000_000,
+            message
+        );
+    }
+    Ok(out.into_bytes())
+}
+
+/// `text` with every occurrence of `word` that is not part of a longer name
+/// replaced with `<hostname>`.
+fn mask(text: &str, word: &str) -> String {
+    let name = |c: Option<char>| c.map_or(false, |c| c.is_ascii_alphanumeric() || c == '-');
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find(word) {
+        let before = rest[..i].chars().last().or_else(|| out.chars().last());
+        let after = rest[i + word.len()..].chars().next();
+        out += &rest[..i];
+        match name(before) || name(after) {
+            true => out += word,
+            false => out += "<hostname>",
+        }
+        rest = &rest[i + word.len()..];
+    }
+    out + rest
+}
+
+/// Appends a regular file to a ustar archive.
+fn append(tar: &mut Vec<u8>, name: &str, data: &[u8], mtime: u64) {
+    let mut header = [0u8; 512];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+
+    field(0, format!("{}/{}", PREFIX, name).as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", data.len()).as_bytes());
+    field(136, format!("{:011o}\0", mtime).as_bytes());
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    tar.resize((tar.len() + 511) / 512 * 512, 0);
+}
+
+impl output::Document for schema::ReportBundle {
+    fn table(&self) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            match &file.error {
+                None => out += &format!("{}\n", file.name),
+                Some(error) => out += &format!("{} (missing: {})\n", file.name, error),
+            }
+        }
+        out
+    }
+}
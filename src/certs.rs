@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeping a directory of the platform's certificates, laid out as `cache-dir`.
+//!
+//! `certs sync` is meant to run periodically, e.g. from cron: what is present and
+//! still chains to AMD's keys is left untouched, so that the files only change
+//! when the platform's certificates do, as after a TCB update.
+
+use super::*;
+use openssl::x509::X509;
+use schema::SyncState;
+use std::os::unix::fs::OpenOptionsExt;
+
+#[derive(StructOpt)]
+pub enum Certs {
+    #[structopt(about = "Fetch the platform's missing or stale certificates into a directory")]
+    Sync {
+        #[structopt(
+            long,
+            possible_values = kds::PRODUCTS,
+            case_insensitive = true,
+            help = "Processor generation, defaults to the local processor's"
+        )]
+        product: Option<kds::Product>,
+
+        #[structopt(long, help = "Remove the chip's VCEKs at other TCB versions")]
+        prune: bool,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "Directory, defaults to the configured cache-dir"
+        )]
+        dir: Option<PathBuf>,
+    },
+}
+
+pub fn cmd(cmd: Certs) -> Result<()> {
+    match cmd {
+        Certs::Sync {
+            product,
+            prune,
+            dir,
+        } => {
+            let dir = dir.or(config::get().cache_dir).ok_or_else(|| {
+                error::Context::new(
+                    "no directory to synchronize",
+                    Box::new(Error::new(
+                        ErrorKind::InvalidInput,
+                        "give one or configure cache-dir",
+                    )),
+                )
+            })?;
+            sync(dir, product, prune)
+        }
+    }
+}
+
+fn sync(dir: PathBuf, product: Option<kds::Product>, prune: bool) -> Result<()> {
+    let mut fw = firmware()?;
+    let id = issue("GET_ID", || fw.get_identifier()).context("error fetching identifier")?;
+    let mut chain = issue("PDH_CERT_EXPORT", || fw.pdh_cert_export())
+        .context("unable to export SEV certificates")?;
+    drop(fw);
+
+    // The product is told before writing anything, so that failing to leaves no partial sync.
+    let snp = match snp_status() {
+        Ok(status) if status.initialized => {
+            Some((kds::Product::or_local(product)?, status.reported_tcb))
+        }
+        _ => {
+            tracing::debug!("SEV-SNP is not initialized, skipping the VCEK");
+            None
+        }
+    };
+
+    let mut files = vec![sync_cek(&dir, &id, &mut chain)?];
+    if let Some((product, tcb)) = snp {
+        files.extend(sync_vcek(&dir, product, &id, tcb, prune)?);
+    }
+
+    output::print(&schema::CertsSync { dir, files });
+    Ok(())
+}
+
+/// Keeps the CEK signing the platform's PEK, signed by a built-in ASK.
+fn sync_cek(dir: &Path, id: &[u8], chain: &mut sev::Chain) -> Result<schema::SyncedFile> {
+    let path = cek_path(dir, id);
+    let old = std::fs::read(&path).ok();
+
+    let kept = old
+        .as_deref()
+        .and_then(|mut old| sev::Certificate::decode(&mut old, ()).ok());
+    if kept.map_or(false, |cek| chains(chain, cek)) {
+        return install(dir, &path, old.as_deref().unwrap(), old.as_deref());
+    }
+
+    // The mock's CEK is signed by the ASK already, and AMD does not know its identifier.
+    let cek = match device::backend() {
+        device::Backend::Mock => chain.cek,
+        device::Backend::Sev => block_on(download(&cek_url(id), Usage::CEK))?,
+    };
+    if !chains(chain, cek) {
+        return Err(error::Context::new(
+            "the CEK downloaded does not chain the PEK to a built-in ASK",
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        ));
+    }
+
+    let mut data = Vec::new();
+    cek.encode(&mut data, ())
+        .context("unable to encode the CEK")?;
+    install(dir, &path, &data, old.as_deref())
+}
+
+/// Whether `cek` signs the PEK of `chain` and a built-in ASK signs `cek`, which
+/// replaces the CEK of `chain`.
+fn chains(chain: &mut sev::Chain, cek: sev::Certificate) -> bool {
+    chain.cek = cek;
+    (&chain.cek, &chain.pek).verify().is_ok() && ca_chain_builtin(chain).is_ok()
+}
+
+/// Keeps the VCEK of the chip at the reported TCB version and the ASK and ARK
+/// signing it, and with `prune` removes the VCEKs of the chip at other versions.
+fn sync_vcek(
+    dir: &Path,
+    product: kds::Product,
+    id: &[u8],
+    tcb: platform::TcbVersion,
+    prune: bool,
+) -> Result<Vec<schema::SyncedFile>> {
+    // GET_ID reports both IDs of a two-socket platform; the VCEK is the first socket's.
+    let chip = id.get(..kds::CHIP_ID_SIZE).ok_or_else(|| {
+        error::Context::new(
+            &format!(
+                "chip ID is {} bytes, expected {}",
+                id.len(),
+                kds::CHIP_ID_SIZE
+            ),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        )
+    })?;
+    let vcek_path = kds::vcek_path(dir, product, chip, tcb);
+    let chain_path = kds::cert_chain_path(dir, product);
+    let old_vcek = std::fs::read(&vcek_path).ok();
+    let old_chain = std::fs::read(&chain_path).ok();
+
+    let fetch_vcek = || fetch(&kds::vcek_url(product, chip, tcb), "VCEK");
+    let fetch_chain = || fetch(&kds::cert_chain_url(product), "certificate chain");
+    let kept = match (old_vcek.clone(), old_chain.clone()) {
+        (Some(vcek), Some(chain)) => Some((vcek, chain)),
+        (Some(vcek), None) => Some((vcek, fetch_chain()?)),
+        (None, Some(chain)) => Some((fetch_vcek()?, chain)),
+        (None, None) => None,
+    };
+    let (vcek, chain) = match kept {
+        Some((vcek, chain)) if chained(&vcek, &chain)? => (vcek, chain),
+        // What was kept is stale or damaged, so both are downloaded anew.
+        _ => {
+            let mut bodies = fetch_all(vec![
+                (kds::vcek_url(product, chip, tcb), "VCEK".into()),
+                (kds::cert_chain_url(product), "certificate chain".into()),
+            ])?;
+            let chain = bodies.pop().unwrap();
+            let vcek = bodies.pop().unwrap();
+            if !chained(&vcek, &chain)? {
+                return Err(error::Context::new(
+                    "the VCEK downloaded is not signed by the ASK and ARK downloaded",
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                ));
+            }
+            (vcek, chain)
+        }
+    };
+
+    let mut files = vec![
+        install(dir, &vcek_path, &vcek, old_vcek.as_deref())?,
+        install(dir, &chain_path, &chain, old_chain.as_deref())?,
+    ];
+    if prune {
+        files.extend(prune_vceks(dir, &vcek_path, chip)?);
+    }
+    Ok(files)
+}
+
+/// Whether `vcek`, in DER format, is signed by the ASK and ARK of `chain`, in PEM
+/// format.
+fn chained(vcek: &[u8], chain: &[u8]) -> Result<bool> {
+    sandbox::run("certificates", || {
+        let vcek = match X509::from_der(vcek) {
+            Ok(vcek) => vcek,
+            Err(_) => return Ok(false),
+        };
+        let chain = X509::stack_from_pem(chain).unwrap_or_default();
+        Ok(appraisal::vcek_chain(&vcek, &chain).is_ok())
+    })
+}
+
+/// Removes the VCEKs of `chip` kept next to `current`, other than it.
+fn prune_vceks(dir: &Path, current: &Path, chip: &[u8]) -> Result<Vec<schema::SyncedFile>> {
+    let parent = current.parent().unwrap();
+    let prefix = format!("{}-", hex(chip));
+    let entries =
+        std::fs::read_dir(parent).context(format!("unable to read {}", parent.display()))?;
+
+    let mut removed = Vec::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if path == current || !name.starts_with(&prefix) || !name.ends_with(".der") {
+            continue;
+        }
+
+        tracing::debug!(path = %path.display(), "removing file");
+        std::fs::remove_file(&path).context(format!("unable to remove {}", path.display()))?;
+        removed.push(schema::SyncedFile {
+            name: relative(dir, &path),
+            state: SyncState::Removed,
+        });
+    }
+    Ok(removed)
+}
+
+/// Writes `data` to `path` unless it holds it already (`old`). The file is
+/// replaced by renaming, so that readers never see it partly written.
+fn install(dir: &Path, path: &Path, data: &[u8], old: Option<&[u8]>) -> Result<schema::SyncedFile> {
+    let state = match old {
+        Some(old) if old == data => SyncState::Current,
+        Some(_) => SyncState::Replaced,
+        None => SyncState::Fetched,
+    };
+
+    if state != SyncState::Current {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context(format!("unable to create {}", parent.display()))?;
+        }
+
+        let tmp = path.with_extension("tmp");
+        tracing::debug!(path = %path.display(), "writing file");
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o644)
+            .open(&tmp)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, data))
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .context(format!("unable to write {}", path.display()))?;
+    }
+
+    Ok(schema::SyncedFile {
+        name: relative(dir, path),
+        state,
+    })
+}
+
+fn relative(dir: &Path, path: &Path) -> String {
+    path.strip_prefix(dir).unwrap_or(path).display().to_string()
+}
+
+impl output::Document for schema::CertsSync {
+    fn table(&self) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            let state = match file.state {
+                SyncState::Current => "current",
+                SyncState::Fetched => "fetched",
+                SyncState::Replaced => "replaced",
+                SyncState::Removed => "removed",
+            };
+            out += &format!("{:<8}  {}\n", state, file.name);
+        }
+        out
+    }
+}
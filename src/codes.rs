@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The status codes of the SEV and SEV-SNP firmware, as defined by the SEV API
+//! and SEV-SNP firmware ABI specifications.
+//!
+//! The kernel's `fw_error` and QEMU's error messages only carry the number, which
+//! `sevctl error` turns into a name, a description and the usual causes.
+
+use super::*;
+use std::convert::TryFrom;
+
+/// A firmware status code.
+pub struct Code {
+    pub value: u32,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub causes: &'static [&'static str],
+}
+
+/// Every status code defined so far.
+pub const CODES: &[Code] = &[
+    Code {
+        value: 0x00,
+        name: "SUCCESS",
+        description: "The command completed successfully",
+        causes: &[],
+    },
+    Code {
+        value: 0x01,
+        name: "INVALID_PLATFORM_STATE",
+        description: "The platform state is invalid for this command",
+        causes: &[
+            "The platform is not initialized (the kernel initializes it when the ccp module loads)",
+            "PLATFORM_RESET, PEK_GEN or PEK_CERT_IMPORT was issued while guests are running",
+        ],
+    },
+    Code {
+        value: 0x02,
+        name: "INVALID_GUEST_STATE",
+        description: "The guest state is invalid for this command",
+        causes: &[
+            "Launch commands were issued out of order, e.g. LAUNCH_SECRET before LAUNCH_MEASURE",
+            "ACTIVATE was issued for a guest that is already active",
+        ],
+    },
+    Code {
+        value: 0x03,
+        name: "INVALID_CONFIG",
+        description: "The platform configuration is invalid",
+        causes: &["SEV-ES or SEV-SNP is disabled in the BIOS while the command requires it"],
+    },
+    Code {
+        value: 0x04,
+        name: "INVALID_LENGTH",
+        description: "A memory buffer is too small",
+        causes: &["A certificate or blob passed to the command has the wrong size"],
+    },
+    Code {
+        value: 0x05,
+        name: "ALREADY_OWNED",
+        description: "The platform is already owned",
+        causes: &["PEK_CERT_IMPORT was issued while an OCA certificate is installed; reset first"],
+    },
+    Code {
+        value: 0x06,
+        name: "INVALID_CERTIFICATE",
+        description: "The certificate is invalid",
+        causes: &[
+            "The guest owner's certificate chain was not issued by this platform",
+            "The OCA certificate imported is not self-signed or uses an unsupported key",
+        ],
+    },
+    Code {
+        value: 0x07,
+        name: "POLICY_FAILURE",
+        description: "The request is not allowed by the guest policy",
+        causes: &[
+            "The policy requires a newer firmware (API version bits) than the platform's",
+            "The policy forbids sharing keys, debugging or migration as the command requires",
+            "The policy requires SEV-ES on a platform without it",
+        ],
+    },
+    Code {
+        value: 0x08,
+        name: "INACTIVE",
+        description: "The guest is inactive",
+        causes: &["The guest was deactivated or never activated"],
+    },
+    Code {
+        value: 0x09,
+        name: "INVALID_ADDRESS",
+        description: "The address provided is invalid",
+        causes: &["A guest or system physical address is misaligned or out of range"],
+    },
+    Code {
+        value: 0x0a,
+        name: "BAD_SIGNATURE",
+        description: "The provided signature is invalid",
+        causes: &["The ID block or ID authentication structure was signed with another key"],
+    },
+    Code {
+        value: 0x0b,
+        name: "BAD_MEASUREMENT",
+        description: "The provided measurement is invalid",
+        causes: &[
+            "LAUNCH_SECRET was packaged for another launch measurement",
+            "The ID block's launch digest does not match the launched image",
+        ],
+    },
+    Code {
+        value: 0x0c,
+        name: "ASID_OWNED",
+        description: "The ASID is already owned",
+        causes: &["ACTIVATE was given an ASID another guest uses"],
+    },
+    Code {
+        value: 0x0d,
+        name: "INVALID_ASID",
+        description: "The ASID is invalid",
+        causes: &[
+            "ACTIVATE was given an ASID outside the platform's range",
+            "An SEV guest was given an ASID reserved for SEV-ES or SEV-SNP guests",
+        ],
+    },
+    Code {
+        value: 0x0e,
+        name: "WBINVD_REQUIRED",
+        description: "WBINVD must be issued before the command",
+        causes: &["ACTIVATE reused an ASID without the caches being flushed"],
+    },
+    Code {
+        value: 0x0f,
+        name: "DFFLUSH_REQUIRED",
+        description: "DF_FLUSH must be issued before the command",
+        causes: &["ACTIVATE reused the ASID of a decommissioned guest before DF_FLUSH"],
+    },
+    Code {
+        value: 0x10,
+        name: "INVALID_GUEST",
+        description: "The guest handle is invalid",
+        causes: &["The guest was decommissioned or the handle belongs to no guest"],
+    },
+    Code {
+        value: 0x11,
+        name: "INVALID_COMMAND",
+        description: "The command issued is invalid",
+        causes: &["The firmware is too old to support the command"],
+    },
+    Code {
+        value: 0x12,
+        name: "ACTIVE",
+        description: "The guest is active",
+        causes: &["DECOMMISSION was issued before DEACTIVATE"],
+    },
+    Code {
+        value: 0x13,
+        name: "HWERROR_PLATFORM",
+        description: "A hardware error occurred; the platform state is preserved",
+        causes: &["A transient hardware fault; retry, then check the system event log"],
+    },
+    Code {
+        value: 0x14,
+        name: "HWERROR_UNSAFE",
+        description: "A hardware error occurred; the platform state is lost",
+        causes: &["A hardware fault; the platform must be reset and the guests relaunched"],
+    },
+    Code {
+        value: 0x15,
+        name: "UNSUPPORTED",
+        description: "The feature is not supported",
+        causes: &["The firmware or the BIOS configuration does not support the feature"],
+    },
+    Code {
+        value: 0x16,
+        name: "INVALID_PARAM",
+        description: "A parameter is invalid",
+        causes: &[
+            "A reserved field or policy bit is set",
+            "A page was launched with a type or size the command does not accept",
+        ],
+    },
+    Code {
+        value: 0x17,
+        name: "RESOURCE_LIMIT",
+        description: "A resource limit was reached",
+        causes: &["Too many guests are running, or the firmware ran out of memory"],
+    },
+    Code {
+        value: 0x18,
+        name: "SECURE_DATA_INVALID",
+        description: "The integrity check of the secure data failed",
+        causes: &["The persistent state of the platform is corrupted; reset it"],
+    },
+    Code {
+        value: 0x19,
+        name: "INVALID_PAGE_SIZE",
+        description: "The RMP page size is incorrect",
+        causes: &["A 2MB page was given where the command expects a 4KB page, or the reverse"],
+    },
+    Code {
+        value: 0x1a,
+        name: "INVALID_PAGE_STATE",
+        description: "The RMP page state is incorrect",
+        causes: &[
+            "A page was added to a guest twice or was not assigned to the firmware",
+            "The hypervisor did not transition the page to the firmware state first",
+        ],
+    },
+    Code {
+        value: 0x1b,
+        name: "INVALID_MDATA_ENTRY",
+        description: "The metadata entry is invalid",
+        causes: &["The page's metadata was tampered with or belongs to another page"],
+    },
+    Code {
+        value: 0x1c,
+        name: "INVALID_PAGE_OWNER",
+        description: "The page ownership is incorrect",
+        causes: &["The page is assigned to another guest"],
+    },
+    Code {
+        value: 0x1d,
+        name: "AEAD_OFLOW",
+        description: "The AEAD algorithm would overflow",
+        causes: &["Too many pages were exported under one key; restart the migration"],
+    },
+    Code {
+        value: 0x1f,
+        name: "EXIT_RING_BUFFER",
+        description: "The command buffer ring processing was exited",
+        causes: &["The ring buffer mode was stopped, e.g. by a higher priority command"],
+    },
+    Code {
+        value: 0x20,
+        name: "RMP_INIT_REQUIRED",
+        description: "The RMP must be reinitialized",
+        causes: &["SNP_INIT was not issued, e.g. because the kernel lacks SEV-SNP host support"],
+    },
+    Code {
+        value: 0x21,
+        name: "BAD_SVN",
+        description: "The SVN of the installed firmware is not compatible",
+        causes: &["The committed TCB is newer than the firmware being installed"],
+    },
+    Code {
+        value: 0x22,
+        name: "BAD_VERSION",
+        description: "The firmware version is not compatible",
+        causes: &["The guest policy requires a newer firmware than the platform's"],
+    },
+    Code {
+        value: 0x23,
+        name: "SHUTDOWN_REQUIRED",
+        description: "SHUTDOWN must be issued before the command",
+        causes: &["A live firmware update or re-initialization was attempted while initialized"],
+    },
+    Code {
+        value: 0x24,
+        name: "UPDATE_FAILED",
+        description: "The firmware update failed",
+        causes: &["The image is corrupt or not signed for this processor"],
+    },
+    Code {
+        value: 0x25,
+        name: "RESTORE_REQUIRED",
+        description: "The firmware state must be restored",
+        causes: &["A previous firmware update was interrupted"],
+    },
+    Code {
+        value: 0x26,
+        name: "RMP_INITIALIZATION_FAILED",
+        description: "The RMP initialization failed",
+        causes: &["The RMP table reserved by the BIOS is misconfigured or too small"],
+    },
+    Code {
+        value: 0x27,
+        name: "INVALID_KEY",
+        description: "The key requested is invalid",
+        causes: &["The key selected in a derive-key or export request does not exist"],
+    },
+];
+
+/// The status code `value`, if it is defined.
+pub fn lookup(value: u32) -> Option<&'static Code> {
+    CODES.iter().find(|code| code.value == value)
+}
+
+/// Parses a status code given by number (decimal, or hex with `0x`) or name.
+pub fn parse(code: &str) -> Result<&'static Code> {
+    let code = code.trim();
+    let found = match parse_u64(code) {
+        Ok(value) => u32::try_from(value).ok().and_then(lookup),
+        Err(_) => CODES.iter().find(|c| c.name.eq_ignore_ascii_case(code)),
+    };
+
+    found.ok_or_else(|| {
+        error::Context::new(
+            &format!("unknown firmware status code {}", code),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        )
+    })
+}
+
+impl From<&Code> for schema::FirmwareStatus {
+    fn from(code: &Code) -> Self {
+        Self {
+            code: code.value,
+            name: code.name.to_string(),
+            description: code.description.to_string(),
+            causes: code.causes.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+impl output::Document for schema::FirmwareStatus {
+    fn table(&self) -> String {
+        let mut out = format!("{:#04x} {}: {}\n", self.code, self.name, self.description);
+        for cause in &self.causes {
+            out += &format!("  - {}\n", cause);
+        }
+        out
+    }
+}
+
+pub fn cmd(code: String) -> Result<()> {
+    output::print(&schema::FirmwareStatus::from(parse(&code)?));
+    Ok(())
+}
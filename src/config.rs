@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Defaults read from configuration files, so that fleets need not repeat the
+//! same flags in every invocation.
+//!
+//! `/etc/sevctl/config.toml` is read first, then `$XDG_CONFIG_HOME/sevctl/config.toml`
+//! (`~/.config/sevctl/config.toml`), whose settings take precedence; command line
+//! flags override both. `SEVCTL_CONFIG` names a single file to read instead.
+//!
+//! ```toml
+//! output = "json"
+//! cache-dir = "/var/cache/sevctl"
+//! proxy = "http://proxy.example.com:3128"
+//! kds-url = "https://kdsintf.amd.com"
+//! firmware-retries = 5
+//! firmware-retry-ms = 100
+//! advisories = "/etc/sevctl/advisories.toml"
+//! audit-log = "/var/log/sevctl/audit.log"
+//! nonce-dir = "/var/lib/sevctl/nonces"
+//! tcb-minimums = "https://mirror.example.com/sevctl/tcb-minimums.toml"
+//! snp-arks = ["/etc/sevctl/ark-milan.pem", "/etc/sevctl/ark-genoa.pem"]
+//! ```
+
+use super::*;
+use serde::Deserialize;
+use std::sync::Mutex;
+
+/// Where AMD's Key Distribution Service is reached unless configured otherwise.
+pub const KDS_URL: &str = "https://kdsintf.amd.com";
+
+/// How often a firmware command is retried while the SEV device is busy.
+pub const FIRMWARE_RETRIES: u32 = 5;
+
+/// The delay before the first retry, which doubles with every retry.
+pub const FIRMWARE_RETRY_MS: u64 = 100;
+
+/// The settings of a configuration file, all optional.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// The default of `--output`.
+    pub output: Option<output::Format>,
+
+    /// Where certificates downloaded from the KDS are kept for reuse.
+    pub cache_dir: Option<PathBuf>,
+
+    /// The proxy for requests to the KDS and other HTTPS services.
+    pub proxy: Option<String>,
+
+    /// The base URL of the KDS, e.g. a mirror reachable from isolated hosts.
+    pub kds_url: Option<String>,
+
+    /// How often a firmware command is retried while the SEV device is busy.
+    pub firmware_retries: Option<u32>,
+
+    /// The delay before the first retry in milliseconds, doubled for each retry.
+    pub firmware_retry_ms: Option<u64>,
+
+    /// The advisory table of `firmware check`, instead of the bundled one.
+    pub advisories: Option<PathBuf>,
+
+    /// Where the audit log goes: a file, `syslog` (the default) or `off`.
+    pub audit_log: Option<String>,
+
+    /// Where `nonce new --store` keeps nonces, instead of the user's state directory.
+    pub nonce_dir: Option<PathBuf>,
+
+    /// The table of `snp tcb-check`, a file or URL, instead of the bundled one.
+    pub tcb_minimums: Option<String>,
+
+    /// The AMD ARKs SEV-SNP certificate chains are trusted up to.
+    pub snp_arks: Option<Vec<PathBuf>>,
+}
+
+impl Config {
+    /// Overrides the settings of `self` with those set in `other`.
+    fn merge(self, other: Config) -> Self {
+        Config {
+            output: other.output.or(self.output),
+            cache_dir: other.cache_dir.or(self.cache_dir),
+            proxy: other.proxy.or(self.proxy),
+            kds_url: other.kds_url.or(self.kds_url),
+            firmware_retries: other.firmware_retries.or(self.firmware_retries),
+            firmware_retry_ms: other.firmware_retry_ms.or(self.firmware_retry_ms),
+            advisories: other.advisories.or(self.advisories),
+            audit_log: other.audit_log.or(self.audit_log),
+            nonce_dir: other.nonce_dir.or(self.nonce_dir),
+            tcb_minimums: other.tcb_minimums.or(self.tcb_minimums),
+            snp_arks: other.snp_arks.or(self.snp_arks),
+        }
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).context(format!("unable to read {}", path.display())),
+        };
+
+        toml::from_str(&text).context(format!("invalid configuration {}", path.display()))
+    }
+
+    /// The base URL of the KDS.
+    pub fn kds_url(&self) -> &str {
+        self.kds_url
+            .as_deref()
+            .unwrap_or(KDS_URL)
+            .trim_end_matches('/')
+    }
+
+    /// How often a busy firmware command is retried, and the first delay.
+    pub fn firmware_retries(&self) -> (u32, Duration) {
+        (
+            self.firmware_retries.unwrap_or(FIRMWARE_RETRIES),
+            Duration::from_millis(self.firmware_retry_ms.unwrap_or(FIRMWARE_RETRY_MS)),
+        )
+    }
+
+    /// Where stored nonces are kept: `nonce-dir`, or else
+    /// `$XDG_STATE_HOME/sevctl/nonces` (`~/.local/state/sevctl/nonces`).
+    pub fn nonce_dir(&self) -> Option<PathBuf> {
+        self.nonce_dir.clone().or_else(|| {
+            let state = match std::env::var_os("XDG_STATE_HOME") {
+                Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+                _ => Path::new(&std::env::var_os("HOME")?).join(".local/state"),
+            };
+            Some(state.join("sevctl/nonces"))
+        })
+    }
+
+    /// The configured proxy, for reqwest clients.
+    #[cfg(feature = "network")]
+    pub fn proxy(&self) -> Result<Option<reqwest::Proxy>> {
+        match &self.proxy {
+            Some(url) => Ok(Some(
+                reqwest::Proxy::all(url).context(format!("invalid proxy {}", url))?,
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+/// Reads the configuration files, which later calls to [`get`] return.
+pub fn init() -> Result<Config> {
+    let config = match std::env::var_os("SEVCTL_CONFIG") {
+        Some(path) => Config::load(Path::new(&path))?,
+        None => {
+            let mut config = Config::load(Path::new("/etc/sevctl/config.toml"))?;
+            if let Some(dir) = user_dir() {
+                config = config.merge(Config::load(&dir.join("sevctl/config.toml"))?);
+            }
+            config
+        }
+    };
+
+    *CONFIG.lock().unwrap() = Some(config.clone());
+    Ok(config)
+}
+
+/// The configuration read by [`init`], or the defaults if it was not called.
+pub fn get() -> Config {
+    CONFIG.lock().unwrap().clone().unwrap_or_default()
+}
+
+fn user_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(dir.into()),
+        _ => std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")),
+    }
+}
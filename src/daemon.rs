@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A gRPC daemon exposing sevctl's main operations, as described by
+//! `proto/sevctl/v1/sevctl.proto`.
+//!
+//! The messages are written out by hand rather than generated, so that building
+//! sevctl needs no protobuf compiler; they must be kept in sync with the proto.
+
+use super::*;
+use std::convert::Infallible;
+use std::future::Future;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::Status;
+
+/// The messages of the sevctl.v1 API.
+pub mod v1 {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ShowRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ShowResponse {
+        #[prost(uint32, tag = "1")]
+        pub api_major: u32,
+        #[prost(uint32, tag = "2")]
+        pub api_minor: u32,
+        #[prost(uint32, tag = "3")]
+        pub build_id: u32,
+        #[prost(string, tag = "4")]
+        pub state: String,
+        #[prost(bool, tag = "5")]
+        pub owned: bool,
+        #[prost(bool, tag = "6")]
+        pub es: bool,
+        #[prost(uint32, tag = "7")]
+        pub guests: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportRequest {
+        #[prost(bool, tag = "1")]
+        pub full: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportResponse {
+        #[prost(bytes = "vec", tag = "1")]
+        pub chain: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct VerifyRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub sev: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub oca: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub ca: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct VerifyResponse {
+        #[prost(bool, tag = "1")]
+        pub valid: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateSessionRequest {
+        #[prost(bytes = "vec", tag = "1")]
+        pub chain: Vec<u8>,
+        #[prost(string, tag = "2")]
+        pub policy: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CreateSessionResponse {
+        #[prost(bytes = "vec", tag = "1")]
+        pub godh: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub session: Vec<u8>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub tek: Vec<u8>,
+        #[prost(bytes = "vec", tag = "4")]
+        pub tik: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BuildMeasurementRequest {
+        #[prost(string, repeated, tag = "1")]
+        pub args: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BuildMeasurementResponse {
+        #[prost(bytes = "vec", tag = "1")]
+        pub digest: Vec<u8>,
+        #[prost(bytes = "vec", tag = "2")]
+        pub measurement: Vec<u8>,
+    }
+}
+
+/// The fully qualified name of the service.
+const SERVICE: &str = "sevctl.v1.Sevctl";
+
+pub fn cmd(listen: String) -> Result<()> {
+    let addr = listen
+        .parse()
+        .context(format!("invalid listen address {}", listen))?;
+
+    let runtime = tokio::runtime::Runtime::new().context("unable to start async runtime")?;
+
+    println!("listening on {}", addr);
+    runtime
+        .block_on(
+            tonic::transport::Server::builder()
+                .add_service(Sevctl)
+                .serve(addr),
+        )
+        .context("gRPC server failed")
+}
+
+/// Runs the part of a handler that blocks on the firmware or the filesystem on the
+/// runtime's blocking threads.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .context("request handler panicked")?
+}
+
+async fn show(_: v1::ShowRequest) -> Result<v1::ShowResponse> {
+    let status = blocking(platform_status).await?;
+
+    Ok(v1::ShowResponse {
+        api_major: status.build.version.major.into(),
+        api_minor: status.build.version.minor.into(),
+        build_id: status.build.build.into(),
+        state: format!("{:?}", status.state).to_lowercase(),
+        owned: status.flags.contains(::sev::firmware::Flags::OWNED),
+        es: status
+            .flags
+            .contains(::sev::firmware::Flags::ENCRYPTED_STATE),
+        guests: status.guests,
+    })
+}
+
+async fn export(request: v1::ExportRequest) -> Result<v1::ExportResponse> {
+    Ok(v1::ExportResponse {
+        chain: export::encode_async(request.full).await?,
+    })
+}
+
+async fn verify(request: v1::VerifyRequest) -> Result<v1::VerifyResponse> {
+    let mut schain = match &request.sev[..] {
+        [] => chain_async().await?,
+        mut buf => sev::Chain::decode(&mut buf, ()).context("unable to decode SEV chain")?,
+    };
+    let cchain = match &request.ca[..] {
+        [] => ca_chain_builtin(&schain)?,
+        mut buf => ca::Chain::decode(&mut buf, ()).context("unable to decode CA chain")?,
+    };
+    if !request.oca.is_empty() {
+        schain.oca =
+            sev::Certificate::decode(&mut &request.oca[..], ()).context("unable to decode OCA")?;
+    }
+
+    Ok(v1::VerifyResponse {
+        valid: verify::chains(&schain, &cchain, true),
+    })
+}
+
+async fn create_session(request: v1::CreateSessionRequest) -> Result<v1::CreateSessionResponse> {
+    let artifacts = blocking(move || {
+        let pdh = session::target_pdh(&request.chain, roots_builtin())?;
+        session::Artifacts::generate(&pdh, session::sev_policy(&request.policy)?)
+    })
+    .await?;
+
+    Ok(v1::CreateSessionResponse {
+        godh: artifacts.godh,
+        session: artifacts.session,
+        tek: artifacts.tek.to_vec(),
+        tik: artifacts.tik.to_vec(),
+    })
+}
+
+async fn build_measurement(
+    request: v1::BuildMeasurementRequest,
+) -> Result<v1::BuildMeasurementResponse> {
+    let (digest, measurement) =
+        blocking(move || measurement::build_from_args(request.args)).await?;
+
+    Ok(v1::BuildMeasurementResponse {
+        digest: digest.to_vec(),
+        measurement: measurement.map(|m| m.to_vec()).unwrap_or_default(),
+    })
+}
+
+/// The sevctl.v1.Sevctl service, routing each call to its handler.
+#[derive(Clone)]
+struct Sevctl;
+
+impl tonic::transport::NamedService for Sevctl {
+    const NAME: &'static str = SERVICE;
+}
+
+impl<B> Service<http::Request<B>> for Sevctl
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix('/')
+            .and_then(|p| p.strip_prefix(SERVICE))
+            .and_then(|p| p.strip_prefix('/'))
+            .unwrap_or("")
+            .to_string();
+
+        match method.as_str() {
+            "Show" => unary(Rpc(show), request),
+            "Export" => unary(Rpc(export), request),
+            "Verify" => unary(Rpc(verify), request),
+            "CreateSession" => unary(Rpc(create_session), request),
+            "BuildMeasurement" => unary(Rpc(build_measurement), request),
+            _ => Box::pin(async move {
+                Ok(Status::unimplemented(format!("no such method {}", method)).to_http())
+            }),
+        }
+    }
+}
+
+/// Decodes a request, runs its handler and encodes the response.
+fn unary<H, Q, A, R, B>(
+    rpc: Rpc<H>,
+    request: http::Request<B>,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    H: Fn(Q) -> R + Send + 'static,
+    Q: prost::Message + Default + Send + 'static,
+    A: prost::Message + Send + 'static,
+    R: Future<Output = Result<A>> + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+        Ok(grpc.unary(rpc, request).await)
+    })
+}
+
+/// A handler for one method.
+struct Rpc<H>(H);
+
+impl<H, Q, A, R> Service<tonic::Request<Q>> for Rpc<H>
+where
+    H: Fn(Q) -> R,
+    R: Future<Output = Result<A>> + Send + 'static,
+{
+    type Response = tonic::Response<A>;
+    type Error = Status;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: tonic::Request<Q>) -> Self::Future {
+        let response = (self.0)(request.into_inner());
+
+        Box::pin(async move { response.await.map(tonic::Response::new).map_err(status) })
+    }
+}
+
+/// Reports an error with its causes to the client.
+fn status(e: error::Context) -> Status {
+    let mut message = e.to_string();
+    let mut err: &(dyn std::error::Error + 'static) = &e;
+    while let Some(cause) = err.source() {
+        message += &format!(": {}", cause);
+        err = cause;
+    }
+
+    eprintln!("error: {}", message);
+    Status::internal(message)
+}
@@ -0,0 +1,362 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access to the SEV firmware, either directly through the SEV device (/dev/sev
+//! unless told otherwise) or through a privileged `sevctl helper` listening on a
+//! unix socket. Builds with the `mock` feature can use a fake firmware instead.
+
+use super::*;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Where `sevctl helper` listens unless told otherwise.
+pub const HELPER_SOCKET: &str = "/run/sevctl/helper.sock";
+
+/// The SEV device opened unless `SEVCTL_SEV_DEVICE` or [`init`] names another.
+pub const SEV_DEVICE: &str = "/dev/sev";
+
+/// The backends `--backend` accepts.
+pub const BACKENDS: &[&str] = &["sev", "mock"];
+
+/// What firmware commands are issued to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// The SEV device, or the helper.
+    Sev,
+
+    /// The fake firmware of [`mock`], if built with the `mock` feature.
+    Mock,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sev" => Ok(Backend::Sev),
+            "mock" => Ok(Backend::Mock),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown backend {} (expected {})", s, BACKENDS.join(", ")),
+            )),
+        }
+    }
+}
+
+static BACKEND: Mutex<Backend> = Mutex::new(Backend::Sev);
+
+/// Sets the backend firmware commands are issued to afterwards.
+pub fn select(backend: Backend) {
+    *BACKEND.lock().unwrap() = backend;
+}
+
+/// The backend firmware commands are issued to.
+pub fn backend() -> Backend {
+    *BACKEND.lock().unwrap()
+}
+
+static PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Sets the SEV device opened afterwards, e.g. a device node of a test
+/// environment or one bind-mounted into a container.
+pub fn init(path: PathBuf) {
+    *PATH.lock().unwrap() = Some(path);
+}
+
+/// The SEV device to open.
+pub fn path() -> PathBuf {
+    if let Some(path) = PATH.lock().unwrap().as_ref() {
+        return path.clone();
+    }
+
+    match std::env::var_os("SEVCTL_SEV_DEVICE") {
+        Some(path) if !path.is_empty() => path.into(),
+        _ => SEV_DEVICE.into(),
+    }
+}
+
+/// The firmware commands sevctl issues.
+pub trait Device: Send {
+    fn platform_status(&mut self) -> std::io::Result<Status>;
+    fn pdh_cert_export(&mut self) -> std::io::Result<sev::Chain>;
+    fn get_identifier(&mut self) -> std::io::Result<Vec<u8>>;
+    fn platform_reset(&mut self) -> std::io::Result<()>;
+    fn pdh_generate(&mut self) -> std::io::Result<()>;
+    fn pek_csr(&mut self) -> std::io::Result<sev::Certificate>;
+    fn pek_cert_import(
+        &mut self,
+        pek: &sev::Certificate,
+        oca: &sev::Certificate,
+    ) -> std::io::Result<()>;
+    fn snp_platform_status(&mut self) -> std::io::Result<platform::SnpStatus>;
+    fn snp_commit(&mut self) -> std::io::Result<()>;
+    fn snp_set_config(
+        &mut self,
+        reported_tcb: platform::TcbVersion,
+        mask_chip_id: bool,
+    ) -> std::io::Result<()>;
+}
+
+/// What the firmware opened afterwards shares, once [`share`] is called.
+#[derive(Default)]
+struct Cache {
+    /// The handle, while no [`Shared`] holds it.
+    fw: Option<Box<dyn Device>>,
+
+    status: Option<Status>,
+    snp_status: Option<platform::SnpStatus>,
+}
+
+static SHARED: Mutex<Option<Cache>> = Mutex::new(None);
+
+/// Makes the firmware opened afterwards share one handle, and answer the status
+/// commands from the first reads until a command changes the platform.
+///
+/// A command opens the firmware in each of its steps; this is for commands that
+/// finish, since a service would keep reporting the status read at its start.
+pub fn share() {
+    *SHARED.lock().unwrap() = Some(Cache::default());
+}
+
+/// Opens the firmware, through the helper named by `SEVCTL_HELPER` if set.
+///
+/// Without permission to open the SEV device, the helper at its default socket is
+/// used if it is running. When replaying a recording, nothing is opened. Once
+/// [`share`] is called, the handle opened first is reused while no other step
+/// holds it.
+pub fn open() -> Result<Box<dyn Device>> {
+    let cached = match SHARED.lock().unwrap().as_mut() {
+        Some(cache) => Some(cache.fw.take()),
+        None => None,
+    };
+
+    match cached {
+        None => open_firmware(),
+        Some(Some(fw)) => {
+            tracing::debug!("reusing the firmware handle");
+            Ok(Box::new(Shared(Some(fw))))
+        }
+        Some(None) => Ok(Box::new(Shared(Some(open_firmware()?)))),
+    }
+}
+
+fn open_firmware() -> Result<Box<dyn Device>> {
+    if let Some(player) = replay::player() {
+        return Ok(player);
+    }
+
+    open_backend().map(replay::wrap)
+}
+
+fn open_backend() -> Result<Box<dyn Device>> {
+    if backend() == Backend::Mock {
+        return mock();
+    }
+
+    if let Some(path) = std::env::var_os("SEVCTL_HELPER") {
+        tracing::debug!(path = ?path, "using the helper");
+        return Ok(Box::new(helper::Client::new(path.into())));
+    }
+
+    let path = path();
+    tracing::debug!(path = %path.display(), "opening the SEV device");
+    match Local::open() {
+        Ok(local) => Ok(Box::new(local)),
+        Err(e)
+            if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::NotFound)
+                && Path::new(HELPER_SOCKET).exists() =>
+        {
+            tracing::debug!(error = %e, path = HELPER_SOCKET, "using the helper");
+            Ok(Box::new(helper::Client::new(HELPER_SOCKET.into())))
+        }
+        Err(e) => Err(e).context(format!("unable to open {}", path.display())),
+    }
+}
+
+#[cfg(feature = "mock")]
+fn mock() -> Result<Box<dyn Device>> {
+    tracing::debug!("using the mock firmware");
+    Ok(Box::new(mock::Mock::new()?))
+}
+
+#[cfg(not(feature = "mock"))]
+fn mock() -> Result<Box<dyn Device>> {
+    Err(error::Context::new(
+        "the mock backend is not available",
+        Box::new(Error::new(
+            ErrorKind::Unsupported,
+            "sevctl was built without the mock feature",
+        )),
+    ))
+}
+
+/// A handle shared by the steps of a command, returned to the cache when dropped.
+struct Shared(Option<Box<dyn Device>>);
+
+impl Shared {
+    fn fw(&mut self) -> &mut dyn Device {
+        &mut **self.0.as_mut().unwrap()
+    }
+
+    /// Runs a command changing the platform, whose status is then read anew.
+    fn change<T>(
+        &mut self,
+        command: impl FnOnce(&mut dyn Device) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        if let Some(cache) = SHARED.lock().unwrap().as_mut() {
+            cache.status = None;
+            cache.snp_status = None;
+        }
+        command(self.fw())
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        if let Some(cache) = SHARED.lock().unwrap().as_mut() {
+            if cache.fw.is_none() {
+                cache.fw = self.0.take();
+            }
+        }
+    }
+}
+
+impl Device for Shared {
+    fn platform_status(&mut self) -> std::io::Result<Status> {
+        if let Some(status) = SHARED
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|c| c.status.clone())
+        {
+            tracing::debug!("using the status read earlier");
+            return Ok(status);
+        }
+
+        let status = self.fw().platform_status()?;
+        if let Some(cache) = SHARED.lock().unwrap().as_mut() {
+            cache.status = Some(status.clone());
+        }
+        Ok(status)
+    }
+
+    fn pdh_cert_export(&mut self) -> std::io::Result<sev::Chain> {
+        self.fw().pdh_cert_export()
+    }
+
+    fn get_identifier(&mut self) -> std::io::Result<Vec<u8>> {
+        self.fw().get_identifier()
+    }
+
+    fn platform_reset(&mut self) -> std::io::Result<()> {
+        self.change(|fw| fw.platform_reset())
+    }
+
+    fn pdh_generate(&mut self) -> std::io::Result<()> {
+        self.change(|fw| fw.pdh_generate())
+    }
+
+    fn pek_csr(&mut self) -> std::io::Result<sev::Certificate> {
+        self.fw().pek_csr()
+    }
+
+    fn pek_cert_import(
+        &mut self,
+        pek: &sev::Certificate,
+        oca: &sev::Certificate,
+    ) -> std::io::Result<()> {
+        self.change(|fw| fw.pek_cert_import(pek, oca))
+    }
+
+    fn snp_platform_status(&mut self) -> std::io::Result<platform::SnpStatus> {
+        if let Some(status) = SHARED
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|c| c.snp_status.clone())
+        {
+            tracing::debug!("using the status read earlier");
+            return Ok(status);
+        }
+
+        let status = self.fw().snp_platform_status()?;
+        if let Some(cache) = SHARED.lock().unwrap().as_mut() {
+            cache.snp_status = Some(status.clone());
+        }
+        Ok(status)
+    }
+
+    fn snp_commit(&mut self) -> std::io::Result<()> {
+        self.change(|fw| fw.snp_commit())
+    }
+
+    fn snp_set_config(
+        &mut self,
+        reported_tcb: platform::TcbVersion,
+        mask_chip_id: bool,
+    ) -> std::io::Result<()> {
+        self.change(|fw| fw.snp_set_config(reported_tcb, mask_chip_id))
+    }
+}
+
+/// The firmware behind the SEV device.
+pub struct Local(File);
+
+impl Local {
+    pub fn open() -> std::io::Result<Self> {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path())
+            .map(Local)
+    }
+}
+
+impl Device for Local {
+    fn platform_status(&mut self) -> std::io::Result<Status> {
+        platform::status(&self.0)
+    }
+
+    fn pdh_cert_export(&mut self) -> std::io::Result<sev::Chain> {
+        platform::pdh_cert_export(&self.0)
+    }
+
+    fn get_identifier(&mut self) -> std::io::Result<Vec<u8>> {
+        platform::get_id(&self.0)
+    }
+
+    fn platform_reset(&mut self) -> std::io::Result<()> {
+        platform::reset(&self.0)
+    }
+
+    fn pdh_generate(&mut self) -> std::io::Result<()> {
+        platform::pdh_generate(&self.0)
+    }
+
+    fn pek_csr(&mut self) -> std::io::Result<sev::Certificate> {
+        platform::pek_csr(&self.0)
+    }
+
+    fn pek_cert_import(
+        &mut self,
+        pek: &sev::Certificate,
+        oca: &sev::Certificate,
+    ) -> std::io::Result<()> {
+        platform::pek_cert_import(&self.0, pek, oca)
+    }
+
+    fn snp_platform_status(&mut self) -> std::io::Result<platform::SnpStatus> {
+        platform::snp_status(&self.0)
+    }
+
+    fn snp_commit(&mut self) -> std::io::Result<()> {
+        platform::snp_commit(&self.0)
+    }
+
+    fn snp_set_config(
+        &mut self,
+        reported_tcb: platform::TcbVersion,
+        mask_chip_id: bool,
+    ) -> std::io::Result<()> {
+        platform::snp_set_config(&self.0, reported_tcb, mask_chip_id)
+    }
+}
@@ -0,0 +1,483 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A diagnosis of the host, a layer above the checks of `sevctl ok`.
+//!
+//! One cause often fails several checks: without the ccp driver, there is no SEV
+//! device and kvm_amd disables SEV too, and an old firmware fails both SEV-ES and
+//! SEV-SNP. Each rule recognizes such a cause from the check results and the
+//! platform status, and names the failed checks it explains. The rules are in
+//! priority order, the root causes first, and a rule only yields a finding if it
+//! explains a failure no earlier one did. Failures no rule explains are reported
+//! as they are.
+
+use super::*;
+use schema::{CheckResult, CheckState, Finding};
+
+/// What the checks and the platform status queries found.
+struct Facts {
+    checks: Vec<CheckResult>,
+    cpuid: Option<platform::Cpuid>,
+
+    /// The firmware version from PLATFORM_STATUS.
+    firmware: Option<(u8, u8, u8)>,
+
+    /// The advisories that apply to the firmware.
+    advisories: Vec<CheckResult>,
+}
+
+impl Facts {
+    /// Those of `ids` whose checks failed.
+    fn failed(&self, ids: &[&str]) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter(|c| c.state == CheckState::Fail && ids.contains(&c.id.as_str()))
+            .map(|c| c.id.clone())
+            .collect()
+    }
+
+    fn passed(&self, id: &str) -> bool {
+        self.checks
+            .iter()
+            .any(|c| c.id == id && c.state == CheckState::Pass)
+    }
+
+    fn details(&self, id: &str) -> String {
+        self.checks
+            .iter()
+            .find(|c| c.id == id)
+            .and_then(|c| c.details.clone())
+            .unwrap_or_default()
+    }
+
+    fn firmware(&self) -> String {
+        match self.firmware {
+            Some((major, minor, build)) => format!("{}.{}.{}", major, minor, build),
+            None => "of unknown version".into(),
+        }
+    }
+}
+
+/// A cause, the failed checks it explains, and what to do about it.
+struct Diagnosis {
+    cause: String,
+    explains: Vec<String>,
+    steps: Vec<String>,
+}
+
+/// A rule recognizing a cause.
+struct Rule {
+    /// The stable ID of its findings.
+    id: &'static str,
+    diagnose: fn(&Facts) -> Option<Diagnosis>,
+}
+
+/// The checks explained by SEV being unavailable.
+const SEV: &[&str] = &[
+    "cpu.mem-encryption",
+    "cpu.sme",
+    "cpu.sev",
+    "cpu.sev-es",
+    "cpu.page-flush-msr",
+    "cpu.snp",
+    "kvm.sev-param",
+    "kvm.sev-es-param",
+    "kvm.sev-snp-param",
+    "iommu.snp",
+    "dev.sev",
+    "fw.platform-status",
+    "fw.sev-es",
+    "fw.snp-status",
+    "fw.snp-version",
+    "fw.snp-initialized",
+    "fw.rmp-initialized",
+];
+
+/// The rules, in priority order.
+const RULES: &[Rule] = &[
+    Rule {
+        id: "not-amd",
+        diagnose: |facts| {
+            facts.failed(&["cpu.amd"]).first()?;
+            Some(Diagnosis {
+                cause: "this is not an AMD processor, which SEV requires".into(),
+                explains: facts.failed(&[&["cpu.amd"], SEV].concat()),
+                steps: vec!["run SEV guests on a host with an AMD EPYC processor".into()],
+            })
+        },
+    },
+    Rule {
+        id: "sev-disabled",
+        diagnose: |facts| {
+            facts.failed(&["cpu.mem-encryption", "cpu.sev"]).first()?;
+            Some(Diagnosis {
+                cause: "the processor does not report SEV: it is disabled in the BIOS, or \
+                        not supported"
+                    .into(),
+                explains: facts.failed(SEV),
+                steps: vec![
+                    "enable SMEE (Secure Memory Encryption) and SEV in the BIOS setup".into(),
+                    "set the SEV-ES ASID space limit in the BIOS above 1, so that SEV-ES and \
+                     SEV-SNP guests have ASIDs"
+                        .into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "kvm-module",
+        diagnose: |facts| {
+            let explains = facts.failed(&["kvm.module"]);
+            explains.first()?;
+            Some(Diagnosis {
+                cause: "the kvm_amd module is not loaded".into(),
+                explains,
+                steps: vec![
+                    "load it: modprobe kvm_amd".into(),
+                    "if it does not load, enable SVM (AMD-V) in the BIOS and look for the \
+                     reason in dmesg | grep -i kvm"
+                        .into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "no-device",
+        diagnose: |facts| {
+            facts.failed(&["dev.sev"]).first()?;
+            if device::backend() != device::Backend::Sev || device::path().exists() {
+                return None;
+            }
+
+            // kvm_amd disables SEV when it finds no PSP to run guests with.
+            Some(Diagnosis {
+                cause: format!(
+                    "there is no SEV device ({}): the ccp driver is not loaded, or did not \
+                     find or initialize the PSP, and KVM disables SEV without it",
+                    device::path().display()
+                ),
+                explains: facts.failed(&[
+                    "dev.sev",
+                    "kvm.sev-param",
+                    "kvm.sev-es-param",
+                    "kvm.sev-snp-param",
+                ]),
+                steps: vec![
+                    "load the driver, then reload KVM: modprobe ccp && modprobe -r kvm_amd \
+                     && modprobe kvm_amd"
+                        .into(),
+                    "if the device does not appear, look for the reason in dmesg | grep -i \
+                     -e ccp -e sev"
+                        .into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "device-access",
+        diagnose: |facts| {
+            let explains = facts.failed(&["dev.sev"]);
+            explains.first()?;
+            Some(Diagnosis {
+                cause: format!(
+                    "sevctl cannot use the SEV device: {}",
+                    facts.details("dev.sev")
+                ),
+                explains,
+                steps: vec![
+                    format!(
+                        "run sevctl as root, or give a group access to {} with a udev rule",
+                        device::path().display()
+                    ),
+                    "or run sevctl helper as root, which sevctl uses when it cannot open \
+                     the device (see sevctl helper --help)"
+                        .into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "no-es-asids",
+        diagnose: |facts| {
+            let cpuid = facts.cpuid.as_ref()?;
+            if !facts.passed("cpu.sev-es") || cpuid.min_sev_asid > 1 {
+                return None;
+            }
+
+            // SEV-SNP guests use the SEV-ES ASIDs too.
+            let explains = facts.failed(&["kvm.sev-es-param", "fw.sev-es", "kvm.sev-snp-param"]);
+            explains.first()?;
+            Some(Diagnosis {
+                cause: "no ASIDs are reserved for SEV-ES and SEV-SNP guests".into(),
+                explains,
+                steps: vec![
+                    "set the SEV-ES ASID space limit in the BIOS above 1".into(),
+                    "then reload kvm_amd, or reboot".into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "firmware-old",
+        diagnose: |facts| {
+            let snp = facts.failed(&["fw.snp-version"]);
+            let es = facts.failed(&["fw.sev-es"]);
+            let cause = match (es.is_empty(), snp.is_empty()) {
+                (true, true) => return None,
+                (false, false) => format!(
+                    "the SEV firmware {} is too old, which explains both the SEV-ES and the \
+                     SEV-SNP failures",
+                    facts.firmware()
+                ),
+                (false, true) => format!(
+                    "the SEV firmware {} does not support SEV-ES",
+                    facts.firmware()
+                ),
+                (true, false) => format!("the SEV firmware {} predates SEV-SNP", facts.firmware()),
+            };
+
+            // KVM and the kernel disable what the firmware does not support.
+            let mut also = vec!["fw.sev-es", "fw.snp-version"];
+            if !es.is_empty() {
+                also.push("kvm.sev-es-param");
+            }
+            if !snp.is_empty() {
+                also.extend(&[
+                    "kvm.sev-snp-param",
+                    "fw.snp-initialized",
+                    "fw.rmp-initialized",
+                ]);
+            }
+
+            Some(Diagnosis {
+                cause,
+                explains: facts.failed(&also),
+                steps: firmware_update(),
+            })
+        },
+    },
+    Rule {
+        id: "kvm-params",
+        diagnose: |facts| {
+            let explains =
+                facts.failed(&["kvm.sev-param", "kvm.sev-es-param", "kvm.sev-snp-param"]);
+            explains.first()?;
+            let options: Vec<String> = explains
+                .iter()
+                .map(|id| match id.as_str() {
+                    "kvm.sev-param" => "sev=1",
+                    "kvm.sev-es-param" => "sev_es=1",
+                    _ => "sev_snp=1",
+                })
+                .map(String::from)
+                .collect();
+
+            Some(Diagnosis {
+                cause: "KVM has SEV guests disabled".into(),
+                explains,
+                steps: vec![
+                    format!(
+                        "add \"options kvm_amd {}\" to /etc/modprobe.d/kvm_amd.conf, then \
+                         reload kvm_amd: modprobe -r kvm_amd && modprobe kvm_amd",
+                        options.join(" ")
+                    ),
+                    "if they stay disabled, look for the reason in dmesg | grep -i sev".into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "snp-unsupported",
+        diagnose: |facts| {
+            facts.failed(&["cpu.snp"]).first()?;
+            Some(Diagnosis {
+                cause: "the processor does not report SEV-SNP: it is disabled in the BIOS, or \
+                        the processor predates it (EPYC 7003)"
+                    .into(),
+                explains: facts.failed(&[
+                    "cpu.snp",
+                    "iommu.snp",
+                    "kvm.sev-snp-param",
+                    "fw.snp-initialized",
+                    "fw.rmp-initialized",
+                ]),
+                steps: vec!["enable SEV-SNP and SNP memory coverage in the BIOS setup".into()],
+            })
+        },
+    },
+    Rule {
+        id: "snp-iommu",
+        diagnose: |facts| {
+            facts.failed(&["iommu.snp"]).first()?;
+            Some(Diagnosis {
+                cause: "the IOMMU does not support SEV-SNP, which needs it enabled".into(),
+                explains: facts.failed(&["iommu.snp", "fw.snp-initialized"]),
+                steps: vec![
+                    "enable the IOMMU in the BIOS setup".into(),
+                    "remove amd_iommu=off from the kernel command line".into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "snp-init",
+        diagnose: |facts| {
+            facts
+                .failed(&["fw.snp-initialized", "fw.rmp-initialized"])
+                .first()?;
+            Some(Diagnosis {
+                cause: "the kernel did not initialize SEV-SNP".into(),
+                explains: facts.failed(&[
+                    "fw.snp-initialized",
+                    "fw.rmp-initialized",
+                    "kvm.sev-snp-param",
+                ]),
+                steps: vec![
+                    "check that the BIOS reserves the RMP (SNP memory coverage): sevctl snp rmp"
+                        .into(),
+                    "use a kernel with SEV-SNP host support, and look for the reason in dmesg \
+                     | grep -i -e snp -e rmp"
+                        .into(),
+                ],
+            })
+        },
+    },
+    Rule {
+        id: "firmware-advisories",
+        diagnose: |facts| {
+            facts.advisories.first()?;
+            let ids: Vec<&str> = facts.advisories.iter().map(|a| a.id.as_str()).collect();
+            Some(Diagnosis {
+                cause: format!(
+                    "the SEV firmware {} is affected by known issues: {}",
+                    facts.firmware(),
+                    ids.join(", ")
+                ),
+                explains: vec![],
+                steps: [
+                    vec!["see sevctl firmware check for the fixed versions".to_string()],
+                    firmware_update(),
+                ]
+                .concat(),
+            })
+        },
+    },
+];
+
+fn firmware_update() -> Vec<String> {
+    vec![
+        "install a newer firmware image (from linux-firmware or AMD) with sevctl firmware \
+         update, then reboot"
+            .into(),
+        "or update the BIOS, which includes the firmware".into(),
+    ]
+}
+
+pub fn cmd(snp: bool) -> Result<()> {
+    let checks: Vec<&ok::Check> = match snp {
+        true => ok::CHECKS.iter().chain(ok::SNP_CHECKS).collect(),
+        false => ok::checks(),
+    };
+
+    let firmware = platform_status().ok().map(|status| {
+        (
+            status.build.version.major,
+            status.build.version.minor,
+            status.build.build,
+        )
+    });
+    let facts = Facts {
+        checks: ok::run(&checks).checks,
+        cpuid: platform::cpuid(),
+        firmware,
+        advisories: firmware
+            .map(|running| firmware::known_issues(running).unwrap_or_default())
+            .unwrap_or_default(),
+    };
+
+    let diagnosis = schema::Diagnosis {
+        findings: diagnose(&facts),
+        checks: facts.checks,
+    };
+    output::print(&diagnosis);
+
+    match diagnosis.findings.len() {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            "the host is not ready",
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} problems found", n),
+            )),
+        )),
+    }
+}
+
+/// The findings of the rules, then the failures they do not explain.
+fn diagnose(facts: &Facts) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    for rule in RULES {
+        let diagnosis = match (rule.diagnose)(facts) {
+            Some(diagnosis) => diagnosis,
+            None => continue,
+        };
+
+        let explained = |id: &String| findings.iter().any(|f| f.explains.contains(id));
+        if !diagnosis.explains.is_empty() && diagnosis.explains.iter().all(explained) {
+            continue;
+        }
+
+        let explains = diagnosis
+            .explains
+            .iter()
+            .filter(|id| !explained(id))
+            .cloned()
+            .collect();
+        findings.push(Finding {
+            id: rule.id.to_string(),
+            cause: diagnosis.cause,
+            explains,
+            steps: diagnosis.steps,
+        });
+    }
+
+    for check in &facts.checks {
+        let explained = findings.iter().any(|f| f.explains.contains(&check.id));
+        if check.state != CheckState::Fail || explained {
+            continue;
+        }
+
+        let cause = match &check.details {
+            Some(details) => format!("{} failed: {}", check.name, details),
+            None => format!("{} failed", check.name),
+        };
+        findings.push(Finding {
+            id: check.id.clone(),
+            cause,
+            explains: vec![check.id.clone()],
+            steps: vec![],
+        });
+    }
+
+    findings
+}
+
+impl output::Document for schema::Diagnosis {
+    fn table(&self) -> String {
+        if self.findings.is_empty() {
+            let passed = self.checks.len();
+            return format!("no problems found, all {} checks passed\n", passed);
+        }
+
+        let mut out = String::new();
+        for (i, finding) in self.findings.iter().enumerate() {
+            out += &format!("{}. {}\n", i + 1, finding.cause);
+            if !finding.explains.is_empty() {
+                out += &format!("   explains: {}\n", finding.explains.join(", "));
+            }
+            for step in &finding.steps {
+                out += &format!("   - {}\n", step);
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Extraction of launch parameters from libvirt domain definitions.
+
+use super::*;
+use measurement::{ContextArgs, Inputs};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Where to read a libvirt domain definition from.
+#[derive(StructOpt)]
+pub struct DomainArgs {
+    #[structopt(
+        long,
+        help = "libvirt domain XML file, or domain name when --connect is given"
+    )]
+    pub domain: Option<String>,
+
+    #[structopt(
+        long,
+        requires = "domain",
+        help = "libvirt connection URI to read a defined domain from (e.g. qemu:///system)"
+    )]
+    pub connect: Option<String>,
+}
+
+/// The launch parameters found in a domain definition.
+#[derive(Default)]
+struct Domain {
+    loader: Option<String>,
+    kernel: Option<String>,
+    initrd: Option<String>,
+    cmdline: Option<String>,
+    vcpus: Option<u32>,
+    cpu_model: Option<String>,
+    policy: Option<String>,
+}
+
+impl DomainArgs {
+    /// Fills in every launch parameter not given on the command line from the domain.
+    pub fn apply(&self, inputs: &mut Inputs, context: &mut ContextArgs) -> Result<()> {
+        let xml = match (&self.domain, &self.connect) {
+            (None, _) => return Ok(()),
+            (Some(path), None) => std::fs::read_to_string(path)
+                .context(format!("unable to read domain XML {}", path))?,
+            (Some(name), Some(uri)) => dumpxml(uri, name)?,
+        };
+
+        let Domain {
+            loader,
+            kernel,
+            initrd,
+            cmdline,
+            vcpus,
+            cpu_model,
+            policy,
+        } = parse(&xml)?;
+
+        if inputs.digest.is_none() && inputs.firmware.is_none() {
+            inputs.firmware = loader.map(PathBuf::from);
+        }
+
+        if inputs.kernel.is_none() {
+            inputs.kernel = kernel.map(PathBuf::from);
+            inputs.initrd = inputs.initrd.take().or_else(|| initrd.map(PathBuf::from));
+            inputs.cmdline = inputs.cmdline.take().or(cmdline);
+        }
+
+        if let Some(vcpus) = vcpus {
+            if inputs.vcpus.num_cpus == 1 {
+                inputs.vcpus.num_cpus = vcpus;
+            }
+        }
+
+        if inputs.vcpus.vcpu_type.is_none() && inputs.vcpus.vcpu_sig.is_none() {
+            inputs.vcpus.vcpu_type = cpu_model;
+        }
+
+        if context.policy.is_none() {
+            context.policy = policy;
+        }
+
+        Ok(())
+    }
+}
+
+fn dumpxml(uri: &str, name: &str) -> Result<String> {
+    virsh(uri, &["dumpxml", name], None)
+}
+
+/// Runs a virsh command on a libvirt connection, optionally feeding it input, and
+/// returns its output.
+pub fn virsh(uri: &str, args: &[&str], input: Option<&str>) -> Result<String> {
+    let mut child = Command::new("virsh")
+        .arg("--connect")
+        .arg(uri)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("unable to run virsh")?;
+
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().unwrap();
+        stdin
+            .write_all(input.as_bytes())
+            .context("unable to write to virsh")?;
+    }
+
+    let output = child.wait_with_output().context("unable to run virsh")?;
+    if !output.status.success() {
+        return Err(error::Context::new(
+            &format!(
+                "virsh {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Box::<Error>::new(ErrorKind::Other.into()),
+        ));
+    }
+
+    String::from_utf8(output.stdout).context("virsh output is not valid UTF-8")
+}
+
+fn parse(xml: &str) -> Result<Domain> {
+    let doc = roxmltree::Document::parse(xml).context("invalid domain XML")?;
+    let root = doc.root_element();
+
+    let child = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|n| n.has_tag_name(name))
+            .and_then(|n| n.text())
+            .map(|t| t.trim().to_string())
+    };
+
+    let mut domain = Domain::default();
+
+    if let Some(os) = root.children().find(|n| n.has_tag_name("os")) {
+        domain.loader = child(os, "loader");
+        domain.kernel = child(os, "kernel");
+        domain.initrd = child(os, "initrd");
+        domain.cmdline = child(os, "cmdline");
+    }
+
+    if let Some(vcpus) = child(root, "vcpu") {
+        domain.vcpus = Some(vcpus.parse().context("invalid vcpu count in domain XML")?);
+    }
+
+    if let Some(cpu) = root.children().find(|n| n.has_tag_name("cpu")) {
+        if cpu.attribute("mode") == Some("custom") {
+            domain.cpu_model = child(cpu, "model");
+        }
+    }
+
+    if let Some(sev) = root
+        .children()
+        .find(|n| n.has_tag_name("launchSecurity") && n.attribute("type") == Some("sev"))
+    {
+        domain.policy = child(sev, "policy");
+    }
+
+    Ok(domain)
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encodings of binary values such as digests, nonces and keys.
+//!
+//! Tools in the SEV ecosystem exchange these values as hex strings, base64 strings
+//! or raw files, so inputs accept any of them and outputs can be produced in each.
+
+use super::*;
+use std::str::FromStr;
+
+/// The encodings accepted for inputs, for `possible_values`.
+pub const INPUT_FORMS: &[&str] = &["auto", "hex", "base64", "raw"];
+
+/// The encodings accepted for outputs, for `possible_values`.
+pub const OUTPUT_FORMS: &[&str] = &["hex", "base64", "raw"];
+
+/// The encoding of a binary value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Form {
+    /// Detect the encoding of an input from its size and contents.
+    Auto,
+    Hex,
+    Base64,
+    Raw,
+}
+
+impl FromStr for Form {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        Ok(match s {
+            "auto" => Form::Auto,
+            "hex" => Form::Hex,
+            "base64" => Form::Base64,
+            "raw" => Form::Raw,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown encoding {} (expected auto, hex, base64 or raw)", s),
+                ))
+            }
+        })
+    }
+}
+
+/// Decodes a value of `len` bytes.
+///
+/// In the `Auto` form, input of exactly `len` bytes is taken as raw, and
+/// anything else as hex or, failing that, base64.
+pub fn decode(form: Form, buf: &[u8], len: usize, what: &str) -> Result<Vec<u8>> {
+    let invalid = |form: &str| {
+        error::Context::new(
+            &format!("{} is not valid {}", what, form),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        )
+    };
+
+    let text = || std::str::from_utf8(buf).map(str::trim);
+    let from_hex = || text().ok().and_then(|s| unhex(s).ok());
+    let from_base64 = || text().ok().and_then(|s| base64::decode(s).ok());
+
+    let bytes = match form {
+        Form::Raw => buf.to_vec(),
+        Form::Hex => from_hex().ok_or_else(|| invalid("hex"))?,
+        Form::Base64 => from_base64().ok_or_else(|| invalid("base64"))?,
+        Form::Auto if buf.len() == len => buf.to_vec(),
+        Form::Auto => from_hex()
+            .filter(|b| b.len() == len)
+            .or_else(from_base64)
+            .ok_or_else(|| invalid("hex, base64 or raw"))?,
+    };
+
+    if bytes.len() != len {
+        return Err(error::Context::new(
+            &format!("{} is {} bytes, expected {}", what, bytes.len(), len),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        ));
+    }
+
+    Ok(bytes)
+}
+
+/// Decodes a fixed-size value given on the command line.
+pub fn arg<T: Default + AsMut<[u8]>>(form: Form, value: &str, what: &str) -> Result<T> {
+    if form == Form::Raw {
+        return Err(error::Context::new(
+            &format!("{} given on the command line cannot be raw", what),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let mut out = T::default();
+    let len = out.as_mut().len();
+    out.as_mut()
+        .copy_from_slice(&decode(form, value.as_bytes(), len, what)?);
+    Ok(out)
+}
+
+/// Encodes a value for display; raw values cannot be displayed and are shown as hex.
+pub fn encode(form: Form, bytes: &[u8]) -> String {
+    match form {
+        Form::Base64 => base64::encode(bytes),
+        _ => hex(bytes),
+    }
+}
@@ -3,7 +3,13 @@
 //! Types for adding context to errors that occur during operation
 //! while still preserving some of the "backtrace-y" nature that we would
 //! normally get with simply panicking.
+//!
+//! Every error is also categorized as a [`Kind`] with a stable numeric code, so
+//! that automation can tell failures apart without matching their messages.
 
+use super::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// This implies an error that _always_ has context associated with it.
@@ -15,27 +21,208 @@ pub trait Contextual<T> {
     fn context<S: AsRef<str>>(self, context: S) -> Result<T>;
 }
 
-impl<T, E: 'static + std::error::Error> Contextual<T> for std::result::Result<T, E> {
+impl<T, E: 'static + std::error::Error + Send + Sync> Contextual<T> for std::result::Result<T, E> {
     fn context<S: AsRef<str>>(self, context: S) -> Result<T> {
         self.map_err(|e| Context::new(context.as_ref(), Box::new(e)))
     }
 }
 
+/// The category of an error. The codes are stable: new kinds get new codes, and
+/// the codes of existing kinds never change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// Anything not categorized below.
+    Other = 1,
+
+    /// An argument or input file is malformed or inconsistent.
+    InvalidInput = 2,
+
+    /// A file, device or resource does not exist.
+    NotFound = 3,
+
+    /// Access to a file or device, e.g. /dev/sev, was denied.
+    PermissionDenied = 4,
+
+    /// The firmware rejected a command with a status code.
+    Firmware = 5,
+
+    /// A request to a remote service, e.g. the KDS, failed.
+    Network = 6,
+
+    /// An operation did not complete in time.
+    Timeout = 7,
+
+    /// The device was busy, even after retrying.
+    Busy = 8,
+
+    /// A cryptographic operation, e.g. a signature check, failed.
+    Crypto = 9,
+}
+
+impl Kind {
+    /// The stable numeric code.
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    /// Categorizes an error by the first cause in its chain that tells its kind.
+    fn of(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if let Some(context) = err.downcast_ref::<Context>() {
+                return context.kind;
+            }
+            if err.is::<Firmware>() {
+                return Kind::Firmware;
+            }
+            #[cfg(feature = "network")]
+            if err.is::<reqwest::Error>() {
+                return Kind::Network;
+            }
+            if err.is::<openssl::error::ErrorStack>() {
+                return Kind::Crypto;
+            }
+            if let Some(e) = err.downcast_ref::<Error>() {
+                if let Some(kind) = Self::of_io(e) {
+                    return kind;
+                }
+            }
+
+            next = err.source();
+        }
+
+        Kind::Other
+    }
+
+    fn of_io(e: &Error) -> Option<Self> {
+        if let Some(inner) = e.get_ref() {
+            match Self::of(inner) {
+                Kind::Other => (),
+                kind => return Some(kind),
+            }
+        }
+        if matches!(e.raw_os_error(), Some(libc::EBUSY) | Some(libc::EAGAIN)) {
+            return Some(Kind::Busy);
+        }
+
+        Some(match e.kind() {
+            ErrorKind::InvalidInput | ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                Kind::InvalidInput
+            }
+            ErrorKind::NotFound => Kind::NotFound,
+            ErrorKind::PermissionDenied => Kind::PermissionDenied,
+            ErrorKind::TimedOut => Kind::Timeout,
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::AddrNotAvailable => Kind::Network,
+            _ => return None,
+        })
+    }
+}
+
+/// A command rejected by the firmware with a status code (see [`codes`]).
+#[derive(Debug)]
+pub struct Firmware {
+    pub status: u32,
+
+    /// The error returned by the kernel along with the status.
+    pub cause: Error,
+}
+
+impl fmt::Display for Firmware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match codes::lookup(self.status) {
+            Some(code) => write!(
+                f,
+                "{} (firmware error {:#x} {})",
+                self.cause, self.status, code.name
+            ),
+            None => write!(f, "{} (firmware error {:#x})", self.cause, self.status),
+        }
+    }
+}
+
+impl std::error::Error for Firmware {}
+
 /// A wrapper error type used to hold a description of the context surrounding
 /// the error.
 #[derive(Debug)]
 pub struct Context {
     context: String,
-    cause: Box<dyn std::error::Error>,
+    kind: Kind,
+    cause: Box<dyn std::error::Error + Send + Sync>,
 }
 
 impl Context {
-    pub fn new(context: &str, cause: Box<dyn std::error::Error>) -> Self {
+    pub fn new(context: &str, cause: Box<dyn std::error::Error + Send + Sync>) -> Self {
         Self {
             context: context.into(),
+            kind: Kind::of(&*cause),
             cause,
         }
     }
+
+    /// Overrides the kind found from the cause.
+    pub fn with_kind(mut self, kind: Kind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The firmware status code, if the firmware rejected a command.
+    pub fn firmware_status(&self) -> Option<u32> {
+        let mut next: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(err) = next {
+            if let Some(firmware) = err.downcast_ref::<Firmware>() {
+                return Some(firmware.status);
+            }
+            if let Some(firmware) = err
+                .downcast_ref::<Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref::<Firmware>())
+            {
+                return Some(firmware.status);
+            }
+
+            next = err.source();
+        }
+
+        None
+    }
+
+    /// The error in machine-readable form.
+    pub fn report(&self) -> schema::ErrorReport {
+        let mut causes = Vec::new();
+        let mut err: &(dyn std::error::Error + 'static) = self;
+        while let Some(cause) = err.source() {
+            causes.push(cause.to_string());
+            err = cause;
+        }
+
+        schema::ErrorReport {
+            code: self.kind.code(),
+            kind: self.kind,
+            message: self.context.clone(),
+            causes,
+            firmware_status: self.firmware_status(),
+        }
+    }
+}
+
+impl output::Document for schema::ErrorReport {
+    fn table(&self) -> String {
+        let mut out = format!("error: {}\n", self.message);
+        for cause in &self.causes {
+            out += &format!("caused by: {}\n", cause);
+        }
+        out
+    }
 }
 
 impl fmt::Display for Context {
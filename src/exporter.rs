@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A Prometheus exporter sampling the platform state for fleet monitoring.
+
+use super::*;
+use http::Response;
+use openssl::asn1::Asn1Time;
+use openssl::x509::X509;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(StructOpt)]
+pub struct ExporterArgs {
+    #[structopt(long, default_value = "0.0.0.0:9750", help = "Address to listen on")]
+    listen: String,
+
+    #[structopt(long, default_value = "60", help = "Seconds between samples")]
+    interval: u64,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "PEM certificate (chain), e.g. a VCEK chain, whose remaining validity is reported"
+    )]
+    cert: Vec<PathBuf>,
+}
+
+pub fn cmd(args: ExporterArgs) -> Result<()> {
+    if args.interval == 0 {
+        return Err(error::Context::new(
+            "--interval must be at least one second",
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let metrics = Arc::new(Mutex::new(sample(&args.cert)));
+
+    let (sampled, certs, interval) = (metrics.clone(), args.cert, args.interval);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(interval));
+        *sampled.lock().unwrap() = sample(&certs);
+    });
+
+    http::serve(&args.listen, None, move |request| {
+        std::future::ready(match request.path.split('?').next() {
+            Some("/metrics") => Response::ok(
+                "text/plain; version=0.0.4",
+                metrics.lock().unwrap().clone().into_bytes(),
+            ),
+            _ => Response::error(404, "not found"),
+        })
+    })
+}
+
+/// Samples everything the exporter reports, in the Prometheus text format.
+fn sample(certs: &[PathBuf]) -> String {
+    let mut out = Metrics::default();
+
+    let status = platform_status();
+    out.gauge(
+        "sevctl_up",
+        "Whether the SEV platform status could be read",
+        &[("", status.is_ok() as u64 as f64)],
+    );
+    match status {
+        Ok(status) => {
+            out.gauge(
+                "sevctl_guests",
+                "Number of active SEV guests",
+                &[("", status.guests as f64)],
+            );
+            out.gauge(
+                "sevctl_firmware_info",
+                "The SEV firmware version and platform state",
+                &[(
+                    &format!(
+                        r#"api="{}.{}",build="{}",state="{}""#,
+                        status.build.version.major,
+                        status.build.version.minor,
+                        status.build.build,
+                        format!("{:?}", status.state).to_lowercase()
+                    ),
+                    1.0,
+                )],
+            );
+            out.gauge(
+                "sevctl_firmware_build",
+                "The SEV firmware build ID",
+                &[("", status.build.build as f64)],
+            );
+            out.gauge(
+                "sevctl_owned",
+                "Whether the platform is owned by an external OCA",
+                &[(
+                    "",
+                    status.flags.contains(::sev::firmware::Flags::OWNED) as u64 as f64,
+                )],
+            );
+        }
+        Err(e) => eprintln!("unable to sample the platform status: {}", e),
+    }
+
+    // SEV-SNP is reported separately as hosts without it are still useful to monitor.
+    let snp = snp_status();
+    out.gauge(
+        "sevctl_snp_up",
+        "Whether the SEV-SNP platform status could be read",
+        &[("", snp.is_ok() as u64 as f64)],
+    );
+    if let Ok(snp) = snp {
+        out.gauge(
+            "sevctl_snp_guests",
+            "Number of active SEV-SNP guests",
+            &[("", snp.guests as f64)],
+        );
+        out.gauge(
+            "sevctl_snp_firmware_info",
+            "The SEV-SNP firmware version",
+            &[(
+                &format!(
+                    r#"api="{}.{}",build="{}""#,
+                    snp.api_major, snp.api_minor, snp.build
+                ),
+                1.0,
+            )],
+        );
+        out.gauge(
+            "sevctl_snp_initialized",
+            "Whether SEV-SNP is initialized",
+            &[("", snp.initialized as u64 as f64)],
+        );
+        out.gauge(
+            "sevctl_snp_rmp_initialized",
+            "Whether the reverse map table is initialized",
+            &[("", snp.rmp_initialized as u64 as f64)],
+        );
+    }
+
+    if let Ok(asids) = platform::asids() {
+        let total: Vec<_> = asids
+            .iter()
+            .map(|a| (format!(r#"type="{}""#, a.kind), a.capacity as f64))
+            .collect();
+        let free: Vec<_> = asids
+            .iter()
+            .filter_map(|a| {
+                let used = a.used?;
+                let free = a.capacity.saturating_sub(used) as f64;
+                Some((format!(r#"type="{}""#, a.kind), free))
+            })
+            .collect();
+
+        out.gauge("sevctl_asids", "Number of guest ASIDs", &labelled(&total));
+        out.gauge(
+            "sevctl_asids_free",
+            "Number of guest ASIDs not in use",
+            &labelled(&free),
+        );
+    }
+
+    // SEV certificates do not expire, but the X.509 ones of SEV-SNP do.
+    let mut expiry = Vec::new();
+    for path in certs {
+        match days_remaining(path) {
+            Ok(days) => expiry.extend(days.into_iter().map(|(subject, days)| {
+                (
+                    format!(
+                        r#"file="{}",subject="{}""#,
+                        escape(&path.display().to_string()),
+                        escape(&subject)
+                    ),
+                    days as f64,
+                )
+            })),
+            Err(e) => eprintln!("unable to check {}: {}", path.display(), e),
+        }
+    }
+    out.gauge(
+        "sevctl_certificate_expiry_days",
+        "Days until the certificate expires",
+        &labelled(&expiry),
+    );
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    out.gauge(
+        "sevctl_last_sample_timestamp_seconds",
+        "When the metrics were sampled",
+        &[("", now as f64)],
+    );
+
+    out.0
+}
+
+/// The days each certificate in a PEM file remains valid, by subject.
+fn days_remaining(path: &Path) -> Result<Vec<(String, i32)>> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let pem = std::fs::read(path).context("unable to read certificate")?;
+    let certs = X509::stack_from_pem(&pem).context("invalid PEM certificate")?;
+    let now = Asn1Time::days_from_now(0).context("unable to get the current time")?;
+
+    certs
+        .iter()
+        .map(|cert| {
+            let subject = cert
+                .subject_name()
+                .entries()
+                .filter_map(|e| e.data().as_utf8().ok().map(|s| s.to_string()))
+                .collect::<Vec<_>>()
+                .join(",");
+            let diff = now
+                .diff(cert.not_after())
+                .context("unable to compare certificate dates")?;
+            Ok((subject, diff.days))
+        })
+        .collect()
+}
+
+fn labelled(values: &[(String, f64)]) -> Vec<(&str, f64)> {
+    values.iter().map(|(l, v)| (l.as_str(), *v)).collect()
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#)
+}
+
+/// Metrics in the Prometheus text exposition format.
+#[derive(Default)]
+struct Metrics(String);
+
+impl Metrics {
+    /// Adds a gauge with one sample per set of labels.
+    fn gauge(&mut self, name: &str, help: &str, samples: &[(&str, f64)]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE {} gauge", name);
+        for (labels, value) in samples {
+            let _ = if labels.is_empty() {
+                writeln!(self.0, "{} {}", name, value)
+            } else {
+                writeln!(self.0, "{}{{{}}} {}", name, labels, value)
+            };
+        }
+    }
+}
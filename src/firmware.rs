@@ -0,0 +1,495 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Updates of the SEV firmware.
+//!
+//! The kernel does not let userspace issue DOWNLOAD_FIRMWARE or
+//! SNP_DOWNLOAD_FIRMWARE_EX. Instead, the ccp driver issues DOWNLOAD_FIRMWARE itself
+//! when it initializes the platform, with the first image it finds in the firmware
+//! directory under a name given by the CPU family and model, so an update is
+//! installed there and takes effect on the next boot or reload of the driver.
+//!
+//! Known issues of the firmware are checked against a table of advisories, the
+//! bundled `advisories.toml` unless another is configured.
+
+use super::*;
+use schema::{CheckResult, CheckState};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+/// The advisories known when sevctl was built.
+const ADVISORIES: &str = include_str!("advisories.toml");
+
+#[derive(StructOpt)]
+pub enum Firmware {
+    #[structopt(about = "Install an SEV firmware image for the ccp driver to load")]
+    Update {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            default_value = "/lib/firmware",
+            help = "Directory the kernel loads firmware from"
+        )]
+        firmware_dir: PathBuf,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Attestation report to take the committed firmware version from"
+        )]
+        report: Option<PathBuf>,
+
+        #[structopt(long, help = "Install an image older than the running firmware")]
+        allow_downgrade: bool,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "Firmware image (e.g. amd_sev_fam19h_model0xh.sbin)"
+        )]
+        image: PathBuf,
+    },
+
+    #[structopt(about = "Check the running firmware against known advisories")]
+    Check {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Advisory table (TOML) to use instead of the bundled one"
+        )]
+        advisories: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            possible_values = platform::GENERATIONS,
+            case_insensitive = true,
+            help = "Processor generation, defaults to the local processor's"
+        )]
+        generation: Option<platform::Generation>,
+
+        #[structopt(
+            long,
+            help = "Firmware version (major.minor.build), defaults to the running firmware's"
+        )]
+        version: Option<String>,
+    },
+}
+
+pub fn cmd(cmd: Firmware) -> Result<()> {
+    match cmd {
+        Firmware::Update {
+            firmware_dir,
+            report,
+            allow_downgrade,
+            image,
+        } => update(&firmware_dir, report.as_deref(), allow_downgrade, &image),
+        Firmware::Check {
+            advisories,
+            generation,
+            version,
+        } => check(
+            advisories.or(config::get().advisories),
+            generation,
+            version.as_deref(),
+        ),
+    }
+}
+
+/// A table of advisories.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Advisories {
+    #[serde(default)]
+    advisory: Vec<Advisory>,
+}
+
+/// A known issue of the firmware.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Advisory {
+    /// A CVE or AMD security bulletin ID.
+    id: String,
+    title: String,
+    url: Option<String>,
+
+    /// The first fixed version of each affected generation, as `major.minor.build`.
+    fixed: BTreeMap<String, String>,
+}
+
+impl Advisories {
+    fn load(path: Option<&Path>) -> Result<Self> {
+        let (text, name) = match path {
+            Some(path) => {
+                tracing::debug!(path = %path.display(), "reading file");
+                let text = std::fs::read_to_string(path)
+                    .context(format!("unable to read {}", path.display()))?;
+                (text, path.display().to_string())
+            }
+            None => (ADVISORIES.to_string(), "bundled advisories".to_string()),
+        };
+
+        let advisories: Self =
+            toml::from_str(&text).context(format!("invalid advisory table {}", name))?;
+        for advisory in &advisories.advisory {
+            for version in advisory.fixed.values() {
+                parse_version(version)
+                    .context(format!("invalid version of {} in {}", advisory.id, name))?;
+            }
+        }
+        Ok(advisories)
+    }
+
+    /// A verdict per advisory on `running`, the firmware version of a processor of
+    /// `generation`.
+    fn evaluate(
+        &self,
+        generation: platform::Generation,
+        running: (u8, u8, u8),
+    ) -> Vec<CheckResult> {
+        let version = format!("{}.{}.{}", running.0, running.1, running.2);
+        self.advisory
+            .iter()
+            .map(|advisory| {
+                let fixed = advisory
+                    .fixed
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&generation.to_string()));
+                let (state, details) = match fixed.map(|(_, fixed)| fixed) {
+                    None => (CheckState::Skip, format!("does not affect {}", generation)),
+                    Some(fixed) if running >= parse_version(fixed).unwrap() => {
+                        (CheckState::Pass, format!("fixed in {}", fixed))
+                    }
+                    Some(fixed) => (
+                        CheckState::Fail,
+                        format!("{} is affected, update to {} or later", version, fixed),
+                    ),
+                };
+                let details = match (&advisory.url, state) {
+                    (Some(url), CheckState::Fail) => format!("{} ({})", details, url),
+                    _ => details,
+                };
+
+                CheckResult {
+                    id: advisory.id.clone(),
+                    name: advisory.title.clone(),
+                    state,
+                    details: Some(details),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parses a firmware version given as `major.minor.build`.
+fn parse_version(version: &str) -> Result<(u8, u8, u8)> {
+    let parts: Vec<_> = version.split('.').map(str::parse::<u8>).collect();
+    match parts.as_slice() {
+        [Ok(major), Ok(minor), Ok(build)] => Ok((*major, *minor, *build)),
+        _ => Err(error::Context::new(
+            &format!("{} is not major.minor.build", version),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        )),
+    }
+}
+
+/// Checks the running firmware against every advisory, failing if any applies.
+fn check(
+    advisories: Option<PathBuf>,
+    generation: Option<platform::Generation>,
+    version: Option<&str>,
+) -> Result<()> {
+    let advisories = Advisories::load(advisories.as_deref())?;
+
+    let generation = platform::Generation::or_local(generation, "--generation")?;
+    let running = match version {
+        Some(version) => parse_version(version)?,
+        None => {
+            let status = platform_status()?;
+            (
+                status.build.version.major,
+                status.build.version.minor,
+                status.build.build,
+            )
+        }
+    };
+    let version = format!("{}.{}.{}", running.0, running.1, running.2);
+
+    let checks = advisories.evaluate(generation, running);
+    let affected = checks
+        .iter()
+        .filter(|c| c.state == CheckState::Fail)
+        .count();
+    let total = checks.len();
+    output::print(&schema::Checks { checks });
+
+    match affected {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            &format!(
+                "firmware {} on {} is affected by known issues",
+                version, generation
+            ),
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} of {} advisories apply", n, total),
+            )),
+        )),
+    }
+}
+
+/// The configured advisories that apply to the running firmware of the local
+/// processor generation.
+pub fn known_issues(running: (u8, u8, u8)) -> Result<Vec<CheckResult>> {
+    let advisories = Advisories::load(config::get().advisories.as_deref())?;
+    let generation = platform::Generation::local().ok_or_else(|| {
+        error::Context::new(
+            "unable to tell the processor generation",
+            Box::<Error>::new(ErrorKind::Unsupported.into()),
+        )
+    })?;
+
+    Ok(advisories
+        .evaluate(generation, running)
+        .into_iter()
+        .filter(|c| c.state == CheckState::Fail)
+        .collect())
+}
+
+/// The names the ccp driver tries for the local CPU before `amd/sev.fw`, in order:
+/// one for the model and one for its group of 16 models, e.g.
+/// `amd/amd_sev_fam19h_model0xh.sbin`.
+fn image_names() -> Option<[String; 2]> {
+    if platform::cpu_vendor()? != "AuthenticAMD" {
+        return None;
+    }
+
+    let (family, model) = platform::cpu_model()?;
+    Some([
+        format!("amd/amd_sev_fam{:02x}h_model{:02x}h.sbin", family, model),
+        format!(
+            "amd/amd_sev_fam{:02x}h_model{:x}xh.sbin",
+            family,
+            model >> 4
+        ),
+    ])
+}
+
+/// The size of the header of a signed PSP image.
+const IMAGE_HEADER_LEN: usize = 0x100;
+
+/// Checks that `data` is a signed PSP firmware image, returning the firmware version
+/// of its header.
+///
+/// The header holds the `$PS1` magic at 0x10, the size of the signed body at 0x14,
+/// and the build, minor and major version at 0x60.
+fn image_version(image: &Path, data: &[u8]) -> Result<(u8, u8, u8)> {
+    let invalid = |why: &str| {
+        error::Context::new(
+            &format!("{} is not an SEV firmware image", image.display()),
+            Box::new(Error::new(ErrorKind::InvalidInput, why.to_string())),
+        )
+    };
+
+    if data.len() < IMAGE_HEADER_LEN {
+        return Err(invalid("it is shorter than an image header"));
+    }
+    if &data[0x10..0x14] != b"$PS1" {
+        return Err(invalid("it has no $PS1 header"));
+    }
+    let signed = u32::from_le_bytes([data[0x14], data[0x15], data[0x16], data[0x17]]) as usize;
+    if signed == 0 || IMAGE_HEADER_LEN + signed > data.len() {
+        return Err(invalid("it is shorter than its header says"));
+    }
+
+    Ok((data[0x62], data[0x61], data[0x60]))
+}
+
+/// The version of the committed firmware, as an attestation report gives it.
+fn committed_version(report: &Path) -> Result<(u8, u8, u8)> {
+    tracing::debug!(path = %report.display(), "reading file");
+    let bytes = std::fs::read(report).context(format!("unable to read {}", report.display()))?;
+    let r = report::Report::new(bytes)?.document();
+    Ok((r.committed_major, r.committed_minor, r.committed_build))
+}
+
+fn version_string(version: (u8, u8, u8)) -> String {
+    format!("{}.{}.{}", version.0, version.1, version.2)
+}
+
+fn update(
+    firmware_dir: &Path,
+    report: Option<&Path>,
+    allow_downgrade: bool,
+    image: &Path,
+) -> Result<()> {
+    let progress = progress::start(&format!("Reading {}", image.display()));
+    tracing::debug!(path = %image.display(), "reading file");
+    let data = std::fs::read(image).context(format!("unable to read {}", image.display()))?;
+    let version = image_version(image, &data)?;
+
+    let [model, group] = image_names().ok_or_else(|| {
+        error::Context::new(
+            "unable to tell the firmware image name",
+            Box::new(Error::new(ErrorKind::Unsupported, "not an AMD CPU")),
+        )
+    })?;
+
+    // Replace the image the driver would load rather than shadowing or being
+    // shadowed by it.
+    let name = match firmware_dir.join(&model).exists() {
+        true => model,
+        false => group,
+    };
+    let path = firmware_dir.join(name);
+
+    progress.step("Querying the running firmware");
+    let current = platform_status().ok().map(|status| {
+        (
+            status.build.version.major,
+            status.build.version.minor,
+            status.build.build,
+        )
+    });
+    let snp = snp_status().map_or(false, |status| status.initialized);
+    let committed = report.map(committed_version).transpose()?;
+
+    // The platform would refuse the image when loading it, leaving SEV unavailable.
+    if let Some(committed) = committed.filter(|c| version < *c) {
+        return Err(error::Context::new(
+            &format!(
+                "the image holds firmware {}, older than the committed firmware {}",
+                version_string(version),
+                version_string(committed)
+            ),
+            Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                "the platform rejects images older than the committed firmware",
+            )),
+        ));
+    }
+    if let Some(current) = current.filter(|c| version < *c && !allow_downgrade) {
+        return Err(error::Context::new(
+            &format!(
+                "the image holds firmware {}, older than the running firmware {}",
+                version_string(version),
+                version_string(current)
+            ),
+            Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                "install it anyway with --allow-downgrade",
+            )),
+        ));
+    }
+
+    let mut warnings = Vec::new();
+    match (committed, current) {
+        (None, _) if snp => warnings.push(
+            "the committed firmware is unknown, so the image may be rejected as older; \
+             give an attestation report with --report to check"
+                .to_string(),
+        ),
+        (Some(committed), Some(current)) if committed != current => warnings.push(format!(
+            "the running firmware {} is not committed ({} committed), so the platform \
+             may roll back to the committed one",
+            version_string(current),
+            version_string(committed)
+        )),
+        _ => (),
+    }
+
+    progress.step(&format!("Installing {}", path.display()));
+    let unchanged = std::fs::read(&path).map_or(false, |old| old == data);
+    if !unchanged {
+        install(&path, &data)?;
+    }
+
+    drop(progress);
+    output::print(&schema::FirmwareUpdate {
+        path,
+        changed: !unchanged,
+        image_version: version_string(version),
+        current_version: current.map(version_string),
+        committed_version: committed.map(version_string),
+        snp,
+        warnings,
+    });
+    Ok(())
+}
+
+/// Replaces the image at `path` so that the driver finds either the old image or the
+/// new one whole, even if the update is interrupted.
+fn install(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dir.join(format!(".{}.tmp", name));
+    tracing::debug!(path = %tmp.display(), "writing file");
+    let installed = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o644)
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.set_permissions(std::fs::Permissions::from_mode(0o644))?;
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .and_then(|()| File::open(dir)?.sync_all());
+
+    if installed.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    installed.context(format!("unable to install {}", path.display()))
+}
+
+impl output::Document for schema::FirmwareUpdate {
+    fn table(&self) -> String {
+        let mut out = match self.changed {
+            true => format!("installed {}\n", self.path.display()),
+            false => format!("{} is already installed\n", self.path.display()),
+        };
+        out += &format!(
+            "image firmware: {}\ncurrent firmware: {}\n",
+            self.image_version,
+            self.current_version.as_deref().unwrap_or("unknown")
+        );
+        if let Some(committed) = &self.committed_version {
+            out += &format!("committed firmware: {}\n", committed);
+        }
+        for warning in &self.warnings {
+            out += &format!("warning: {}\n", warning);
+        }
+        out += "the ccp driver loads the image when it initializes the platform, e.g. on the \
+                next boot; check the new version with sevctl show version\n";
+        if self.snp {
+            out += "the platform rejects images older than the committed firmware; once the \
+                    update is verified, commit it with sevctl snp commit to prevent rollback\n";
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_header() {
+        let path = Path::new("amd_sev_fam19h_model0xh.sbin");
+        let mut image = vec![0u8; IMAGE_HEADER_LEN + 0x40];
+        image[0x10..0x14].copy_from_slice(b"$PS1");
+        image[0x14..0x18].copy_from_slice(&0x40u32.to_le_bytes());
+        image[0x60..0x63].copy_from_slice(&[21, 55, 1]);
+        assert_eq!(image_version(path, &image).unwrap(), (1, 55, 21));
+
+        // The signed body must be in the file.
+        assert!(image_version(path, &image[..IMAGE_HEADER_LEN + 0x3f]).is_err());
+        assert!(image_version(path, &image[..0x80]).is_err());
+
+        image[0x10] = b'#';
+        assert!(image_version(path, &image).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! GUIDs as used by OVMF and the EFI tables it shares with the hypervisor.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+/// A GUID, stored in the mixed-endian EFI byte order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Guid([u8; 16]);
+
+impl Guid {
+    /// Parses a well-known GUID constant, panicking on malformed input.
+    pub fn from_static(s: &'static str) -> Self {
+        s.parse().unwrap()
+    }
+
+    /// Wraps GUID bytes already in EFI byte order.
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// The GUID in EFI byte order.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl FromStr for Guid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid GUID: {}", s));
+
+        let fields: Vec<&str> = s.split('-').collect();
+        let lens = [8, 4, 4, 4, 12];
+        if fields.len() != lens.len()
+            || fields.iter().zip(lens.iter()).any(|(f, l)| f.len() != *l)
+            || !fields
+                .iter()
+                .all(|f| f.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return Err(invalid());
+        }
+
+        let hex: String = fields.concat();
+        let mut bytes = [0u8; 16];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+
+        // The first three fields are stored little-endian.
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9]
+        )?;
+
+        for x in &b[10..] {
+            write!(f, "{:02x}", x)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let s = "96b582de-1fb2-45f7-baea-a366c55a082d";
+        let guid: Guid = s.parse().unwrap();
+        assert_eq!(guid.to_string(), s);
+        assert_eq!(
+            guid.as_bytes(),
+            &[
+                0xde, 0x82, 0xb5, 0x96, 0xb2, 0x1f, 0xf7, 0x45, 0xba, 0xea, 0xa3, 0x66, 0xc5, 0x5a,
+                0x08, 0x2d
+            ]
+        );
+        assert_eq!(Guid::from_bytes(*guid.as_bytes()), guid);
+    }
+
+    #[test]
+    fn uppercase() {
+        let guid: Guid = "96B582DE-1FB2-45F7-BAEA-A366C55A082D".parse().unwrap();
+        assert_eq!(
+            guid,
+            Guid::from_static("96b582de-1fb2-45f7-baea-a366c55a082d")
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        for s in &[
+            "",
+            "96b582de1fb245f7baeaa366c55a082d",
+            "96b582de-1fb2-45f7-baea-a366c55a082",
+            "96b582de-1fb2-45f7-baea-a366c55a082d0",
+            "96b582d-e1fb2-45f7-baea-a366c55a082d",
+            "96b582de-1fb2-45f7-baea-a366c55a082g",
+            "+6b582de-1fb2-45f7-baea-a366c55a082d",
+        ] {
+            assert!(s.parse::<Guid>().is_err(), "{}", s);
+        }
+    }
+}
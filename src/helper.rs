@@ -0,0 +1,550 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A small privileged helper issuing firmware commands on behalf of unprivileged
+//! sevctl processes, so that only the helper needs access to /dev/sev.
+//!
+//! The protocol is one JSON object per line in each direction and one request
+//! per connection: `{"command": "PLATFORM_STATUS"}` is answered with either
+//! `{"ok": ...}` or `{"error": "..."}`, the latter with the firmware `status`
+//! code and the kernel's `errno` if any. Certificates and identifiers are
+//! base64 encoded.
+
+use super::*;
+use device::Device;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// How long either side waits for the other, in total for a request.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest request line, which holds two certificates at most.
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// How many connections are served at once; more are turned away.
+const MAX_CONNECTIONS: usize = 16;
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The commands that change the platform, refused unless --allow-modify is given.
+const MODIFYING: &[&str] = &[
+    "PLATFORM_RESET",
+    "PDH_GEN",
+    "PEK_CERT_IMPORT",
+    "SNP_COMMIT",
+    "SNP_SET_CONFIG",
+];
+
+#[derive(StructOpt)]
+pub struct HelperArgs {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        default_value = device::HELPER_SOCKET,
+        help = "Unix socket to listen on"
+    )]
+    socket: PathBuf,
+
+    #[structopt(
+        long,
+        default_value = "0660",
+        parse(try_from_str = parse_mode),
+        help = "Octal permissions of the socket, which control who may use the helper"
+    )]
+    mode: u32,
+
+    #[structopt(
+        long,
+        help = "Also serve the commands that change the platform (reset, rotate, provision, snp commit and snp config)"
+    )]
+    allow_modify: bool,
+}
+
+fn parse_mode(mode: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(mode, 8)
+}
+
+pub fn cmd(args: HelperArgs) -> Result<()> {
+    if let Some(dir) = args.socket.parent() {
+        std::fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+    }
+
+    remove_stale(&args.socket)?;
+
+    let listener = UnixListener::bind(&args.socket)
+        .context(format!("unable to listen on {}", args.socket.display()))?;
+    std::fs::set_permissions(&args.socket, std::fs::Permissions::from_mode(args.mode))
+        .context("unable to set the socket permissions")?;
+
+    println!("listening on {}", args.socket.display());
+    systemd::notify("READY=1");
+
+    // Each connection has its own thread, so that a slow client cannot hold up the others;
+    // the firmware driver serializes the commands themselves.
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("unable to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        if CONNECTIONS.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+            eprintln!("too many connections, dropping one");
+            continue;
+        }
+
+        let allow_modify = args.allow_modify;
+        let spawned = std::thread::Builder::new()
+            .name("helper-connection".into())
+            .spawn(move || {
+                if let Err(e) = answer(&stream, allow_modify) {
+                    eprintln!("unable to answer request: {}", e);
+                }
+                CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+            });
+        if let Err(e) = spawned {
+            CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+            eprintln!("unable to serve connection: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a socket left behind by a previous run, which would make binding fail.
+///
+/// Anything else at the path, or a socket of another user or still being listened on,
+/// is not the helper's to remove.
+fn remove_stale(path: &Path) -> Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context(format!("unable to inspect {}", path.display())),
+    };
+
+    // SAFETY: geteuid cannot fail.
+    let euid = unsafe { libc::geteuid() };
+    let problem = if !metadata.file_type().is_socket() {
+        "is not a socket"
+    } else if metadata.uid() != euid {
+        "is a socket of another user"
+    } else if UnixStream::connect(path).is_ok() {
+        "is in use by another helper"
+    } else {
+        return std::fs::remove_file(path).context(format!("unable to remove {}", path.display()));
+    };
+
+    Err(error::Context::new(
+        &format!("{} {}", path.display(), problem),
+        Box::new(Error::new(
+            ErrorKind::AlreadyExists,
+            "remove it or choose another --socket",
+        )),
+    ))
+}
+
+/// Reads a request line, of at most [`MAX_REQUEST`] bytes, within [`TIMEOUT`] in total.
+fn read_request(stream: &UnixStream) -> std::io::Result<Value> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut line = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "request not received in time",
+            ));
+        }
+        stream.set_read_timeout(Some(left))?;
+
+        let n = (&mut &*stream).read(&mut buf)?;
+        line.extend_from_slice(&buf[..n]);
+        let end = line.iter().position(|b| *b == b'\n');
+        if let Some(end) = end {
+            line.truncate(end);
+        }
+        if line.len() > MAX_REQUEST {
+            return Err(invalid(format!(
+                "request is larger than {} bytes",
+                MAX_REQUEST
+            )));
+        }
+        if n == 0 || end.is_some() {
+            break;
+        }
+    }
+
+    serde_json::from_slice(&line).map_err(invalid)
+}
+
+fn answer(stream: &UnixStream, allow_modify: bool) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let request = read_request(stream)?;
+    let command = request["command"].as_str().unwrap_or_default();
+
+    if !MODIFYING.contains(&command) {
+        let result = execute(command, &request, allow_modify);
+        return (&mut &*stream).write_all(format!("{}\n", response(&result)).as_bytes());
+    }
+
+    // Commands changing the platform are not issued unless they can be audited.
+    let (uid, pid) = peer(stream);
+    let mut audit = audit::Log::open();
+    let result = match &audit {
+        Ok(_) => execute(command, &request, allow_modify),
+        Err(e) => Err(Error::new(ErrorKind::Other, e.to_string())),
+    };
+    eprintln!(
+        "{} requested by uid {} (pid {}): {}",
+        command,
+        uid,
+        pid,
+        match &result {
+            Ok(_) => "done".to_string(),
+            Err(e) => e.to_string(),
+        }
+    );
+    if let Ok(log) = &mut audit {
+        if let Err(e) = log.record(&audit::request(uid, pid as u32, &request, &result)) {
+            eprintln!("{}", e);
+        }
+    }
+
+    let response = response(&result);
+    (&mut &*stream).write_all(format!("{}\n", response).as_bytes())
+}
+
+fn execute(command: &str, request: &Value, allow_modify: bool) -> std::io::Result<Value> {
+    if MODIFYING.contains(&command) && !allow_modify {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{} is not allowed by the helper (see --allow-modify)",
+                command
+            ),
+        ));
+    }
+
+    // The device is opened per request so that the helper may start before the
+    // firmware driver is loaded.
+    let mut fw = device::Local::open().map_err(|e| {
+        let path = device::path();
+        Error::new(
+            e.kind(),
+            format!("unable to open {}: {}", path.display(), e),
+        )
+    })?;
+
+    serve(&mut fw, request)
+}
+
+/// Issues the firmware command of a request, returning what the helper answers.
+pub fn serve(fw: &mut dyn Device, request: &Value) -> std::io::Result<Value> {
+    let command = request["command"].as_str().unwrap_or_default();
+    Ok(match command {
+        "PLATFORM_STATUS" => {
+            let status = fw.platform_status()?;
+            json!({
+                "api-major": status.build.version.major,
+                "api-minor": status.build.version.minor,
+                "build-id": status.build.build,
+                "state": status.state as u8,
+                "flags": status.flags.bits(),
+                "guests": status.guests,
+            })
+        }
+        "PDH_CERT_EXPORT" => encoded(&fw.pdh_cert_export()?)?,
+        "GET_ID" => Value::from(base64::encode(fw.get_identifier()?)),
+        "PLATFORM_RESET" => fw.platform_reset().map(|_| Value::Null)?,
+        "PDH_GEN" => fw.pdh_generate().map(|_| Value::Null)?,
+        "PEK_CSR" => encoded(&fw.pek_csr()?)?,
+        "PEK_CERT_IMPORT" => {
+            let pek = decoded(&request["pek"])?;
+            let oca = decoded(&request["oca"])?;
+            fw.pek_cert_import(&pek, &oca).map(|_| Value::Null)?
+        }
+        "SNP_PLATFORM_STATUS" => {
+            let status = fw.snp_platform_status()?;
+            json!({
+                "api-major": status.api_major,
+                "api-minor": status.api_minor,
+                "build-id": status.build,
+                "initialized": status.initialized,
+                "rmp-initialized": status.rmp_initialized,
+                "mask-chip-id": status.mask_chip_id,
+                "guests": status.guests,
+                "current-tcb": u64::from(status.current_tcb),
+                "reported-tcb": u64::from(status.reported_tcb),
+            })
+        }
+        "SNP_COMMIT" => fw.snp_commit().map(|_| Value::Null)?,
+        "SNP_SET_CONFIG" => {
+            let reported_tcb = field::<u64>(request, "reported-tcb")?.into();
+            let mask_chip_id = flag(request, "mask-chip-id")?;
+            fw.snp_set_config(reported_tcb, mask_chip_id)
+                .map(|_| Value::Null)?
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown command {:?}", command),
+            ))
+        }
+    })
+}
+
+/// The response to a request: `{"ok": ...}`, or `{"error": "..."}` with the
+/// firmware status code and the kernel's errno if any.
+pub fn response(result: &std::io::Result<Value>) -> Value {
+    let e = match result {
+        Ok(value) => return json!({ "ok": value }),
+        Err(e) => e,
+    };
+
+    let firmware = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<error::Firmware>());
+    let cause = firmware.map_or(e, |firmware| &firmware.cause);
+    let mut response = json!({ "error": cause.to_string() });
+    if let Some(firmware) = firmware {
+        response["status"] = firmware.status.into();
+    }
+    if let Some(errno) = cause.raw_os_error() {
+        response["errno"] = errno.into();
+    }
+    response
+}
+
+/// The result a response carries, with the error as the firmware returned it.
+pub fn result(mut response: Value) -> std::io::Result<Value> {
+    let message = match response["error"].as_str() {
+        Some(message) => message,
+        None => return Ok(response["ok"].take()),
+    };
+
+    let cause = match response["errno"].as_i64() {
+        Some(errno) => Error::from_raw_os_error(errno as i32),
+        None => Error::new(ErrorKind::Other, message.to_string()),
+    };
+    Err(match response["status"].as_u64() {
+        Some(status) => Error::new(
+            cause.kind(),
+            error::Firmware {
+                status: status as u32,
+                cause,
+            },
+        ),
+        None => cause,
+    })
+}
+
+/// The uid and pid of the process on the other end, for the log.
+fn peer(stream: &UnixStream) -> (libc::uid_t, libc::pid_t) {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    // SAFETY: `cred` is a ucred of `len` bytes, as SO_PEERCRED expects.
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    match ret {
+        0 => (cred.uid, cred.pid),
+        _ => (libc::uid_t::MAX, 0),
+    }
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+fn encoded(value: &impl Encoder<(), Error = Error>) -> std::io::Result<Value> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf, ())?;
+    Ok(Value::from(base64::encode(buf)))
+}
+
+fn decoded<T: Decoder<(), Error = Error>>(value: &Value) -> std::io::Result<T> {
+    let buf = value
+        .as_str()
+        .and_then(|v| base64::decode(v).ok())
+        .ok_or_else(|| invalid("invalid base64 value"))?;
+    T::decode(&mut &buf[..], ())
+}
+
+fn field<T: TryFrom<u64>>(value: &Value, name: &str) -> std::io::Result<T> {
+    value[name]
+        .as_u64()
+        .and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| invalid(format!("missing or invalid {}", name)))
+}
+
+fn flag(value: &Value, name: &str) -> std::io::Result<bool> {
+    value[name]
+        .as_bool()
+        .ok_or_else(|| invalid(format!("missing or invalid {}", name)))
+}
+
+/// Carries requests to the firmware and brings back what it answers.
+pub trait Transport: Send {
+    fn call(&mut self, request: Value) -> std::io::Result<Value>;
+}
+
+/// The helper listening on a unix socket.
+pub struct Socket(PathBuf);
+
+impl Transport for Socket {
+    fn call(&mut self, request: Value) -> std::io::Result<Value> {
+        let stream = UnixStream::connect(&self.0).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("unable to reach the helper at {}: {}", self.0.display(), e),
+            )
+        })?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        (&mut &stream).write_all(format!("{}\n", request).as_bytes())?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        result(serde_json::from_str(&line).map_err(invalid)?)
+    }
+}
+
+/// The firmware behind a helper, or anything else speaking its protocol.
+pub struct Client<T = Socket>(T);
+
+impl Client {
+    pub fn new(socket: PathBuf) -> Self {
+        Client(Socket(socket))
+    }
+}
+
+impl<T: Transport> Client<T> {
+    pub fn with(transport: T) -> Self {
+        Client(transport)
+    }
+
+    fn call(&mut self, request: Value) -> std::io::Result<Value> {
+        self.0.call(request)
+    }
+
+    fn command(&mut self, command: &str) -> std::io::Result<Value> {
+        self.call(json!({ "command": command }))
+    }
+}
+
+impl<T: Transport> Device for Client<T> {
+    fn platform_status(&mut self) -> std::io::Result<Status> {
+        use ::sev::firmware::{Flags, State};
+
+        let status = self.command("PLATFORM_STATUS")?;
+        Ok(Status {
+            build: ::sev::Build {
+                version: ::sev::Version {
+                    major: field(&status, "api-major")?,
+                    minor: field(&status, "api-minor")?,
+                },
+                build: field(&status, "build-id")?,
+            },
+            state: match field::<u8>(&status, "state")? {
+                0 => State::Uninitialized,
+                1 => State::Initialized,
+                2 => State::Working,
+                s => return Err(invalid(format!("unknown platform state {}", s))),
+            },
+            flags: Flags::from_bits_truncate(field(&status, "flags")?),
+            guests: field(&status, "guests")?,
+        })
+    }
+
+    fn pdh_cert_export(&mut self) -> std::io::Result<sev::Chain> {
+        decoded(&self.command("PDH_CERT_EXPORT")?)
+    }
+
+    fn get_identifier(&mut self) -> std::io::Result<Vec<u8>> {
+        self.command("GET_ID")?
+            .as_str()
+            .and_then(|id| base64::decode(id).ok())
+            .ok_or_else(|| invalid("invalid identifier"))
+    }
+
+    fn platform_reset(&mut self) -> std::io::Result<()> {
+        self.command("PLATFORM_RESET").map(|_| ())
+    }
+
+    fn pdh_generate(&mut self) -> std::io::Result<()> {
+        self.command("PDH_GEN").map(|_| ())
+    }
+
+    fn pek_csr(&mut self) -> std::io::Result<sev::Certificate> {
+        decoded(&self.command("PEK_CSR")?)
+    }
+
+    fn pek_cert_import(
+        &mut self,
+        pek: &sev::Certificate,
+        oca: &sev::Certificate,
+    ) -> std::io::Result<()> {
+        self.call(json!({
+            "command": "PEK_CERT_IMPORT",
+            "pek": encoded(pek)?,
+            "oca": encoded(oca)?,
+        }))
+        .map(|_| ())
+    }
+
+    fn snp_platform_status(&mut self) -> std::io::Result<platform::SnpStatus> {
+        let status = self.command("SNP_PLATFORM_STATUS")?;
+        Ok(platform::SnpStatus {
+            api_major: field(&status, "api-major")?,
+            api_minor: field(&status, "api-minor")?,
+            build: field(&status, "build-id")?,
+            initialized: flag(&status, "initialized")?,
+            rmp_initialized: flag(&status, "rmp-initialized")?,
+            mask_chip_id: flag(&status, "mask-chip-id")?,
+            guests: field(&status, "guests")?,
+            current_tcb: field::<u64>(&status, "current-tcb")?.into(),
+            reported_tcb: field::<u64>(&status, "reported-tcb")?.into(),
+        })
+    }
+
+    fn snp_commit(&mut self) -> std::io::Result<()> {
+        self.command("SNP_COMMIT").map(|_| ())
+    }
+
+    fn snp_set_config(
+        &mut self,
+        reported_tcb: platform::TcbVersion,
+        mask_chip_id: bool,
+    ) -> std::io::Result<()> {
+        self.call(json!({
+            "command": "SNP_SET_CONFIG",
+            "reported-tcb": u64::from(reported_tcb),
+            "mask-chip-id": mask_chip_id,
+        }))
+        .map(|_| ())
+    }
+}
@@ -0,0 +1,289 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal HTTP/1.1 server for sevctl's service modes.
+//!
+//! Every connection carries a single request, which is enough for the small
+//! documents these services return. Connections are served as tasks of an async
+//! runtime, so that slow clients or guests do not each hold a thread.
+
+use super::*;
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_openssl::SslStream;
+
+/// The largest request head accepted.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// How long a client may take to send its request.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A parsed request head.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Looks up a header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut start = lines.next()?.split(' ');
+        let method = start.next()?.to_string();
+        let path = start.next()?.to_string();
+
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+            .collect();
+
+        Some(Self {
+            method,
+            path,
+            headers,
+        })
+    }
+}
+
+/// A response to send back.
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self::ok("application/json", format!("{}\n", value).into_bytes())
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", message).into_bytes(),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Loads a PEM certificate (chain) and private key to serve HTTPS with.
+pub fn acceptor(cert: &Path, key: &Path) -> Result<SslAcceptor> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())
+        .context("unable to create TLS context")?;
+    builder
+        .set_certificate_chain_file(cert)
+        .context(format!("unable to load certificate {}", cert.display()))?;
+    builder
+        .set_private_key_file(key, SslFiletype::PEM)
+        .context(format!("unable to load private key {}", key.display()))?;
+    builder
+        .check_private_key()
+        .context("certificate does not match private key")?;
+    Ok(builder.build())
+}
+
+/// Serves requests on `addr`, or on the socket passed by systemd. On SIGTERM or
+/// SIGINT, stops accepting connections and returns once the open ones are answered.
+pub fn serve<F, R>(addr: &str, tls: Option<SslAcceptor>, handler: F) -> Result<()>
+where
+    F: Fn(Request) -> R + Send + Sync + 'static,
+    R: Future<Output = Response> + Send + 'static,
+{
+    let listener = match systemd::listener()? {
+        Some(listener) => listener,
+        None => {
+            std::net::TcpListener::bind(addr).context(format!("unable to listen on {}", addr))?
+        }
+    };
+    listener
+        .set_nonblocking(true)
+        .context("unable to set up the listening socket")?;
+
+    tokio::runtime::Runtime::new()
+        .context("unable to start async runtime")?
+        .block_on(accept(listener, tls, handler))
+}
+
+async fn accept<F, R>(
+    listener: std::net::TcpListener,
+    tls: Option<SslAcceptor>,
+    handler: F,
+) -> Result<()>
+where
+    F: Fn(Request) -> R + Send + Sync + 'static,
+    R: Future<Output = Response> + Send + 'static,
+{
+    let listener =
+        TcpListener::from_std(listener).context("unable to set up the listening socket")?;
+    let local = listener
+        .local_addr()
+        .context("unable to get the listening address")?;
+
+    let mut terminate = signal(SignalKind::terminate()).context("unable to handle SIGTERM")?;
+    let mut interrupt = signal(SignalKind::interrupt()).context("unable to handle SIGINT")?;
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    println!("listening on {}://{}", scheme, local);
+    systemd::notify("READY=1");
+
+    let tls = tls.map(Arc::new);
+    let handler = Arc::new(handler);
+    let mut connections = JoinSet::new();
+
+    loop {
+        let stream = tokio::select! {
+            _ = terminate.recv() => break,
+            _ = interrupt.recv() => break,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!("connection failed: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        let tls = tls.clone();
+        let handler = handler.clone();
+        connections.spawn(async move {
+            if let Err(e) = connection(stream, tls.as_deref(), &*handler).await {
+                eprintln!("{}", e);
+            }
+        });
+    }
+
+    systemd::notify("STOPPING=1");
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+async fn connection<F, R>(
+    mut stream: TcpStream,
+    tls: Option<&SslAcceptor>,
+    handler: &F,
+) -> Result<()>
+where
+    F: Fn(Request) -> R,
+    R: Future<Output = Response>,
+{
+    match tls {
+        Some(tls) => {
+            let handshake_failed = |e: &dyn std::fmt::Display| {
+                error::Context::new(
+                    &format!("TLS handshake failed: {}", e),
+                    Box::<Error>::new(ErrorKind::ConnectionAborted.into()),
+                )
+            };
+
+            let ssl = Ssl::new(tls.context()).map_err(|e| handshake_failed(&e))?;
+            let mut stream = SslStream::new(ssl, stream).map_err(|e| handshake_failed(&e))?;
+            match timeout(TIMEOUT, Pin::new(&mut stream).accept()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => return Err(handshake_failed(&e)),
+                Err(_) => return Err(handshake_failed(&"timed out")),
+            }
+            exchange(&mut stream, handler).await
+        }
+        None => exchange(&mut stream, handler).await,
+    }
+}
+
+async fn exchange<S, F, R>(stream: &mut S, handler: &F) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(Request) -> R,
+    R: Future<Output = Response>,
+{
+    let head = match timeout(TIMEOUT, read_head(stream)).await {
+        Ok(head) => head?,
+        Err(_) => {
+            return Err(error::Context::new(
+                "unable to read request",
+                Box::<Error>::new(ErrorKind::TimedOut.into()),
+            ))
+        }
+    };
+    let head = match head {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+
+    let response = match std::str::from_utf8(&head).ok().and_then(Request::parse) {
+        Some(request) if request.method == "GET" => handler(request).await,
+        Some(_) => Response::error(405, "only GET is supported"),
+        None => Response::error(400, "malformed request"),
+    };
+
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    )
+    .into_bytes();
+    out.extend_from_slice(&response.body);
+
+    stream
+        .write_all(&out)
+        .await
+        .context("unable to send response")?;
+    let _ = stream.shutdown().await;
+    Ok(())
+}
+
+/// Reads the request head, or `None` if the client hangs up or sends too much.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<Vec<u8>>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            head.truncate(end);
+            return Ok(Some(head));
+        }
+
+        let n = stream
+            .read(&mut buf)
+            .await
+            .context("unable to read request")?;
+        if n == 0 || head.len() + n > MAX_HEAD {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+}
@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The non-volatile state of platforms initialized with INIT_EX.
+//!
+//! With `ccp.init_ex_path` set, the ccp driver initializes the platform with
+//! INIT_EX, which keeps the firmware's non-volatile state (the OCA, PEK and PDH
+//! identity) in that file rather than in the SPI flash. The driver reads the file
+//! when it initializes the platform and writes it back after every command changing
+//! the state, so backups of it keep the platform identity across reinstalls.
+
+use super::*;
+use ::sev::firmware::State;
+
+/// The kernel parameter naming the state file, empty unless INIT_EX is used.
+const INIT_EX_PATH: &str = "/sys/module/ccp/parameters/init_ex_path";
+
+/// The size of the state file.
+const NV_SIZE: usize = 32 * 1024;
+
+#[derive(StructOpt)]
+pub enum InitEx {
+    #[structopt(about = "Show the INIT_EX state file and the platform state")]
+    Show {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "State file, defaults to the ccp driver's init_ex_path"
+        )]
+        file: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Have the ccp driver initialize the platform from the state file")]
+    Init,
+
+    #[structopt(about = "Copy the INIT_EX state file to a backup")]
+    Backup {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "State file, defaults to the ccp driver's init_ex_path"
+        )]
+        file: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str), help = "Backup file path")]
+        destination: PathBuf,
+    },
+
+    #[structopt(about = "Restore the INIT_EX state file from a backup")]
+    Restore {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "State file, defaults to the ccp driver's init_ex_path"
+        )]
+        file: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Restore even though the platform is initialized and may overwrite it"
+        )]
+        force: bool,
+
+        #[structopt(parse(from_os_str), help = "Backup file path")]
+        source: PathBuf,
+    },
+}
+
+pub fn cmd(cmd: InitEx) -> Result<()> {
+    match cmd {
+        InitEx::Show { file } => {
+            let path = state_file(file)?;
+            output::print(&describe(&path, &read(&path)?));
+            Ok(())
+        }
+
+        InitEx::Init => {
+            let path = state_file(None)?;
+
+            // The driver initializes the platform before exporting the PDH, with
+            // INIT_EX since init_ex_path is set.
+            let mut fw = firmware()?;
+            issue("PDH_CERT_EXPORT", || fw.pdh_cert_export())
+                .context("unable to initialize the platform")?;
+
+            output::print(&describe(&path, &read(&path)?));
+            Ok(())
+        }
+
+        InitEx::Backup { file, destination } => {
+            let path = state_file(file)?;
+            let data = read(&path)?;
+            session::write(&destination, &data, 0o600)?;
+            output::print(&describe(&destination, &data));
+            Ok(())
+        }
+
+        InitEx::Restore {
+            file,
+            force,
+            source,
+        } => {
+            let path = state_file(file)?;
+            let data = read(&source)?;
+
+            // The driver writes its copy of the state back after the next command
+            // changing it, undoing the restore until it initializes the platform again.
+            let initialized = platform_status().map_or(false, |s| s.state != State::Uninitialized);
+            if initialized && !force {
+                return Err(error::Context::new(
+                    "the platform is initialized and would overwrite the restored state",
+                    Box::new(Error::new(
+                        ErrorKind::InvalidInput,
+                        "restore before the ccp driver initializes it, or give --force",
+                    )),
+                ));
+            }
+
+            session::write(&path, &data, 0o600)?;
+            output::print(&describe(&path, &data));
+            Ok(())
+        }
+    }
+}
+
+/// The state file: `file` if given, or the one the ccp driver uses.
+fn state_file(file: Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(file) = file {
+        return Ok(file);
+    }
+
+    match std::fs::read_to_string(INIT_EX_PATH) {
+        Ok(path) if !path.trim().is_empty() => Ok(PathBuf::from(path.trim())),
+        _ => Err(error::Context::new(
+            "the ccp driver does not use INIT_EX",
+            Box::new(Error::new(
+                ErrorKind::NotFound,
+                "set ccp.init_ex_path, or give --file",
+            )),
+        )),
+    }
+}
+
+/// Reads a state file, checking its size.
+fn read(path: &Path) -> Result<Vec<u8>> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let data = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+    if data.len() != NV_SIZE {
+        return Err(error::Context::new(
+            &format!("{} is not an INIT_EX state file", path.display()),
+            Box::new(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} bytes instead of {}", data.len(), NV_SIZE),
+            )),
+        ));
+    }
+    Ok(data)
+}
+
+fn describe(path: &Path, data: &[u8]) -> schema::InitExState {
+    let digest = openssl::hash::hash(openssl::hash::MessageDigest::sha256(), data)
+        .map(|digest| hex(&digest))
+        .unwrap_or_default();
+
+    schema::InitExState {
+        path: path.to_path_buf(),
+        sha256: digest,
+        platform_state: platform_status()
+            .ok()
+            .map(|status| format!("{:?}", status.state).to_lowercase()),
+    }
+}
+
+impl output::Document for schema::InitExState {
+    fn table(&self) -> String {
+        format!(
+            "file: {}\nsha256: {}\nplatform state: {}\n",
+            self.path.display(),
+            self.sha256,
+            self.platform_state.as_deref().unwrap_or("unknown")
+        )
+    }
+}
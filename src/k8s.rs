@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Node capability reports for Kubernetes schedulers.
+//!
+//! The labels follow node-feature-discovery's `cpu-security` feature names, and the
+//! resources name the guest ASIDs the way a device plugin advertises them. SEV-ES and
+//! SEV-SNP guests draw from the same pool of ASIDs.
+
+use super::*;
+use std::str::FromStr;
+
+/// The prefix of node-feature-discovery labels.
+const NFD_PREFIX: &str = "feature.node.kubernetes.io/";
+
+#[derive(StructOpt)]
+pub enum K8s {
+    #[structopt(about = "Report the node's SEV capabilities and guest ASIDs")]
+    Resources {
+        #[structopt(
+            long,
+            possible_values = &["json", "nfd"],
+            help = "Output format: json, or nfd for a node-feature-discovery features.d file"
+        )]
+        format: Option<Format>,
+    },
+}
+
+/// The output format of `k8s resources`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Nfd,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        Ok(match s {
+            "json" => Format::Json,
+            "nfd" => Format::Nfd,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown format {} (expected json or nfd)", s),
+                ))
+            }
+        })
+    }
+}
+
+pub fn cmd(k8s: K8s) -> Result<()> {
+    match k8s {
+        K8s::Resources { format } => {
+            // --format predates --output, and is kept as its alias: nfd is the table form.
+            match format {
+                Some(Format::Json) => output::init(output::Format::Json),
+                Some(Format::Nfd) => output::init(output::Format::Table),
+                None => (),
+            }
+
+            output::print(&Node::probe().document());
+            Ok(())
+        }
+    }
+}
+
+/// The SEV capabilities of this node.
+struct Node {
+    sev: bool,
+    es: bool,
+    snp: bool,
+
+    /// ASIDs for SEV guests, and for SEV-ES and SEV-SNP guests.
+    sev_asids: u64,
+    es_asids: u64,
+
+    /// The ASIDs not in use, if the kernel reports usage.
+    sev_free: Option<u64>,
+    es_free: Option<u64>,
+}
+
+impl Node {
+    fn probe() -> Self {
+        let cpuid = platform::cpuid();
+        let cpu = |f: fn(&platform::Cpuid) -> bool| cpuid.as_ref().map_or(false, f);
+
+        // The kernel's ASID accounting reflects what KVM actually set up, so it takes
+        // precedence over the CPU's limits.
+        let asids = platform::asids().unwrap_or_default();
+        let pool = |kind: &str| asids.iter().find(|a| a.kind == kind);
+        let (sev_asids, es_asids) = match (pool("sev"), pool("sev_es")) {
+            (Some(sev), Some(es)) => (sev.capacity, es.capacity),
+            _ => match &cpuid {
+                Some(c) => (
+                    (c.asids + 1).saturating_sub(c.min_sev_asid).into(),
+                    c.min_sev_asid.saturating_sub(1).into(),
+                ),
+                None => (0, 0),
+            },
+        };
+        let free = |kind: &str| pool(kind).and_then(|a| Some(a.capacity.saturating_sub(a.used?)));
+
+        let sev = cpu(|c| c.sev) && platform::kvm_param("sev");
+        let es = sev && cpu(|c| c.es) && platform::kvm_param("sev_es");
+        let snp = es && cpu(|c| c.snp) && platform::kvm_param("sev_snp");
+
+        Self {
+            sev,
+            es,
+            snp,
+            sev_asids: if sev { sev_asids } else { 0 },
+            es_asids: if es { es_asids } else { 0 },
+            sev_free: free("sev"),
+            es_free: free("sev_es"),
+        }
+    }
+
+    /// The node-feature-discovery labels.
+    fn labels(&self) -> Vec<(String, String)> {
+        let label =
+            |name: &str, value: String| (format!("{}cpu-security.{}", NFD_PREFIX, name), value);
+
+        vec![
+            label("sev.enabled", self.sev.to_string()),
+            label("sev.es.enabled", self.es.to_string()),
+            label("sev.snp.enabled", self.snp.to_string()),
+            label("sev.asids", self.sev_asids.to_string()),
+            label("sev.encrypted_state_ids", self.es_asids.to_string()),
+        ]
+    }
+
+    /// The ASIDs a device plugin would advertise, by resource name.
+    fn asids(&self, sev: u64, es: u64) -> schema::Asids {
+        schema::Asids {
+            sev,
+            sev_es: es,
+            sev_snp: if self.snp { es } else { 0 },
+        }
+    }
+
+    fn document(&self) -> schema::NodeResources {
+        let available = match (self.sev_free, self.es_free) {
+            (Some(sev), Some(es)) => {
+                Some(self.asids(if self.sev { sev } else { 0 }, if self.es { es } else { 0 }))
+            }
+            _ => None,
+        };
+
+        schema::NodeResources {
+            labels: self.labels().into_iter().collect(),
+            resources: schema::Resources {
+                capacity: self.asids(self.sev_asids, self.es_asids),
+                available,
+            },
+        }
+    }
+}
+
+/// The table form is a node-feature-discovery `features.d` file.
+impl output::Document for schema::NodeResources {
+    fn table(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(name, value)| format!("{}={}\n", name, value))
+            .collect()
+    }
+}
@@ -0,0 +1,279 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A client for the Confidential Containers key broker service (KBS) protocol.
+//!
+//! The guest requests a challenge (`/auth`), answers it with an attestation report
+//! binding the challenge nonce and an ephemeral public key (`/attest`), and then
+//! fetches resources, which the KBS returns encrypted to that key.
+
+use super::*;
+#[cfg(feature = "network")]
+use openssl::{pkey, rsa, symm};
+#[cfg(feature = "network")]
+use serde_json::{json, Value};
+
+/// The KBS protocol version spoken.
+#[cfg(feature = "network")]
+const PROTOCOL_VERSION: &str = "0.1.0";
+
+/// The size of the ephemeral RSA key resources are encrypted to.
+#[cfg(feature = "network")]
+const TEE_KEY_BITS: u32 = 2048;
+
+#[derive(StructOpt)]
+pub enum Kbs {
+    #[structopt(about = "Attest this SEV-SNP guest to a KBS and fetch a resource")]
+    GetResource {
+        #[structopt(long, help = "Base URL of the KBS (e.g. https://kbs.example.com:8080)")]
+        url: String,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "PEM CA certificate to trust for the KBS, in addition to the system ones"
+        )]
+        cacert: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "File to write the resource to (with mode 0600) instead of stdout"
+        )]
+        output: Option<PathBuf>,
+
+        #[structopt(help = "Resource to fetch, as <repository>/<type>/<tag>")]
+        resource: String,
+    },
+}
+
+pub fn cmd(kbs: Kbs) -> Result<()> {
+    match kbs {
+        Kbs::GetResource {
+            url,
+            cacert,
+            output,
+            resource,
+        } => {
+            if resource.split('/').count() != 3 || resource.split('/').any(str::is_empty) {
+                return Err(invalid(&format!(
+                    "invalid resource {}, expected <repository>/<type>/<tag>",
+                    resource
+                )));
+            }
+
+            let data = get_resource(&url, cacert.as_deref(), &resource)?;
+
+            match output {
+                Some(path) => session::write(&path, &data, 0o600),
+                None => std::io::stdout()
+                    .write_all(&data)
+                    .context("unable to write resource"),
+            }
+        }
+    }
+}
+
+/// Attests this guest to the KBS at `url` and fetches a resource.
+#[cfg(feature = "network")]
+fn get_resource(url: &str, cacert: Option<&Path>, resource: &str) -> Result<secure::Secret> {
+    let mut client = Client::new(url, cacert)?;
+    client.attest()?;
+    client.resource(resource)
+}
+
+#[cfg(not(feature = "network"))]
+fn get_resource(url: &str, _cacert: Option<&Path>, _resource: &str) -> Result<secure::Secret> {
+    Err(offline(&format!("unable to reach the KBS at {}", url)))
+}
+
+/// A KBS session.
+#[cfg(feature = "network")]
+struct Client {
+    http: reqwest::blocking::Client,
+    base: String,
+    key: rsa::Rsa<pkey::Private>,
+    cookie: Option<String>,
+}
+
+#[cfg(feature = "network")]
+impl Client {
+    fn new(url: &str, cacert: Option<&Path>) -> Result<Self> {
+        let mut builder = reqwest::blocking::Client::builder();
+        if let Some(path) = cacert {
+            tracing::debug!(path = %path.display(), "reading file");
+            let pem = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+            let cert = reqwest::Certificate::from_pem(&pem)
+                .context(format!("invalid CA certificate {}", path.display()))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if let Some(proxy) = config::get().proxy()? {
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(Self {
+            http: builder.build().context("unable to create HTTP client")?,
+            base: format!("{}/kbs/v0", url.trim_end_matches('/')),
+            key: rsa::Rsa::generate(TEE_KEY_BITS).context("unable to generate TEE key")?,
+            cookie: None,
+        })
+    }
+
+    /// Answers the KBS challenge with an attestation report.
+    fn attest(&mut self) -> Result<()> {
+        let challenge = self.post(
+            "auth",
+            &json!({
+                "version": PROTOCOL_VERSION,
+                "tee": "snp",
+                "extra-params": "",
+            }),
+        )?;
+        let nonce = challenge["nonce"]
+            .as_str()
+            .ok_or_else(|| invalid("KBS challenge has no nonce"))?;
+
+        // The report binds the nonce and the key, serialized the way the KBS does.
+        let key = self.public_key();
+        let runtime = format!(
+            r#"{{"nonce":{},"tee-pubkey":{{"kty":{},"alg":{},"n":{},"e":{}}}}}"#,
+            Value::from(nonce),
+            key["kty"],
+            key["alg"],
+            key["n"],
+            key["e"]
+        );
+
+        let mut data = [0u8; 64];
+        data[..48].copy_from_slice(
+            &openssl::hash::hash(openssl::hash::MessageDigest::sha384(), runtime.as_bytes())
+                .context("unable to hash runtime data")?,
+        );
+        let report = report::request(&data)?;
+
+        let evidence = json!({
+            "attestation_report": report.document(),
+            "cert_chain": null,
+        });
+
+        self.post(
+            "attest",
+            &json!({
+                "tee-pubkey": key,
+                "tee-evidence": evidence.to_string(),
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Fetches and decrypts a resource.
+    fn resource(&self, path: &str) -> Result<secure::Secret> {
+        let url = format!("{}/resource/{}", self.base, path);
+        let mut request = self.http.get(&url);
+        if let Some(cookie) = &self.cookie {
+            request = request.header("Cookie", cookie.as_str());
+        }
+
+        tracing::info!(%url, "sending request");
+        let response = request.send().context(format!("unable to fetch {}", url))?;
+        let jwe = Self::json(response, "resource")?;
+        self.decrypt(&jwe)
+    }
+
+    /// The ephemeral public key as a JSON web key.
+    fn public_key(&self) -> Value {
+        json!({
+            "kty": "RSA",
+            "alg": "RSA1_5",
+            "n": base64::encode_config(self.key.n().to_vec(), base64::URL_SAFE_NO_PAD),
+            "e": base64::encode_config(self.key.e().to_vec(), base64::URL_SAFE_NO_PAD),
+        })
+    }
+
+    /// Decrypts a resource returned in the JWE JSON serialization (RSA1_5, A256GCM).
+    fn decrypt(&self, jwe: &Value) -> Result<secure::Secret> {
+        let field = |name: &str| -> Result<Vec<u8>> {
+            let value = jwe[name]
+                .as_str()
+                .ok_or_else(|| invalid(&format!("KBS resource has no {}", name)))?;
+            base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+                .or_else(|_| base64::decode(value))
+                .map_err(|_| invalid(&format!("KBS resource has an invalid {}", name)))
+        };
+
+        let header: Value = serde_json::from_slice(&field("protected")?)
+            .map_err(|_| invalid("KBS resource has an invalid protected header"))?;
+        if header["alg"] != "RSA1_5" || header["enc"] != "A256GCM" {
+            return Err(invalid(&format!(
+                "unsupported KBS resource encryption {}",
+                header
+            )));
+        }
+
+        let wrapped = field("encrypted_key")?;
+        let mut cek = secure::Secret::new(self.key.size() as usize);
+        let len = self
+            .key
+            .private_decrypt(&wrapped, &mut cek, rsa::Padding::PKCS1)
+            .context("unable to unwrap the resource key")?;
+
+        // The protected header is authenticated as additional data, as JWE specifies.
+        let aad = jwe["protected"].as_str().unwrap_or("").as_bytes();
+        symm::decrypt_aead(
+            symm::Cipher::aes_256_gcm(),
+            &cek[..len],
+            Some(&field("iv")?),
+            aad,
+            &field("ciphertext")?,
+            &field("tag")?,
+        )
+        .map(secure::Secret::take)
+        .context("unable to decrypt the resource")
+    }
+
+    fn post(&mut self, endpoint: &str, body: &Value) -> Result<Value> {
+        let url = format!("{}/{}", self.base, endpoint);
+        let mut request = self
+            .http
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        if let Some(cookie) = &self.cookie {
+            request = request.header("Cookie", cookie.as_str());
+        }
+
+        tracing::info!(%url, "sending request");
+        let response = request.send().context(format!("unable to reach {}", url))?;
+
+        // The KBS tracks the session in a cookie.
+        if let Some(cookie) = response.headers().get("Set-Cookie") {
+            let cookie = cookie.to_str().unwrap_or("");
+            self.cookie = cookie.split(';').next().map(str::to_string);
+        }
+
+        Self::json(response, endpoint)
+    }
+
+    fn json(response: reqwest::blocking::Response, what: &str) -> Result<Value> {
+        let status = response.status();
+        let text = response
+            .text()
+            .context(format!("unable to read KBS {} response", what))?;
+
+        if !status.is_success() {
+            return Err(error::Context::new(
+                &format!("KBS {} failed with {}: {}", what, status, text.trim()),
+                Box::<Error>::new(ErrorKind::Other.into()),
+            ));
+        }
+
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+
+        serde_json::from_str(&text).context(format!("invalid KBS {} response", what))
+    }
+}
+
+fn invalid(msg: &str) -> error::Context {
+    error::Context::new(msg, Box::<Error>::new(ErrorKind::InvalidData.into()))
+}
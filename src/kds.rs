@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The SEV-SNP certificates of AMD's Key Distribution Service (KDS).
+//!
+//! The VCEK signing a platform's attestation reports is derived from its chip ID
+//! and TCB version, and the KDS serves it at
+//! `/vcek/v1/{product}/{chip id}?blSPL=..&teeSPL=..&snpSPL=..&ucodeSPL=..`, signed by
+//! the ASK and ARK of the processor generation at `/vcek/v1/{product}/cert_chain`.
+
+use super::*;
+use openssl::x509::X509;
+use std::str::FromStr;
+
+/// The product names the KDS knows, for `possible_values`.
+pub const PRODUCTS: &[&str] = &["Milan", "Genoa", "Turin"];
+
+/// The size of a chip ID.
+pub const CHIP_ID_SIZE: usize = 64;
+
+/// A processor generation, as named in KDS URLs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Product {
+    Milan,
+    Genoa,
+    Turin,
+}
+
+impl FromStr for Product {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "milan" => Ok(Product::Milan),
+            "genoa" => Ok(Product::Genoa),
+            "turin" => Ok(Product::Turin),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown product {} (expected {})", s, PRODUCTS.join(", ")),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Product {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Product::Milan => "Milan",
+            Product::Genoa => "Genoa",
+            Product::Turin => "Turin",
+        })
+    }
+}
+
+impl Product {
+    /// The generation of the local processor, if it supports SEV-SNP.
+    pub fn local() -> Option<Self> {
+        platform::Generation::local()?.product()
+    }
+
+    /// `given`, or the generation of the local processor.
+    pub fn or_local(given: Option<Self>) -> Result<Self> {
+        if let Some(product) = given {
+            return Ok(product);
+        }
+
+        let generation = platform::Generation::or_local(None, "--product")?;
+        generation.product().ok_or_else(|| {
+            error::Context::new(
+                &format!("the KDS has no SEV-SNP certificates for {}", generation),
+                Box::new(Error::new(ErrorKind::Unsupported, "give --product")),
+            )
+        })
+    }
+
+    /// The generation whose ASK issued `vcek`, in PEM or DER format, if the ASK's
+    /// name (e.g. `SEV-Milan`) tells.
+    pub fn issuer(vcek: &[u8]) -> Result<Option<Self>> {
+        let name = sandbox::run("VCEK", || {
+            let vcek = X509::from_pem(vcek)
+                .or_else(|_| X509::from_der(vcek))
+                .context("unable to decode the VCEK")?;
+            Ok(vcek
+                .issuer_name()
+                .entries_by_nid(openssl::nid::Nid::COMMONNAME)
+                .next()
+                .and_then(|entry| entry.data().as_utf8().ok())
+                .map(|name| name.to_string()))
+        })?;
+
+        Ok(name.and_then(|name| name.rsplit('-').next()?.parse().ok()))
+    }
+}
+
+/// The URL of the VCEK of the chip `id` at the TCB version `tcb`.
+pub fn vcek_url(product: Product, id: &[u8], tcb: platform::TcbVersion) -> String {
+    format!(
+        "{}/vcek/v1/{}/{}?blSPL={:02}&teeSPL={:02}&snpSPL={:02}&ucodeSPL={:02}",
+        config::get().kds_url(),
+        product,
+        hex(id),
+        tcb.bootloader,
+        tcb.tee,
+        tcb.snp,
+        tcb.microcode
+    )
+}
+
+/// Downloads the VCEK of the chip `id` at the TCB version `tcb` in DER format, or
+/// reads it from the cache directory.
+pub fn vcek(product: Product, id: &[u8], tcb: platform::TcbVersion) -> Result<Vec<u8>> {
+    let cache = cached(product, id, tcb);
+    if let Some(path) = cache.as_ref().filter(|path| path.exists()) {
+        tracing::debug!(path = %path.display(), "using cached VCEK");
+        return std::fs::read(path).context(format!("unable to read {}", path.display()));
+    }
+
+    let der = fetch(&vcek_url(product, id, tcb), "VCEK")?;
+    keep(&der, cache.as_deref())?;
+    Ok(der)
+}
+
+/// [`vcek`] and [`cert_chain`], downloaded at once unless either is cached.
+pub fn vcek_and_chain(
+    product: Product,
+    id: &[u8],
+    tcb: platform::TcbVersion,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cache = cached(product, id, tcb);
+    let chain_cached = config::get()
+        .cache_dir
+        .map_or(false, |dir| cert_chain_path(&dir, product).exists());
+    if chain_cached || cache.as_ref().map_or(false, |path| path.exists()) {
+        return Ok((vcek(product, id, tcb)?, cert_chain(product)?));
+    }
+
+    let mut bodies = fetch_all(vec![
+        (vcek_url(product, id, tcb), "VCEK".into()),
+        (cert_chain_url(product), "certificate chain".into()),
+    ])?;
+    let chain = bodies.pop().unwrap();
+    let der = bodies.pop().unwrap();
+    keep(&der, cache.as_deref())?;
+    Ok((der, chain))
+}
+
+/// Where the VCEK of the chip `id` at the TCB version `tcb` is cached, if
+/// `cache-dir` is configured.
+fn cached(product: Product, id: &[u8], tcb: platform::TcbVersion) -> Option<PathBuf> {
+    config::get()
+        .cache_dir
+        .map(|dir| vcek_path(&dir, product, id, tcb))
+}
+
+/// Where the VCEK of the chip `id` at the TCB version `tcb` is kept in the cache
+/// directory `dir`.
+pub fn vcek_path(dir: &Path, product: Product, id: &[u8], tcb: platform::TcbVersion) -> PathBuf {
+    dir.join("vcek").join(product.to_string()).join(format!(
+        "{}-{:016x}.der",
+        hex(id),
+        u64::from(tcb)
+    ))
+}
+
+/// Where the ASK and ARK of a processor generation are kept in the cache
+/// directory `dir`.
+pub fn cert_chain_path(dir: &Path, product: Product) -> PathBuf {
+    dir.join("cert_chain").join(format!("{}.pem", product))
+}
+
+/// Checks that a downloaded VCEK parses, and caches it at `cache`.
+fn keep(der: &[u8], cache: Option<&Path>) -> Result<()> {
+    sandbox::run("VCEK", || {
+        X509::from_der(der)
+            .map(|_| ())
+            .context("unable to parse downloaded VCEK")
+    })?;
+    if let Some(path) = cache {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+        }
+        std::fs::write(path, der).context(format!("unable to write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Downloads the ASK and ARK of a processor generation, in that order, in PEM
+/// format, or reads them from the cache directory (see `certs sync`).
+pub fn cert_chain(product: Product) -> Result<Vec<u8>> {
+    let cache = config::get()
+        .cache_dir
+        .map(|dir| cert_chain_path(&dir, product));
+    if let Some(path) = cache.as_ref().filter(|path| path.exists()) {
+        tracing::debug!(path = %path.display(), "using cached certificate chain");
+        return std::fs::read(path).context(format!("unable to read {}", path.display()));
+    }
+
+    fetch(&cert_chain_url(product), "certificate chain")
+}
+
+/// The URL of the ASK and ARK of a processor generation.
+pub fn cert_chain_url(product: Product) -> String {
+    format!("{}/vcek/v1/{}/cert_chain", config::get().kds_url(), product)
+}
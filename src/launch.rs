@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The guest owner's side of an SEV launch, driven over QMP.
+
+use super::*;
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(StructOpt)]
+pub enum Launch {
+    #[structopt(about = "Verify a paused guest's measurement, inject its secret and resume it")]
+    Attest {
+        #[structopt(long, parse(from_os_str), help = "QMP socket of the guest")]
+        qmp: PathBuf,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Directory holding the launch session created by session create"
+        )]
+        session: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Prefix of the session's file names (session create --name)"
+        )]
+        name: Option<String>,
+
+        #[structopt(flatten)]
+        inputs: measurement::Inputs,
+
+        #[structopt(flatten)]
+        domain: domain::DomainArgs,
+
+        #[structopt(flatten)]
+        context: measurement::ContextArgs,
+
+        #[structopt(flatten)]
+        secret: secret::SecretArgs,
+    },
+}
+
+pub fn cmd(launch: Launch) -> Result<()> {
+    match launch {
+        Launch::Attest {
+            qmp,
+            session,
+            name,
+            mut inputs,
+            domain,
+            mut context,
+            secret,
+        } => {
+            let path = |file: &str| session::artifact(&session, name.as_deref(), file);
+
+            let tik = context.tik.get_or_insert_with(|| path("tik.bin")).clone();
+            let tik = session::read_key(&tik, context.in_form)?;
+            let tek = session::read_key(&path("tek.bin"), context.in_form)?;
+            let secret = secret.data(inputs.firmware.as_deref())?;
+
+            domain.apply(&mut inputs, &mut context)?;
+            context.platform()?;
+
+            let mut log = Log::open(&path("launch.log"))?;
+            let mut qmp = qmp::Qmp::connect(&qmp)?;
+
+            let info = qmp.query_sev()?;
+            log.handle = info.handle;
+            log.record(&format!("connected, guest state {}", info.state))?;
+
+            match info.state.as_str() {
+                "running" => {
+                    log.record("guest is already running, nothing to do")?;
+                    output::print(&log.document());
+                    return Ok(());
+                }
+                "launch-secret" => (),
+                state => {
+                    return Err(error::Context::new(
+                        &format!("guest is not waiting for a launch secret (state {})", state),
+                        Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                    ))
+                }
+            }
+
+            let measurement = context.query(&mut qmp)?;
+            log.record(&format!("launch measurement {}", measurement))?;
+
+            // Refuse to release the secret to a guest that is not the expected one.
+            if let Err(e) = measurement::check(&measurement, &inputs, &context) {
+                log.record("measurement mismatch, secret withheld")?;
+                return Err(e);
+            }
+            log.record("measurement verified")?;
+
+            // A previous run may have injected the secret and failed to resume the guest.
+            // The measurement includes a fresh nonce, so it identifies this launch.
+            let injected = format!("secret injected for {}", measurement);
+            if log.done(&injected)? {
+                log.record("secret already injected, skipping injection")?;
+            } else {
+                let measurement = encoding::decode(
+                    encoding::Form::Base64,
+                    measurement.as_bytes(),
+                    measurement::LAUNCH_MEASURE_LEN,
+                    "launch measurement",
+                )?;
+
+                let (header, data) = secret::build(&tek, &tik, &measurement[..32], &secret)?;
+                qmp.sev_inject_launch_secret(&header, &data)?;
+                log.record(&injected)?;
+            }
+
+            qmp.cont()?;
+            log.record("guest resumed")?;
+            output::print(&log.document());
+            Ok(())
+        }
+    }
+}
+
+/// The launch log kept in the session directory, which lets a failed launch be
+/// resumed without repeating completed steps for the same guest.
+struct Log {
+    path: PathBuf,
+    file: File,
+    handle: u32,
+
+    /// The steps recorded by this run.
+    steps: Vec<String>,
+}
+
+impl Log {
+    fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("unable to open {}", path.display()))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            handle: 0,
+            steps: Vec::new(),
+        })
+    }
+
+    /// Appends a step to the log, tagged with the time and guest handle.
+    fn record(&mut self, step: &str) -> Result<()> {
+        tracing::info!(handle = self.handle, "{}", step);
+        self.steps.push(step.to_string());
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        writeln!(self.file, "{} handle={} {}", time, self.handle, step)
+            .context(format!("unable to write {}", self.path.display()))
+    }
+
+    /// The steps recorded by this run.
+    fn document(&self) -> schema::LaunchAttest {
+        schema::LaunchAttest {
+            handle: self.handle,
+            steps: self.steps.clone(),
+        }
+    }
+
+    /// Whether the log records a step for the current guest.
+    fn done(&self, step: &str) -> Result<bool> {
+        let file =
+            File::open(&self.path).context(format!("unable to open {}", self.path.display()))?;
+        let tag = format!("handle={} {}", self.handle, step);
+
+        for line in BufReader::new(file).lines() {
+            let line = line.context(format!("unable to read {}", self.path.display()))?;
+            if line.split_once(' ').map(|(_, rest)| rest) == Some(tag.as_str()) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+impl output::Document for schema::LaunchAttest {
+    fn table(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| format!("{}\n", step))
+            .collect()
+    }
+}
@@ -0,0 +1,905 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The library behind the `sevctl` command line utility, for embedding its workflows in other
+//! Rust programs instead of running the binary.
+//!
+//! The firmware is reached through [`firmware()`], directly or through `sevctl helper`, and
+//! [`chain()`] assembles the platform's certificate chain. The workflows of the subcommands live
+//! in the module of the same name, e.g. [`measurement::build`] computes a launch measurement,
+//! [`session::Artifacts`] are the launch session files and [`secret`] packages launch secrets.
+//! Each module's `cmd` function runs the subcommand as the binary does, printing its results.
+//!
+//! ```no_run
+//! # fn main() -> sevctl::error::Result<()> {
+//! let chain = sevctl::chain()?;
+//! let ca = sevctl::ca_chain_builtin(&chain)?;
+//! assert!(sevctl::verify::chains(&chain, &ca, true));
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(clippy::all)]
+
+pub mod agent;
+pub mod appraisal;
+pub mod attestation;
+pub mod audit;
+pub mod bench;
+pub mod bundle;
+pub mod certs;
+pub mod codes;
+pub mod config;
+#[cfg(feature = "grpc")]
+pub mod daemon;
+pub mod device;
+pub mod doctor;
+pub mod domain;
+mod encoding;
+pub mod error;
+pub mod exporter;
+pub mod firmware;
+pub mod guid;
+pub mod helper;
+mod http;
+pub mod initex;
+pub mod k8s;
+pub mod kbs;
+pub mod kds;
+pub mod launch;
+#[cfg(feature = "libvirt")]
+pub mod libvirt;
+pub mod man;
+pub mod measurement;
+pub mod migration;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod nonce;
+pub mod ok;
+pub mod output;
+pub mod ovmf;
+pub mod platform;
+pub mod policy;
+pub mod progress;
+pub mod qmp;
+pub mod replay;
+pub mod report;
+pub mod sandbox;
+pub mod schema;
+pub mod secret;
+pub mod secure;
+pub mod serve;
+pub mod session;
+pub mod snp;
+mod systemd;
+pub mod tcb;
+pub mod trace;
+mod vcpu;
+pub mod vmsa;
+mod vsock;
+
+use error::{Contextual, Result};
+
+use structopt::StructOpt;
+
+use codicon::*;
+
+use ::sev::certs::*;
+use ::sev::firmware::Status;
+use ::sev::Generation;
+
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The version of sevctl.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Runs a future to completion for blocking callers, on the runtime whose blocking
+/// thread the caller is (e.g. a gRPC handler's), or else on a runtime of its own.
+fn block_on<T>(future: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => handle.block_on(future),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("unable to start async runtime")?
+            .block_on(future),
+    }
+}
+
+fn fetch(url: &str, what: &str) -> Result<Vec<u8>> {
+    block_on(fetch_async(url, what))
+}
+
+/// Fetches every `(url, what)` of `requests` at once, sharing the connections of
+/// one client, and returns the bodies in the same order.
+fn fetch_all(requests: Vec<(String, String)>) -> Result<Vec<Vec<u8>>> {
+    block_on(fetch_all_async(requests))
+}
+
+#[cfg(feature = "network")]
+fn client() -> Result<reqwest::Client> {
+    let mut client = reqwest::Client::builder();
+    if let Some(proxy) = config::get().proxy()? {
+        client = client.proxy(proxy);
+    }
+    client.build().context("unable to create HTTP client")
+}
+
+#[cfg(feature = "network")]
+async fn fetch_async(url: &str, what: &str) -> Result<Vec<u8>> {
+    fetch_with(&client()?, url, what).await
+}
+
+#[cfg(feature = "network")]
+async fn fetch_all_async(requests: Vec<(String, String)>) -> Result<Vec<Vec<u8>>> {
+    let client = client()?;
+    let whats: Vec<_> = requests.iter().map(|(_, what)| what.clone()).collect();
+    let _progress = progress::start(&format!("Downloading the {}", whats.join(" and the ")));
+
+    let tasks: Vec<_> = requests
+        .into_iter()
+        .map(|(url, what)| {
+            let client = client.clone();
+            tokio::spawn(async move { fetch_with(&client, &url, &what).await })
+        })
+        .collect();
+
+    let mut bodies = Vec::new();
+    for task in tasks {
+        bodies.push(task.await.context("download task failed")??);
+    }
+    Ok(bodies)
+}
+
+#[cfg(feature = "network")]
+#[tracing::instrument(name = "fetch", skip(client), err)]
+async fn fetch_with(client: &reqwest::Client, url: &str, what: &str) -> Result<Vec<u8>> {
+    let progress = progress::start(&format!("Downloading the {}", what));
+    tracing::info!("sending request");
+    let mut rsp = client.get(url).send().await;
+    let mut http_request_replies = Vec::new();
+    for request_wait_seconds in &[0, 2, 4, 6, 9] {
+        tokio::time::sleep(Duration::from_secs(*request_wait_seconds)).await;
+        match &rsp {
+            // HTTP request has succeeded, ensure that the status code does not indicate an error.
+            Ok(found) => {
+                if found.status().is_success() {
+                    break;
+                } else {
+                    tracing::debug!(status = %found.status(), "retrying request");
+                    http_request_replies.push(format!(
+                        "Attempt #{}, Error: Received HTTP response #{}",
+                        http_request_replies.len() + 1,
+                        found.status()
+                    ));
+                    rsp = client.get(url).send().await;
+                }
+            }
+            // HTTP request has failed.
+            Err(_) => break,
+        }
+    }
+    let mut rsp = rsp.context(format!(
+        "Failed to complete request: {}\nError codes received from server:\n{}",
+        what,
+        http_request_replies.join("\n")
+    ))?;
+
+    let total = rsp.content_length();
+    let mut buf = Vec::new();
+    while let Some(chunk) = rsp
+        .chunk()
+        .await
+        .context(format!("unable to complete {} download", what))?
+    {
+        buf.extend_from_slice(&chunk);
+        progress.bytes(buf.len() as u64, total);
+    }
+
+    Ok(buf)
+}
+
+#[cfg(not(feature = "network"))]
+async fn fetch_async(url: &str, what: &str) -> Result<Vec<u8>> {
+    tracing::debug!(url, "not fetching");
+    Err(offline(&format!("unable to download the {}", what)))
+}
+
+#[cfg(not(feature = "network"))]
+async fn fetch_all_async(requests: Vec<(String, String)>) -> Result<Vec<Vec<u8>>> {
+    let mut bodies = Vec::new();
+    for (url, what) in requests {
+        bodies.push(fetch_async(&url, &what).await?);
+    }
+    Ok(bodies)
+}
+
+/// The error of what needs the network, in builds without it.
+#[cfg(not(feature = "network"))]
+fn offline(context: &str) -> error::Context {
+    error::Context::new(
+        context,
+        Box::new(Error::new(
+            ErrorKind::Unsupported,
+            "sevctl was built without network support",
+        )),
+    )
+    .with_kind(error::Kind::Network)
+}
+
+async fn download(url: &str, usage: Usage) -> Result<sev::Certificate> {
+    let buf = fetch_async(url, &usage.to_string()).await?;
+
+    sev::Certificate::decode(&mut &buf[..], ())
+        .context(format!("unable to parse downloaded {}", usage))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_u64(value: &str) -> std::result::Result<u64, std::num::ParseIntError> {
+    if value.starts_with("0x") || value.starts_with("0X") {
+        u64::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    }
+}
+
+fn unhex(s: &str) -> std::io::Result<Vec<u8>> {
+    let s = s.trim();
+    if s.len() % 2 != 0 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::new(ErrorKind::InvalidData, "not a hex string"));
+    }
+
+    Ok((0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+/// Opens the SEV firmware, see [`device::open`].
+pub fn firmware() -> Result<Box<dyn device::Device>> {
+    device::open()
+}
+
+/// Issues a firmware command, tracing it in a span named after it.
+fn issue<T>(name: &str, command: impl FnOnce() -> std::io::Result<T>) -> std::io::Result<T> {
+    let _span = tracing::info_span!("firmware", command = name).entered();
+    tracing::info!("issuing firmware command");
+    command().map_err(|e| {
+        tracing::error!(error = %e);
+        e
+    })
+}
+
+/// Fetches the SEV platform status.
+pub fn platform_status() -> Result<Status> {
+    let mut fw = firmware()?;
+    issue("PLATFORM_STATUS", || fw.platform_status()).context("unable to fetch platform status")
+}
+
+/// Fetches the SEV-SNP platform status.
+pub fn snp_status() -> Result<platform::SnpStatus> {
+    let mut fw = firmware()?;
+    issue("SNP_PLATFORM_STATUS", || fw.snp_platform_status())
+        .context("unable to fetch SNP platform status")
+}
+
+/// Exports the platform's SEV certificate chain, with the CEK downloaded from AMD.
+pub fn chain() -> Result<sev::Chain> {
+    block_on(chain_async())
+}
+
+/// [`chain()`] for async callers.
+pub async fn chain_async() -> Result<sev::Chain> {
+    let (mut chain, id) = tokio::task::spawn_blocking(|| -> Result<_> {
+        let mut fw = firmware()?;
+        let chain = issue("PDH_CERT_EXPORT", || fw.pdh_cert_export())
+            .context("unable to export SEV certificates")?;
+        let id = issue("GET_ID", || fw.get_identifier()).context("error fetching identifier")?;
+        Ok((chain, id))
+    })
+    .await
+    .context("firmware task failed")??;
+
+    // The mock's CEK is signed by the ASK already, and AMD does not know its identifier.
+    if device::backend() == device::Backend::Mock {
+        return Ok(chain);
+    }
+
+    let config = config::get();
+    let cache = config.cache_dir.as_ref().map(|dir| cek_path(dir, &id));
+
+    chain.cek = match cache.as_ref().filter(|path| path.exists()) {
+        Some(path) => {
+            tracing::debug!(path = %path.display(), "using cached CEK");
+            let mut file =
+                File::open(path).context(format!("unable to open {}", path.display()))?;
+            sev::Certificate::decode(&mut file, ())
+                .context(format!("unable to decode {}", path.display()))?
+        }
+        None => {
+            let cek = download(&cek_url(&id), Usage::CEK).await?;
+            if let Some(path) = &cache {
+                store(path, &cek)?;
+            }
+            cek
+        }
+    };
+
+    Ok(chain)
+}
+
+/// Where the CEK of the platform `id` is kept in the cache directory `dir`.
+pub fn cek_path(dir: &Path, id: &[u8]) -> PathBuf {
+    dir.join("cek")
+        .join(format!("{}.cert", hex(id).to_uppercase()))
+}
+
+/// The URL of the CEK of the platform `id` at the KDS.
+fn cek_url(id: &[u8]) -> String {
+    format!(
+        "{}/cek/id/{}",
+        config::get().kds_url(),
+        hex(id).to_uppercase()
+    )
+}
+
+/// Keeps a downloaded certificate in the cache directory.
+fn store(path: &Path, cert: &sev::Certificate) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context(format!("unable to create {}", dir.display()))?;
+    }
+
+    let mut file = File::create(path).context(format!("unable to create {}", path.display()))?;
+    cert.encode(&mut file, ())
+        .context(format!("unable to write {}", path.display()))
+}
+
+/// An AMD CA chain to verify SEV certificate chains against.
+pub struct Root {
+    /// The generation it belongs to or, if unknown, where it was read from.
+    pub name: String,
+
+    pub chain: ca::Chain,
+}
+
+/// The AMD CA chains built into sevctl, one per generation.
+pub fn roots_builtin() -> Vec<Root> {
+    vec![
+        (platform::Generation::Naples, Generation::Naples),
+        (platform::Generation::Rome, Generation::Rome),
+    ]
+    .into_iter()
+    .map(|(name, generation)| Root {
+        name: name.to_string(),
+        chain: generation.into(),
+    })
+    .collect()
+}
+
+/// The root among `roots` whose ASK signed the CEK of `chain`. Failing that, the
+/// error names the built-in root that did, if any.
+pub fn select_root(chain: &sev::Chain, roots: Vec<Root>) -> Result<Root> {
+    let tried = roots
+        .iter()
+        .map(|root| root.name.clone())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Some(root) = roots
+        .into_iter()
+        .find(|root| (&root.chain.ask, &chain.cek).verify().is_ok())
+    {
+        tracing::debug!(root = %root.name, "selected CA chain");
+        return Ok(root);
+    }
+
+    Err(
+        match roots_builtin()
+            .into_iter()
+            .find(|root| (&root.chain.ask, &chain.cek).verify().is_ok())
+        {
+            Some(root) => error::Context::new(
+                &format!(
+                    "the CEK is signed by the {} ASK, not by that of {}",
+                    root.name, tried
+                ),
+                Box::new(Error::new(
+                    ErrorKind::InvalidData,
+                    "the CA chain is of another generation",
+                )),
+            ),
+            None => error::Context::new(
+                &format!("the CEK is not signed by the ASK of {}", tried),
+                Box::new(Error::new(
+                    ErrorKind::NotFound,
+                    "give the CA chain of the platform's generation",
+                )),
+            ),
+        },
+    )
+}
+
+/// The AMD certificate chain built into sevctl for the generation of `chain`.
+pub fn ca_chain_builtin(chain: &sev::Chain) -> Result<ca::Chain> {
+    select_root(chain, roots_builtin()).map(|root| root.chain)
+}
+
+/// Reads a CA chain, named after the built-in root it is a copy of, if any.
+pub fn read_root(filename: PathBuf) -> Result<Root> {
+    tracing::debug!(path = %filename.display(), "reading file");
+    let mut file = File::open(&filename).context("unable to open CA certificate chain file")?;
+    let chain = ca::Chain::decode(&mut file, ()).context("unable to decode chain")?;
+
+    let name = roots_builtin()
+        .into_iter()
+        .find(|root| root.chain.ark == chain.ark)
+        .map_or_else(|| filename.display().to_string(), |root| root.name);
+    Ok(Root { name, chain })
+}
+
+pub mod reset {
+    use super::*;
+
+    pub fn cmd() -> Result<()> {
+        let mut fw = firmware()?;
+        issue("PLATFORM_RESET", || fw.platform_reset()).context("error resetting platform")
+    }
+}
+
+pub mod show {
+    use super::*;
+    use ::sev::firmware::Flags;
+
+    #[derive(StructOpt)]
+    pub enum Show {
+        #[structopt(about = "Show the current platform flags")]
+        Flags,
+
+        #[structopt(about = "Show the SEV state of a running guest over QMP")]
+        Guest {
+            #[structopt(long, parse(from_os_str), help = "QMP socket of the guest")]
+            qmp: PathBuf,
+        },
+
+        #[structopt(about = "Show the current number of guests")]
+        Guests,
+
+        #[structopt(about = "Show the platform's firmware version")]
+        Version,
+    }
+
+    pub fn cmd(show: Show) -> Result<()> {
+        if let Show::Guest { qmp } = show {
+            return guest(&qmp);
+        }
+
+        let status = platform_status()?;
+
+        match show {
+            Show::Version => output::print(&schema::FirmwareVersion {
+                api_major: status.build.version.major,
+                api_minor: status.build.version.minor,
+                build_id: status.build.build,
+            }),
+            Show::Guests => output::print(&schema::Guests {
+                guests: status.guests,
+            }),
+            Show::Flags => output::print(&schema::PlatformFlags {
+                owned: status.flags.contains(Flags::OWNED),
+                es: status.flags.contains(Flags::ENCRYPTED_STATE),
+            }),
+            Show::Guest { .. } => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn guest(path: &Path) -> Result<()> {
+        let mut qmp = qmp::Qmp::connect(path)?;
+        let info = qmp.query_sev()?;
+
+        // The measurement is only available until the guest is started.
+        let measurement = match info.state.as_str() {
+            "launch-secret" => Some(qmp.query_sev_launch_measure()?),
+            _ => None,
+        };
+
+        output::print(&schema::GuestState {
+            enabled: info.enabled,
+            state: info.state,
+            handle: info.handle,
+            api_major: info.api_major,
+            api_minor: info.api_minor,
+            build_id: info.build_id,
+            policy: info.policy,
+            measurement,
+        });
+
+        Ok(())
+    }
+
+    impl output::Document for schema::FirmwareVersion {
+        fn table(&self) -> String {
+            format!("{}.{}.{}\n", self.api_major, self.api_minor, self.build_id)
+        }
+    }
+
+    impl output::Document for schema::Guests {
+        fn table(&self) -> String {
+            format!("{}\n", self.guests)
+        }
+    }
+
+    impl output::Document for schema::PlatformFlags {
+        fn table(&self) -> String {
+            let mut out = String::new();
+            if self.owned {
+                out += "owned\n";
+            }
+            if self.es {
+                out += "es\n";
+            }
+            out
+        }
+    }
+
+    impl output::Document for schema::GuestState {
+        fn table(&self) -> String {
+            let mut out = format!(
+                "enabled: {}\nstate: {}\nhandle: {}\napi: {}.{} (build {})\npolicy: {:#x}\n",
+                self.enabled,
+                self.state,
+                self.handle,
+                self.api_major,
+                self.api_minor,
+                self.build_id,
+                self.policy
+            );
+            if let Some(measurement) = &self.measurement {
+                out += &format!("measurement: {}\n", measurement);
+            }
+            out
+        }
+    }
+}
+
+pub mod export {
+    use super::*;
+    use std::io::Write;
+
+    pub fn cmd(
+        full: bool,
+        libvirt: bool,
+        name: Option<String>,
+        policy: Option<String>,
+        dest: PathBuf,
+    ) -> Result<()> {
+        if libvirt {
+            // structopt requires --policy with --libvirt.
+            return self::libvirt(dest, name.as_deref(), &policy.unwrap());
+        }
+
+        let mut file = File::create(dest).context("unable to create output file")?;
+
+        file.write_all(&encode(full)?)
+            .context("unable to write output file")?;
+
+        Ok(())
+    }
+
+    /// Encodes the platform's SEV certificate chain, followed by the CA chain if `full`.
+    pub fn encode(full: bool) -> Result<Vec<u8>> {
+        block_on(encode_async(full))
+    }
+
+    /// [`encode`] for async callers.
+    pub async fn encode_async(full: bool) -> Result<Vec<u8>> {
+        let chain = chain_async().await?;
+
+        let mut out = std::io::Cursor::new(Vec::new());
+
+        if full {
+            let full_chain = Chain {
+                ca: ca_chain_builtin(&chain)?,
+                sev: chain,
+            };
+
+            full_chain
+                .encode(&mut out, ())
+                .context("certificate chain encoding failed")?;
+        } else {
+            chain
+                .encode(&mut out, ())
+                .context("certificate chain encoding failed")?;
+        }
+
+        Ok(out.into_inner())
+    }
+
+    /// Writes the full chain into `dir` under the name `session create` produces its
+    /// files next to, and prints the domain's `<launchSecurity>` element, with the
+    /// GODH certificate and session blob once they are there.
+    fn libvirt(dir: PathBuf, name: Option<&str>, policy: &str) -> Result<()> {
+        let policy = session::sev_policy(policy)?;
+        let cpuid = platform::cpuid().filter(|cpuid| cpuid.sev).ok_or_else(|| {
+            error::Context::new(
+                "the CPU reports no C-bit position",
+                Box::new(Error::new(
+                    ErrorKind::Unsupported,
+                    "export for libvirt on the host of the domain",
+                )),
+            )
+        })?;
+
+        std::fs::create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+        let path = |file: &str| session::artifact(&dir, name, file);
+        session::write(&path("platform.chain"), &encode(true)?, 0o644)?;
+
+        let read = |path: &Path| {
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|s| s.trim().to_string())
+        };
+        let (godh, blob) = (path("godh.b64"), path("session.b64"));
+        let (dh_cert, session) = match (read(&godh), read(&blob)) {
+            (Some(godh), Some(blob)) => (Some(godh), Some(blob)),
+            _ => (None, None),
+        };
+
+        output::print(&schema::LibvirtLaunchSecurity {
+            chain: path("platform.chain"),
+            cbitpos: cpuid.cbitpos,
+            reduced_phys_bits: cpuid.reduced_phys_bits,
+            policy: format!("{:#06x}", policy),
+            dh_cert,
+            session,
+            dh_cert_path: godh,
+            session_path: blob,
+        });
+        Ok(())
+    }
+
+    impl output::Document for schema::LibvirtLaunchSecurity {
+        fn table(&self) -> String {
+            let mut out = "<launchSecurity type='sev'>\n".to_string();
+            out += &format!("  <cbitpos>{}</cbitpos>\n", self.cbitpos);
+            out += &format!(
+                "  <reducedPhysBits>{}</reducedPhysBits>\n",
+                self.reduced_phys_bits
+            );
+            out += &format!("  <policy>{}</policy>\n", self.policy);
+            match (&self.dh_cert, &self.session) {
+                (Some(godh), Some(blob)) => {
+                    out += &format!("  <dhCert>{}</dhCert>\n", godh);
+                    out += &format!("  <session>{}</session>\n", blob);
+                }
+                _ => {
+                    out += &format!(
+                        "  <!-- dhCert and session: {} and {} from session create -->\n",
+                        self.dh_cert_path.display(),
+                        self.session_path.display()
+                    )
+                }
+            }
+            out + "</launchSecurity>\n"
+        }
+    }
+}
+
+pub mod verify {
+    use super::*;
+    use colorful::*;
+    use std::convert::TryInto;
+    use std::fmt::Display;
+
+    /// The indentation of each signer in the table, which draws the chain as a tree.
+    const INDENT: &[&str] = &["", "   ", "   ", "      ", "         "];
+
+    pub fn cmd(
+        sev: Option<PathBuf>,
+        oca: Option<PathBuf>,
+        ca: Vec<PathBuf>,
+        generation: Option<platform::Generation>,
+    ) -> Result<()> {
+        let mut schain = sev_chain(sev)?;
+        let roots = match (ca.is_empty(), generation) {
+            (false, _) => ca.into_iter().map(read_root).collect::<Result<Vec<_>>>()?,
+            (true, None) => roots_builtin(),
+            (true, Some(generation)) => {
+                let roots: Vec<_> = roots_builtin()
+                    .into_iter()
+                    .filter(|root| root.name == generation.to_string())
+                    .collect();
+                if roots.is_empty() {
+                    return Err(error::Context::new(
+                        &format!(
+                            "the ASK and ARK of {} are not built into sevctl",
+                            generation
+                        ),
+                        Box::new(Error::new(ErrorKind::NotFound, "give them with --ca")),
+                    ));
+                }
+                roots
+            }
+        };
+        let cchain = select_root(&schain, roots)?.chain;
+
+        if let Some(filename) = oca {
+            tracing::debug!(path = %filename.display(), "reading file");
+            let mut file = File::open(filename).context("unable to open OCA certificate file")?;
+
+            schain.oca = sev::Certificate::decode(&mut file, ()).context("unable to decode OCA")?;
+        }
+
+        let checked = check(&schain, &cchain);
+        output::print(&checked);
+
+        if checked.valid {
+            Ok(())
+        } else {
+            Err(error::Context::new(
+                "SEV/CA certificate verification failed",
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ))
+        }
+    }
+
+    /// Checks every signature in the chains, displaying them unless `quiet`.
+    pub fn chains(schain: &sev::Chain, cchain: &ca::Chain, quiet: bool) -> bool {
+        let checked = check(schain, cchain);
+        if !quiet {
+            print!("{}", output::Document::table(&checked));
+        }
+
+        checked.valid
+    }
+
+    /// Checks every signature in the chains.
+    #[tracing::instrument(name = "verify", skip_all)]
+    pub fn check(schain: &sev::Chain, cchain: &ca::Chain) -> schema::ChainVerification {
+        let signatures = vec![
+            status(&schain.pek, &schain.pdh),
+            status(&schain.oca, &schain.pek),
+            status(&schain.cek, &schain.pek),
+            status(&cchain.ask, &schain.cek),
+            status(&cchain.ark, &cchain.ask),
+        ];
+
+        schema::ChainVerification {
+            valid: signatures
+                .iter()
+                .all(|s| s.valid && s.self_signature_valid != Some(false)),
+            pdh: schain.pdh.to_string(),
+            signatures,
+        }
+    }
+
+    fn status<'a, P, C>(p: &'a P, c: &'a C) -> schema::SignatureCheck
+    where
+        P: Display,
+        C: Display,
+        &'a P: TryInto<Usage, Error = Error>,
+        &'a C: TryInto<Usage, Error = Error>,
+        (&'a P, &'a P): Verifiable,
+        (&'a P, &'a C): Verifiable,
+    {
+        let sig_valid = (p, c).verify().is_ok();
+        if sig_valid {
+            tracing::info!(signer = %p, signee = %c, "valid signature");
+        } else {
+            tracing::error!(signer = %p, signee = %c, "invalid signature");
+        }
+
+        schema::SignatureCheck {
+            signer: p.to_string(),
+            signee: c.try_into().unwrap().to_string(),
+            valid: sig_valid,
+            self_signature_valid: match p.try_into().unwrap() {
+                Usage::OCA | Usage::ARK => Some((p, p).verify().is_ok()),
+                _ => None,
+            },
+        }
+    }
+
+    impl output::Document for schema::ChainVerification {
+        fn table(&self) -> String {
+            let mut out = format!("{}\n", self.pdh);
+            for (pfx, s) in INDENT.iter().zip(&self.signatures) {
+                let lnk = if s.valid {
+                    "⬑".green()
+                } else {
+                    "⬑̸".red()
+                };
+                out += &match s.self_signature_valid {
+                    Some(valid) => {
+                        let slf = if valid { "•".green() } else { "•̷".red() };
+                        format!("{}{}{} {}\n", pfx, slf, lnk, s.signer)
+                    }
+                    None => format!("{} {} {}\n", pfx, lnk, s.signer),
+                };
+            }
+
+            out + "\n • = self signed, ⬑ = signs, •̷ = invalid self sign, ⬑̸ = invalid signs\n"
+        }
+    }
+
+    fn sev_chain(filename: Option<PathBuf>) -> Result<sev::Chain> {
+        Ok(match filename {
+            None => chain()?,
+            Some(f) => {
+                tracing::debug!(path = %f.display(), "reading file");
+                let mut file =
+                    File::open(f).context("unable to open SEV certificate chain file")?;
+
+                sev::Chain::decode(&mut file, ()).context("unable to decode chain")?
+            }
+        })
+    }
+}
+
+pub mod generate {
+    use super::*;
+
+    pub fn cmd(oca_path: PathBuf, key_path: PathBuf) -> Result<()> {
+        let (mut oca, prv) = sev::Certificate::generate(sev::Usage::OCA)
+            .context("unable to generate OCA key pair")?;
+        prv.sign(&mut oca).context("key signing failed")?;
+
+        // Write the certificate
+        let mut crt = File::create(oca_path).context("unable to create certificate file")?;
+        oca.encode(&mut crt, ())
+            .context("unable to write certificate file")?;
+
+        // Write the private key
+        let mut key = Vec::new();
+        prv.encode(&mut key, ())
+            .context("unable to encode private key")?;
+        session::write(&key_path, &secure::Secret::take(key), 0o600)
+    }
+}
+
+pub mod rotate {
+    use super::*;
+
+    pub fn cmd() -> Result<()> {
+        let mut fw = firmware()?;
+        issue("PDH_GEN", || fw.pdh_generate()).context("unable to rotate PDH")?;
+
+        Ok(())
+    }
+}
+
+pub mod provision {
+    use super::*;
+
+    pub fn cmd(oca_path: PathBuf, prv_key_path: PathBuf) -> Result<()> {
+        let progress = progress::start("Opening the SEV device");
+        let mut fw = firmware()?;
+        tracing::debug!(path = %oca_path.display(), "reading file");
+        let cert = File::open(oca_path.clone())
+            .context(format!("failed to open {}", oca_path.display()))
+            .and_then(|mut f| {
+                sev::Certificate::decode(&mut f, ()).context("failed to decode OCA")
+            })?;
+
+        let prv_key = secure::Secret::read(&prv_key_path)?;
+        let prv_key = PrivateKey::<sev::Usage>::decode(&mut &prv_key[..], &cert)
+            .context("failed to decode OCA private key")?;
+
+        progress.step("Requesting the PEK signing request");
+        let mut pek = issue("PEK_CSR", || fw.pek_csr()).context("cross signing request failed")?;
+        progress.step("Signing the PEK with the OCA key");
+        prv_key
+            .sign(&mut pek)
+            .context("failed to sign PEK with OCA private key")?;
+        progress.step("Importing the signed PEK");
+        issue("PEK_CERT_IMPORT", || fw.pek_cert_import(&pek, &cert))
+            .context("failed to import the newly-signed PEK")?;
+
+        Ok(())
+    }
+}
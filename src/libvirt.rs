@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for SEV guests managed by libvirt, built on `virsh`.
+
+use super::*;
+use domain::virsh;
+
+#[derive(StructOpt)]
+pub enum Libvirt {
+    #[structopt(about = "Fetch the launch measurement of a paused SEV domain")]
+    Measurement {
+        #[structopt(flatten)]
+        conn: Connection,
+
+        #[structopt(help = "Name of the domain")]
+        domain: String,
+    },
+
+    #[structopt(about = "Set the SEV policy and launch session of a domain")]
+    SetLaunchSecurity {
+        #[structopt(flatten)]
+        conn: Connection,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Directory holding the launch session created by session create"
+        )]
+        session: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Prefix of the session's file names (session create --name)"
+        )]
+        name: Option<String>,
+
+        #[structopt(long, help = "Guest policy the session was created for")]
+        policy: String,
+
+        #[structopt(help = "Name of the domain")]
+        domain: String,
+    },
+
+    #[structopt(about = "List the domains configured for SEV")]
+    List {
+        #[structopt(flatten)]
+        conn: Connection,
+    },
+}
+
+/// The libvirt connection to manage domains on.
+#[derive(StructOpt)]
+pub struct Connection {
+    #[structopt(
+        long,
+        default_value = "qemu:///system",
+        help = "libvirt connection URI"
+    )]
+    connect: String,
+}
+
+pub fn cmd(libvirt: Libvirt) -> Result<()> {
+    match libvirt {
+        Libvirt::Measurement { conn, domain } => {
+            let info = virsh(&conn.connect, &["domlaunchsecinfo", &domain], None)?;
+            let measurement = info
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(key, _)| key.trim() == "sev-measurement")
+                .map(|(_, value)| value.trim().to_string())
+                .ok_or_else(|| {
+                    error::Context::new(
+                        &format!("domain {} reported no launch measurement", domain),
+                        Box::<Error>::new(ErrorKind::InvalidData.into()),
+                    )
+                })?;
+
+            output::print(&schema::LibvirtMeasurement {
+                domain,
+                measurement,
+            });
+            Ok(())
+        }
+
+        Libvirt::SetLaunchSecurity {
+            conn,
+            session,
+            name,
+            policy,
+            domain,
+        } => {
+            let path = |file: &str| session::artifact(&session, name.as_deref(), file);
+            let read = |path: PathBuf| {
+                std::fs::read_to_string(&path)
+                    .map(|s| s.trim().to_string())
+                    .context(format!("unable to read {}", path.display()))
+            };
+
+            let policy = session::sev_policy(&policy)?;
+            let godh = read(path("godh.b64"))?;
+            let blob = read(path("session.b64"))?;
+
+            let xml = virsh(&conn.connect, &["dumpxml", "--inactive", &domain], None)?;
+            let xml = set_launch_security(&xml, policy, &godh, &blob)?;
+            virsh(&conn.connect, &["define", "/dev/stdin"], Some(&xml))?;
+            Ok(())
+        }
+
+        Libvirt::List { conn } => {
+            let names = virsh(&conn.connect, &["list", "--all", "--name"], None)?;
+            let mut domains = Vec::new();
+            for name in names.lines().map(str::trim).filter(|n| !n.is_empty()) {
+                let xml = virsh(&conn.connect, &["dumpxml", name], None)?;
+                let doc = roxmltree::Document::parse(&xml).context("invalid domain XML")?;
+
+                if let Some(sev) = launch_security(&doc) {
+                    let policy = sev
+                        .children()
+                        .find(|n| n.has_tag_name("policy"))
+                        .and_then(|n| n.text())
+                        .unwrap_or("unknown");
+                    domains.push(schema::LibvirtDomain {
+                        name: name.to_string(),
+                        policy: policy.trim().to_string(),
+                    });
+                }
+            }
+
+            output::print(&schema::LibvirtDomains { domains });
+            Ok(())
+        }
+    }
+}
+
+impl output::Document for schema::LibvirtMeasurement {
+    fn table(&self) -> String {
+        format!("{}\n", self.measurement)
+    }
+}
+
+impl output::Document for schema::LibvirtDomains {
+    fn table(&self) -> String {
+        self.domains
+            .iter()
+            .map(|d| format!("{} policy {}\n", d.name, d.policy))
+            .collect()
+    }
+
+    fn csv(&self) -> String {
+        let rows = self
+            .domains
+            .iter()
+            .map(|d| vec![d.name.clone(), d.policy.clone()])
+            .collect();
+        output::csv(&["name", "policy"], rows)
+    }
+}
+
+fn launch_security<'a, 'input>(
+    doc: &'a roxmltree::Document<'input>,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    doc.root_element()
+        .children()
+        .find(|n| n.has_tag_name("launchSecurity") && n.attribute("type") == Some("sev"))
+}
+
+/// Replaces the domain's `<launchSecurity>` element with one carrying the policy and
+/// session, keeping other settings such as `<cbitpos>` and `<reducedPhysBits>`.
+fn set_launch_security(xml: &str, policy: u32, godh: &str, session: &str) -> Result<String> {
+    let doc = roxmltree::Document::parse(xml).context("invalid domain XML")?;
+
+    let mut element = String::from("<launchSecurity type='sev'>\n");
+    let old = launch_security(&doc);
+
+    if let Some(old) = old {
+        for child in old.children().filter(|n| n.is_element()) {
+            let tag = child.tag_name().name();
+            if !["policy", "dhCert", "session"].contains(&tag) {
+                let text = child.text().unwrap_or("").trim();
+                element += &format!("    <{0}>{1}</{0}>\n", tag, text);
+            }
+        }
+    }
+
+    element += &format!("    <policy>{:#06x}</policy>\n", policy);
+    element += &format!("    <dhCert>{}</dhCert>\n", godh);
+    element += &format!("    <session>{}</session>\n", session);
+    element += "  </launchSecurity>";
+
+    let range = match old {
+        Some(old) => old.range(),
+        None => {
+            // Insert the element before the closing </domain> tag.
+            let end = doc.root_element().range().end;
+            let at = xml[..end].rfind("</").unwrap();
+            element = format!("  {}\n", element);
+            at..at
+        }
+    };
+
+    Ok(format!(
+        "{}{}{}",
+        &xml[..range.start],
+        element,
+        &xml[range.end..]
+    ))
+}
@@ -3,106 +3,1237 @@
 //! `sevctl` is a command line utility for managing the AMD Secure Encrypted Virtualization (SEV) platform.
 //! It currently supports the entire management API for the Naples generation of processors.
 //!
+//! Its workflows are also available as the `sevctl` library crate, for Rust programs that would
+//! rather call them than run the binary, and to C and C++ through the `sevctl-ffi` crate in `ffi/`,
+//! which builds `libsevctl_ffi` with the declarations in `ffi/include/sevctl.h`. Python programs can
+//! import the `sevctl` module built from `python/` with `maturin build`, which covers chain
+//! verification, measurements, sessions and secrets.
+//!
+//! sevctl builds with Rust 1.68 or later.
+//!
+//! Downloads from the KDS and other servers, the KBS client and trace export need the `network`
+//! feature, which is on by default. Without it, sevctl is smaller and never connects anywhere:
+//! commands that would download something, e.g. the CEK unless it is cached, fail with a
+//! `network` error saying that sevctl was built without network support, and work once the
+//! certificates are given as files.
+//!
+//! ```console
+//! $ cargo build --release --no-default-features
+//! ```
+//!
 //! # Usage
 //!
 //! ## help
 //!
-//! Every `sevctl` (sub)command comes with a quick `--help` option for a reference on its use. For example:
+//! Every `sevctl` (sub)command comes with a quick `--help` option for a reference on its use. For example:
+//!
+//! ```console
+//! $ sevctl --help
+//! ```
+//!
+//! or
+//!
+//! ```console
+//! $ sevctl show --help
+//! ```
+//!
+//! ## output
+//!
+//! `--output` (`-o`), given before the subcommand, selects how results are printed: `table` (the
+//! default) for people, `json` or `yaml` for scripts, `csv` for spreadsheets, or `quiet` to rely
+//! on the exit status alone. The JSON and YAML documents carry a `schema_version` like every
+//! other document (see `schema`). Commands that write files or run services print progress
+//! messages regardless.
+//!
+//! CSV output starts with a header line. Check results (`ok`, `snp ok`, `firmware check` and the
+//! verdicts of the `verify` commands) have a row per check with its `id`, `name`, `state` and
+//! `details`, so that the results of many hosts can be concatenated and compared. Other documents,
+//! such as those of `show`, have a `field,value` row per field of their JSON form, nested fields
+//! named by their path.
+//!
+//! When a command fails without a result, the JSON and YAML formats print the error instead (see
+//! `schema failure`), with its `kind` and a stable numeric `code`: 1 `other`, 2 `invalid-input`,
+//! 3 `not-found`, 4 `permission-denied`, 5 `firmware` (along with the `firmware-status`, see
+//! `error`), 6 `network`, 7 `timeout`, 8 `busy` and 9 `crypto`.
+//!
+//! ```console
+//! $ sevctl --output json show version
+//! $ sevctl -o yaml verify
+//! $ sevctl -o csv ok
+//! id,name,state,details
+//! cpu.amd,AMD CPU,pass,AuthenticAMD
+//! ...
+//! ```
+//!
+//! With `--with-metadata`, JSON and YAML documents also carry a `metadata` object with the
+//! `hostname`, the `timestamp` (in seconds since the Unix epoch), the `sevctl-version` and the
+//! `firmware-version` if the firmware answers, so that the results collected from many hosts stay
+//! attributable once aggregated.
+//!
+//! ```console
+//! $ sevctl -o json --with-metadata ok
+//! ```
+//!
+//! Operations that can take a while, such as KDS downloads (with a progress bar when the size is
+//! known), `firmware update` and `provision`, show a spinner with their current step on stderr
+//! when it is a terminal. `--quiet` and `-v` hide it.
+//!
+//! ## config
+//!
+//! Defaults are read from `/etc/sevctl/config.toml` and then `~/.config/sevctl/config.toml` (or
+//! `$XDG_CONFIG_HOME/sevctl/config.toml`), whose settings take precedence; `SEVCTL_CONFIG` names a
+//! single file to read instead. Command line flags override both. `cache-dir` keeps the CEK,
+//! VCEK and ASK/ARK certificates downloaded from the KDS for reuse (see `certs sync`), `proxy` applies to every HTTPS request, and
+//! `kds-url` points at a KDS mirror. Firmware commands failing because the SEV device is busy
+//! (e.g. with commands libvirt issues concurrently) are retried `firmware-retries` times, first
+//! after `firmware-retry-ms` milliseconds and then twice as long each time. `advisories` replaces
+//! the advisory table bundled for `firmware check`, and `tcb-minimums` the table of `snp
+//! tcb-check`. `audit-log` is where the audit log goes (see `audit`), and `nonce-dir` where stored
+//! nonces are kept (see `nonce`). `snp-arks` lists the AMD ARKs that SEV-SNP certificate chains
+//! must end in (see `snp report verify`).
+//!
+//! ```toml
+//! output = "json"
+//! cache-dir = "/var/cache/sevctl"
+//! proxy = "http://proxy.example.com:3128"
+//! kds-url = "https://kdsintf.amd.com"
+//! firmware-retries = 5
+//! firmware-retry-ms = 100
+//! advisories = "/etc/sevctl/advisories.toml"
+//! audit-log = "/var/log/sevctl/audit.log"
+//! nonce-dir = "/var/lib/sevctl/nonces"
+//! tcb-minimums = "https://mirror.example.com/sevctl/tcb-minimums.toml"
+//! snp-arks = ["/etc/sevctl/ark-milan.pem", "/etc/sevctl/ark-genoa.pem"]
+//! ```
+//!
+//! ## logging
+//!
+//! `-v`, given before the subcommand, logs the firmware commands issued and the network requests
+//! made on stderr, so that stdout stays machine-parseable; `-vv` adds the files read and other
+//! details, and `-vvv` the internals of the HTTP client. `-q` silences everything.
+//!
+//! ```console
+//! $ sevctl -vv export /tmp/chain.cert
+//! ```
+//!
+//! ## sev-device
+//!
+//! `--sev-device` (or `SEVCTL_SEV_DEVICE`), given before the subcommand, makes every command issue
+//! its firmware commands to another device node than /dev/sev, e.g. in a test environment or a
+//! container where the device is bind-mounted elsewhere. The helper accepts it too.
+//!
+//! ```console
+//! $ sevctl --sev-device /run/psp/sev0 show version
+//! ```
+//!
+//! A command opens the device once and its steps share the handle, along with the first platform
+//! status read, which is read anew after a command changes the platform. The services (`daemon`,
+//! `exporter`, `helper` and `serve`) and `bench` read the status every time instead.
+//!
+//! ## backend
+//!
+//! Built with the `mock` feature, sevctl issues its firmware commands to a fake firmware given
+//! `--backend mock` (or `SEVCTL_BACKEND=mock`) before the subcommand, so that most commands can
+//! be developed and tested without EPYC hardware. It reports an initialized SEV and SEV-SNP
+//! platform and exports the certificate chain of a Rome platform, which verifies against the
+//! built-in AMD certificates; commands changing the platform succeed without changing it.
+//! `SEVCTL_MOCK_FAIL` makes commands fail with firmware status codes, given by number or name.
+//! Checks of the CPU, KVM and the kernel still look at the host. `cargo test --features mock` also
+//! runs the commands against it.
+//!
+//! ```console
+//! $ cargo build --features mock
+//! $ sevctl --backend mock verify
+//! $ SEVCTL_MOCK_FAIL=PDH_GEN=INVALID_PLATFORM_STATE sevctl --backend mock rotate
+//! ```
+//!
+//! ## record
+//!
+//! `--record` (before the subcommand) writes every firmware command issued and what the firmware
+//! answered, including failures, to a JSON file (see `schema recording`). `--replay` runs a
+//! command against such a file instead of the firmware: the commands are answered from it in
+//! order, and the run fails as soon as it issues one that was not recorded next; network requests,
+//! e.g. for the CEK, are still made. A recording taken
+//! on a machine showing a bug thus reproduces it elsewhere, and can be kept to check the fix.
+//!
+//! ```console
+//! # sevctl --record rotate.json rotate
+//! $ sevctl --replay rotate.json rotate
+//! ```
+//!
+//! ## audit
+//!
+//! The commands changing the platform (`reset`, `rotate`, `provision`, `snp commit`,
+//! `snp config set`, `firmware update`, `init-ex init` and `init-ex restore`), those releasing a
+//! secret to a guest (`secret inject` and `launch attest`), and those the helper issues for other
+//! processes, are recorded in an audit log once they complete: the time,
+//! the invoking user and process, the command line (or the helper request) and the outcome,
+//! including the status code of a command the firmware rejected (see `schema audit`). Entries go
+//! to syslog, and so the journal, with the `authpriv` facility. The `audit-log` setting names a
+//! file to append them to as JSON lines instead, or turns them `off`; the commands do not run if
+//! the file cannot be opened. `chattr +a` makes such a file append-only.
+//!
+//! ```console
+//! $ journalctl -t sevctl
+//! # tail -n1 /var/log/sevctl/audit.log | jq .
+//! ```
+//!
+//! ## agent
+//!
+//! Inside an SEV-SNP guest, `agent --vsock` answers attestation report requests from the host over
+//! vsock, so reports can be collected without guest networking. Reports are obtained through the
+//! kernel's configfs-tsm interface.
+//!
+//! ```console
+//! $ sevctl agent --vsock --port 5000
+//! ```
+//!
+//! On the host, `guest-report` requests a report from the agent of the guest with vsock CID
+//! `--cid`, binding up to 64 bytes of `--report-data` (e.g. a fresh nonce). The report is
+//! displayed field by field as `snp report show` does, or written raw to `--output`.
+//!
+//! ```console
+//! $ sevctl guest-report --cid 3 --report-data "$(openssl rand -hex 64)" --output report.bin
+//! ```
+//!
+//! ## attestation
+//!
+//! SEV and SEV-ES guests, which predate SEV-SNP reports, can be attested while they run with the
+//! firmware's ATTESTATION command. On the host, `attestation request` asks the guest's QEMU over
+//! its QMP socket (`query-sev-attestation-report`) for a report binding the guest owner's 16-byte
+//! `--mnonce`, writes it raw and shows its fields: the nonce, the launch digest and the policy.
+//!
+//! ```console
+//! # sevctl attestation request --qmp /run/qemu/guest.qmp --mnonce "$(openssl rand -hex 16)" report.bin
+//! ```
+//!
+//! The guest owner then runs `attestation verify` with the platform's certificate chain, as
+//! written by `export`, to check that the PEK is certified up to the ARK (`pek.chain`), that it
+//! signed the report (`report.signature`) and that the report binds the same `--mnonce`. With
+//! `--launch-digest` (see `measurement build`) and `--policy`, the guest must also have been
+//! launched with that digest and policy.
+//!
+//! ```console
+//! $ sevctl attestation verify --chain chain.cert --mnonce "$MNONCE" --launch-digest "$DIGEST" report.bin
+//! [ PASS ] pek.chain - PEK certified up to the ARK
+//! [ PASS ] report.signature - Report signed by the PEK
+//! [ PASS ] mnonce - Nonce expected
+//! [ PASS ] launch-digest - Launch digest expected: 4f0d2c...
+//! ```
+//!
+//! ## bench
+//!
+//! Issues each of the firmware commands that only read the platform state (PLATFORM_STATUS,
+//! PDH_CERT_EXPORT, GET_ID and SNP_PLATFORM_STATUS, or those given with `--command`) `-n` times
+//! and reports their latency in microseconds: the minimum, mean, median, 90th and 99th
+//! percentiles and maximum. Comparing the results across firmware versions or platforms shows
+//! where provisioning spends its time. Through the helper, the latency includes its round trip.
+//! The LAUNCH commands are not measured, as the kernel only issues them for KVM guests.
+//!
+//! ```console
+//! # sevctl bench -n 1000 --command PDH_CERT_EXPORT
+//! command (µs)               min      mean       p50       p90       p99       max
+//! PDH_CERT_EXPORT           2412      2590      2544      2713      3380      4102
+//! 1000 iterations each
+//! ```
+//!
+//! ## certs
+//!
+//! `certs sync` keeps the platform's certificates in a directory, by default `cache-dir`, laid out
+//! as sevctl's cache so that the commands reading it work offline: the CEK (`cek/<ID>.cert`) and,
+//! once SEV-SNP is initialized, the VCEK at the reported TCB version
+//! (`vcek/<product>/<chip ID>-<TCB>.der`) and the ASK and ARK signing it
+//! (`cert_chain/<product>.pem`). Files already present are kept as long as they still chain to
+//! AMD's keys; only missing, stale or damaged ones are downloaded, and written by renaming, so
+//! that running it from cron or configuration management leaves the directory untouched until
+//! the platform's certificates change, e.g. after a TCB update. `--prune` removes the chip's VCEKs
+//! at other TCB versions.
+//!
+//! ```console
+//! # sevctl certs sync --prune /var/cache/sevctl
+//! current   cek/4F2A...C1.cert
+//! fetched   vcek/Milan/77844581...-0000000000000003.der
+//! current   cert_chain/Milan.pem
+//! removed   vcek/Milan/77844581...-0000000000000002.der
+//! ```
+//!
+//! ## completions
+//!
+//! Prints a completion script for bash, zsh or fish (or PowerShell and Elvish), generated from the
+//! command line definitions, so it always matches the installed `sevctl`. Options taking one of a
+//! fixed set of values, such as `--output` and the `--in-form` and `--out-form` encodings, complete
+//! to those values.
+//!
+//! ```console
+//! $ sevctl completions bash > /etc/bash_completion.d/sevctl
+//! $ sevctl completions zsh > "${fpath[1]}/_sevctl"
+//! $ sevctl completions fish > ~/.config/fish/completions/sevctl.fish
+//! ```
+//!
+//! ## daemon
+//!
+//! When built with the `grpc` feature, `sevctl daemon` serves the `show`, `export`, `verify`,
+//! `session create` and `measurement build` operations over gRPC, for management stacks that
+//! would otherwise shell out to sevctl. The API is described by `proto/sevctl/v1/sevctl.proto`;
+//! `BuildMeasurement` takes the arguments of `measurement build`, with paths on the daemon's host.
+//!
+//! The daemon does not authenticate clients and returns the TEK and TIK of the sessions it
+//! creates, so it listens on `127.0.0.1:50051` unless told otherwise with `--listen`.
+//!
+//! ```console
+//! $ sevctl daemon --listen 127.0.0.1:50051
+//! ```
+//!
+//! ## doctor
+//!
+//! Runs the checks of `ok` (and of `snp ok` with `--snp`) and queries the platform status, then
+//! explains the failures: one cause often fails several checks, such as a firmware too old for
+//! both SEV-ES and SEV-SNP, or a missing SEV device, without which KVM disables SEV too. Each
+//! finding names its cause, the failed checks it explains and the steps to fix it, root causes
+//! first, so that fixing the first often fixes the rest. Firmware affected by known issues (see
+//! `firmware check`) is reported too. It fails if anything is found; `-o json` writes the check
+//! results with the findings.
+//!
+//! ```console
+//! $ sevctl doctor --snp
+//! 1. the SEV firmware 1.49.3 is too old, which explains both the SEV-ES and the SEV-SNP failures
+//!    explains: fw.sev-es, fw.snp-version, kvm.sev-es-param, kvm.sev-snp-param
+//!    - install a newer firmware image (from linux-firmware or AMD) with sevctl firmware update, then reboot
+//!    - or update the BIOS, which includes the firmware
+//! ```
+//!
+//! ## error
+//!
+//! Describes a status code of the SEV or SEV-SNP firmware, which the kernel and QEMU only report
+//! as a number, with its usual causes. The code may be given in decimal or hex, or by name.
+//!
+//! ```console
+//! $ sevctl error 0x16
+//! 0x16 INVALID_PARAM: A parameter is invalid
+//!   - A reserved field or policy bit is set
+//!   - A page was launched with a type or size the command does not accept
+//! ```
+//!
+//! ## export
+//!
+//! Exports the SEV certificate chain to the provided file path.
+//!
+//! ```console
+//! $ sevctl export /path/to/where/you/want/the-certificate
+//! ```
+//!
+//! With `--libvirt`, the destination is the directory of a guest's launch session: the full
+//! chain is written there as `<name>_platform.chain`, beside the `<name>_godh.b64` and
+//! `<name>_session.b64` that `session create --name <name>` makes from it and `libvirt
+//! set-launch-security` reads. The domain's `<launchSecurity>` element is printed with the C-bit
+//! position and reduced physical address bits of the host, the `--policy`, and the GODH
+//! certificate and session blob once they are in the directory; before, a comment names the
+//! files still missing. Export on the host of the domain, then again after creating the session:
+//!
+//! ```console
+//! # sevctl export --libvirt --name myvm --policy 0x05 ./sessions/
+//! $ sevctl session create --name myvm --outdir ./sessions/ 0x05 ./sessions/myvm_platform.chain
+//! # sevctl export --libvirt --name myvm --policy 0x05 ./sessions/
+//! <launchSecurity type='sev'>
+//!   <cbitpos>51</cbitpos>
+//!   <reducedPhysBits>1</reducedPhysBits>
+//!   <policy>0x0005</policy>
+//!   <dhCert>AQAAAAAAAA...</dhCert>
+//!   <session>jzlqBfCnE2...</session>
+//! </launchSecurity>
+//! ```
+//!
+//! ## exporter
+//!
+//! Serves platform metrics for Prometheus on `/metrics` over plain HTTP, sampled every
+//! `--interval` seconds: the SEV and SEV-SNP guest counts and firmware versions, the total and
+//! free guest ASIDs (from the misc cgroup controller), and the days remaining on each
+//! certificate in the `--cert` PEM files, such as a VCEK chain. SEV certificates themselves do
+//! not expire.
+//!
+//! ```console
+//! $ sevctl exporter --listen 0.0.0.0:9750 --cert /etc/sev/vcek-chain.pem
+//! ```
+//!
+//! ## firmware
+//!
+//! The kernel does not let userspace issue DOWNLOAD_FIRMWARE or SNP_DOWNLOAD_FIRMWARE_EX; the ccp
+//! driver downloads the image it finds under `/lib/firmware/amd` when it initializes the platform.
+//! `firmware update` installs an image there under the name the driver looks for on the local CPU
+//! (e.g. `amd_sev_fam19h_model0xh.sbin`, or the model-specific name if one is already installed),
+//! and shows the running firmware version. The new firmware is loaded on the next boot or reload
+//! of the ccp driver, after which `show version` confirms it. On an SEV-SNP platform, images older
+//! than the committed firmware are rejected, and `snp commit` prevents rolling the update back.
+//!
+//! The image must be a signed PSP image, whose header gives its firmware version. An image older
+//! than the running firmware is refused unless `--allow-downgrade` is given, and one older than
+//! the committed firmware, which `--report` takes from an attestation report, is always refused.
+//! The image is written to a temporary file and renamed into place, so the driver never loads a
+//! partial one.
+//!
+//! ```console
+//! # sevctl firmware update --report report.bin amd_sev_fam19h_model0xh.sbin
+//! installed /lib/firmware/amd/amd_sev_fam19h_model0xh.sbin
+//! image firmware: 1.55.22
+//! current firmware: 1.55.21
+//! committed firmware: 1.55.21
+//! ...
+//! ```
+//!
+//! `firmware check` compares the running firmware version with the first fixed version of each
+//! known issue affecting the processor generation, failing if any applies. The table bundled with
+//! sevctl can be replaced with a newer one with `--advisories` or the `advisories` setting of the
+//! configuration. `--generation` and `--version` check another host's firmware.
+//!
+//! ```console
+//! $ sevctl firmware check --generation Naples --version 0.17.5
+//! [ FAIL ] CVE-2019-9836 - Platform key disclosure through invalid ECC curve points: 0.17.5 is affected, update to 0.17.11 or later (https://seclists.org/fulldisclosure/2019/Jun/46)
+//! error: firmware 0.17.5 on Naples is affected by known issues
+//! caused by: 1 of 1 advisories apply
+//! ```
+//!
+//! Every advisory names the first fixed version for each affected generation (Naples, Rome,
+//! Milan, Genoa or Turin); generations not listed are not affected.
+//!
+//! ```toml
+//! [[advisory]]
+//! id = "CVE-2019-9836"
+//! title = "Platform key disclosure through invalid ECC curve points"
+//! url = "https://seclists.org/fulldisclosure/2019/Jun/46"
+//! fixed = { Naples = "0.17.11" }
+//! ```
+//!
+//! ## generate
+//!
+//! Generates a new (self-signed) OCA certificate and key. The key is only readable by its owner.
+//!
+//! ```console
+//! $ sevctl generate ~/my-cert ~/my-key
+//! ```
+//!
+//! ## helper
+//!
+//! Issues firmware commands on behalf of other sevctl processes, so that only the helper needs
+//! access to /dev/sev while the CLI, `serve` and `exporter` run unprivileged. The helper listens
+//! on a unix socket whose `--mode` controls who may use it, and only serves the commands that
+//! change the platform (`reset`, `rotate`, `provision`, `snp commit` and `snp config`) with
+//! `--allow-modify`; it logs who requested those. A client must send its request, of at most
+//! 64 KiB, within 30 seconds. The helper replaces a socket left behind by a previous run, but
+//! refuses to start if anything else is at `--socket`.
+//!
+//! ```console
+//! # sevctl helper --socket /run/sevctl/helper.sock --mode 0660
+//! $ sevctl show guests
+//! ```
+//!
+//! sevctl uses the helper at `/run/sevctl/helper.sock` when it cannot open /dev/sev itself, or
+//! the one named by `SEVCTL_HELPER` instead of /dev/sev. An example unit, which lets the members
+//! of the `sevctl` group use the helper, is in `dist/systemd`.
+//!
+//! ## init-ex
+//!
+//! With the `ccp.init_ex_path` kernel parameter, the ccp driver initializes the platform with
+//! INIT_EX, keeping the firmware's non-volatile state, and with it the platform identity (OCA, PEK
+//! and PDH), in that 32 KiB file rather than in the SPI flash. `init-ex show` shows the file's
+//! SHA-256 digest and the platform state, and `init-ex init` has the driver initialize the
+//! platform from the file. `init-ex backup` copies the file, and
+//! `init-ex restore` puts a backup back before the driver initializes the platform (e.g. from an
+//! initramfs), as the driver would otherwise overwrite it with its own copy. `--file` names
+//! another state file than the driver's.
+//!
+//! ```console
+//! # sevctl init-ex backup /var/backups/sev-nv.bin
+//! file: /var/backups/sev-nv.bin
+//! sha256: 899ad638f1d6bb972f0ff9aab3d2b9cea2b2605f227d53ed0b65cb9940587e87
+//! platform state: initialized
+//! # sevctl init-ex restore /var/backups/sev-nv.bin
+//! ```
+//!
+//! ## k8s
+//!
+//! `k8s resources` reports whether SEV, SEV-ES and SEV-SNP are supported by the CPU and enabled
+//! in `kvm_amd`, with the guest ASIDs available to each, for cluster schedulers. It prints the
+//! node-feature-discovery `cpu-security` labels, as a `features.d` file. With `--output json` it
+//! adds the ASID capacity (and, where the misc cgroup controller reports usage, availability) as
+//! `amd.com/sev`, `amd.com/sev-es` and `amd.com/sev-snp` resources; SEV-ES and SEV-SNP guests
+//! share their ASIDs. `--format json` and `--format nfd` remain as aliases of `--output json` and
+//! `--output table`.
+//!
+//! ```console
+//! $ sevctl k8s resources --output json
+//! $ sevctl k8s resources > /etc/kubernetes/node-feature-discovery/features.d/sev
+//! ```
+//!
+//! ## kbs
+//!
+//! Inside an SEV-SNP guest, `kbs get-resource` retrieves a secret from a key broker service (KBS)
+//! of the Confidential Containers project. It requests a challenge from the KBS, answers it with
+//! an attestation report (obtained through the kernel's configfs-tsm interface) that binds the
+//! challenge nonce and an ephemeral RSA key, and fetches the resource, which the KBS releases
+//! encrypted to that key once it has appraised the report. `--cacert` adds a CA to trust for the
+//! KBS's TLS certificate.
+//!
+//! ```console
+//! $ sevctl kbs get-resource --url https://kbs.example.com:8080 --output luks.key default/keys/luks
+//! ```
+//!
+//! ## launch
+//!
+//! `launch attest` runs the guest owner's side of a launch against a guest QEMU started paused
+//! with the session created by `session create`: it verifies the launch measurement reported over
+//! QMP against the expected launch inputs (the same options as `measurement verify`), injects the
+//! secret encrypted with the session's TEK and TIK, and resumes the guest. The TEK and TIK are
+//! read from the `--session` directory, using the `--name` prefix if the session has one.
+//!
+//! ```console
+//! $ sevctl launch attest --qmp /run/qemu/guest.qmp --session ./sessions/ --name myvm \
+//!     --firmware OVMF.fd --secret luks:luks.key
+//! ```
+//!
+//! Every step is logged to `launch.log` in the session directory, tagged with the guest's SEV
+//! handle, and the steps of the run are printed once it completes. Running the command again after a failure resumes the launch: a secret
+//! already injected for the same launch measurement is not injected again, and a running guest is
+//! left alone.
+//!
+//! ## libvirt
+//!
+//! When built with the `libvirt` feature, `sevctl libvirt` manages SEV guests defined in libvirt
+//! through `virsh`, without dropping down to QMP. `--connect` selects the connection
+//! (`qemu:///system` by default).
+//!
+//! `libvirt list` lists the domains configured for SEV with their policies, and `libvirt
+//! measurement` prints the launch measurement of a domain started paused, as reported by
+//! `virDomainGetLaunchSecurityInfo`.
+//!
+//! ```console
+//! $ sevctl libvirt list
+//! $ sevctl libvirt measurement guest
+//! ```
+//!
+//! `libvirt set-launch-security` writes the policy and the GODH certificate and session blob of a
+//! session created by `session create` into the domain's `<launchSecurity>` element, keeping its
+//! other settings.
+//!
+//! ```console
+//! $ sevctl libvirt set-launch-security --session ./sessions/ --name myvm --policy 0x05 guest
+//! ```
+//!
+//! ## man
+//!
+//! Generates man pages in roff from the command line definitions, so that they always match the
+//! binary. Without `--dir`, the single `sevctl.1` page covering every command is printed; with it,
+//! `sevctl.1` and a `sevctl-<command>.1` page per command are written to the directory.
+//!
+//! ```console
+//! $ sevctl man > sevctl.1
+//! $ sevctl man --dir /usr/share/man/man1
+//! ```
+//!
+//! ## measurement
+//!
+//! Computes the launch digest a guest owner should expect from the regions measured during the
+//! launch or from a precomputed `--digest`. The OVMF image given with `--firmware` is measured
+//! first, exactly as QEMU maps it into the guest, followed by any additional `--data` regions
+//! (LAUNCH_UPDATE_DATA) and `--vmsa` pages (SEV-ES LAUNCH_UPDATE_VMSA) in launch order. When the
+//! TIK and the launch context (API version, build ID, policy and the nonce returned by
+//! LAUNCH_MEASURE) are supplied, the expected LAUNCH_MEASURE measurement is computed as well.
+//! Instead of `--api-major`, `--api-minor` and `--build-id`, `--from-platform` reads the version
+//! of the local SEV firmware, and `--platform-info` reads it from a JSON file such as the output
+//! of QEMU's `query-sev`.
+//!
+//! For SEV-ES guests (`--sev-es`, or a policy with the ES bit set), the initial VMSA of every vCPU
+//! is measured last. The VMSAs are generated from the QEMU/KVM reset state for `--num-cpus`
+//! vCPUs; application processors start at the reset vector the OVMF image declares in its SEV-ES
+//! reset block, or at `--ap-eip`. The vCPU signature loaded into RDX comes from
+//! `--vcpu-type`, which accepts the QEMU `-cpu` models (`EPYC`, `EPYC-Rome`, `EPYC-Milan`,
+//! `EPYC-Genoa` and their versions) or the code names `naples`, `rome`, `milan` and `genoa`.
+//! `--vcpu-family`, `--vcpu-model` and `--vcpu-stepping` override parts of the model, and
+//! `--vcpu-sig` sets the signature outright.
+//!
+//! For measured direct boot, `--kernel`, `--initrd` and `--cmdline` add the kernel hashes table
+//! QEMU builds for `-kernel`, `-initrd` and `-append` right after the firmware. The firmware must
+//! reserve an area for that table.
+//!
+//! ```console
+//! $ sevctl measurement build --firmware OVMF.fd --api-major 0 --api-minor 24 --build-id 15 \
+//!     --policy 0x01 --nonce 4fbe0bedbad6c86ae8f68971d103e554 --tik tik.bin
+//! ```
+//!
+//! The digest, nonce and TIK may each be hex, base64 or raw; their encoding is detected unless
+//! `--in-form` names it. `--out-form` selects hex (the default) or base64 output, while `raw`
+//! writes just the final value (the measurement if computed, else the digest) to stdout.
+//!
+//! ```console
+//! $ sevctl measurement build --firmware OVMF.fd --out-form raw > digest.bin
+//! ```
+//!
+//! Instead of repeating the launch parameters, `--domain` reads the firmware (`<loader>`),
+//! kernel, initrd and command line, vCPU count and model, and SEV policy from a libvirt domain
+//! XML file. With `--connect`, `--domain` names a domain defined on that libvirt connection.
+//! Options given on the command line take precedence.
+//!
+//! ```console
+//! $ sevctl measurement build --connect qemu:///system --domain guest --api-major 0 \
+//!     --api-minor 24 --build-id 15 --nonce 4fbe0bedbad6c86ae8f68971d103e554 --tik tik.bin
+//! ```
+//!
+//! `measurement verify` checks the measurement QEMU reports with `query-sev-launch-measure` (the
+//! measurement followed by the nonce, base64-encoded) against the same inputs and the TIK. On a
+//! mismatch it looks for a single launch parameter (platform version, policy bit, SEV-ES, vCPU
+//! count or type) that explains the difference and exits with an error.
+//!
+//! ```console
+//! $ sevctl measurement verify --measurement "$(virsh domlaunchsecinfo guest | awk '/measurement/ {print $3}')" \
+//!     --firmware OVMF.fd --api-major 0 --api-minor 24 --build-id 15 --policy 0x01 --tik tik.bin
+//! ```
+//!
+//! With `--qmp`, the measurement, platform version and policy are queried from the guest's QMP
+//! socket instead (`query-sev-launch-measure` and `query-sev`); values given on the command line
+//! take precedence.
+//!
+//! ```console
+//! $ sevctl measurement verify --qmp /run/qemu/guest.qmp --firmware OVMF.fd --tik tik.bin
+//! ```
+//!
+//! ## migration
+//!
+//! Prepares the guest owner's side of SEV live migration. `migration policy` builds a guest policy
+//! that allows sending the guest (NOSEND clear), optionally only within its domain (`--domain`) or
+//! to SEV platforms (`--sev`); debugging and key sharing stay disallowed unless `--debug` or
+//! `--key-sharing` is given.
+//!
+//! ```console
+//! $ sevctl migration policy --domain --sev --min-api 0.17
+//! 0x11000033
+//! ```
+//!
+//! Before a guest is sent, `migration authorize` checks that its policy allows it and verifies the
+//! target platform's certificate chain up to a trusted AMD root key, read like `session create`
+//! does. It then writes the base64 certificates the source platform's SEND_START wraps the
+//! transport keys for: the target PDH (`pdh.b64`), its PEK and OCA (`plat-certs.b64`), and the
+//! trusted ASK and ARK (`amd-certs.b64`), never those that came with the chain. The target's RECEIVE_START takes the session and source PDH that SEND_START
+//! returns, which need no preparation.
+//!
+//! ```console
+//! $ sevctl migration authorize --name myvm --outdir ./migration/ 0x11000033 target.chain
+//! ```
+//!
+//! To hand a migration off to agents or orchestrators, `migration bundle` verifies the source and
+//! target platforms' certificate chains and writes both, in full, with the guest policy to a
+//! single JSON file. On the receiving side, `migration verify` checks the bundle again without
+//! trusting whoever wrote it, against the built-in AMD roots or `--ca` rather than the CA chains
+//! in the bundle, and prints each verdict, with the SHA-256 digest of each PDH to
+//! compare against the platforms'. It fails if any check does.
+//!
+//! ```console
+//! $ sevctl migration bundle --source source.chain --target target.chain 0x11000033 myvm.json
+//! $ sevctl migration verify myvm.json
+//! [ PASS ] policy.send - Guest policy allows sending: 0x11000033
+//! [ PASS ] source.chain - Source PDH signed up to the ARK: PDH sha256 9c1f...
+//! [ PASS ] target.chain - Target PDH signed up to the ARK: PDH sha256 4e07...
+//! ```
+//!
+//! ## nonce
+//!
+//! A report is fresh if it binds a nonce the verifier chose just before. `nonce new` prints a
+//! random nonce of `--size` bytes: 16 (the default) for `attestation request --mnonce`, up to 64
+//! for SEV-SNP report data. With `--store`, it also keeps the nonce in the nonce directory
+//! (`nonce-dir`, by default `~/.local/state/sevctl/nonces`) under a random ID, optionally only for
+//! `--ttl` seconds, so that the verification can run later, elsewhere in a script or in another
+//! process. `attestation verify --nonce-id` and `snp report verify --nonce-id` then check that
+//! the report binds the stored nonce (`nonce`), zero-padded for SEV-SNP, and that the nonce has
+//! neither expired nor been used: a nonce is used up by the first report that binds it, so a
+//! replayed report fails.
+//!
+//! ```console
+//! $ sevctl -o json nonce new --store --ttl 300
+//! $ sevctl attestation request --qmp /run/qemu/guest.qmp --mnonce "$NONCE" report.bin
+//! $ sevctl attestation verify --chain chain.cert --nonce-id "$ID" report.bin
+//! [ PASS ] pek.chain - PEK certified up to the ARK
+//! [ PASS ] report.signature - Report signed by the PEK
+//! [ PASS ] nonce - Nonce fresh: 3f9a0c6e21d47b85
+//! ```
+//!
+//! `nonce list` shows the stored nonces and whether each is fresh, used or expired, and `nonce
+//! prune` removes those that are used or expired.
+//!
+//! ## ok
+//!
+//! Checks that the CPU, KVM, the SEV device and its firmware are ready to run SEV and SEV-ES
+//! guests, and fails if any check does. Checks that need another to pass are skipped otherwise.
+//! Every check has an ID that stays the same across releases (e.g. `cpu.sev-es`,
+//! `kvm.sev-param` or `rlimit.memlock`), which all output formats include.
+//!
+//! ```console
+//! $ sevctl ok
+//! [ PASS ] cpu.amd - AMD CPU: AuthenticAMD
+//! [ PASS ] cpu.mem-encryption - Memory encryption capabilities (CPUID leaf 0x8000001f)
+//! [ PASS ] cpu.sme - Secure Memory Encryption (SME)
+//! [ PASS ] cpu.sev - Secure Encrypted Virtualization (SEV): 509 ASIDs
+//! ...
+//! ```
+//!
+//! ## ovmf
+//!
+//! Shows the SEV-related parts of an OVMF firmware image: the entries of its GUIDed structure
+//! table (kernel hashes table, secret area, SEV-ES AP reset vector) and the memory sections
+//! listed in its SEV metadata. This helps debugging images that fail to launch or measure
+//! as expected.
+//!
+//! ```console
+//! $ sevctl ovmf show OVMF.fd
+//! ```
+//!
+//! ## policy
+//!
+//! Decodes a guest policy value into its individual flags and highlights security-relevant
+//! implications, such as debugging being allowed. Values larger than 32 bits (or any value when
+//! `--snp` is given) are interpreted as SEV-SNP guest policies. Like every result, the decoded
+//! policy can be printed as JSON or YAML with `--output`, e.g. for auditing scripts.
+//!
+//! ```console
+//! $ sevctl policy explain 0x05
+//! ```
+//!
+//! ```console
+//! $ sevctl policy explain --snp 0x30000
+//! ```
+//!
+//! ## provision
+//!
+//! Installs the operator-provided OCA certificate to take ownership of the platform.
+//!
+//! ```console
+//! $ sevctl provision ~/owners-cert ~/owners-private-key
+//! ```
+//!
+//! ## report-bundle
+//!
+//! Gathers what an issue report against sevctl, the kernel or the firmware needs into a single
+//! tarball: the results of `ok` and `snp ok`, the output of `show version`, `show flags` and
+//! `show guests` as JSON, the kernel version, the parameters of the `kvm_amd` and `ccp` modules,
+//! the SEV-related kernel messages and the platform's certificate chain, as written by `export`.
+//! What cannot be gathered (e.g. the kernel messages, when not run as root) is left out, and
+//! `manifest.json` in the tarball says why. Nothing secret or identifying beyond the public
+//! certificates is included: no chip ID, no guest, no owner key, and the host name is masked in
+//! the kernel messages.
+//!
+//! ```console
+//! # sevctl report-bundle sevctl-bundle.tar
+//! ok.json
+//! snp-ok.json
+//! show-version.json
+//! ...
+//! ```
+//!
+//! ## reset
+//!
+//! Resets the SEV platform. This will clear all persistent data managed by the platform.
+//!
+//! ```console
+//! $ sevctl reset
+//! ```
+//!
+//! ## rotate
+//!
+//! Rotates the Platform Diffie-Hellman (PDH).
+//!
+//! ```console
+//! $ sevctl rotate
+//! ```
+//!
+//! ## schema
+//!
+//! Every JSON document sevctl writes or serves (results printed with `--output json` and the
+//! `serve` endpoints) carries a `schema_version`, which is increased whenever a field is removed
+//! or changes its meaning; fields may be added without it. `schema` prints the JSON Schema of a
+//! document, so that parsers can be generated or checked against it.
+//!
+//! ```console
+//! $ sevctl schema serve-status
+//! $ sevctl schema guest-report
+//! ```
+//!
+//! ## secret
+//!
+//! Encrypts a secret with the TEK of a launch session and authenticates it with the TIK, bound to
+//! the launch measurement QEMU reported. The output holds the base64 `packet-header` and `secret`
+//! arguments of QEMU's `sev-inject-launch-secret` command. Use the packet only once the
+//! measurement has been checked, e.g. with `measurement verify`; `secret inject` does both.
+//!
+//! ```console
+//! $ sevctl secret build --tek tek.bin --tik tik.bin --measurement "$MEASUREMENT" secret.bin
+//! ```
+//!
+//! Instead of a single secret file, repeated `--secret <guid>:<file>` options build the secret
+//! table OVMF exposes to the guest (e.g. through the Linux `efi_secret` driver), so one injection
+//! can carry several secrets such as a LUKS passphrase and an API token. Well-known GUIDs can be
+//! given by name: `luks` (disk passphrase), `coco-offline-kbc` and `coco-online-kbc`
+//! (Confidential Containers key broker clients). With `--firmware`, the secret is checked to fit
+//! the secret area of the OVMF image.
+//!
+//! ```console
+//! $ sevctl secret build --tek tek.bin --tik tik.bin --measurement "$MEASUREMENT" \
+//!     --firmware OVMF.fd --secret luks:luks.key --secret <guid>:token.txt
+//! ```
+//!
+//! For the common case of unlocking an encrypted disk, `secret luks-passphrase` prompts for the
+//! passphrase (or reads it from `--passphrase-file`) and writes a secret table holding it under
+//! the well-known disk passphrase GUID, ready to be passed to `secret build`.
+//!
+//! `secret inject` performs the whole injection flow against a paused guest: it queries the launch
+//! measurement and context over QMP, verifies the measurement against the expected launch inputs
+//! (the same options as `measurement verify`), builds the secret packet, injects it with
+//! `sev-inject-launch-secret` and resumes the guest. Nothing is injected if the measurement does
+//! not match.
+//!
+//! ```console
+//! $ sevctl secret inject --qmp /run/qemu/guest.qmp --firmware OVMF.fd --tek tek.bin \
+//!     --tik tik.bin secret.bin
+//! ```
+//!
+//! `secret show` displays the header of a packet written by `secret build`, in any `--output`
+//! format, to debug failed injections. With `--tik` and `--measurement` it validates the MAC, and with `--tek` it decrypts
+//! the secret and lists the entries of its secret table.
+//!
+//! ```console
+//! $ sevctl secret build --tek tek.bin --tik tik.bin --measurement "$MEASUREMENT" secret.bin > packet
+//! $ sevctl secret show --tek tek.bin --tik tik.bin --measurement "$MEASUREMENT" packet
+//! ```
+//!
+//! ```console
+//! $ sevctl secret luks-passphrase luks.table
+//! $ sevctl secret build --tek tek.bin --tik tik.bin --measurement "$MEASUREMENT" luks.table
+//! ```
+//!
+//! ## serve
+//!
+//! Serves the host's attestation material to guest owners over HTTPS, so they can fetch it
+//! programmatically instead of copying files around. `--cert` and `--key` hold the server's TLS
+//! certificate and key, and every endpoint except the certificate chain requires the bearer token
+//! stored in `--token-file` (`Authorization: Bearer <token>`).
+//!
+//! ```console
+//! $ sevctl serve --cert server.pem --key server.key --token-file token --qmp-dir /run/qemu
+//! ```
+//!
+//! | Endpoint                             | Content                                                       |
+//! |--------------------------------------|---------------------------------------------------------------|
+//! | `/sev/chain`                         | The SEV certificate chain, as written by `sevctl export`      |
+//! | `/sev/status`                        | Platform version, state, ownership, SEV-ES support and guests |
+//! | `/sev/guests/<guest>/launch-measure` | `query-sev` state and launch measurement of a paused guest    |
+//!
+//! `session create --host` reads the certificate chain from `/sev/chain`. Guests are looked up
+//! as `<guest>.qmp` sockets in `--qmp-dir`, and their launch measurement document can be passed to
+//! `measurement verify` as `--platform-info`, with its `data` as the `--measurement`.
+//!
+//! ```console
+//! $ curl -H "Authorization: Bearer $TOKEN" https://sev-host.example.com:8443/sev/guests/vm/launch-measure > vm.json
+//! $ sevctl measurement verify --platform-info vm.json --measurement "$(jq -r .data vm.json)" \
+//!     --policy "$(jq -r .policy vm.json)" --firmware OVMF.fd --tik tik.bin
+//! ```
+//!
+//! `serve` and `exporter` run as `Type=notify` systemd services: they report readiness once
+//! listening, accept a socket passed by socket activation in place of their `--listen` address,
+//! and on SIGTERM stop accepting connections and exit once the open ones are answered. Example
+//! units are in `dist/systemd`.
+//!
+//! ## session
+//!
+//! Generates the launch session artifacts (GODH certificate, session blob, TEK and TIK) for a
+//! target platform from the desired guest policy and the platform's exported certificate chain.
+//! The `--name` option prefixes every generated file name, and `--outdir` selects where they are
+//! written, so sessions for many guests can be prepared side by side.
+//!
+//! ```console
+//! $ sevctl session create --name myvm --outdir ./sessions/ 0x05 platform.chain
+//! ```
+//!
+//! This produces `myvm_godh.b64`, `myvm_session.b64`, `myvm_tek.bin` and `myvm_tik.bin`.
+//!
+//! The chain may be either the SEV chain or the full chain produced by `sevctl export --full`. It
+//! can also be read from standard input (`-`), downloaded from a URL (`--url`) or fetched from a
+//! host running `sevctl` (`--host`). In every case the chain is verified up to an AMD root key
+//! sevctl trusts before it is used: the ASK and ARK built into sevctl, or those given with `--ca`.
+//! A CA chain included in the input is never trusted by itself; its ARK must be the trusted one.
+//!
+//! ```console
+//! $ sevctl session create --host sev-host.example.com 0x05
+//! ```
+//!
+//! A session blob can be decoded to display its nonce, wrapped keys and MACs. When the TIK and
+//! the expected policy are supplied, the policy MAC is validated as well.
+//!
+//! ```console
+//! $ sevctl session show session.b64
+//! ```
+//!
+//! ```console
+//! $ sevctl session show --tik tik.bin --policy 0x05 session.b64
+//! ```
+//!
+//! ## show
+//!
+//! Describes the state of the SEV platform.
+//!
+//! ```console
+//! $ sevctl show flags
+//! ```
+//!
+//! ```console
+//! $ sevctl show guests
+//! ```
+//!
+//! `show guest` queries the SEV state of a running QEMU guest over its QMP socket: its launch
+//! state, firmware handle, platform version and policy, and its launch measurement while it is
+//! still paused waiting for a secret. QMP commands time out after 30 seconds.
+//!
+//! ```console
+//! $ sevctl show guest --qmp /run/qemu/guest.qmp
+//! ```
+//!
+//! ## snp
+//!
+//! Computes the launch digest an SEV-SNP guest's attestation report should contain. Like QEMU,
+//! the OVMF image is measured page by page below 4 GiB, followed by the pages described by its
+//! SEV metadata (zero, secrets and CPUID pages, and the kernel hashes table for measured direct
+//! boot with `--kernel`, `--initrd` and `--cmdline`), and the VMSA of every vCPU. The vCPUs are
+//! configured as for `measurement build`; the SNP feature bit is always set in `--sev-features`
+//! and the application processors' `--ap-eip` defaults to the one OVMF declares.
+//!
+//! ```console
+//! $ sevctl snp measurement --firmware OVMF.fd --num-cpus 4 --vcpu-type EPYC-Milan
+//! ```
+//!
+//! `snp id-block` builds the ID block binding that launch digest to a family ID, image ID, guest
+//! SVN and policy, and the ID authentication information signing it with the `--id-key` and,
+//! optionally, signing the ID key with the `--author-key`. Both keys are ECDSA P-384 private keys
+//! in PEM format. The output holds the base64 values of the `id-block`, `id-auth` and
+//! `author-key-enabled` properties of QEMU's `sev-snp-guest` object.
+//!
+//! ```console
+//! $ sevctl snp id-block --digest "$DIGEST" --id-key id.pem --author-key author.pem
+//! ```
+//!
+//! `snp policy build` composes the 64-bit guest policy passed to the launch (e.g. QEMU's
+//! `sev-snp-guest` `policy` property) from named flags and the minimum firmware ABI version, always
+//! setting the must-be-one bit 17 and no reserved bits. Debugging stays disallowed unless `--debug`
+//! is given. `snp policy explain` decodes a policy, e.g. one received from a third party, and warns
+//! about debugging, a cleared bit 17 and reserved bits, as `policy explain --snp` does.
+//!
+//! ```console
+//! $ sevctl snp policy build --smt --no-debug --min-abi 1.51 --single-socket
+//! 0x130133
+//! $ sevctl snp policy explain 0x130133
+//! ```
+//!
+//! `snp show` issues SNP_PLATFORM_STATUS and shows the firmware version and build, the state of
+//! SEV-SNP and the RMP, whether the chip ID is masked in attestation reports, the number of
+//! guests, and the current and reported TCB versions.
+//!
+//! ```console
+//! $ sevctl snp show
+//! api version: 1.55
+//! build: 21
+//! state: initialized
+//! rmp initialized: true
+//! mask chip id: false
+//! guests: 2
+//! current tcb: bootloader 3, tee 0, snp 14, microcode 209
+//! reported tcb: bootloader 3, tee 0, snp 14, microcode 209
+//! ```
+//!
+//! `snp rmp` shows where the BIOS reserved the Reverse Map Table (RMP), read from the RMP_BASE and
+//! RMP_END MSRs through the msr driver (as root, with the `msr` module loaded), and how much memory
+//! its entries cover compared to the system RAM in /proc/iomem; the kernel disables SEV-SNP if the
+//! RMP does not cover all of it. The kernel does not expose which pages are assigned to guests.
+//!
+//! ```console
+//! # sevctl snp rmp
+//! rmp: 0x4fde00000-0x53de03fff
+//! size: 1024 MiB
+//! entries: 67108864
+//! covered memory: 262144 MiB
+//! system ram: 262144 MiB
+//! ```
+//!
+//! `snp config set` issues SNP_SET_CONFIG to change the TCB version attestation reports are signed
+//! with and whether they mask the chip ID, keeping the setting not given. `--reported-tcb` takes
+//! `current`, the number found in attestation reports, or the components; it may not be above the
+//! installed TCB. The resulting configuration is shown.
+//!
+//! ```console
+//! # sevctl snp config set --reported-tcb bootloader=3,tee=0,snp=8,microcode=115 --mask-chip-id true
+//! reported tcb: bootloader 3, tee 0, snp 8, microcode 115
+//! mask chip id: true
+//! ```
+//!
+//! `snp commit` issues SNP_COMMIT, which makes the installed firmware and the reported TCB the
+//! oldest the platform accepts: older firmware can no longer be installed. This cannot be undone,
+//! so the command only proceeds with `--yes`.
+//!
+//! ```console
+//! # sevctl snp commit --yes
+//! ```
+//!
+//! `snp tcb` compares the TCB versions that matter for rollback protection and warns when the
+//! reported TCB is below the installed firmware's, or when the installed firmware is newer than the
+//! committed one: until `snp commit`, it can be rolled back, and a live update not written to the
+//! flash is lost on reset. The platform status lacks the committed versions, so they are shown
+//! only with `--report`, which takes all the versions from an attestation report of a guest on
+//! the platform instead.
+//!
+//! ```console
+//! $ sevctl snp tcb --report report.bin
+//! current tcb: bootloader 3, tee 0, snp 14, microcode 209
+//! reported tcb: bootloader 3, tee 0, snp 8, microcode 115
+//! committed tcb: bootloader 3, tee 0, snp 14, microcode 209
+//! current version: 1.55.21
+//! committed version: 1.55.21
+//! warning: the reported TCB understates the installed firmware (snp 8 < 14, microcode 115 < 209), ...
+//! ```
+//!
+//! `snp tcb-check` is a one-shot security posture check: it compares the installed, reported and
+//! (with `--report`) committed TCB versions, component by component, with the minimums AMD
+//! recommends for the processor generation in its firmware releases and security bulletins. Those
+//! of a table bundled with sevctl are used unless `--minimums` or the `tcb-minimums` setting names
+//! another, as a file or an HTTPS URL (e.g. of a mirror kept up to date). It fails if any version
+//! is below its minimum.
+//!
+//! ```console
+//! $ sevctl snp tcb-check
+//! [ PASS ] current.bootloader - Installed bootloader SVN at least 3: 3
+//! ...
+//! [ FAIL ] reported.snp - Reported snp SVN at least 14: 8 is below 14 (SEV-SNP firmware 1.55.21)
+//! ```
+//!
+//! `snp fetch-vcek` downloads the VCEK, the key signing a chip's attestation reports at a TCB
+//! version, from the KDS (`kds-url` in the configuration) in DER or, with `--pem`, PEM format. The
+//! chip ID (`--id`), the TCB components (`--bl`, `--tee`, `--snp` and `--ucode`) and the processor
+//! generation (`--product`) default to the local platform's reported values, and are all given to
+//! prepare verification material on another machine. The generation is told from the CPU family
+//! and model, and the KDS only serves Milan and later. VCEKs are kept in `cache-dir` if configured.
+//!
+//! ```console
+//! $ sevctl snp fetch-vcek --product Milan --id "$CHIP_ID" --bl 3 --tee 0 --snp 14 --ucode 209 vcek.der
+//! ```
+//!
+//! Inside an SEV-SNP guest, `snp report` requests an attestation report with SNP_GET_REPORT on
+//! /dev/sev-guest, binding the contents of the `--data` file (up to 64 bytes, e.g. a nonce or the
+//! hash of a key) or, with `--random`, 64 random bytes. The raw report is written to the output
+//! file and a summary of it is shown.
+//!
+//! ```console
+//! # sevctl snp report --data nonce.bin report.bin
+//! ```
+//!
+//! `--report-data-sha512` binds the SHA-512 digest of a file of any size instead, such as a public
+//! key whose possession the report should prove or the transcript of a nonce exchange. A verifier
+//! holding the same file checks the binding with `snp report verify --report-data-sha512`.
+//!
+//! ```console
+//! # sevctl snp report --report-data-sha512 tls.pub report.bin
+//! ```
+//!
+//! With `--certs` or `--verify`, the report is requested with SNP_GET_EXT_REPORT, which also
+//! returns the certificates the host provides. `--certs` writes them to a directory as
+//! `vcek.der`, `ask.der`, `ark.der` and `vlek.der` (or named by GUID if unknown), and `--verify`
+//! checks the report with them as `snp report verify` does, applying the `--policy` appraisal
+//! file and the `--report-data-sha512` binding if given, without network access. The host chooses
+//! those certificates, so their ARK must be one given with `--ark` or set with `snp-arks`.
+//!
+//! ```console
+//! # sevctl snp report --random --certs certs --verify --ark ark-milan.pem --policy appraisal.toml report.bin
+//! ```
+//!
+//! `snp derived-key` asks the firmware inside an SEV-SNP guest for a key derived with
+//! SNP_GET_DERIVED_KEY, e.g. to seal data to the guest. The key is always bound to the guest's
+//! VMPL (`--vmpl`) and derived from the VCEK or, with `--vmrk`, the VM root key; `--policy`,
+//! `--image-id`, `--family-id` and `--measurement` bind it to those fields of the guest, and
+//! `--guest-svn` and `--tcb` to versions at most the current ones, so that the key can still be
+//! derived after an update. The 32-byte key is written to the output file with mode 0600.
 //!
 //! ```console
-//! $ sevctl --help
+//! # sevctl snp derived-key --measurement --policy --tcb bootloader=3,tee=0,snp=8,microcode=115 seal.key
 //! ```
 //!
-//! or
+//! `snp report show` decodes every field of a raw attestation report, e.g. one written by `snp
+//! report` or `guest-report`: the guest policy flags, the platform info and signing key bits, the
+//! digests and IDs in hex, and the TCB versions by component. With `--output json` the fields are
+//! printed as `guest-report` does.
 //!
 //! ```console
-//! $ sevctl show --help
+//! $ sevctl snp report show report.bin
 //! ```
 //!
-//! ## export
+//! `snp report verify` checks that the VCEK is signed by the ASK and ARK, that the ARK is a trusted
+//! one, that the VCEK's hwID and TCB extensions are the report's chip ID and reported TCB, and that
+//! the VCEK signed the report. It then applies the rules of the `--policy` appraisal file, giving a
+//! verdict per rule. Anyone can create an ARK, so only those given with `--ark` or listed in the
+//! `snp-arks` setting are trusted, compared by key; install AMD's ARK of every processor generation
+//! whose reports are verified, as published with the ASK at `vcek/v1/<product>/cert_chain` on the
+//! KDS, after checking its fingerprint.
+//! The VCEK for the report's chip ID and reported TCB and the ASK and ARK are downloaded from the
+//! KDS, both at once, unless given with `--vcek` and `--chain`; the ASK and ARK downloaded for a
+//! given VCEK are those of the generation whose ASK issued it, so that another host's report
+//! verifies without `--product`. With `--report-data-sha512`, the report data must also be the SHA-512 digest of
+//! the given file (`report-data`). Every rule of the policy is optional:
 //!
-//! Exports the SEV certificate chain to the provided file path.
+//! ```toml
+//! # The guest policy may set no other flags (see `policy explain --snp`), e.g. not DEBUG.
+//! allowed-policy-flags = ["SMT"]
+//! measurements = ["4f0d2c..."]
+//! id-key-digests = ["a1b2c3..."]
+//!
+//! # The reported TCB must be at least this, component by component.
+//! [min-tcb]
+//! bootloader = 3
+//! snp = 8
+//! microcode = 115
+//! ```
 //!
 //! ```console
-//! $ sevctl export /path/to/where/you/want/the-certificate
+//! $ sevctl snp report verify --policy appraisal.toml report.bin
+//! [ PASS ] vcek.chain - VCEK signed by the ASK and a trusted ARK
+//! [ PASS ] vcek.identity - VCEK issued for the chip and TCB of the report
+//! [ PASS ] report.signature - Report signed by the VCEK
+//! [ PASS ] tcb.bootloader - Reported bootloader SVN at least 3: 3
+//! [ FAIL ] tcb.snp - Reported snp SVN at least 8: 7 is below 8
+//! ...
 //! ```
 //!
-//! ## generate
+//! The certificates, which may come from the network or from the host, are decoded and the
+//! signatures checked by OpenSSL in a child process that a seccomp filter confines to computing
+//! and returning the verdicts, and so is a downloaded VCEK before `snp fetch-vcek` writes it. The
+//! child is killed if it makes any other system call, and the verification fails.
 //!
-//! Generates a new (self-signed) OCA certificate and key.
+//! `snp ok` checks that the host is ready to run SEV-SNP guests like `ok` does for SEV: the CPU's
+//! SNP support (`cpu.snp`), `kvm_amd.sev_snp` (`kvm.sev-snp-param`), the IOMMUs' SNP support
+//! (`iommu.snp`), the SNP commands of the SEV device (`fw.snp-status`), a firmware implementing
+//! the SEV-SNP ABI (`fw.snp-version`), and the initialization of SEV-SNP (`fw.snp-initialized`)
+//! and of the RMP (`fw.rmp-initialized`).
 //!
 //! ```console
-//! $ sevctl generate ~/my-cert ~/my-key
+//! $ sevctl snp ok
 //! ```
 //!
-//! ## provision
+//! ## verify
 //!
-//! Installs the operator-provided OCA certificate to take ownership of the platform.
+//! Verifies the full SEV/CA certificate chain. File paths to these certificates can be supplied as
+//! command line arguments if they are stored on the local filesystem. If they are not supplied, the
+//! well-known public components will be downloaded from their remote locations.
 //!
 //! ```console
-//! $ sevctl provision ~/owners-cert ~/owners-private-key
+//! $ sevctl verify
 //! ```
 //!
-//! ## reset
+//! The CA chain is the one whose ASK signed the CEK, among the Naples and Rome chains built into
+//! sevctl or, for other generations, the `--ca` files, which may be given several times.
+//! `--generation` allows only the built-in chain of one generation. When the CEK was signed by
+//! another generation's ASK, the error says which:
 //!
-//! Resets the SEV platform. This will clear all persistent data managed by the platform.
+//! ```console
+//! $ sevctl verify --sev rome.chain --generation Naples
+//! error: the CEK is signed by the Rome ASK, not by that of Naples
+//! caused by: the CA chain is of another generation
+//! ```
+//!
+//! ## vmsa
+//!
+//! Builds the initial VMSA page of a vCPU, with the reset state, vCPU signature and SEV features
+//! used for SEV-ES and SEV-SNP launch measurements, to inspect it or to pass it to other tools.
+//! The vCPU is configured with the same options as `measurement build`. `--snp` sets the SNP
+//! feature bit, and `--ap` builds an application processor's VMSA, whose reset vector is taken
+//! from the `--firmware` image unless `--ap-eip` is given.
 //!
 //! ```console
-//! $ sevctl reset
+//! $ sevctl vmsa build --vcpu-type EPYC-Milan --snp --ap --firmware OVMF.fd ap.vmsa
 //! ```
 //!
-//! ## rotate
+//! `vmsa build-set` writes the VMSAs of all `--num-cpus` vCPUs to a directory, the BSP first and
+//! then the APs, named so that they sort in launch order. The files can be passed as `--vmsa`
+//! arguments to `measurement build` in that order.
 //!
-//! Rotates the Platform Diffie-Hellman (PDH).
+//! ```console
+//! $ sevctl vmsa build-set --vcpu-type EPYC-Milan --num-cpus 4 --firmware OVMF.fd vmsas
+//! vmsas/vcpu0.vmsa
+//! vmsas/vcpu1.vmsa
+//! vmsas/vcpu2.vmsa
+//! vmsas/vcpu3.vmsa
+//! $ sevctl measurement build --firmware OVMF.fd $(printf -- '--vmsa %s ' vmsas/*.vmsa) ...
+//! ```
+//!
+//! Individual fields of an existing VMSA can be changed in place, for example to adjust the entry
+//! point for direct boot. Registers are named as in the Linux `sev_es_save_area` structure, and
+//! segment fields as `<segment>.<selector|attrib|limit|base>`. Values that do not fit their field
+//! are rejected.
 //!
 //! ```console
-//! $ sevctl rotate
+//! $ sevctl vmsa update --field rip=0x1000 --field cs.base=0 bsp.vmsa
 //! ```
 //!
-//! ## show
+//! For direct Linux boot or unikernels, `vmsa build --profile` takes the non-default initial
+//! register state from a TOML file, so that it can be kept under version control. Keys use the
+//! same field names, and values larger than a TOML integer can be given as strings.
 //!
-//! Describes the state of the SEV platform.
+//! ```console
+//! $ cat boot.toml
+//! rip = 0x100000
+//! rsi = 0x7000
+//! cs.base = 0
+//! cs.attrib = 0x29b
+//! efer = "0x1d00"
+//! $ sevctl vmsa build --vcpu-type EPYC-Milan --profile boot.toml bsp.vmsa
+//! ```
+//!
+//! `vmsa show` prints every segment register and field of a VMSA and decodes the SEV_FEATURES
+//! flags, which helps tracking down SEV-ES launch measurement mismatches.
 //!
 //! ```console
-//! $ sevctl show flags
+//! $ sevctl vmsa show bsp.vmsa
 //! ```
 //!
+//! With `--output json` (or its older alias `--format json`), the fields are written as a JSON
+//! object of hex strings instead, for post-processing by other tools or review. `vmsa build --from-json` turns such an object back
+//! into a VMSA; fields it does not name are left zero.
+//!
 //! ```console
-//! $ sevctl show guests
+//! $ sevctl vmsa show --format json bsp.vmsa > bsp.json
+//! $ sevctl vmsa build --from-json bsp.json bsp.vmsa
 //! ```
 //!
-//! ## verify
+//! `vmsa diff` lists the fields in which two VMSAs differ, e.g. to pinpoint which register a
+//! hypervisor initializes differently from the expected VMSA. It exits with an error if they
+//! differ.
 //!
-//! Verifies the full SEV/CA certificate chain. File paths to these certificates can be supplied as
-//! command line arguments if they are stored on the local filesystem. If they are not supplied, the
-//! well-known public components will be downloaded from their remote locations.
+//! ```console
+//! $ sevctl vmsa diff expected.vmsa actual.vmsa
+//! ```
+//!
+//! # Tracing
+//!
+//! Firmware commands, certificate downloads and verification steps are traced. With
+//! `--otlp-endpoint` (or `OTEL_EXPORTER_OTLP_ENDPOINT`), the spans of a command are sent to an
+//! OpenTelemetry collector over OTLP/HTTP when it ends. A W3C `TRACEPARENT` in the environment
+//! makes them part of the caller's trace, so a provisioning pipeline spanning several hosts can be
+//! followed as one trace.
 //!
 //! ```console
-//! $ sevctl verify
+//! $ TRACEPARENT=00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01 \
+//!     sevctl --otlp-endpoint http://collector:4318 export --full chain.cert
 //! ```
-
 #![deny(clippy::all)]
 #![deny(missing_docs)]
 
-mod error;
-
-use error::{Contextual, Result};
+use sevctl::output::Document;
+use sevctl::*;
 
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
-use codicon::*;
-
-use ::sev::certs::*;
-use ::sev::firmware::{Firmware, Status};
-use ::sev::Generation;
-
-use std::fs::File;
-use std::io::{Error, ErrorKind};
 use std::path::PathBuf;
 use std::process::exit;
-use std::time::Duration;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 
 #[derive(StructOpt)]
@@ -112,11 +1243,128 @@ struct Sevctl {
 
     #[structopt(short, long, help = "Don't print anything to the console")]
     pub quiet: bool,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_occurrences),
+        conflicts_with = "quiet",
+        help = "Log what is done on stderr: -v for firmware commands and requests, -vv for more"
+    )]
+    pub verbose: u8,
+
+    #[structopt(
+        short,
+        long,
+        possible_values = output::FORMATS,
+        help = "How to print results: table (the default), json, yaml, csv or quiet"
+    )]
+    pub output: Option<output::Format>,
+
+    #[structopt(
+        long,
+        help = "Add the host name, time, sevctl and firmware versions to JSON and YAML results"
+    )]
+    pub with_metadata: bool,
+
+    #[structopt(
+        long,
+        env = "OTEL_EXPORTER_OTLP_ENDPOINT",
+        hide_env_values = true,
+        help = "OpenTelemetry collector to export trace spans to (OTLP over HTTP)"
+    )]
+    pub otlp_endpoint: Option<String>,
+
+    #[structopt(
+        long,
+        env = "SEVCTL_SEV_DEVICE",
+        parse(from_os_str),
+        help = "SEV device to issue firmware commands to instead of /dev/sev"
+    )]
+    pub sev_device: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "SEVCTL_BACKEND",
+        possible_values = device::BACKENDS,
+        help = "What to issue firmware commands to: sev (the default) or mock"
+    )]
+    pub backend: Option<device::Backend>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "replay",
+        help = "Record the firmware commands issued and their results to a file"
+    )]
+    pub record: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Answer firmware commands from a file written by --record"
+    )]
+    pub replay: Option<PathBuf>,
 }
 
 #[derive(StructOpt)]
 #[structopt(author = AUTHORS, version = VERSION, about = "Utilities for managing the SEV environment")]
 enum SevctlCmd {
+    #[structopt(about = "Answer attestation report requests from the host (in an SEV-SNP guest)")]
+    Agent {
+        #[structopt(long, help = "Listen on vsock, the only transport supported so far")]
+        vsock: bool,
+
+        #[structopt(long, default_value = "5000", help = "vsock port to listen on")]
+        port: u32,
+    },
+
+    #[structopt(about = "Request and verify attestation reports of running SEV guests")]
+    Attestation {
+        #[structopt(subcommand)]
+        cmd: attestation::Attestation,
+    },
+
+    #[structopt(about = "Measure the latency of firmware commands")]
+    Bench {
+        #[structopt(flatten)]
+        args: bench::BenchArgs,
+    },
+
+    #[structopt(about = "Keep the platform's certificates in a directory")]
+    Certs {
+        #[structopt(subcommand)]
+        cmd: certs::Certs,
+    },
+
+    #[structopt(about = "Generate a shell completion script")]
+    Completions {
+        #[structopt(
+            possible_values = &Shell::variants(),
+            help = "Shell to complete for: bash, zsh, fish, powershell or elvish"
+        )]
+        shell: Shell,
+    },
+
+    #[cfg(feature = "grpc")]
+    #[structopt(about = "Serve the main operations over gRPC")]
+    Daemon {
+        #[structopt(long, default_value = "127.0.0.1:50051", help = "Address to listen on")]
+        listen: String,
+    },
+
+    #[structopt(about = "Diagnose why the host is not ready and what to do about it")]
+    Doctor {
+        #[structopt(long, help = "Diagnose SEV-SNP too")]
+        snp: bool,
+    },
+
+    #[structopt(about = "Describe a firmware status code")]
+    Error {
+        #[structopt(help = "Status code, in decimal or hex (e.g. 0x16), or its name")]
+        code: String,
+    },
+
     #[structopt(about = "Export the SEV or entire certificate chain")]
     Export {
         #[structopt(
@@ -126,10 +1374,43 @@ enum SevctlCmd {
         )]
         full: bool,
 
-        #[structopt(parse(from_os_str), help = "Certificate chain output file path")]
+        #[structopt(
+            long,
+            conflicts_with = "full",
+            requires = "policy",
+            help = "Write the chain into a directory laid out for libvirt and print the domain's <launchSecurity>"
+        )]
+        libvirt: bool,
+
+        #[structopt(
+            long,
+            requires = "libvirt",
+            help = "Prefix of the file names, as given to session create --name"
+        )]
+        name: Option<String>,
+
+        #[structopt(long, requires = "libvirt", help = "Guest policy for <launchSecurity>")]
+        policy: Option<String>,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "Certificate chain output file path, or directory with --libvirt"
+        )]
         destination: PathBuf,
     },
 
+    #[structopt(about = "Export platform metrics for Prometheus")]
+    Exporter {
+        #[structopt(flatten)]
+        args: exporter::ExporterArgs,
+    },
+
+    #[structopt(about = "Update the SEV firmware")]
+    Firmware {
+        #[structopt(subcommand)]
+        cmd: firmware::Firmware,
+    },
+
     #[structopt(about = "Generate a new self-signed OCA certificate and key")]
     Generate {
         #[structopt(parse(from_os_str), help = "OCA certificate output file path")]
@@ -139,6 +1420,108 @@ enum SevctlCmd {
         key: PathBuf,
     },
 
+    #[structopt(about = "Fetch an attestation report from the agent in a guest")]
+    GuestReport {
+        #[structopt(long, help = "vsock CID of the guest")]
+        cid: u32,
+
+        #[structopt(long, default_value = "5000", help = "vsock port of the agent")]
+        port: u32,
+
+        #[structopt(
+            long,
+            help = "Hex data (up to 64 bytes, e.g. a nonce) to bind into the report"
+        )]
+        report_data: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "File to write the raw report to instead of displaying it"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Issue firmware commands on behalf of unprivileged sevctl processes")]
+    Helper {
+        #[structopt(flatten)]
+        args: helper::HelperArgs,
+    },
+
+    #[structopt(about = "Manage the non-volatile state of platforms initialized with INIT_EX")]
+    InitEx {
+        #[structopt(subcommand)]
+        cmd: initex::InitEx,
+    },
+
+    #[structopt(about = "Report the node's SEV capabilities to Kubernetes")]
+    K8s {
+        #[structopt(subcommand)]
+        cmd: k8s::K8s,
+    },
+
+    #[structopt(about = "Retrieve secrets from a key broker service")]
+    Kbs {
+        #[structopt(subcommand)]
+        cmd: kbs::Kbs,
+    },
+
+    #[structopt(about = "Drive the guest owner's side of a guest launch")]
+    Launch {
+        #[structopt(subcommand)]
+        cmd: Box<launch::Launch>,
+    },
+
+    #[cfg(feature = "libvirt")]
+    #[structopt(about = "Manage SEV guests through libvirt")]
+    Libvirt {
+        #[structopt(subcommand)]
+        cmd: libvirt::Libvirt,
+    },
+
+    #[structopt(about = "Generate man pages in roff")]
+    Man {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Write sevctl.1 and a sevctl-<command>.1 per command to this directory"
+        )]
+        dir: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Compute expected launch measurements")]
+    Measurement {
+        #[structopt(subcommand)]
+        cmd: Box<measurement::Measurement>,
+    },
+
+    #[structopt(about = "Prepare the guest owner's side of SEV live migration")]
+    Migration {
+        #[structopt(subcommand)]
+        cmd: migration::Migration,
+    },
+
+    #[structopt(about = "Generate and keep nonces for attestation reports")]
+    Nonce {
+        #[structopt(subcommand)]
+        cmd: nonce::Nonce,
+    },
+
+    #[structopt(about = "Check that the host is ready to run SEV guests")]
+    Ok,
+
+    #[structopt(about = "Inspect OVMF firmware images")]
+    Ovmf {
+        #[structopt(subcommand)]
+        cmd: ovmf::OvmfCmd,
+    },
+
+    #[structopt(about = "Decode and explain guest policies")]
+    Policy {
+        #[structopt(subcommand)]
+        cmd: policy::Policy,
+    },
+
     #[structopt(about = "Take ownership of the SEV platform")]
     Provision {
         #[structopt(parse(from_os_str), help = "Path to the owner's OCA certificate")]
@@ -148,18 +1531,60 @@ enum SevctlCmd {
         key: PathBuf,
     },
 
+    #[structopt(about = "Gather the host's SEV state into a tarball for bug reports")]
+    ReportBundle {
+        #[structopt(parse(from_os_str), help = "Tarball to write")]
+        destination: PathBuf,
+    },
+
     #[structopt(about = "Reset the SEV platform state")]
     Reset,
 
     #[structopt(about = "Rotate PDH")]
     Rotate,
 
+    #[structopt(about = "Print the JSON Schema of a structured output")]
+    Schema {
+        #[structopt(subcommand)]
+        cmd: schema::Schema,
+    },
+
+    #[structopt(about = "Prepare secrets for injection into a guest")]
+    Secret {
+        #[structopt(subcommand)]
+        cmd: Box<secret::Secret>,
+    },
+
+    #[structopt(about = "Serve the platform's attestation material over HTTPS")]
+    Serve {
+        #[structopt(flatten)]
+        args: serve::ServeArgs,
+    },
+
+    #[structopt(about = "Create and inspect launch session artifacts")]
+    Session {
+        #[structopt(subcommand)]
+        cmd: session::Session,
+    },
+
     #[structopt(about = "Display information about the SEV platform")]
     Show {
         #[structopt(subcommand)]
         cmd: show::Show,
     },
 
+    #[structopt(about = "Utilities for SEV-SNP guests")]
+    Snp {
+        #[structopt(subcommand)]
+        cmd: snp::Snp,
+    },
+
+    #[structopt(about = "Build initial vCPU register state (VMSA) pages")]
+    Vmsa {
+        #[structopt(subcommand)]
+        cmd: vmsa::VmsaCmd,
+    },
+
     #[structopt(about = "Verify certificate chain")]
     Verify {
         #[structopt(long, parse(from_os_str), help = "Read SEV chain from specified file")]
@@ -172,371 +1597,268 @@ enum SevctlCmd {
         )]
         oca: Option<PathBuf>,
 
-        #[structopt(long, parse(from_os_str), help = "Read CA chain from specified file")]
-        ca: Option<PathBuf>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            number_of_values = 1,
+            help = "Read CA chain from specified file (repeatable, the one signing the CEK is used)"
+        )]
+        ca: Vec<PathBuf>,
+
+        #[structopt(
+            long,
+            possible_values = platform::GENERATIONS,
+            case_insensitive = true,
+            conflicts_with = "ca",
+            help = "Use only the built-in CA chain of this generation"
+        )]
+        generation: Option<platform::Generation>,
     },
 }
-
-fn download(url: &str, usage: Usage) -> Result<sev::Certificate> {
-    let mut rsp = reqwest::blocking::get(url);
-    let mut http_request_replies = Vec::new();
-    for request_wait_seconds in &[0, 2, 4, 6, 9] {
-        std::thread::sleep(Duration::from_secs(*request_wait_seconds));
-        match &rsp {
-            // HTTP request has succeeded, ensure that the status code does not indicate an error.
-            Ok(found) => {
-                if found.status().is_success() {
-                    break;
-                } else {
-                    http_request_replies.push(format!(
-                        "Attempt #{}, Error: Received HTTP response #{}",
-                        http_request_replies.len() + 1,
-                        found.status()
-                    ));
-                    rsp = reqwest::blocking::get(url);
-                }
-            }
-            // HTTP request has failed.
-            Err(_) => break,
+fn main() {
+    let sevctl = Sevctl::from_args();
+    let config = config::init().unwrap_or_else(|err| {
+        let format = match sevctl.quiet {
+            true => output::Format::Quiet,
+            false => sevctl.output.unwrap_or(output::Format::Table),
+        };
+        fail(&err, format)
+    });
+    let format = match sevctl.quiet {
+        true => output::Format::Quiet,
+        false => sevctl
+            .output
+            .or(config.output)
+            .unwrap_or(output::Format::Table),
+    };
+    output::init(format);
+    if sevctl.with_metadata {
+        output::with_metadata();
+    }
+    let level = match sevctl.verbose {
+        0 => None,
+        1 => Some(tracing::Level::INFO),
+        2 => Some(tracing::Level::DEBUG),
+        _ => Some(tracing::Level::TRACE),
+    };
+    trace::init(level, sevctl.otlp_endpoint.as_deref());
+    progress::init(format != output::Format::Quiet && level.is_none());
+    if let Some(backend) = sevctl.backend {
+        device::select(backend);
+    }
+    if let Some(path) = sevctl.sev_device {
+        device::init(path);
+    }
+    if !repeats(&sevctl.cmd) {
+        device::share();
+    }
+    if let Some(path) = sevctl.record {
+        replay::record(path);
+    }
+    if let Some(path) = sevctl.replay {
+        if let Err(err) = replay::replay(path) {
+            fail(&err, format);
         }
     }
-    let mut rsp = rsp.context(format!(
-        "Failed to complete request: {}\nError codes received from server:\n{}",
-        usage,
-        http_request_replies.join("\n")
-    ))?;
-
-    let mut buf = Vec::new();
-    rsp.copy_to(&mut buf)
-        .context(format!("unable to complete {} download", usage))?;
-
-    sev::Certificate::decode(&mut &buf[..], ())
-        .context(format!("unable to parse downloaded {}", usage))
-}
-
-fn firmware() -> Result<Firmware> {
-    Firmware::open().context("unable to open /dev/sev")
-}
-
-fn platform_status() -> Result<Status> {
-    firmware()?
-        .platform_status()
-        .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-        .context("unable to fetch platform status")
-}
-
-fn chain() -> Result<sev::Chain> {
-    const CEK_SVC: &str = "https://kdsintf.amd.com/cek/id";
-
-    let mut chain = firmware()?
-        .pdh_cert_export()
-        .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-        .context("unable to export SEV certificates")?;
-
-    let id = firmware()?
-        .get_identifier()
-        .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-        .context("error fetching identifier")?;
-    let url = format!("{}/{}", CEK_SVC, id);
 
-    chain.cek = download(&url, Usage::CEK)?;
+    let span = tracing::info_span!("sevctl", command = sevctl.cmd.name());
+    let _entered = span.enter();
 
-    Ok(chain)
-}
-
-fn ca_chain_builtin(chain: &sev::Chain) -> Result<ca::Chain> {
-    use std::convert::TryFrom;
-
-    Generation::try_from(chain)
-        .map_err(|_| {
-            Error::new(
-                ErrorKind::NotFound,
-                "could not find a matching builtin certificate",
-            )
-        })
-        .context("failed to deduce platform generation")
-        .map(|g| g.into())
-}
+    // Commands changing the platform do not run unless they can be audited.
+    let audited = audited(&sevctl.cmd);
+    let mut audit = audited.map(|_| audit::Log::open().unwrap_or_else(|err| fail(&err, format)));
 
-fn main() {
-    let sevctl = Sevctl::from_args();
-    let status = match sevctl.cmd {
-        SevctlCmd::Export { full, destination } => export::cmd(full, destination),
+    let mut status = match sevctl.cmd {
+        SevctlCmd::Agent { vsock, port } => agent::cmd(vsock, port),
+        SevctlCmd::Attestation { cmd } => attestation::cmd(cmd),
+        SevctlCmd::Bench { args } => bench::cmd(args),
+        SevctlCmd::Certs { cmd } => certs::cmd(cmd),
+        SevctlCmd::Completions { shell } => {
+            Sevctl::clap().gen_completions_to("sevctl", shell, &mut std::io::stdout());
+            Ok(())
+        }
+        #[cfg(feature = "grpc")]
+        SevctlCmd::Daemon { listen } => daemon::cmd(listen),
+        SevctlCmd::Doctor { snp } => doctor::cmd(snp),
+        SevctlCmd::Error { code } => codes::cmd(code),
+        SevctlCmd::Export {
+            full,
+            libvirt,
+            name,
+            policy,
+            destination,
+        } => export::cmd(full, libvirt, name, policy, destination),
+        SevctlCmd::Exporter { args } => exporter::cmd(args),
+        SevctlCmd::Firmware { cmd } => firmware::cmd(cmd),
         SevctlCmd::Generate { cert, key } => generate::cmd(cert, key),
+        SevctlCmd::GuestReport {
+            cid,
+            port,
+            report_data,
+            output,
+        } => agent::guest_report(cid, port, report_data, output),
+        SevctlCmd::Helper { args } => helper::cmd(args),
+        SevctlCmd::InitEx { cmd } => initex::cmd(cmd),
+        SevctlCmd::K8s { cmd } => k8s::cmd(cmd),
+        SevctlCmd::Kbs { cmd } => kbs::cmd(cmd),
+        SevctlCmd::Launch { cmd } => launch::cmd(*cmd),
+        #[cfg(feature = "libvirt")]
+        SevctlCmd::Libvirt { cmd } => libvirt::cmd(cmd),
+        SevctlCmd::Man { dir } => man::cmd(Sevctl::clap(), dir),
+        SevctlCmd::Measurement { cmd } => measurement::cmd(*cmd),
+        SevctlCmd::Migration { cmd } => migration::cmd(cmd),
+        SevctlCmd::Nonce { cmd } => nonce::cmd(cmd),
+        SevctlCmd::Ok => ok::cmd(),
+        SevctlCmd::Ovmf { cmd } => ovmf::cmd(cmd),
+        SevctlCmd::Policy { cmd } => policy::cmd(cmd),
         SevctlCmd::Provision { cert, key } => provision::cmd(cert, key),
+        SevctlCmd::ReportBundle { destination } => bundle::cmd(destination),
         SevctlCmd::Reset => reset::cmd(),
         SevctlCmd::Rotate => rotate::cmd(),
+        SevctlCmd::Schema { cmd } => schema::cmd(cmd),
+        SevctlCmd::Secret { cmd } => secret::cmd(*cmd),
+        SevctlCmd::Serve { args } => serve::cmd(args),
+        SevctlCmd::Session { cmd } => session::cmd(cmd),
         SevctlCmd::Show { cmd } => show::cmd(cmd),
-        SevctlCmd::Verify { sev, oca, ca } => verify::cmd(sevctl.quiet, sev, oca, ca),
+        SevctlCmd::Snp { cmd } => snp::cmd(cmd),
+        SevctlCmd::Vmsa { cmd } => vmsa::cmd(cmd),
+        SevctlCmd::Verify {
+            sev,
+            oca,
+            ca,
+            generation,
+        } => verify::cmd(sev, oca, ca, generation),
     };
 
-    if let Err(err) = status {
-        if sevctl.quiet {
-            exit(1);
-        }
-        eprintln!("error: {}", err);
-        let mut err: &(dyn std::error::Error + 'static) = &err;
-        while let Some(cause) = err.source() {
-            eprintln!("caused by: {}", cause);
-            err = cause;
+    if let (Some(command), Some(log)) = (audited, audit.as_mut()) {
+        if let Err(err) = log.record(&audit::command(command, &status)) {
+            match status {
+                Ok(()) => status = Err(err),
+                Err(_) => tracing::error!(error = %err),
+            }
         }
-
-        exit(1);
-    }
-}
-
-mod reset {
-    use super::*;
-
-    pub fn cmd() -> Result<()> {
-        firmware()?
-            .platform_reset()
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-            .context("error resetting platform")
     }
-}
 
-mod show {
-    use super::*;
-    use ::sev::firmware::Flags;
-
-    #[derive(StructOpt)]
-    pub enum Show {
-        #[structopt(about = "Show the current platform flags")]
-        Flags,
-
-        #[structopt(about = "Show the current number of guests")]
-        Guests,
-
-        #[structopt(about = "Show the platform's firmware version")]
-        Version,
+    if let Err(err) = &status {
+        tracing::error!(error = %err);
     }
+    drop(_entered);
+    drop(span);
+    trace::flush();
 
-    pub fn cmd(show: Show) -> Result<()> {
-        let status = platform_status()?;
-
-        match show {
-            Show::Version => println!("{}", status.build),
-            Show::Guests => println!("{}", status.guests),
-            Show::Flags => {
-                for f in [Flags::OWNED, Flags::ENCRYPTED_STATE].iter() {
-                    println!(
-                        "{}",
-                        match status.flags & *f {
-                            Flags::ENCRYPTED_STATE => "es",
-                            Flags::OWNED => "owned",
-                            _ => continue,
-                        }
-                    );
-                }
-            }
-        }
-
-        Ok(())
+    if let Err(err) = status {
+        fail(&err, format);
     }
 }
 
-mod export {
-    use super::*;
-    use std::io::Write;
-
-    pub fn cmd(full: bool, dest: PathBuf) -> Result<()> {
-        let chain = chain()?;
-
-        let mut out = std::io::Cursor::new(Vec::new());
-
-        if full {
-            let full_chain = Chain {
-                ca: ca_chain_builtin(&chain)?,
-                sev: chain,
-            };
-
-            full_chain
-                .encode(&mut out, ())
-                .context("certificate chain encoding failed")?;
-        } else {
-            chain
-                .encode(&mut out, ())
-                .context("certificate chain encoding failed")?;
+impl SevctlCmd {
+    /// The command's name as given on the command line, e.g. `guest-report`.
+    fn name(&self) -> &'static str {
+        match self {
+            SevctlCmd::Agent { .. } => "agent",
+            SevctlCmd::Attestation { .. } => "attestation",
+            SevctlCmd::Bench { .. } => "bench",
+            SevctlCmd::Certs { .. } => "certs",
+            SevctlCmd::Completions { .. } => "completions",
+            #[cfg(feature = "grpc")]
+            SevctlCmd::Daemon { .. } => "daemon",
+            SevctlCmd::Doctor { .. } => "doctor",
+            SevctlCmd::Error { .. } => "error",
+            SevctlCmd::Export { .. } => "export",
+            SevctlCmd::Exporter { .. } => "exporter",
+            SevctlCmd::Firmware { .. } => "firmware",
+            SevctlCmd::Generate { .. } => "generate",
+            SevctlCmd::GuestReport { .. } => "guest-report",
+            SevctlCmd::Helper { .. } => "helper",
+            SevctlCmd::InitEx { .. } => "init-ex",
+            SevctlCmd::K8s { .. } => "k8s",
+            SevctlCmd::Kbs { .. } => "kbs",
+            SevctlCmd::Launch { .. } => "launch",
+            #[cfg(feature = "libvirt")]
+            SevctlCmd::Libvirt { .. } => "libvirt",
+            SevctlCmd::Man { .. } => "man",
+            SevctlCmd::Measurement { .. } => "measurement",
+            SevctlCmd::Migration { .. } => "migration",
+            SevctlCmd::Nonce { .. } => "nonce",
+            SevctlCmd::Ok => "ok",
+            SevctlCmd::Ovmf { .. } => "ovmf",
+            SevctlCmd::Policy { .. } => "policy",
+            SevctlCmd::Provision { .. } => "provision",
+            SevctlCmd::ReportBundle { .. } => "report-bundle",
+            SevctlCmd::Reset => "reset",
+            SevctlCmd::Rotate => "rotate",
+            SevctlCmd::Schema { .. } => "schema",
+            SevctlCmd::Secret { .. } => "secret",
+            SevctlCmd::Serve { .. } => "serve",
+            SevctlCmd::Session { .. } => "session",
+            SevctlCmd::Show { .. } => "show",
+            SevctlCmd::Snp { .. } => "snp",
+            SevctlCmd::Vmsa { .. } => "vmsa",
+            SevctlCmd::Verify { .. } => "verify",
         }
-
-        let mut file = File::create(dest).context("unable to create output file")?;
-
-        file.write_all(&out.into_inner())
-            .context("unable to write output file")?;
-
-        Ok(())
     }
 }
 
-mod verify {
-    use super::*;
-    use colorful::*;
-    use std::convert::TryInto;
-    use std::fmt::Display;
-
-    pub fn cmd(
-        quiet: bool,
-        sev: Option<PathBuf>,
-        oca: Option<PathBuf>,
-        ca: Option<PathBuf>,
-    ) -> Result<()> {
-        let mut schain = sev_chain(sev)?;
-        let cchain = match ca {
-            Some(ca) => ca_chain(ca)?,
-            None => ca_chain_builtin(&schain)?,
-        };
-        let mut err = false;
-
-        if let Some(filename) = oca {
-            let mut file = File::open(filename).context("unable to open OCA certificate file")?;
-
-            schain.oca = sev::Certificate::decode(&mut file, ()).context("unable to decode OCA")?;
-        }
-
-        if !quiet {
-            println!("{}", schain.pdh);
-        }
-        err |= status("", &schain.pek, &schain.pdh, quiet);
-        err |= status("   ", &schain.oca, &schain.pek, quiet);
-        err |= status("   ", &schain.cek, &schain.pek, quiet);
-        err |= status("      ", &cchain.ask, &schain.cek, quiet);
-        err |= status("         ", &cchain.ark, &cchain.ask, quiet);
-
-        if !quiet {
-            println!("\n • = self signed, ⬑ = signs, •̷ = invalid self sign, ⬑̸ = invalid signs");
-        }
-
-        if err as i32 == 0 {
-            Ok(())
-        } else {
-            Err(error::Context::new(
-                "SEV/CA certificate verification failed",
-                Box::<Error>::new(ErrorKind::InvalidData.into()),
-            ))
+/// The name of a command changing the platform or releasing a secret, which the audit
+/// log records.
+fn audited(cmd: &SevctlCmd) -> Option<&'static str> {
+    match cmd {
+        SevctlCmd::Firmware {
+            cmd: firmware::Firmware::Update { .. },
+        } => Some("firmware update"),
+        SevctlCmd::InitEx {
+            cmd: initex::InitEx::Init,
+        } => Some("init-ex init"),
+        SevctlCmd::InitEx {
+            cmd: initex::InitEx::Restore { .. },
+        } => Some("init-ex restore"),
+        SevctlCmd::Launch { cmd } if matches!(**cmd, launch::Launch::Attest { .. }) => {
+            Some("launch attest")
         }
-    }
-
-    fn status<'a, P, C>(pfx: &str, p: &'a P, c: &'a C, quiet: bool) -> bool
-    where
-        P: Display,
-        C: Display,
-        &'a P: TryInto<Usage, Error = Error>,
-        (&'a P, &'a P): Verifiable,
-        (&'a P, &'a C): Verifiable,
-    {
-        let sig_valid = (p, c).verify().is_ok();
-        let lnk = if sig_valid {
-            "⬑".green()
-        } else {
-            "⬑̸".red()
-        };
-
-        !match p.try_into().unwrap() {
-            Usage::OCA | Usage::ARK => {
-                let selfsig_valid = (p, p).verify().is_ok();
-                let slf = if selfsig_valid {
-                    "•".green()
-                } else {
-                    "•̷".red()
-                };
-                if !quiet {
-                    println!("{}{}{} {}", pfx, slf, lnk, p);
-                }
-                sig_valid && selfsig_valid
-            }
-
-            _ => {
-                if !quiet {
-                    println!("{} {} {}", pfx, lnk, p);
-                }
-                sig_valid
-            }
+        SevctlCmd::Provision { .. } => Some("provision"),
+        SevctlCmd::Reset => Some("reset"),
+        SevctlCmd::Rotate => Some("rotate"),
+        SevctlCmd::Secret { cmd } if matches!(**cmd, secret::Secret::Inject { .. }) => {
+            Some("secret inject")
         }
-    }
-
-    fn sev_chain(filename: Option<PathBuf>) -> Result<sev::Chain> {
-        Ok(match filename {
-            None => chain()?,
-            Some(f) => {
-                let mut file =
-                    File::open(f).context("unable to open SEV certificate chain file")?;
-
-                sev::Chain::decode(&mut file, ()).context("unable to decode chain")?
-            }
-        })
-    }
-
-    fn ca_chain(filename: PathBuf) -> Result<ca::Chain> {
-        let mut file = File::open(&filename).context("unable to open CA certificate chain file")?;
-        ca::Chain::decode(&mut file, ()).context("unable to decode chain")
+        SevctlCmd::Snp {
+            cmd: snp::Snp::Commit { .. },
+        } => Some("snp commit"),
+        SevctlCmd::Snp {
+            cmd: snp::Snp::Config {
+                cmd: snp::SnpConfig::Set { .. },
+            },
+        } => Some("snp config set"),
+        _ => None,
     }
 }
 
-mod generate {
-    use super::*;
-
-    pub fn cmd(oca_path: PathBuf, key_path: PathBuf) -> Result<()> {
-        let (mut oca, prv) = sev::Certificate::generate(sev::Usage::OCA)
-            .context("unable to generate OCA key pair")?;
-        prv.sign(&mut oca).context("key signing failed")?;
-
-        // Write the certificate
-        let mut crt = File::create(oca_path).context("unable to create certificate file")?;
-        oca.encode(&mut crt, ())
-            .context("unable to write certificate file")?;
-
-        // Write the private key
-        let mut key = File::create(key_path).context("unable to create key file")?;
-        prv.encode(&mut key, ())
-            .context("unable to write key file")?;
-
-        Ok(())
+/// Whether a command issues firmware commands over and over, as services and
+/// `bench` do, which must not reuse the status read earlier.
+fn repeats(cmd: &SevctlCmd) -> bool {
+    match cmd {
+        SevctlCmd::Bench { .. } => true,
+        #[cfg(feature = "grpc")]
+        SevctlCmd::Daemon { .. } => true,
+        SevctlCmd::Exporter { .. } => true,
+        SevctlCmd::Helper { .. } => true,
+        SevctlCmd::Serve { .. } => true,
+        _ => false,
     }
 }
 
-mod rotate {
-    use super::*;
-
-    pub fn cmd() -> Result<()> {
-        firmware()?
-            .pdh_generate()
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-            .context("unable to rotate PDH")?;
-
-        Ok(())
+/// Reports an error with its causes in `format`, and exits.
+///
+/// A command that printed its result before failing (e.g. `verify` with an
+/// invalid chain) already told the outcome, so its error only goes to stderr.
+fn fail(err: &error::Context, format: output::Format) -> ! {
+    match format {
+        output::Format::Json | output::Format::Yaml if !output::printed() => {
+            output::print(&err.report())
+        }
+        output::Format::Quiet => (),
+        _ => eprint!("{}", err.report().table()),
     }
-}
 
-mod provision {
-    use super::*;
-
-    pub fn cmd(oca_path: PathBuf, prv_key_path: PathBuf) -> Result<()> {
-        let mut fw = firmware()?;
-        let cert = File::open(oca_path.clone())
-            .context(format!("failed to open {}", oca_path.display()))
-            .and_then(|mut f| {
-                sev::Certificate::decode(&mut f, ()).context("failed to decode OCA")
-            })?;
-
-        let prv_key = File::open(prv_key_path.clone())
-            .context(format!("failed to open {}", prv_key_path.display()))
-            .and_then(|mut f| {
-                PrivateKey::<sev::Usage>::decode(&mut f, &cert)
-                    .context("failed to decode OCA private key")
-            })?;
-
-        let mut pek = fw
-            .pek_csr()
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-            .context("cross signing request failed")?;
-        prv_key
-            .sign(&mut pek)
-            .context("failed to sign PEK with OCA private key")?;
-        fw.pek_cert_import(&pek, &cert)
-            .map_err(|e| Error::new(ErrorKind::Other, format!("{:?}", e)))
-            .context("failed to import the newly-signed PEK")?;
-
-        Ok(())
-    }
+    exit(1)
 }
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Man pages in roff, generated from the command line definitions so that they
+//! always match the binary.
+//!
+//! `sevctl.1` documents the whole command tree; `sevctl-<command>.1` documents
+//! one top-level command and its subcommands, as packagers of git-style tools
+//! expect.
+
+use super::*;
+use structopt::clap::{App, ErrorKind as ClapErrorKind};
+
+pub fn cmd(app: App, dir: Option<PathBuf>) -> Result<()> {
+    // The pages must not depend on the terminal they were generated in.
+    let app = app.set_term_width(80);
+
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            print!("{}", page(&app, &[]));
+            return Ok(());
+        }
+    };
+
+    std::fs::create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+
+    let mut pages = vec![("sevctl.1".to_string(), page(&app, &[]))];
+    for sub in &app.p.subcommands {
+        let name = sub.get_name().to_string();
+        pages.push((format!("sevctl-{}.1", name), page(&app, &[name])));
+    }
+
+    for (name, text) in pages {
+        let path = dir.join(name);
+        std::fs::write(&path, text).context(format!("unable to write {}", path.display()))?;
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+/// The page of the command at `path` (empty for sevctl itself), including its
+/// subcommands.
+fn page(app: &App, path: &[String]) -> String {
+    let command = std::iter::once("sevctl")
+        .chain(path.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    let sub = lookup(app, path);
+    let about = sub.p.meta.about.unwrap_or("");
+
+    let mut out = format!(
+        ".TH {} 1 \"\" \"sevctl {}\" \"User Commands\"\n",
+        escape(&command.join("-").to_uppercase()),
+        escape(VERSION)
+    );
+    out += &format!(
+        ".SH NAME\n{} \\- {}\n",
+        escape(&command.join("-")),
+        escape(about)
+    );
+    out += &format!(".SH DESCRIPTION\n{}\n", preformatted(&help(app, path)));
+
+    let mut commands = Vec::new();
+    subcommands(sub, path, &mut commands);
+    if !commands.is_empty() {
+        out += ".SH COMMANDS\n";
+        for path in commands {
+            let name = std::iter::once("sevctl")
+                .chain(path.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" ");
+            out += &format!(
+                ".SS \"{}\"\n{}\n",
+                escape(&name),
+                preformatted(&help(app, &path))
+            );
+        }
+    }
+
+    out += &format!(
+        ".SH SEE ALSO\n{}\n",
+        match path.is_empty() {
+            true => "The README of sevctl at https://github.com/enarx/sevctl".to_string(),
+            false => "sevctl(1)".to_string(),
+        }
+    );
+    out
+}
+
+/// The paths of every command below `app`, depth first.
+fn subcommands(app: &App, path: &[String], out: &mut Vec<Vec<String>>) {
+    for sub in &app.p.subcommands {
+        if sub.get_name() == "help" {
+            continue;
+        }
+
+        let mut path = path.to_vec();
+        path.push(sub.get_name().to_string());
+        out.push(path.clone());
+        subcommands(sub, &path, out);
+    }
+}
+
+fn lookup<'a, 'b>(app: &'a App<'a, 'b>, path: &[String]) -> &'a App<'a, 'b> {
+    path.iter().fold(app, |app, name| {
+        app.p
+            .subcommands
+            .iter()
+            .find(|sub| sub.get_name() == name)
+            .expect("man page of an unknown command")
+    })
+}
+
+/// The `--help` text of the command at `path`, as clap displays it.
+fn help(app: &App, path: &[String]) -> String {
+    let args = std::iter::once("sevctl")
+        .chain(path.iter().map(String::as_str))
+        .chain(std::iter::once("--help"));
+
+    match app.clone().get_matches_from_safe(args) {
+        Err(e) if e.kind == ClapErrorKind::HelpDisplayed => e.message,
+        _ => String::new(),
+    }
+}
+
+/// Text shown as is, line by line.
+fn preformatted(text: &str) -> String {
+    let lines = text
+        .trim_end()
+        .lines()
+        .map(escape)
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(".nf\n{}\n.fi", lines)
+}
+
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    match text.starts_with('.') || text.starts_with('\'') {
+        true => format!("\\&{}", text),
+        false => text,
+    }
+}
@@ -0,0 +1,835 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Computation of the expected SEV launch measurement.
+//!
+//! The launch digest is the SHA-256 hash of every region the platform measures during the
+//! launch (LAUNCH_UPDATE_DATA followed by LAUNCH_UPDATE_VMSA), in order. LAUNCH_MEASURE then
+//! returns an HMAC of that digest and the launch context, keyed with the TIK.
+
+use super::*;
+use ::sev::{Build, Version};
+use openssl::hash;
+use std::io::{Read, Write};
+
+#[derive(StructOpt)]
+pub enum Measurement {
+    #[structopt(about = "Compute the expected launch digest and measurement")]
+    Build {
+        #[structopt(flatten)]
+        inputs: Inputs,
+
+        #[structopt(flatten)]
+        domain: domain::DomainArgs,
+
+        #[structopt(flatten)]
+        context: ContextArgs,
+
+        #[structopt(
+            long,
+            default_value = "hex",
+            possible_values = encoding::OUTPUT_FORMS,
+            help = "Encoding of the output: hex, base64 or raw (writes only the final value)"
+        )]
+        out_form: encoding::Form,
+    },
+
+    #[structopt(about = "Verify a launch measurement reported by QEMU")]
+    Verify {
+        #[structopt(
+            long,
+            required_unless = "qmp",
+            help = "Launch measurement and nonce, as returned by query-sev-launch-measure"
+        )]
+        measurement: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "QMP socket of the guest to query the measurement and launch context from"
+        )]
+        qmp: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        inputs: Inputs,
+
+        #[structopt(flatten)]
+        domain: domain::DomainArgs,
+
+        #[structopt(flatten)]
+        context: ContextArgs,
+    },
+}
+
+/// The regions measured during the launch.
+#[derive(StructOpt)]
+pub struct Inputs {
+    #[structopt(
+        long,
+        conflicts_with_all = &["firmware", "data"],
+        help = "Precomputed launch digest"
+    )]
+    pub digest: Option<String>,
+
+    #[structopt(long, parse(from_os_str), help = "OVMF firmware image")]
+    pub firmware: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "firmware",
+        help = "Kernel for measured direct boot"
+    )]
+    pub kernel: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "kernel",
+        help = "Initrd for measured direct boot"
+    )]
+    pub initrd: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        requires = "kernel",
+        help = "Kernel command line for measured direct boot"
+    )]
+    pub cmdline: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "Region passed to LAUNCH_UPDATE_DATA, in launch order"
+    )]
+    pub data: Vec<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "VMSA passed to LAUNCH_UPDATE_VMSA (SEV-ES), in launch order"
+    )]
+    pub vmsa: Vec<PathBuf>,
+
+    #[structopt(
+        long,
+        conflicts_with = "vmsa",
+        help = "Measure generated initial VMSAs for an SEV-ES guest"
+    )]
+    pub sev_es: bool,
+
+    #[structopt(flatten)]
+    pub vcpus: vcpu::VcpuArgs,
+}
+
+/// The launch context the measurement is bound to.
+#[derive(StructOpt)]
+pub struct ContextArgs {
+    #[structopt(long, help = "Platform API major version")]
+    pub api_major: Option<u8>,
+
+    #[structopt(long, help = "Platform API minor version")]
+    pub api_minor: Option<u8>,
+
+    #[structopt(long, help = "Platform firmware build ID")]
+    pub build_id: Option<u8>,
+
+    #[structopt(
+        long,
+        conflicts_with = "platform-info",
+        help = "Read the platform API version and build ID from the local SEV firmware"
+    )]
+    pub from_platform: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "JSON file with the platform api-major, api-minor and build-id (e.g. from query-sev)"
+    )]
+    pub platform_info: Option<PathBuf>,
+
+    #[structopt(long, help = "Guest policy (decimal or 0x-prefixed hexadecimal)")]
+    pub policy: Option<String>,
+
+    #[structopt(long, help = "Nonce returned by LAUNCH_MEASURE")]
+    pub nonce: Option<String>,
+
+    #[structopt(long, parse(from_os_str), help = "TIK from the launch session")]
+    pub tik: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "auto",
+        possible_values = encoding::INPUT_FORMS,
+        help = "Encoding of the digest, nonce and TIK: auto, hex, base64 or raw"
+    )]
+    pub in_form: encoding::Form,
+}
+
+/// Everything the launch measurement HMAC covers besides the TIK.
+#[derive(Clone)]
+pub struct Context {
+    pub build: Build,
+    pub policy: u32,
+    pub digest: [u8; 32],
+    pub mnonce: [u8; 16],
+}
+
+impl Context {
+    /// Computes the measurement LAUNCH_MEASURE returns for this context.
+    pub fn measure(&self, tik: &[u8]) -> Result<[u8; 32]> {
+        let mut msg = Vec::with_capacity(1 + 3 + 4 + 32 + 16);
+        msg.push(0x04);
+        msg.push(self.build.version.major);
+        msg.push(self.build.version.minor);
+        msg.push(self.build.build);
+        msg.extend_from_slice(&self.policy.to_le_bytes());
+        msg.extend_from_slice(&self.digest);
+        msg.extend_from_slice(&self.mnonce);
+
+        session::mac(tik, &msg)
+    }
+}
+
+pub fn cmd(measurement: Measurement) -> Result<()> {
+    match measurement {
+        Measurement::Build {
+            inputs,
+            domain,
+            context,
+            out_form,
+        } => {
+            let (digest, measurement) = build(inputs, domain, context)?;
+
+            if out_form == encoding::Form::Raw {
+                let value = measurement.as_ref().map_or(&digest[..], |m| &m[..]);
+                return std::io::stdout()
+                    .write_all(value)
+                    .context("unable to write to stdout");
+            }
+
+            output::print(&schema::LaunchDigest {
+                launch_digest: encoding::encode(out_form, &digest),
+                measurement: measurement.map(|m| encoding::encode(out_form, &m)),
+            });
+            Ok(())
+        }
+
+        Measurement::Verify {
+            measurement,
+            qmp,
+            mut inputs,
+            domain,
+            mut context,
+        } => {
+            domain.apply(&mut inputs, &mut context)?;
+            context.platform()?;
+
+            let measurement = match qmp {
+                Some(path) => {
+                    let mut qmp = qmp::Qmp::connect(&path)?;
+                    let reported = context.query(&mut qmp)?;
+                    measurement.unwrap_or(reported)
+                }
+                None => measurement.unwrap(),
+            };
+
+            let verification = verify(&measurement, &inputs, &context)?;
+            output::print(&verification);
+            mismatch(&verification)
+        }
+    }
+}
+
+/// Computes the launch digest, and the measurement if the TIK is given.
+pub fn build(
+    mut inputs: Inputs,
+    domain: domain::DomainArgs,
+    mut context: ContextArgs,
+) -> Result<([u8; 32], Option<[u8; 32]>)> {
+    domain.apply(&mut inputs, &mut context)?;
+    context.platform()?;
+    let digest = inputs.digest(&context)?;
+    let measurement = match &context.tik {
+        Some(tik) => {
+            let tik = session::read_key(tik, context.in_form)?;
+            Some(context.context(digest)?.measure(&tik)?)
+        }
+        None => None,
+    };
+
+    Ok((digest, measurement))
+}
+
+/// Runs [`build`] with the arguments of `sevctl measurement build`, for callers
+/// that are not the command line.
+pub fn build_from_args(
+    args: impl IntoIterator<Item = String>,
+) -> Result<([u8; 32], Option<[u8; 32]>)> {
+    let argv = ["measurement", "build"].iter().map(|a| a.to_string());
+    match Measurement::from_iter_safe(argv.chain(args)).context("invalid measurement arguments")? {
+        Measurement::Build {
+            inputs,
+            domain,
+            context,
+            ..
+        } => build(inputs, domain, context),
+        Measurement::Verify { .. } => unreachable!(),
+    }
+}
+
+/// The size of the blob returned by query-sev-launch-measure: measurement and nonce.
+pub const LAUNCH_MEASURE_LEN: usize = 32 + 16;
+
+/// Checks a launch measurement against the expected inputs, failing if it is not
+/// the expected one, as when deciding whether to release a secret to the guest.
+pub fn check(measurement: &str, inputs: &Inputs, args: &ContextArgs) -> Result<()> {
+    mismatch(&verify(measurement, inputs, args)?)
+}
+
+/// The error of a measurement that is not the expected one, naming the likely causes.
+fn mismatch(verification: &schema::MeasurementVerification) -> Result<()> {
+    if verification.matches {
+        return Ok(());
+    }
+
+    let hint = match verification.likely_causes.is_empty() {
+        true => "no single launch parameter explains the difference".to_string(),
+        false => format!("likely cause: {}", verification.likely_causes.join("; ")),
+    };
+    Err(error::Context::new(
+        "launch measurement does not match the expected value",
+        Box::new(Error::new(ErrorKind::InvalidData, hint)),
+    ))
+}
+
+/// Checks a launch measurement against the expected inputs, explaining any mismatch.
+#[tracing::instrument(name = "measurement_verify", skip(inputs, args), err)]
+pub fn verify(
+    measurement: &str,
+    inputs: &Inputs,
+    args: &ContextArgs,
+) -> Result<schema::MeasurementVerification> {
+    let blob = encoding::decode(
+        args.in_form,
+        measurement.as_bytes(),
+        LAUNCH_MEASURE_LEN,
+        "launch measurement",
+    )?;
+
+    let mut expected = [0u8; 32];
+    let mut mnonce = [0u8; 16];
+    expected.copy_from_slice(&blob[..32]);
+    mnonce.copy_from_slice(&blob[32..]);
+
+    let tik = match &args.tik {
+        Some(tik) => session::read_key(tik, args.in_form)?,
+        None => {
+            return Err(error::Context::new(
+                "--tik is required to verify the measurement",
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            ))
+        }
+    };
+
+    let context = args.launch(inputs.digest(args)?, mnonce)?;
+    let matches = context.measure(&tik)? == expected;
+    let likely_causes = match matches {
+        true => Vec::new(),
+        false => hints(&expected, &tik, &context, inputs, args)?,
+    };
+
+    Ok(schema::MeasurementVerification {
+        matches,
+        likely_causes,
+    })
+}
+
+impl output::Document for schema::MeasurementVerification {
+    fn table(&self) -> String {
+        if self.matches {
+            return "measurement matches\n".to_string();
+        }
+
+        let mut out = "measurement does not match\n".to_string();
+        if self.likely_causes.is_empty() {
+            out += "no single launch parameter explains the difference; check the firmware, \
+                    kernel, initrd, command line, data regions and TIK\n";
+        }
+        for cause in &self.likely_causes {
+            out += &format!("likely cause: {}\n", cause);
+        }
+        out
+    }
+}
+
+/// Searches for a single launch parameter that, when changed, reproduces the measurement.
+fn hints(
+    expected: &[u8; 32],
+    tik: &[u8],
+    context: &Context,
+    inputs: &Inputs,
+    args: &ContextArgs,
+) -> Result<Vec<String>> {
+    let mut hints = Vec::new();
+    let matches = |ctx: &Context| -> Result<bool> { Ok(ctx.measure(tik)? == *expected) };
+
+    let mut ctx = context.clone();
+    'platform: for major in 0..=1 {
+        for minor in 0..=255 {
+            for build in 0..=255 {
+                ctx.build = Build {
+                    version: Version { major, minor },
+                    build,
+                };
+                if matches(&ctx)? {
+                    hints.push(format!(
+                        "the platform firmware is {}.{} build {}, not {}.{} build {}",
+                        major,
+                        minor,
+                        build,
+                        context.build.version.major,
+                        context.build.version.minor,
+                        context.build.build
+                    ));
+                    break 'platform;
+                }
+            }
+        }
+    }
+
+    let mut ctx = context.clone();
+    for bit in 0..32 {
+        ctx.policy = context.policy ^ (1 << bit);
+        if matches(&ctx)? {
+            hints.push(format!(
+                "the guest policy is {:#x}, not {:#x}",
+                ctx.policy, context.policy
+            ));
+        }
+    }
+
+    // The vCPU configuration only matters for generated VMSAs.
+    if inputs.digest.is_some() || !inputs.vmsa.is_empty() {
+        return Ok(hints);
+    }
+
+    let prefix = inputs.prefix()?;
+    let mut ctx = context.clone();
+    let mut digest_matches = |vmsas: &[vmsa::Vmsa]| -> Result<bool> {
+        ctx.digest = finish(prefix.clone(), vmsas)?;
+        matches(&ctx)
+    };
+
+    let es = inputs.sev_es || args.es()?;
+    if !es {
+        if let Ok(vmsas) = inputs.vcpus()?.vmsas() {
+            if digest_matches(&vmsas)? {
+                hints.push("the guest is an SEV-ES guest".to_string());
+            }
+        }
+        return Ok(hints);
+    }
+
+    if digest_matches(&[])? {
+        hints.push("the guest is not an SEV-ES guest".to_string());
+    }
+
+    let mut vcpus = inputs.vcpus()?;
+    for num_cpus in 1..=MAX_HINT_CPUS {
+        vcpus.num_cpus = num_cpus;
+        if num_cpus == inputs.vcpus.num_cpus {
+            continue;
+        }
+        if let Ok(vmsas) = vcpus.vmsas() {
+            if digest_matches(&vmsas)? {
+                hints.push(format!(
+                    "the guest has {} vCPUs, not {}",
+                    num_cpus, inputs.vcpus.num_cpus
+                ));
+            }
+        }
+    }
+
+    let mut vcpus = inputs.vcpus()?;
+    vcpus.vcpu_sig = None;
+    vcpus.vcpu_family = None;
+    vcpus.vcpu_model = None;
+    vcpus.vcpu_stepping = None;
+    for name in vcpu::types() {
+        vcpus.vcpu_type = Some(name.to_string());
+        if let Ok(vmsas) = vcpus.vmsas() {
+            if digest_matches(&vmsas)? {
+                hints.push(format!("the guest's vCPU type is {}", name));
+            }
+        }
+    }
+
+    Ok(hints)
+}
+
+/// The largest vCPU count tried when looking for the cause of a mismatch.
+const MAX_HINT_CPUS: u32 = 64;
+
+impl Inputs {
+    /// Computes the launch digest, unless it was given precomputed.
+    pub fn digest(&self, context: &ContextArgs) -> Result<[u8; 32]> {
+        if let Some(digest) = &self.digest {
+            return encoding::arg(context.in_form, digest, "launch digest");
+        }
+
+        // Explicit --vmsa pages replace the generated ones.
+        let es = self.vmsa.is_empty() && (self.sev_es || context.es()?);
+        let vmsas = if es {
+            self.vcpus()?.vmsas()?
+        } else {
+            Vec::new()
+        };
+        finish(self.prefix()?, &vmsas)
+    }
+
+    /// The vCPU configuration, with the application processors' reset vector taken
+    /// from the firmware unless given explicitly.
+    fn vcpus(&self) -> Result<vcpu::VcpuArgs> {
+        let mut vcpus = self.vcpus.clone();
+        if vcpus.ap_eip.is_none() {
+            if let Some(firmware) = &self.firmware {
+                let eip = ovmf::Ovmf::load(firmware)?.ap_reset_eip();
+                vcpus.ap_eip = eip.map(|eip| format!("{:#x}", eip));
+            }
+        }
+        Ok(vcpus)
+    }
+
+    /// Hashes every region up to the generated VMSAs.
+    fn prefix(&self) -> Result<hash::Hasher> {
+        let boot = self.kernel.as_ref().map(|kernel| DirectBoot {
+            kernel: kernel.clone(),
+            initrd: self.initrd.clone(),
+            cmdline: self.cmdline.clone(),
+        });
+
+        launch_digest(
+            self.firmware.as_deref(),
+            boot.as_ref(),
+            &self.data,
+            &self.vmsa,
+        )
+    }
+}
+
+impl ContextArgs {
+    /// Fills in the launch context a running guest reports over QMP and returns its
+    /// launch measurement. Values given on the command line take precedence.
+    pub fn query(&mut self, qmp: &mut qmp::Qmp) -> Result<String> {
+        let info = qmp.query_sev()?;
+        self.api_major.get_or_insert(info.api_major);
+        self.api_minor.get_or_insert(info.api_minor);
+        self.build_id.get_or_insert(info.build_id);
+        self.policy
+            .get_or_insert_with(|| format!("{:#x}", info.policy));
+
+        qmp.query_sev_launch_measure()
+    }
+
+    /// Fills in the platform version from `--from-platform` or `--platform-info`,
+    /// unless it was given explicitly.
+    pub fn platform(&mut self) -> Result<()> {
+        let build = if self.from_platform {
+            platform_status()?.build
+        } else if let Some(path) = &self.platform_info {
+            platform_info(path)?
+        } else {
+            return Ok(());
+        };
+
+        self.api_major.get_or_insert(build.version.major);
+        self.api_minor.get_or_insert(build.version.minor);
+        self.build_id.get_or_insert(build.build);
+        Ok(())
+    }
+
+    /// Whether the policy requires SEV-ES.
+    pub fn es(&self) -> Result<bool> {
+        Ok(match &self.policy {
+            Some(policy) => session::sev_policy(policy)? & POLICY_ES != 0,
+            None => false,
+        })
+    }
+
+    /// Builds the launch context, failing when any component is missing.
+    pub fn context(&self, digest: [u8; 32]) -> Result<Context> {
+        let missing = |what: &str| {
+            error::Context::new(
+                &format!("--{} is required to compute the measurement", what),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            )
+        };
+
+        let nonce = self.nonce.as_ref().ok_or_else(|| missing("nonce"))?;
+        self.launch(digest, encoding::arg(self.in_form, nonce, "nonce")?)
+    }
+
+    /// Builds the launch context for a nonce obtained elsewhere.
+    pub fn launch(&self, digest: [u8; 32], mnonce: [u8; 16]) -> Result<Context> {
+        let missing = |what: &str| {
+            error::Context::new(
+                &format!("--{} is required to compute the measurement", what),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            )
+        };
+
+        let policy = self.policy.as_ref().ok_or_else(|| missing("policy"))?;
+
+        Ok(Context {
+            build: Build {
+                version: Version {
+                    major: self.api_major.ok_or_else(|| missing("api-major"))?,
+                    minor: self.api_minor.ok_or_else(|| missing("api-minor"))?,
+                },
+                build: self.build_id.ok_or_else(|| missing("build-id"))?,
+            },
+            policy: session::sev_policy(policy)?,
+            digest,
+            mnonce,
+        })
+    }
+}
+
+/// Reads the platform version from a JSON object, such as the result of QEMU's
+/// `query-sev`, either bare or as a complete QMP response.
+fn platform_info(path: &Path) -> Result<Build> {
+    let json =
+        std::fs::read_to_string(path).context(format!("unable to read {}", path.display()))?;
+    let mut info: serde_json::Value =
+        serde_json::from_str(&json).context(format!("{} is not valid JSON", path.display()))?;
+    if let Some(ret) = info.get_mut("return") {
+        info = ret.take();
+    }
+
+    let field = |name: &str| {
+        info.get(name)
+            .or_else(|| info.get(name.replace('-', "_")))
+            .and_then(|v| v.as_u64())
+            .filter(|v| *v <= u8::MAX as u64)
+            .map(|v| v as u8)
+            .ok_or_else(|| {
+                error::Context::new(
+                    &format!("{} has no valid {}", path.display(), name),
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                )
+            })
+    };
+
+    Ok(Build {
+        version: Version {
+            major: field("api-major")?,
+            minor: field("api-minor")?,
+        },
+        build: field("build-id")?,
+    })
+}
+
+/// The policy bit requiring SEV-ES.
+const POLICY_ES: u32 = 1 << 2;
+
+/// The components of a measured direct (`-kernel`) boot.
+pub struct DirectBoot {
+    pub kernel: PathBuf,
+    pub initrd: Option<PathBuf>,
+    pub cmdline: Option<String>,
+}
+
+const HASH_TABLE_HEADER: &str = "9438d606-4f22-4cc9-b479-a793d411fd21";
+const HASH_TABLE_CMDLINE: &str = "97d02dd8-bd20-4c94-aa78-e7714d36ab2a";
+const HASH_TABLE_INITRD: &str = "44baf731-3a2f-4bd7-9af1-41e29169781d";
+const HASH_TABLE_KERNEL: &str = "4de79437-abd2-427f-b835-d5b172d2045b";
+
+/// The size of a hashes table entry: GUID, 16-bit length and SHA-256 hash.
+const HASH_TABLE_ENTRY_LEN: usize = 16 + 2 + 32;
+
+/// The size of the hashes table: header GUID, 16-bit length and three entries.
+const HASH_TABLE_LEN: usize = 16 + 2 + 3 * HASH_TABLE_ENTRY_LEN;
+
+impl DirectBoot {
+    /// Builds the padded hashes table QEMU places in the firmware's reserved area.
+    pub fn hashes_table(&self) -> Result<Vec<u8>> {
+        // Like QEMU, hash the command line with its terminating NUL.
+        let mut cmdline = self.cmdline.clone().unwrap_or_default().into_bytes();
+        cmdline.push(0);
+
+        let initrd = match &self.initrd {
+            Some(path) => read(path)?,
+            None => Vec::new(),
+        };
+
+        let kernel = read(&self.kernel)?;
+
+        let mut table = Vec::with_capacity(HASH_TABLE_LEN + 16);
+        table.extend_from_slice(guid::Guid::from_static(HASH_TABLE_HEADER).as_bytes());
+        table.extend_from_slice(&(HASH_TABLE_LEN as u16).to_le_bytes());
+
+        for (guid, data) in [
+            (HASH_TABLE_CMDLINE, &cmdline),
+            (HASH_TABLE_INITRD, &initrd),
+            (HASH_TABLE_KERNEL, &kernel),
+        ]
+        .iter()
+        {
+            table.extend_from_slice(guid::Guid::from_static(guid).as_bytes());
+            table.extend_from_slice(&(HASH_TABLE_ENTRY_LEN as u16).to_le_bytes());
+            table.extend_from_slice(
+                &hash::hash(hash::MessageDigest::sha256(), data).context("unable to hash")?,
+            );
+        }
+
+        // The table is measured in 16-byte blocks.
+        table.resize((HASH_TABLE_LEN + 15) / 16 * 16, 0);
+        Ok(table)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let mut buf = Vec::new();
+    File::open(path)
+        .context(format!("unable to open {}", path.display()))?
+        .read_to_end(&mut buf)
+        .context(format!("unable to read {}", path.display()))?;
+    Ok(buf)
+}
+
+/// Hashes the measured regions in launch order.
+fn launch_digest(
+    firmware: Option<&Path>,
+    boot: Option<&DirectBoot>,
+    data: &[PathBuf],
+    vmsa: &[PathBuf],
+) -> Result<hash::Hasher> {
+    if firmware.is_none() && data.is_empty() {
+        return Err(error::Context::new(
+            "one of --digest, --firmware or --data is required",
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let mut hasher =
+        hash::Hasher::new(hash::MessageDigest::sha256()).context("unable to create hasher")?;
+
+    if let Some(firmware) = firmware {
+        let ovmf = ovmf::Ovmf::load(firmware)?;
+        hasher
+            .update(ovmf.data())
+            .context("unable to hash firmware")?;
+
+        if let Some(boot) = boot {
+            if ovmf.entry(ovmf::SEV_HASH_TABLE_RV).is_none() {
+                return Err(error::Context::new(
+                    "firmware does not support measured direct boot (no hashes table area)",
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                ));
+            }
+
+            hasher
+                .update(&boot.hashes_table()?)
+                .context("unable to hash kernel hashes table")?;
+        }
+    }
+
+    for path in data.iter().chain(vmsa.iter()) {
+        hasher
+            .update(&read(path)?)
+            .context("unable to hash region")?;
+    }
+
+    Ok(hasher)
+}
+
+/// Completes the launch digest with the generated VMSAs.
+fn finish(mut hasher: hash::Hasher, vmsas: &[vmsa::Vmsa]) -> Result<[u8; 32]> {
+    for vmsa in vmsas {
+        hasher
+            .update(vmsa.as_bytes())
+            .context("unable to hash VMSA")?;
+    }
+
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(&hasher.finish().context("unable to hash regions")?);
+    Ok(digest)
+}
+
+impl output::Document for schema::LaunchDigest {
+    fn table(&self) -> String {
+        let mut out = format!("launch digest: {}\n", self.launch_digest);
+        if let Some(measurement) = &self.measurement {
+            out += &format!("measurement:   {}\n", measurement);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measure() {
+        let context = Context {
+            build: Build {
+                version: Version {
+                    major: 0,
+                    minor: 24,
+                },
+                build: 15,
+            },
+            policy: 0x05,
+            digest: [0x33; 32],
+            mnonce: [0x44; 16],
+        };
+
+        assert_eq!(
+            hex(&context.measure(&[0x22; 16]).unwrap()),
+            "6376176a9a901fa40d6dd5ca6c2b20630060d2f35441e4120dddfdf1de2e92a6"
+        );
+    }
+
+    #[test]
+    fn hashes_table() {
+        let kernel =
+            std::env::temp_dir().join(format!("sevctl-test-{}.bzImage", std::process::id()));
+        std::fs::write(&kernel, b"kernel image").unwrap();
+        let boot = DirectBoot {
+            kernel: kernel.clone(),
+            initrd: None,
+            cmdline: Some("console=ttyS0".to_string()),
+        };
+        let table = boot.hashes_table();
+        std::fs::remove_file(&kernel).unwrap();
+        let table = table.unwrap();
+
+        // The header and three entries, padded to the 16-byte blocks it is measured in.
+        assert_eq!(HASH_TABLE_LEN, 168);
+        assert_eq!(table.len(), 176);
+        assert_eq!(hex(&table[..16]), "06d63894224fc94cb479a793d411fd21");
+        assert_eq!(table[16..18], (HASH_TABLE_LEN as u16).to_le_bytes());
+        assert!(table[HASH_TABLE_LEN..].iter().all(|b| *b == 0));
+
+        // The initrd entry holds the hash of nothing.
+        let initrd = 18 + HASH_TABLE_ENTRY_LEN;
+        assert_eq!(
+            &table[initrd..initrd + 16],
+            guid::Guid::from_static(HASH_TABLE_INITRD).as_bytes()
+        );
+        assert_eq!(
+            hex(&table[initrd + 18..initrd + HASH_TABLE_ENTRY_LEN]),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let digest = hash::hash(hash::MessageDigest::sha256(), &table).unwrap();
+        assert_eq!(
+            hex(&digest),
+            "1e7d77701e640f633ab4d358c48e902715c76b40e9bc921956ae818e59818ca1"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The guest owner's side of SEV live migration.
+//!
+//! The source platform's SEND_START wraps the guest's transport keys for the target
+//! platform, given its PDH, its platform certificates (PEK and OCA) and the AMD
+//! certificates (ASK and ARK). As whoever holds the target PDH's private key gets the
+//! guest's memory, the guest owner verifies that chain before handing it to the
+//! source hypervisor. The target's RECEIVE_START then takes the session and the
+//! source PDH that SEND_START returns.
+
+use super::*;
+
+#[derive(StructOpt)]
+pub enum Migration {
+    #[structopt(about = "Build an SEV guest policy that allows sending the guest")]
+    Policy {
+        #[structopt(long, help = "Allow debugging")]
+        debug: bool,
+
+        #[structopt(long, help = "Allow sharing keys with other guests")]
+        key_sharing: bool,
+
+        #[structopt(long, help = "Require SEV-ES")]
+        es: bool,
+
+        #[structopt(long, help = "Only allow sending the guest within its domain")]
+        domain: bool,
+
+        #[structopt(long, help = "Only allow sending the guest to SEV platforms")]
+        sev: bool,
+
+        #[structopt(
+            long,
+            default_value = "0.0",
+            help = "Minimum firmware API version (major.minor)"
+        )]
+        min_api: String,
+    },
+
+    #[structopt(about = "Verify a target platform and write its SEND_START certificates")]
+    Authorize {
+        #[structopt(long, help = "Prefix for the generated file names")]
+        name: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            default_value = ".",
+            help = "Directory to write the generated files to"
+        )]
+        outdir: PathBuf,
+
+        #[structopt(
+            long,
+            conflicts_with_all = &["chain", "url"],
+            help = "Fetch the certificate chain from a host running sevctl"
+        )]
+        host: Option<String>,
+
+        #[structopt(
+            long,
+            conflicts_with = "chain",
+            help = "Download the certificate chain from a URL"
+        )]
+        url: Option<String>,
+
+        #[structopt(help = "Policy of the guest to send (decimal or 0x-prefixed hexadecimal)")]
+        policy: String,
+
+        #[structopt(
+            parse(from_os_str),
+            required_unless_one = &["host", "url"],
+            help = "Path to the target platform's certificate chain, or - for stdin"
+        )]
+        chain: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        roots: session::Roots,
+    },
+
+    #[structopt(about = "Package the source and target chains and the guest policy for handoff")]
+    Bundle {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Path to the source platform's certificate chain"
+        )]
+        source: PathBuf,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Path to the target platform's certificate chain"
+        )]
+        target: PathBuf,
+
+        #[structopt(help = "Policy of the guest to send (decimal or 0x-prefixed hexadecimal)")]
+        policy: String,
+
+        #[structopt(parse(from_os_str), help = "Bundle output file path")]
+        destination: PathBuf,
+
+        #[structopt(flatten)]
+        roots: session::Roots,
+    },
+
+    #[structopt(about = "Verify a bundle written by migration bundle")]
+    Verify {
+        #[structopt(parse(from_os_str), help = "Path to the bundle")]
+        bundle: PathBuf,
+
+        #[structopt(flatten)]
+        roots: session::Roots,
+    },
+}
+
+pub fn cmd(migration: Migration) -> Result<()> {
+    match migration {
+        Migration::Policy {
+            debug,
+            key_sharing,
+            es,
+            domain,
+            sev,
+            min_api,
+        } => {
+            let invalid = || {
+                error::Context::new(
+                    &format!("invalid API version {} (expected major.minor)", min_api),
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                )
+            };
+            let (major, minor) = min_api.split_once('.').ok_or_else(invalid)?;
+            let major: u8 = major.parse().map_err(|_| invalid())?;
+            let minor: u8 = minor.parse().map_err(|_| invalid())?;
+
+            // NOSEND is never set.
+            let mut value = (major as u64) << 16 | (minor as u64) << 24;
+            for (set, name) in &[
+                (!debug, "NODBG"),
+                (!key_sharing, "NOKS"),
+                (es, "ES"),
+                (domain, "DOMAIN"),
+                (sev, "SEV"),
+            ] {
+                if *set {
+                    value |= policy::sev_flag(name).unwrap();
+                }
+            }
+
+            output::print(&schema::SevPolicy {
+                policy: format!("{:#x}", value),
+                flags: policy::sev_flags(value)
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+                min_api: format!("{}.{}", major, minor),
+            });
+            Ok(())
+        }
+
+        Migration::Authorize {
+            name,
+            outdir,
+            host,
+            url,
+            policy,
+            chain,
+            roots,
+        } => {
+            sendable(&policy)?;
+
+            // The ASK and ARK written are the trusted ones, not any that came with the chain.
+            let chain =
+                session::target_chain(&session::read_chain(chain, host, url)?, roots.load()?)?;
+            let pdh = encode(&[&chain.sev.pdh])?;
+            let plat_certs = encode(&[&chain.sev.pek, &chain.sev.oca])?;
+            let amd_certs = encode(&[&chain.ca.ask, &chain.ca.ark])?;
+
+            std::fs::create_dir_all(&outdir)
+                .context(format!("unable to create {}", outdir.display()))?;
+            let path = |file: &str| session::artifact(&outdir, name.as_deref(), file);
+
+            session::write(&path("pdh.b64"), pdh.as_bytes(), 0o644)?;
+            session::write(&path("plat-certs.b64"), plat_certs.as_bytes(), 0o644)?;
+            session::write(&path("amd-certs.b64"), amd_certs.as_bytes(), 0o644)
+        }
+
+        Migration::Bundle {
+            source,
+            target,
+            policy,
+            destination,
+            roots,
+        } => {
+            let value = sendable(&policy)?;
+            let chain = |path: PathBuf| -> Result<String> {
+                let chain = session::target_chain(
+                    &session::read_chain(Some(path), None, None)?,
+                    roots.load()?,
+                )?;
+                let mut buf = Vec::new();
+                chain
+                    .encode(&mut buf, ())
+                    .context("unable to encode certificate chain")?;
+                Ok(base64::encode(buf))
+            };
+
+            let bundle = schema::Versioned::new(schema::MigrationBundle {
+                policy: format!("{:#x}", value),
+                source: chain(source).context("invalid source platform")?,
+                target: chain(target).context("invalid target platform")?,
+            });
+            session::write(&destination, bundle.to_string_pretty().as_bytes(), 0o644)
+        }
+
+        Migration::Verify { bundle, roots } => verify(&bundle, &roots),
+    }
+}
+
+/// Parses a guest policy, checking that it allows sending the guest.
+fn sendable(policy: &str) -> Result<u32> {
+    let value = session::sev_policy(policy)?;
+    if value as u64 & policy::sev_flag("NOSEND").unwrap() != 0 {
+        return Err(error::Context::new(
+            "the guest may not be sent to another platform",
+            Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                format!("its policy {:#x} sets NOSEND", value),
+            )),
+        ));
+    }
+    Ok(value)
+}
+
+/// Checks the guest policy and both chains of a bundle, each with its own verdict.
+/// The chains must lead to one of `roots`, not to the CA chains they carry.
+fn verify(path: &Path, roots: &session::Roots) -> Result<()> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let text =
+        std::fs::read_to_string(path).context(format!("unable to read {}", path.display()))?;
+    let bundle: schema::Versioned<schema::MigrationBundle> =
+        serde_json::from_str(&text).context(format!("invalid bundle {}", path.display()))?;
+    let bundle = bundle.document;
+
+    let check = |id: &str, name: &str, outcome: Result<String>| {
+        let (state, details) = match outcome {
+            Ok(details) => (schema::CheckState::Pass, details),
+            Err(e) => (schema::CheckState::Fail, e.to_string()),
+        };
+        schema::CheckResult {
+            id: id.to_string(),
+            name: name.to_string(),
+            state,
+            details: Some(details).filter(|d| !d.is_empty()),
+        }
+    };
+    let pdh = |chain: &str| -> Result<String> {
+        let buf = base64::decode(chain).context("invalid base64")?;
+        let pdh = session::target_pdh(&buf, roots.load()?)?;
+        let mut der = Vec::new();
+        pdh.encode(&mut der, ())
+            .context("unable to encode certificate")?;
+        let digest = openssl::hash::hash(openssl::hash::MessageDigest::sha256(), &der)
+            .context("unable to hash")?;
+        Ok(format!("PDH sha256 {}", hex(&digest)))
+    };
+
+    let checks = vec![
+        check(
+            "policy.send",
+            "Guest policy allows sending",
+            sendable(&bundle.policy).map(|_| bundle.policy.clone()),
+        ),
+        check(
+            "source.chain",
+            "Source PDH signed up to the ARK",
+            pdh(&bundle.source),
+        ),
+        check(
+            "target.chain",
+            "Target PDH signed up to the ARK",
+            pdh(&bundle.target),
+        ),
+    ];
+
+    let failed = checks
+        .iter()
+        .filter(|c| c.state == schema::CheckState::Fail)
+        .count();
+    let total = checks.len();
+    output::print(&schema::Checks { checks });
+
+    match failed {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            "the migration bundle is invalid",
+            Box::new(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} of {} checks failed", n, total),
+            )),
+        )),
+    }
+}
+
+/// Encodes certificates one after the other, in base64.
+fn encode<T: codicon::Encoder<(), Error = Error>>(certs: &[&T]) -> Result<String> {
+    let mut buf = Vec::new();
+    for cert in certs {
+        cert.encode(&mut buf, ())
+            .context("unable to encode certificate")?;
+    }
+    Ok(base64::encode(buf))
+}
+
+impl output::Document for schema::SevPolicy {
+    fn table(&self) -> String {
+        format!("{}\n", self.policy)
+    }
+}
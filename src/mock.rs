@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A fake SEV firmware for developing and testing sevctl without EPYC hardware,
+//! selected with `--backend mock`.
+//!
+//! It answers every firmware command sevctl issues with canned values: a working
+//! SEV and SEV-SNP platform, and the certificate chain of a Rome platform, which
+//! verifies against the built-in AMD certificates. Commands changing the platform
+//! succeed without changing it.
+//!
+//! `SEVCTL_MOCK_FAIL` scripts failures as comma-separated `COMMAND=CODE` pairs,
+//! e.g. `PDH_GEN=INVALID_PLATFORM_STATE,GET_ID=0x3`, where the code is a firmware
+//! status code by number or name. Those commands then fail with that status like
+//! the kernel reports it.
+
+use super::*;
+use ::sev::firmware::{Flags, State};
+use device::Device;
+use std::collections::HashMap;
+
+/// The certificate chain exported, as PDH, PEK, OCA and CEK.
+const CHAIN: &[u8] = include_bytes!("mock.chain");
+
+/// The fake firmware.
+pub struct Mock {
+    /// The status code each failing command returns.
+    failures: HashMap<String, u32>,
+}
+
+impl Mock {
+    pub fn new() -> Result<Self> {
+        let mut failures = HashMap::new();
+        let script = std::env::var("SEVCTL_MOCK_FAIL").unwrap_or_default();
+        for pair in script.split(',').filter(|p| !p.trim().is_empty()) {
+            let (command, code) = pair.split_once('=').ok_or_else(|| {
+                error::Context::new(
+                    &format!("invalid SEVCTL_MOCK_FAIL entry {}", pair),
+                    Box::new(Error::new(ErrorKind::InvalidInput, "expected COMMAND=CODE")),
+                )
+            })?;
+            let code = codes::parse(code).context("invalid SEVCTL_MOCK_FAIL")?;
+            failures.insert(command.trim().to_uppercase(), code.value);
+        }
+
+        Ok(Self { failures })
+    }
+
+    /// Runs a command, unless it is scripted to fail.
+    fn run<T>(&self, command: &str, ok: impl FnOnce() -> std::io::Result<T>) -> std::io::Result<T> {
+        match self.failures.get(command) {
+            Some(&status) => {
+                tracing::debug!(command, status, "failing as scripted");
+                let cause = Error::from_raw_os_error(libc::EIO);
+                Err(Error::new(cause.kind(), error::Firmware { status, cause }))
+            }
+            None => ok(),
+        }
+    }
+
+    fn chain() -> std::io::Result<sev::Chain> {
+        sev::Chain::decode(&mut &CHAIN[..], ())
+    }
+}
+
+impl Device for Mock {
+    fn platform_status(&mut self) -> std::io::Result<Status> {
+        self.run("PLATFORM_STATUS", || {
+            Ok(Status {
+                build: ::sev::Build {
+                    version: ::sev::Version {
+                        major: 1,
+                        minor: 55,
+                    },
+                    build: 21,
+                },
+                state: State::Initialized,
+                flags: Flags::empty(),
+                guests: 0,
+            })
+        })
+    }
+
+    fn pdh_cert_export(&mut self) -> std::io::Result<sev::Chain> {
+        self.run("PDH_CERT_EXPORT", Self::chain)
+    }
+
+    fn get_identifier(&mut self) -> std::io::Result<Vec<u8>> {
+        self.run("GET_ID", || {
+            // Derived from the CEK, as the real identifier is.
+            let mut cek = Vec::new();
+            Self::chain()?.cek.encode(&mut cek, ())?;
+            let id = openssl::hash::hash(openssl::hash::MessageDigest::sha512(), &cek)?;
+            Ok(id.to_vec())
+        })
+    }
+
+    fn platform_reset(&mut self) -> std::io::Result<()> {
+        self.run("PLATFORM_RESET", || Ok(()))
+    }
+
+    fn pdh_generate(&mut self) -> std::io::Result<()> {
+        self.run("PDH_GEN", || Ok(()))
+    }
+
+    fn pek_csr(&mut self) -> std::io::Result<sev::Certificate> {
+        self.run("PEK_CSR", || Ok(Self::chain()?.pek))
+    }
+
+    fn pek_cert_import(
+        &mut self,
+        _pek: &sev::Certificate,
+        _oca: &sev::Certificate,
+    ) -> std::io::Result<()> {
+        self.run("PEK_CERT_IMPORT", || Ok(()))
+    }
+
+    fn snp_platform_status(&mut self) -> std::io::Result<platform::SnpStatus> {
+        self.run("SNP_PLATFORM_STATUS", || {
+            let tcb = platform::TcbVersion {
+                bootloader: 3,
+                tee: 0,
+                snp: 14,
+                microcode: 209,
+            };
+            Ok(platform::SnpStatus {
+                api_major: 1,
+                api_minor: 55,
+                build: 21,
+                initialized: true,
+                rmp_initialized: true,
+                mask_chip_id: false,
+                guests: 0,
+                current_tcb: tcb,
+                reported_tcb: tcb,
+            })
+        })
+    }
+
+    fn snp_commit(&mut self) -> std::io::Result<()> {
+        self.run("SNP_COMMIT", || Ok(()))
+    }
+
+    fn snp_set_config(
+        &mut self,
+        _reported_tcb: platform::TcbVersion,
+        _mask_chip_id: bool,
+    ) -> std::io::Result<()> {
+        self.run("SNP_SET_CONFIG", || Ok(()))
+    }
+}
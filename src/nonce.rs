@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Nonces proving that attestation reports are fresh.
+//!
+//! A verifier generates a nonce, has the report bind it, and checks that the
+//! report binds the same one. Stored nonces carry that state between the steps,
+//! which may run hours apart: each is kept as a JSON file named by its ID in the
+//! nonce directory, and is accepted by one verification only, before it expires.
+
+use super::*;
+use schema::{CheckResult, CheckState};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The largest nonce, which fills an SEV-SNP report's data.
+const MAX_SIZE: usize = 64;
+
+#[derive(StructOpt)]
+pub enum Nonce {
+    #[structopt(about = "Generate a random nonce")]
+    New {
+        #[structopt(
+            long,
+            default_value = "16",
+            help = "Size in bytes: 16 for SEV reports (--mnonce), up to 64 for SEV-SNP reports"
+        )]
+        size: usize,
+
+        #[structopt(long, help = "Keep the nonce for a later --nonce-id")]
+        store: bool,
+
+        #[structopt(
+            long,
+            requires = "store",
+            help = "Seconds after which the stored nonce is no longer accepted"
+        )]
+        ttl: Option<u64>,
+    },
+
+    #[structopt(about = "List the stored nonces")]
+    List,
+
+    #[structopt(about = "Remove the stored nonces that are used or expired")]
+    Prune,
+}
+
+pub fn cmd(nonce: Nonce) -> Result<()> {
+    match nonce {
+        Nonce::New { size, store, ttl } => {
+            if size == 0 || size > MAX_SIZE {
+                return Err(error::Context::new(
+                    &format!("--size must be between 1 and {}", MAX_SIZE),
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                ));
+            }
+
+            let mut value = vec![0u8; size];
+            openssl::rand::rand_bytes(&mut value).context("unable to generate nonce")?;
+            let created = now();
+            let mut nonce = schema::Nonce {
+                id: None,
+                nonce: hex(&value),
+                created,
+                expires: ttl.map(|ttl| created + ttl),
+                used: None,
+            };
+
+            if store {
+                let mut id = [0u8; 8];
+                openssl::rand::rand_bytes(&mut id).context("unable to generate nonce ID")?;
+                nonce.id = Some(hex(&id));
+                save(&nonce)?;
+            }
+
+            output::print(&nonce);
+            Ok(())
+        }
+
+        Nonce::List => {
+            output::print(&schema::Nonces {
+                nonces: load_all()?,
+            });
+            Ok(())
+        }
+
+        Nonce::Prune => {
+            let dir = dir()?;
+            let now = now();
+            for nonce in load_all()? {
+                if nonce.used.is_some() || nonce.expires.map_or(false, |e| e <= now) {
+                    let path = dir.join(format!("{}.json", nonce.id.unwrap_or_default()));
+                    tracing::debug!(path = %path.display(), "removing file");
+                    std::fs::remove_file(&path)
+                        .context(format!("unable to remove {}", path.display()))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A stored nonce, locked while a verification checks and uses it.
+pub struct Stored {
+    id: String,
+    file: File,
+    nonce: schema::Nonce,
+}
+
+impl Stored {
+    /// Loads the nonce stored as `id`.
+    pub fn load(id: &str) -> Result<Self> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(error::Context::new(
+                &format!("invalid nonce ID {}", id),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            ));
+        }
+
+        let path = dir()?.join(format!("{}.json", id));
+        tracing::debug!(path = %path.display(), "reading file");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .context(format!("no nonce is stored as {}", id))?;
+
+        // SAFETY: the descriptor is open for the duration of the call.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(Error::last_os_error())
+                .context(format!("unable to lock {}", path.display()));
+        }
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)
+            .context(format!("unable to read {}", path.display()))?;
+        let stored: schema::Versioned<schema::Nonce> =
+            serde_json::from_str(&text).context(format!("invalid nonce {}", path.display()))?;
+
+        Ok(Self {
+            id: id.to_string(),
+            file,
+            nonce: stored.document,
+        })
+    }
+
+    /// The nonce.
+    pub fn value(&self) -> Result<Vec<u8>> {
+        unhex(&self.nonce.nonce).context(format!("invalid nonce {}", self.id))
+    }
+
+    /// The verdict on a report binding `bound`: it must be this nonce, followed by
+    /// zeros if shorter, which must not have expired nor been used by an earlier
+    /// verification.
+    pub fn check(&self, bound: &[u8]) -> CheckResult {
+        let now = now();
+        let binds = self.value().map_or(false, |value| {
+            bound.len() >= value.len()
+                && bound[..value.len()] == value[..]
+                && bound[value.len()..].iter().all(|b| *b == 0)
+        });
+        let outcome = match (self.nonce.used, self.nonce.expires) {
+            (Some(used), _) => Err(format!(
+                "{} was already used {}s ago, the report may be replayed",
+                self.id,
+                now.saturating_sub(used)
+            )),
+            (None, Some(expires)) if expires <= now => {
+                Err(format!("{} expired {}s ago", self.id, now - expires))
+            }
+            (None, _) if !binds => Err(format!("the report does not bind {}", self.id)),
+            (None, _) => Ok(self.id.clone()),
+        };
+
+        appraisal::result("nonce", "Nonce fresh", outcome)
+    }
+
+    /// Marks the nonce as used, so that no other report is accepted with it.
+    pub fn consume(mut self, check: &CheckResult) -> Result<()> {
+        if check.state != CheckState::Pass {
+            return Ok(());
+        }
+
+        self.nonce.used = Some(now());
+        let document = schema::Versioned::new(&self.nonce).to_string_pretty();
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.set_len(0))
+            .and_then(|_| self.file.write_all(document.as_bytes()))
+            .context(format!("unable to mark nonce {} as used", self.id))
+    }
+}
+
+/// The nonce directory, created if need be.
+fn dir() -> Result<PathBuf> {
+    let dir = config::get().nonce_dir().ok_or_else(|| {
+        error::Context::new(
+            "no nonce directory",
+            Box::new(Error::new(
+                ErrorKind::NotFound,
+                "set nonce-dir in the configuration, or HOME",
+            )),
+        )
+    })?;
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .context(format!("unable to create {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn save(nonce: &schema::Nonce) -> Result<()> {
+    let path = dir()?.join(format!("{}.json", nonce.id.as_deref().unwrap_or_default()));
+    let document = schema::Versioned::new(nonce).to_string_pretty();
+    tracing::debug!(path = %path.display(), "writing file");
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(document.as_bytes()))
+        .context(format!("unable to write {}", path.display()))
+}
+
+/// The stored nonces, oldest first.
+fn load_all() -> Result<Vec<schema::Nonce>> {
+    let dir = dir()?;
+    let mut nonces = Vec::new();
+    for entry in std::fs::read_dir(&dir).context(format!("unable to read {}", dir.display()))? {
+        let path = entry
+            .context(format!("unable to read {}", dir.display()))?
+            .path();
+        if path.extension().map_or(true, |e| e != "json") {
+            continue;
+        }
+
+        let text =
+            std::fs::read_to_string(&path).context(format!("unable to read {}", path.display()))?;
+        let stored: schema::Versioned<schema::Nonce> =
+            serde_json::from_str(&text).context(format!("invalid nonce {}", path.display()))?;
+        nonces.push(stored.document);
+    }
+
+    nonces.sort_by_key(|n| n.created);
+    Ok(nonces)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl output::Document for schema::Nonce {
+    fn table(&self) -> String {
+        match &self.id {
+            Some(id) => format!("{}\nid: {}\n", self.nonce, id),
+            None => format!("{}\n", self.nonce),
+        }
+    }
+}
+
+impl output::Document for schema::Nonces {
+    fn table(&self) -> String {
+        let now = now();
+        let mut out = String::new();
+        for nonce in &self.nonces {
+            let state = match (nonce.used, nonce.expires) {
+                (Some(used), _) => format!("used {}s ago", now.saturating_sub(used)),
+                (None, Some(expires)) if expires <= now => "expired".to_string(),
+                (None, Some(expires)) => format!("fresh, expires in {}s", expires - now),
+                (None, None) => "fresh".to_string(),
+            };
+            out += &format!(
+                "{} {} ({})\n",
+                nonce.id.as_deref().unwrap_or_default(),
+                nonce.nonce,
+                state
+            );
+        }
+        out
+    }
+}
@@ -0,0 +1,406 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks that the host is ready to run SEV and SEV-ES guests, or with
+//! `sevctl snp ok` SEV-SNP guests.
+//!
+//! Every check has an ID (e.g. `cpu.sev-es`) that stays the same across
+//! releases, so that automation and documentation can refer to it. New checks
+//! get new IDs, and the IDs of removed checks are not reused.
+
+use super::*;
+use ::sev::firmware::Flags;
+use schema::CheckState;
+
+/// The outcome of a check that ran: details on success, or why it failed.
+type Outcome = std::result::Result<String, String>;
+
+/// A check of the host.
+pub struct Check {
+    /// The stable ID.
+    pub id: &'static str,
+
+    /// A description for people.
+    pub name: &'static str,
+
+    /// The check that must pass for this one to run; it is skipped otherwise.
+    pub requires: Option<&'static str>,
+
+    pub run: fn() -> Outcome,
+}
+
+/// The checks of `sevctl ok`, each after the ones it requires.
+pub const CHECKS: &[Check] = &[
+    Check {
+        id: "cpu.amd",
+        name: "AMD CPU",
+        requires: None,
+        run: || match platform::cpu_vendor() {
+            Some(vendor) if vendor == "AuthenticAMD" => Ok(vendor),
+            Some(vendor) => Err(vendor),
+            None => Err("not an x86_64 CPU".into()),
+        },
+    },
+    Check {
+        id: "cpu.mem-encryption",
+        name: "Memory encryption capabilities (CPUID leaf 0x8000001f)",
+        requires: Some("cpu.amd"),
+        run: || match platform::cpuid() {
+            Some(_) => Ok(String::new()),
+            None => Err("not reported by the CPU".into()),
+        },
+    },
+    Check {
+        id: "cpu.sme",
+        name: "Secure Memory Encryption (SME)",
+        requires: Some("cpu.mem-encryption"),
+        run: || cpu(|c| c.sme),
+    },
+    Check {
+        id: "cpu.sev",
+        name: "Secure Encrypted Virtualization (SEV)",
+        requires: Some("cpu.mem-encryption"),
+        run: || {
+            cpu(|c| c.sev)?;
+            let cpuid = platform::cpuid().unwrap();
+            Ok(format!("{} ASIDs", cpuid.asids))
+        },
+    },
+    Check {
+        id: "cpu.sev-es",
+        name: "Encrypted State (SEV-ES)",
+        requires: Some("cpu.sev"),
+        run: || {
+            cpu(|c| c.es)?;
+            let cpuid = platform::cpuid().unwrap();
+            Ok(format!("{} ASIDs", cpuid.min_sev_asid.saturating_sub(1)))
+        },
+    },
+    Check {
+        id: "cpu.page-flush-msr",
+        name: "Page flush MSR",
+        requires: Some("cpu.sev"),
+        run: || cpu(|c| c.page_flush_msr),
+    },
+    Check {
+        id: "kvm.module",
+        name: "KVM support for AMD (kvm_amd)",
+        requires: None,
+        run: || match Path::new("/sys/module/kvm_amd").exists() {
+            true => Ok(String::new()),
+            false => Err("the kvm_amd module is not loaded".into()),
+        },
+    },
+    Check {
+        id: "kvm.sev-param",
+        name: "SEV enabled in KVM (kvm_amd.sev)",
+        requires: Some("kvm.module"),
+        run: || param("sev"),
+    },
+    Check {
+        id: "kvm.sev-es-param",
+        name: "SEV-ES enabled in KVM (kvm_amd.sev_es)",
+        requires: Some("kvm.module"),
+        run: || param("sev_es"),
+    },
+    Check {
+        id: "dev.sev",
+        name: "Access to the SEV device",
+        requires: None,
+        run: || {
+            firmware().map_err(failure)?;
+            Ok(device::path().display().to_string())
+        },
+    },
+    Check {
+        id: "fw.platform-status",
+        name: "SEV firmware",
+        requires: Some("dev.sev"),
+        run: || {
+            let status = platform_status().map_err(failure)?;
+            Ok(format!(
+                "API {}.{} build {}, {} guests",
+                status.build.version.major,
+                status.build.version.minor,
+                status.build.build,
+                status.guests
+            ))
+        },
+    },
+    Check {
+        id: "fw.sev-es",
+        name: "SEV-ES supported by the firmware",
+        requires: Some("fw.platform-status"),
+        run: || {
+            let status = platform_status().map_err(failure)?;
+            match status.flags.contains(Flags::ENCRYPTED_STATE) {
+                true => Ok(String::new()),
+                false => Err("not reported in the platform status".into()),
+            }
+        },
+    },
+    Check {
+        id: "rlimit.memlock",
+        name: "Memlock resource limit",
+        requires: None,
+        run: || {
+            let mut limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            // SAFETY: `limit` is a valid rlimit to write to.
+            if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+                return Err(Error::last_os_error().to_string());
+            }
+
+            let show = |value: libc::rlim_t| match value {
+                libc::RLIM_INFINITY => "unlimited".to_string(),
+                value => format!("{} bytes", value),
+            };
+            Ok(format!(
+                "soft {}, hard {}",
+                show(limit.rlim_cur),
+                show(limit.rlim_max)
+            ))
+        },
+    },
+];
+
+/// The first SEV-SNP ABI version, 1.51.
+const SNP_API: (u8, u8) = (1, 51);
+
+/// The checks of `sevctl snp ok` besides those of [`CHECKS`] it needs.
+pub const SNP_CHECKS: &[Check] = &[
+    Check {
+        id: "cpu.snp",
+        name: "Secure Nested Paging (SEV-SNP)",
+        requires: Some("cpu.sev-es"),
+        run: || cpu(|c| c.snp),
+    },
+    Check {
+        id: "kvm.sev-snp-param",
+        name: "SEV-SNP enabled in KVM (kvm_amd.sev_snp)",
+        requires: Some("kvm.module"),
+        run: || param("sev_snp"),
+    },
+    Check {
+        id: "iommu.snp",
+        name: "IOMMU support for SEV-SNP",
+        requires: Some("cpu.snp"),
+        run: iommu_snp,
+    },
+    Check {
+        id: "fw.snp-status",
+        name: "SEV-SNP commands of the SEV device (SNP_PLATFORM_STATUS)",
+        requires: Some("dev.sev"),
+        run: || {
+            let status = snp_status().map_err(failure)?;
+            Ok(format!(
+                "API {}.{} build {}, {} guests",
+                status.api_major, status.api_minor, status.build, status.guests
+            ))
+        },
+    },
+    Check {
+        id: "fw.snp-version",
+        name: "SEV-SNP capable firmware",
+        requires: Some("fw.snp-status"),
+        run: || {
+            let status = snp_status().map_err(failure)?;
+            match (status.api_major, status.api_minor) >= SNP_API {
+                true => Ok(String::new()),
+                false => Err(format!(
+                    "API {}.{} is older than {}.{}",
+                    status.api_major, status.api_minor, SNP_API.0, SNP_API.1
+                )),
+            }
+        },
+    },
+    Check {
+        id: "fw.snp-initialized",
+        name: "SEV-SNP initialized (SNP_INIT)",
+        requires: Some("fw.snp-status"),
+        run: || match snp_status().map_err(failure)?.initialized {
+            true => Ok(String::new()),
+            false => Err("the kernel did not initialize SEV-SNP".into()),
+        },
+    },
+    Check {
+        id: "fw.rmp-initialized",
+        name: "Reverse Map Table (RMP) initialized",
+        requires: Some("fw.snp-status"),
+        run: || match snp_status().map_err(failure)?.rmp_initialized {
+            true => Ok(String::new()),
+            false => Err("check the RMP settings of the BIOS".into()),
+        },
+    },
+];
+
+/// The IDs of the checks of [`CHECKS`] that `sevctl snp ok` runs too.
+const SNP_REQUIRES: &[&str] = &[
+    "cpu.amd",
+    "cpu.mem-encryption",
+    "cpu.sev",
+    "cpu.sev-es",
+    "kvm.module",
+    "dev.sev",
+];
+
+/// The checks of `sevctl ok`.
+pub fn checks() -> Vec<&'static Check> {
+    CHECKS.iter().collect()
+}
+
+/// The checks of `sevctl snp ok`.
+pub fn snp_checks() -> Vec<&'static Check> {
+    CHECKS
+        .iter()
+        .filter(|c| SNP_REQUIRES.contains(&c.id))
+        .chain(SNP_CHECKS)
+        .collect()
+}
+
+/// Whether every AMD IOMMU supports SEV-SNP, as reported by the SNPSup bit
+/// (63) of its extended feature register.
+fn iommu_snp() -> Outcome {
+    const SNP_SUPPORT: u64 = 1 << 63;
+
+    let mut found = 0;
+    for entry in std::fs::read_dir("/sys/class/iommu").map_err(|e| e.to_string())? {
+        let path = entry
+            .map_err(|e| e.to_string())?
+            .path()
+            .join("amd-iommu/features");
+        let features = match std::fs::read_to_string(&path) {
+            Ok(features) => features,
+            Err(_) => continue,
+        };
+
+        // Newer kernels append the second feature register after a colon.
+        let efr = features.trim().split(':').next().unwrap_or("");
+        let efr = u64::from_str_radix(efr, 16)
+            .map_err(|_| format!("unable to parse {}", path.display()))?;
+        if efr & SNP_SUPPORT == 0 {
+            return Err(format!("not supported by {}", path.display()));
+        }
+        found += 1;
+    }
+
+    match found {
+        0 => Err("no AMD IOMMU found".into()),
+        n => Ok(format!("{} IOMMUs", n)),
+    }
+}
+
+fn cpu(capable: fn(&platform::Cpuid) -> bool) -> Outcome {
+    match platform::cpuid().as_ref().map_or(false, capable) {
+        true => Ok(String::new()),
+        false => Err("not supported by the CPU".into()),
+    }
+}
+
+/// An error with its causes, on one line.
+fn failure(e: error::Context) -> String {
+    let report = e.report();
+    std::iter::once(report.message)
+        .chain(report.causes)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+fn param(name: &str) -> Outcome {
+    match platform::kvm_param(name) {
+        true => Ok(String::new()),
+        false => Err(format!("kvm_amd.{} is disabled", name)),
+    }
+}
+
+/// Runs the checks in order, skipping those whose requirement did not pass.
+pub fn run(checks: &[&Check]) -> schema::Checks {
+    let mut results: Vec<schema::CheckResult> = Vec::new();
+    for check in checks {
+        let unmet = check.requires.filter(|id| {
+            !results
+                .iter()
+                .any(|r| r.id == *id && r.state == CheckState::Pass)
+        });
+
+        let (state, details) = match unmet {
+            Some(id) => (CheckState::Skip, format!("requires {}", id)),
+            None => match (check.run)() {
+                Ok(details) => (CheckState::Pass, details),
+                Err(details) => (CheckState::Fail, details),
+            },
+        };
+
+        results.push(schema::CheckResult {
+            id: check.id.to_string(),
+            name: check.name.to_string(),
+            state,
+            details: Some(details).filter(|d| !d.is_empty()),
+        });
+    }
+
+    schema::Checks { checks: results }
+}
+
+impl output::Document for schema::Checks {
+    fn table(&self) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            let state = match check.state {
+                CheckState::Pass => "PASS",
+                CheckState::Fail => "FAIL",
+                CheckState::Skip => "SKIP",
+            };
+            out += &format!("[ {} ] {} - {}", state, check.id, check.name);
+            if let Some(details) = &check.details {
+                out += &format!(": {}", details);
+            }
+            out += "\n";
+        }
+        out
+    }
+
+    fn csv(&self) -> String {
+        let rows = self
+            .checks
+            .iter()
+            .map(|check| {
+                let state = serde_json::to_value(check.state).unwrap();
+                vec![
+                    check.id.clone(),
+                    check.name.clone(),
+                    state.as_str().unwrap_or_default().to_string(),
+                    check.details.clone().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        output::csv(&["id", "name", "state", "details"], rows)
+    }
+}
+
+/// Prints the results of `checks`, failing if any check failed.
+pub fn report(checks: &[&Check]) -> Result<()> {
+    let results = run(checks);
+    output::print(&results);
+
+    let failed = results
+        .checks
+        .iter()
+        .filter(|c| c.state == CheckState::Fail)
+        .count();
+    match failed {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            "the host is not ready",
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} of {} checks failed", n, results.checks.len()),
+            )),
+        )),
+    }
+}
+
+pub fn cmd() -> Result<()> {
+    report(&checks())
+}
@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The output layer shared by the subcommands: a command describes its result as
+//! a [`Document`] and prints it with [`print`], and the global `--output` flag
+//! decides whether it is shown as a table, as JSON, YAML or CSV, or not at all.
+
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The names accepted by `--output`.
+pub const FORMATS: &[&str] = &["table", "json", "yaml", "csv", "quiet"];
+
+/// How results are printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human-readable text.
+    Table,
+
+    /// The document with its schema version, as pretty-printed JSON.
+    Json,
+
+    /// The document with its schema version, as YAML.
+    Yaml,
+
+    /// Rows with a header line, for spreadsheets.
+    Csv,
+
+    /// Nothing; the exit status tells the outcome.
+    Quiet,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "yaml" => Ok(Format::Yaml),
+            "csv" => Ok(Format::Csv),
+            "quiet" => Ok(Format::Quiet),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown output format {} (expected {})",
+                    s,
+                    FORMATS.join(", ")
+                ),
+            )),
+        }
+    }
+}
+
+static FORMAT: Mutex<Format> = Mutex::new(Format::Table);
+
+/// Whether a document was printed, which JSON and YAML allow only once.
+static PRINTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether JSON and YAML documents carry their [`schema::Metadata`].
+static METADATA: AtomicBool = AtomicBool::new(false);
+
+/// Sets the format of everything printed afterwards.
+pub fn init(format: Format) {
+    *FORMAT.lock().unwrap() = format;
+}
+
+/// Adds the host's metadata to the JSON and YAML documents printed afterwards.
+pub fn with_metadata() {
+    METADATA.store(true, Ordering::Relaxed);
+}
+
+/// The format set with [`init`], `Table` by default.
+pub fn format() -> Format {
+    *FORMAT.lock().unwrap()
+}
+
+/// The result of a command.
+pub trait Document: Serialize {
+    /// The human-readable form, one or more lines each ending with a newline.
+    fn table(&self) -> String;
+
+    /// The spreadsheet form: by default, a `field,value` row per field of the
+    /// JSON document, nested fields named by their path (e.g. `checks.0.id`).
+    fn csv(&self) -> String {
+        let mut rows = Vec::new();
+        flatten(
+            String::new(),
+            &serde_json::to_value(self).unwrap(),
+            &mut rows,
+        );
+        csv(&["field", "value"], rows)
+    }
+}
+
+/// CSV text of a header line and `rows`, quoting the fields that need it.
+pub fn csv(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let line = |fields: Vec<String>| {
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|f| match f.contains(&[',', '"', '\n', '\r'][..]) {
+                true => format!("\"{}\"", f.replace('"', "\"\"")),
+                false => f,
+            })
+            .collect();
+        fields.join(",") + "\n"
+    };
+
+    let mut out = line(header.iter().map(|h| h.to_string()).collect());
+    for row in rows {
+        out += &line(row);
+    }
+    out
+}
+
+/// The scalar fields of `value`, each with its path.
+fn flatten(path: String, value: &serde_json::Value, rows: &mut Vec<Vec<String>>) {
+    use serde_json::Value;
+
+    let join = |key: &str| match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    };
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(join(key), value, rows);
+            }
+        }
+        Value::Array(items) => {
+            for (i, value) in items.iter().enumerate() {
+                flatten(join(&i.to_string()), value, rows);
+            }
+        }
+        Value::Null => rows.push(vec![path, String::new()]),
+        Value::String(s) => rows.push(vec![path, s.clone()]),
+        scalar => rows.push(vec![path, scalar.to_string()]),
+    }
+}
+
+/// Whether [`print`] was called, e.g. before the command failed.
+pub fn printed() -> bool {
+    PRINTED.load(Ordering::Relaxed)
+}
+
+/// Prints a command's result in the current format.
+pub fn print<T: Document>(document: &T) {
+    PRINTED.store(true, Ordering::Relaxed);
+    match format() {
+        Format::Table => print!("{}", document.table()),
+        Format::Json => println!("{}", versioned(document).to_string_pretty()),
+        Format::Yaml => print!("{}", serde_yaml::to_string(&versioned(document)).unwrap()),
+        Format::Csv => print!("{}", document.csv()),
+        Format::Quiet => (),
+    }
+}
+
+/// A document to print as JSON or YAML, with the metadata if enabled.
+fn versioned<T: Serialize>(document: &T) -> schema::Versioned<&T> {
+    let mut versioned = schema::Versioned::new(document);
+    if METADATA.load(Ordering::Relaxed) {
+        // The firmware may be unavailable, e.g. when printing why.
+        let firmware_version = platform_status().ok().map(|status| {
+            format!(
+                "{}.{}.{}",
+                status.build.version.major, status.build.version.minor, status.build.build
+            )
+        });
+
+        versioned.metadata = Some(schema::Metadata {
+            hostname: platform::hostname(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            sevctl_version: VERSION.to_string(),
+            firmware_version,
+        });
+    }
+    versioned
+}
@@ -0,0 +1,422 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loading of OVMF firmware images as they are mapped into an SEV guest.
+
+use super::*;
+use guid::Guid;
+use std::io::Read;
+
+/// The granularity at which QEMU maps firmware images into guest memory.
+const PAGE_SIZE: usize = 4096;
+
+/// Marks the footer of the GUIDed structure table at the end of the image.
+const TABLE_FOOTER: &str = "96b582de-1fb2-45f7-baea-a366c55a082d";
+
+/// Locates the area reserved for the SEV kernel hashes table.
+pub const SEV_HASH_TABLE_RV: &str = "7255371f-3a3b-4b04-927b-1da6efa8d454";
+
+/// Holds the reset vector of the SEV-ES application processors.
+const SEV_ES_RESET_BLOCK: &str = "00f771de-1a7e-4fcb-890e-68c77e2fb44e";
+
+/// Locates the area reserved for secrets injected at launch.
+const SEV_SECRET_AREA: &str = "4c2eb361-7d9b-4cc3-8081-127c90d3d294";
+
+/// Holds the offset of the SEV metadata from the end of the image.
+const SEV_METADATA: &str = "dc886566-984a-4798-a75e-5585a7bf67cc";
+
+/// The signature of the SEV metadata ("ASEV").
+const METADATA_SIGNATURE: &[u8] = b"ASEV";
+
+/// The SEV metadata header holds a signature, size, version and section count.
+const METADATA_HEADER_SIZE: usize = 16;
+
+/// Every SEV metadata section is a guest physical address, size and type.
+const METADATA_SECTION_SIZE: usize = 12;
+
+/// The table ends this many bytes before the end of the image.
+const TABLE_END_OFFSET: usize = 32;
+
+/// Every table entry ends with a 16-bit length followed by its GUID.
+const ENTRY_HEADER_SIZE: usize = 18;
+
+#[derive(StructOpt)]
+pub enum OvmfCmd {
+    #[structopt(about = "Show the SEV-related tables of an OVMF firmware image")]
+    Show {
+        #[structopt(parse(from_os_str), help = "Path to the firmware image (OVMF.fd)")]
+        firmware: PathBuf,
+    },
+}
+
+pub fn cmd(cmd: OvmfCmd) -> Result<()> {
+    match cmd {
+        OvmfCmd::Show { firmware } => show(&Ovmf::load(&firmware)?),
+    }
+}
+
+/// A memory region described by the SEV metadata.
+pub struct Section {
+    pub gpa: u32,
+    pub size: u32,
+    pub kind: u32,
+}
+
+impl Section {
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            1 => "SNP_SEC_MEM",
+            2 => "SNP_SECRETS",
+            3 => "CPUID",
+            0x10 => "SNP_KERNEL_HASHES",
+            _ => "unknown",
+        }
+    }
+}
+
+/// An OVMF firmware image.
+pub struct Ovmf {
+    data: Vec<u8>,
+    table: Vec<(Guid, Vec<u8>)>,
+}
+
+impl Ovmf {
+    /// Loads a firmware image (e.g. `OVMF_CODE.fd` or a combined `OVMF.fd`).
+    ///
+    /// QEMU maps the whole image into guest memory and measures it with a single
+    /// LAUNCH_UPDATE_DATA, so the image must consist of whole pages.
+    pub fn load(path: &Path) -> Result<Self> {
+        tracing::debug!(path = %path.display(), "reading file");
+        let mut data = Vec::new();
+        File::open(path)
+            .context(format!("unable to open {}", path.display()))?
+            .read_to_end(&mut data)
+            .context(format!("unable to read {}", path.display()))?;
+
+        if data.is_empty() || data.len() % PAGE_SIZE != 0 {
+            return Err(error::Context::new(
+                &format!(
+                    "firmware image is {} bytes, which is not a whole number of pages",
+                    data.len()
+                ),
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+
+        let table = parse_table(&data)?;
+        Ok(Self { data, table })
+    }
+
+    /// The bytes measured by the platform.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Looks up the payload of a GUIDed structure table entry.
+    pub fn entry(&self, guid: &'static str) -> Option<&[u8]> {
+        let guid = Guid::from_static(guid);
+        self.table
+            .iter()
+            .find(|(g, _)| *g == guid)
+            .map(|(_, data)| &data[..])
+    }
+
+    /// The reset vector of the SEV-ES application processors, if the image has one.
+    pub fn ap_reset_eip(&self) -> Option<u32> {
+        self.entry(SEV_ES_RESET_BLOCK)
+            .filter(|e| e.len() >= 4)
+            .map(|e| u32::from_le_bytes([e[0], e[1], e[2], e[3]]))
+    }
+
+    /// The guest physical address and size of the area secrets are injected into.
+    pub fn secret_area(&self) -> Option<(u32, u32)> {
+        self.entry(SEV_SECRET_AREA)
+            .filter(|e| e.len() >= 8)
+            .map(|e| {
+                (
+                    u32::from_le_bytes([e[0], e[1], e[2], e[3]]),
+                    u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+                )
+            })
+    }
+
+    /// Parses the SEV metadata describing the memory regions OVMF expects the
+    /// hypervisor to prepare, if the image has any.
+    pub fn metadata(&self) -> Result<Option<Vec<Section>>> {
+        let entry = match self.entry(SEV_METADATA) {
+            Some(e) if e.len() >= 4 => e,
+            _ => return Ok(None),
+        };
+
+        let malformed = || {
+            error::Context::new(
+                "firmware image has malformed SEV metadata",
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            )
+        };
+
+        let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+        if offset > self.data.len() || offset < METADATA_HEADER_SIZE {
+            return Err(malformed());
+        }
+
+        let meta = &self.data[self.data.len() - offset..];
+        let word = |i: usize| u32::from_le_bytes([meta[i], meta[i + 1], meta[i + 2], meta[i + 3]]);
+
+        if &meta[..4] != METADATA_SIGNATURE {
+            return Err(malformed());
+        }
+
+        let count = word(12) as usize;
+        let end = METADATA_HEADER_SIZE + count * METADATA_SECTION_SIZE;
+        if end > meta.len() || end > word(4) as usize {
+            return Err(malformed());
+        }
+
+        Ok(Some(
+            (0..count)
+                .map(|i| METADATA_HEADER_SIZE + i * METADATA_SECTION_SIZE)
+                .map(|at| Section {
+                    gpa: word(at),
+                    size: word(at + 4),
+                    kind: word(at + 8),
+                })
+                .collect(),
+        ))
+    }
+}
+
+fn show(ovmf: &Ovmf) -> Result<()> {
+    let mut table = Vec::new();
+    for (guid, data) in &ovmf.table {
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+
+        let known = [
+            (SEV_HASH_TABLE_RV, "SEV kernel hashes table"),
+            (SEV_SECRET_AREA, "SEV secret area"),
+            (SEV_ES_RESET_BLOCK, "SEV-ES AP reset block"),
+            (SEV_METADATA, "SEV metadata"),
+        ];
+        let name = known
+            .iter()
+            .find(|(g, _)| Guid::from_static(g) == *guid)
+            .map(|(g, name)| (*g, *name));
+
+        let value = match name {
+            Some((SEV_HASH_TABLE_RV, _)) | Some((SEV_SECRET_AREA, _)) if data.len() >= 8 => {
+                format!("{:#010x} ({:#x} bytes)", word(0), word(4))
+            }
+            Some((SEV_ES_RESET_BLOCK, _)) if data.len() >= 4 => {
+                format!("AP reset vector {:#010x}", word(0))
+            }
+            Some((SEV_METADATA, _)) if data.len() >= 4 => {
+                format!("offset {:#x} from the end", word(0))
+            }
+            _ => format!("{} bytes", data.len()),
+        };
+
+        table.push(schema::OvmfEntry {
+            guid: guid.to_string(),
+            name: name.map(|(_, name)| name.to_string()),
+            value,
+        });
+    }
+
+    let sections = ovmf.metadata()?.map(|sections| {
+        sections
+            .iter()
+            .map(|s| schema::OvmfSection {
+                gpa: s.gpa,
+                size: s.size,
+                kind: s.kind,
+                kind_name: s.kind_name().to_string(),
+            })
+            .collect()
+    });
+
+    output::print(&schema::OvmfImage {
+        size: ovmf.data.len(),
+        table,
+        sections,
+    });
+    Ok(())
+}
+
+impl output::Document for schema::OvmfImage {
+    fn table(&self) -> String {
+        let mut out = format!("size: {:#x}\n", self.size);
+
+        if self.table.is_empty() {
+            out += "no GUIDed structure table found\n";
+            return out;
+        }
+
+        out += "GUIDed structure table:\n";
+        for entry in &self.table {
+            match &entry.name {
+                Some(name) => out += &format!("  {} {}: {}\n", entry.guid, name, entry.value),
+                None => out += &format!("  {}: {}\n", entry.guid, entry.value),
+            }
+        }
+
+        if let Some(sections) = &self.sections {
+            out += "SEV metadata sections:\n";
+            for s in sections {
+                out += &format!(
+                    "  {:#010x} ({:#x} bytes): {} ({:#x})\n",
+                    s.gpa, s.size, s.kind_name, s.kind
+                );
+            }
+        }
+
+        out
+    }
+}
+
+/// Parses the GUIDed structure table, which is laid out backwards from its footer.
+///
+/// Images without a table (such as non-OVMF firmware) yield an empty table.
+fn parse_table(data: &[u8]) -> Result<Vec<(Guid, Vec<u8>)>> {
+    let mut entries = Vec::new();
+
+    if data.len() < TABLE_END_OFFSET + ENTRY_HEADER_SIZE {
+        return Ok(entries);
+    }
+
+    let footer = data.len() - TABLE_END_OFFSET - ENTRY_HEADER_SIZE;
+    let (size, guid) = header(&data[footer..footer + ENTRY_HEADER_SIZE]);
+    if guid != Guid::from_static(TABLE_FOOTER) {
+        return Ok(entries);
+    }
+
+    let malformed = || {
+        error::Context::new(
+            "firmware image has a malformed GUID table",
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        )
+    };
+
+    if size < ENTRY_HEADER_SIZE || size - ENTRY_HEADER_SIZE > footer {
+        return Err(malformed());
+    }
+
+    let mut table = &data[footer - (size - ENTRY_HEADER_SIZE)..footer];
+    while table.len() >= ENTRY_HEADER_SIZE {
+        let hdr = table.len() - ENTRY_HEADER_SIZE;
+        let (size, guid) = header(&table[hdr..]);
+        if size < ENTRY_HEADER_SIZE || size > table.len() {
+            return Err(malformed());
+        }
+
+        entries.push((guid, table[table.len() - size..hdr].to_vec()));
+        table = &table[..table.len() - size];
+    }
+
+    Ok(entries)
+}
+
+fn header(buf: &[u8]) -> (usize, Guid) {
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&buf[2..ENTRY_HEADER_SIZE]);
+    (
+        u16::from_le_bytes([buf[0], buf[1]]) as usize,
+        Guid::from_bytes(guid),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A two-page image whose GUIDed structure table holds `entries`, the last
+    /// nearest the footer, and with SEV metadata in the first page.
+    fn image(entries: &[(&'static str, Vec<u8>)]) -> Vec<u8> {
+        let mut table = Vec::new();
+        for (guid, data) in entries {
+            table.extend_from_slice(data);
+            table.extend_from_slice(&((data.len() + ENTRY_HEADER_SIZE) as u16).to_le_bytes());
+            table.extend_from_slice(Guid::from_static(guid).as_bytes());
+        }
+        table.extend_from_slice(&((table.len() + ENTRY_HEADER_SIZE) as u16).to_le_bytes());
+        table.extend_from_slice(Guid::from_static(TABLE_FOOTER).as_bytes());
+
+        let mut data = vec![0u8; 2 * PAGE_SIZE];
+        let end = data.len() - TABLE_END_OFFSET;
+        data[end - table.len()..end].copy_from_slice(&table);
+
+        let words: &[u32] = &[0x0080_0000, 0x1000, 1, 0x0080_1000, 0x1000, 2];
+        let meta = &mut data[0x100..];
+        meta[..4].copy_from_slice(METADATA_SIGNATURE);
+        let size = METADATA_HEADER_SIZE + 2 * METADATA_SECTION_SIZE;
+        meta[4..8].copy_from_slice(&(size as u32).to_le_bytes());
+        meta[8..12].copy_from_slice(&1u32.to_le_bytes());
+        meta[12..16].copy_from_slice(&2u32.to_le_bytes());
+        for (i, w) in words.iter().enumerate() {
+            let at = METADATA_HEADER_SIZE + 4 * i;
+            meta[at..at + 4].copy_from_slice(&w.to_le_bytes());
+        }
+
+        data
+    }
+
+    fn ovmf(data: Vec<u8>) -> Result<Ovmf> {
+        let table = parse_table(&data)?;
+        Ok(Ovmf { data, table })
+    }
+
+    #[test]
+    fn table() {
+        let mut secret = 0x0080_c000u32.to_le_bytes().to_vec();
+        secret.extend_from_slice(&0x400u32.to_le_bytes());
+        let offset = (2 * PAGE_SIZE - 0x100) as u32;
+
+        let ovmf = ovmf(image(&[
+            (SEV_METADATA, offset.to_le_bytes().to_vec()),
+            (SEV_SECRET_AREA, secret),
+            (SEV_ES_RESET_BLOCK, 0xffff_b000u32.to_le_bytes().to_vec()),
+        ]))
+        .unwrap();
+
+        assert_eq!(ovmf.table.len(), 3);
+        assert_eq!(ovmf.ap_reset_eip(), Some(0xffff_b000));
+        assert_eq!(ovmf.secret_area(), Some((0x0080_c000, 0x400)));
+        assert!(ovmf.entry(SEV_HASH_TABLE_RV).is_none());
+
+        let sections = ovmf.metadata().unwrap().unwrap();
+        let sections: Vec<_> = sections
+            .iter()
+            .map(|s| (s.gpa, s.size, s.kind_name()))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                (0x0080_0000, 0x1000, "SNP_SEC_MEM"),
+                (0x0080_1000, 0x1000, "SNP_SECRETS")
+            ]
+        );
+    }
+
+    #[test]
+    fn no_table() {
+        let ovmf = ovmf(vec![0u8; PAGE_SIZE]).unwrap();
+        assert!(ovmf.table.is_empty());
+        assert_eq!(ovmf.ap_reset_eip(), None);
+        assert!(ovmf.metadata().unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_table() {
+        // An entry claiming to be longer than the table.
+        let mut data = image(&[(SEV_ES_RESET_BLOCK, vec![0; 4])]);
+        let at = data.len() - TABLE_END_OFFSET - 2 * ENTRY_HEADER_SIZE;
+        data[at..at + 2].copy_from_slice(&0x100u16.to_le_bytes());
+        assert!(ovmf(data).is_err());
+
+        // Metadata pointing outside the image.
+        let ovmf = ovmf(image(&[(
+            SEV_METADATA,
+            0x10_0000u32.to_le_bytes().to_vec(),
+        )]))
+        .unwrap();
+        assert!(ovmf.metadata().is_err());
+    }
+}
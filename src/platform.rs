@@ -0,0 +1,590 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Platform queries: the SEV and SEV-SNP firmware commands, issued directly to the
+//! SEV device since the `sev` crate only opens /dev/sev, the CPU's capabilities and
+//! model-specific registers, and the ASID accounting of the kernel's misc cgroup controller.
+
+use super::*;
+use ::sev::firmware::{Flags, State};
+use std::os::unix::io::AsRawFd;
+
+/// The SEV_ISSUE_CMD ioctl: `_IOWR('S', 0x0, struct sev_issue_cmd)`.
+const SEV_ISSUE_CMD: libc::c_ulong = 0xc010_5300;
+
+/// The command IDs of the kernel's `enum sev_cmd_id`.
+const PLATFORM_RESET: u32 = 0;
+const PLATFORM_STATUS: u32 = 1;
+const PEK_CSR: u32 = 3;
+const PDH_GEN: u32 = 4;
+const PDH_CERT_EXPORT: u32 = 5;
+const PEK_CERT_IMPORT: u32 = 6;
+const GET_ID2: u32 = 8;
+const SNP_PLATFORM_STATUS: u32 = 9;
+const SNP_COMMIT: u32 = 10;
+const SNP_SET_CONFIG: u32 = 11;
+
+/// The size of a SEV certificate as the firmware reads and writes it.
+const CERT_SIZE: usize = std::mem::size_of::<sev::Certificate>();
+
+/// The RMP_BASE and RMP_END MSRs, the addresses of the first and last byte of the RMP.
+const MSR_RMP_BASE: u32 = 0xc001_0132;
+const MSR_RMP_END: u32 = 0xc001_0133;
+
+/// Where the root misc cgroup reports the ASID capacity and usage.
+const MISC_CGROUP: &str = "/sys/fs/cgroup";
+
+/// The argument of SEV_ISSUE_CMD.
+#[repr(C, packed)]
+struct IssueCmd {
+    cmd: u32,
+    data: u64,
+    error: u32,
+}
+
+/// The PLATFORM_STATUS response as laid out by the kernel.
+#[repr(C, packed)]
+#[derive(Default)]
+struct RawStatus {
+    api_major: u8,
+    api_minor: u8,
+    state: u8,
+    flags: u32,
+    build: u8,
+    guest_count: u32,
+}
+
+/// A buffer the firmware writes to.
+#[repr(C, packed)]
+struct Buffer {
+    addr: u64,
+    len: u32,
+}
+
+impl Buffer {
+    fn new(buf: &mut [u8]) -> Self {
+        Self {
+            addr: buf.as_mut_ptr() as u64,
+            len: buf.len() as u32,
+        }
+    }
+}
+
+/// The SNP_PLATFORM_STATUS response as laid out by the kernel.
+#[repr(C, packed)]
+#[derive(Default)]
+struct RawSnpStatus {
+    api_major: u8,
+    api_minor: u8,
+    state: u8,
+    rmp: u8,
+    build_id: u32,
+
+    /// Bit 0 is MASK_CHIP_ID, as set with SNP_SET_CONFIG.
+    config: u32,
+    guest_count: u32,
+    current_tcb: u64,
+    reported_tcb: u64,
+}
+
+/// The SNP_SET_CONFIG argument as laid out by the kernel.
+#[repr(C, packed)]
+struct RawSnpConfig {
+    reported_tcb: u64,
+
+    /// Bit 0 is MASK_CHIP_ID, bit 1 MASK_CHIP_KEY.
+    mask: u32,
+    reserved: [u8; 52],
+}
+
+/// The SEV-SNP platform status.
+#[derive(Clone)]
+pub struct SnpStatus {
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build: u32,
+    pub initialized: bool,
+    pub rmp_initialized: bool,
+
+    /// Whether the chip ID is zeroed in attestation reports.
+    pub mask_chip_id: bool,
+
+    pub guests: u32,
+
+    /// The TCB version of the installed firmware.
+    pub current_tcb: TcbVersion,
+
+    /// The TCB version attestation reports are signed with.
+    pub reported_tcb: TcbVersion,
+}
+
+/// The security version numbers of the firmware components, as packed in a u64.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcbVersion {
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl From<u64> for TcbVersion {
+    fn from(raw: u64) -> Self {
+        let bytes = raw.to_le_bytes();
+        Self {
+            bootloader: bytes[0],
+            tee: bytes[1],
+            snp: bytes[6],
+            microcode: bytes[7],
+        }
+    }
+}
+
+impl From<TcbVersion> for u64 {
+    fn from(tcb: TcbVersion) -> Self {
+        u64::from_le_bytes([tcb.bootloader, tcb.tee, 0, 0, 0, 0, tcb.snp, tcb.microcode])
+    }
+}
+
+/// Issues PLATFORM_STATUS to an open SEV device.
+pub fn status(sev: &impl AsRawFd) -> std::io::Result<Status> {
+    let mut raw = RawStatus::default();
+    issue(sev, PLATFORM_STATUS, &mut raw as *mut RawStatus as u64)?;
+
+    Ok(Status {
+        build: ::sev::Build {
+            version: ::sev::Version {
+                major: raw.api_major,
+                minor: raw.api_minor,
+            },
+            build: raw.build,
+        },
+        state: match raw.state {
+            0 => State::Uninitialized,
+            1 => State::Initialized,
+            2 => State::Working,
+            s => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown platform state {}", s),
+                ))
+            }
+        },
+        flags: Flags::from_bits_truncate(raw.flags),
+        guests: raw.guest_count,
+    })
+}
+
+/// Issues PLATFORM_RESET to an open SEV device.
+pub fn reset(sev: &impl AsRawFd) -> std::io::Result<()> {
+    issue(sev, PLATFORM_RESET, 0)
+}
+
+/// Issues PDH_GEN to an open SEV device.
+pub fn pdh_generate(sev: &impl AsRawFd) -> std::io::Result<()> {
+    issue(sev, PDH_GEN, 0)
+}
+
+/// Issues PEK_CSR to an open SEV device.
+pub fn pek_csr(sev: &impl AsRawFd) -> std::io::Result<sev::Certificate> {
+    let mut csr = vec![0; CERT_SIZE];
+    let mut arg = Buffer::new(&mut csr);
+    issue(sev, PEK_CSR, &mut arg as *mut Buffer as u64)?;
+
+    sev::Certificate::decode(&csr[..], ())
+}
+
+/// Issues PDH_CERT_EXPORT to an open SEV device.
+pub fn pdh_cert_export(sev: &impl AsRawFd) -> std::io::Result<sev::Chain> {
+    let mut pdh = vec![0; CERT_SIZE];
+    let mut certs = vec![0; 3 * CERT_SIZE];
+    // The argument is the two buffers back to back.
+    let mut arg = [Buffer::new(&mut pdh), Buffer::new(&mut certs)];
+    issue(sev, PDH_CERT_EXPORT, arg.as_mut_ptr() as u64)?;
+
+    let cert = |i: usize| sev::Certificate::decode(&certs[i * CERT_SIZE..][..CERT_SIZE], ());
+    Ok(sev::Chain {
+        pdh: sev::Certificate::decode(&pdh[..], ())?,
+        pek: cert(0)?,
+        oca: cert(1)?,
+        cek: cert(2)?,
+    })
+}
+
+/// Issues PEK_CERT_IMPORT to an open SEV device.
+pub fn pek_cert_import(
+    sev: &impl AsRawFd,
+    pek: &sev::Certificate,
+    oca: &sev::Certificate,
+) -> std::io::Result<()> {
+    let (mut pek_buf, mut oca_buf) = (Vec::new(), Vec::new());
+    pek.encode(&mut pek_buf, ())?;
+    oca.encode(&mut oca_buf, ())?;
+
+    let mut arg = [Buffer::new(&mut pek_buf), Buffer::new(&mut oca_buf)];
+    issue(sev, PEK_CERT_IMPORT, arg.as_mut_ptr() as u64)
+}
+
+/// Issues GET_ID2 to an open SEV device, returning the CPU's unique ID.
+pub fn get_id(sev: &impl AsRawFd) -> std::io::Result<Vec<u8>> {
+    let mut id = vec![0; 64];
+    let mut arg = Buffer::new(&mut id);
+    issue(sev, GET_ID2, &mut arg as *mut Buffer as u64)?;
+
+    // The firmware reports the length of the ID it wrote.
+    id.truncate(arg.len as usize);
+    Ok(id)
+}
+
+/// Issues SNP_PLATFORM_STATUS to an open SEV device.
+pub fn snp_status(sev: &impl AsRawFd) -> std::io::Result<SnpStatus> {
+    let mut raw = RawSnpStatus::default();
+    issue(
+        sev,
+        SNP_PLATFORM_STATUS,
+        &mut raw as *mut RawSnpStatus as u64,
+    )?;
+
+    Ok(SnpStatus {
+        api_major: raw.api_major,
+        api_minor: raw.api_minor,
+        build: raw.build_id,
+        initialized: raw.state != 0,
+        rmp_initialized: raw.rmp & 1 != 0,
+        mask_chip_id: raw.config & 1 != 0,
+        guests: raw.guest_count,
+        current_tcb: raw.current_tcb.into(),
+        reported_tcb: raw.reported_tcb.into(),
+    })
+}
+
+/// Issues SNP_COMMIT to an open SEV device.
+pub fn snp_commit(sev: &impl AsRawFd) -> std::io::Result<()> {
+    issue(sev, SNP_COMMIT, 0)
+}
+
+/// Issues SNP_SET_CONFIG to an open SEV device.
+pub fn snp_set_config(
+    sev: &impl AsRawFd,
+    reported_tcb: TcbVersion,
+    mask_chip_id: bool,
+) -> std::io::Result<()> {
+    let mut raw = RawSnpConfig {
+        reported_tcb: reported_tcb.into(),
+        mask: mask_chip_id as u32,
+        reserved: [0; 52],
+    };
+    issue(sev, SNP_SET_CONFIG, &mut raw as *mut RawSnpConfig as u64)
+}
+
+/// Issues a command, retrying with exponential backoff while the device is busy
+/// (e.g. with commands libvirt issues concurrently), as configured.
+fn issue(sev: &impl AsRawFd, cmd: u32, data: u64) -> std::io::Result<()> {
+    let (retries, mut delay) = config::get().firmware_retries();
+    let mut attempt = 0;
+    loop {
+        match issue_once(sev, cmd, data) {
+            Err(e) if busy(&e) && attempt < retries => {
+                tracing::warn!(error = %e, ?delay, "firmware busy, retrying");
+                std::thread::sleep(delay);
+                attempt += 1;
+                delay = delay.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn busy(e: &Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EBUSY) | Some(libc::EAGAIN))
+}
+
+fn issue_once(sev: &impl AsRawFd, cmd: u32, data: u64) -> std::io::Result<()> {
+    let mut arg = IssueCmd {
+        cmd,
+        data,
+        error: 0,
+    };
+
+    // SAFETY: `arg` is a valid sev_issue_cmd whose data points to the command's buffer.
+    let ret = unsafe { libc::ioctl(sev.as_raw_fd(), SEV_ISSUE_CMD, &mut arg as *mut IssueCmd) };
+    if ret == -1 {
+        let cause = Error::last_os_error();
+        return Err(match arg.error {
+            0 => cause,
+            status => Error::new(cause.kind(), error::Firmware { status, cause }),
+        });
+    }
+
+    Ok(())
+}
+
+/// The ASIDs of one kind (`sev` or `sev_es`).
+pub struct Asids {
+    pub kind: String,
+    pub capacity: u64,
+
+    /// The ASIDs in use, if the kernel reports usage for the root cgroup.
+    pub used: Option<u64>,
+}
+
+/// Reads the ASID capacity and usage from the misc cgroup controller.
+pub fn asids() -> Result<Vec<Asids>> {
+    let path = Path::new(MISC_CGROUP).join("misc.capacity");
+    let capacity =
+        std::fs::read_to_string(&path).context(format!("unable to read {}", path.display()))?;
+    let current =
+        std::fs::read_to_string(Path::new(MISC_CGROUP).join("misc.current")).unwrap_or_default();
+
+    let entries = |text: &str| -> Vec<(String, u64)> {
+        text.lines()
+            .filter_map(|line| line.split_once(' '))
+            .filter(|(kind, _)| kind.starts_with("sev"))
+            .filter_map(|(kind, value)| Some((kind.to_string(), value.trim().parse().ok()?)))
+            .collect()
+    };
+    let current = entries(&current);
+
+    Ok(entries(&capacity)
+        .into_iter()
+        .map(|(kind, capacity)| Asids {
+            used: current.iter().find(|(k, _)| *k == kind).map(|(_, v)| *v),
+            kind,
+            capacity,
+        })
+        .collect())
+}
+
+/// The memory encryption capabilities reported by CPUID leaf 0x8000001f.
+pub struct Cpuid {
+    pub sme: bool,
+    pub sev: bool,
+
+    /// Whether the VM page flush MSR is available.
+    pub page_flush_msr: bool,
+
+    pub es: bool,
+    pub snp: bool,
+
+    /// The number of guests that can be encrypted at once, i.e. the highest ASID.
+    pub asids: u32,
+
+    /// The lowest ASID usable by an SEV guest; those below are reserved for SEV-ES and SEV-SNP.
+    pub min_sev_asid: u32,
+
+    /// The page table bit marking a page encrypted, libvirt's `<cbitpos>`.
+    pub cbitpos: u32,
+
+    /// How many physical address bits encryption takes, libvirt's `<reducedPhysBits>`.
+    pub reduced_phys_bits: u32,
+}
+
+/// Reads the memory encryption capabilities, if the CPU reports them.
+#[cfg(target_arch = "x86_64")]
+pub fn cpuid() -> Option<Cpuid> {
+    use std::arch::x86_64::__cpuid;
+
+    const LEAF: u32 = 0x8000_001f;
+
+    // SAFETY: CPUID is available on every x86_64 CPU, and the extended leaf is
+    // only read when the CPU reports it.
+    unsafe {
+        if __cpuid(0x8000_0000).eax < LEAF {
+            return None;
+        }
+
+        let leaf = __cpuid(LEAF);
+        Some(Cpuid {
+            sme: leaf.eax & (1 << 0) != 0,
+            sev: leaf.eax & (1 << 1) != 0,
+            page_flush_msr: leaf.eax & (1 << 2) != 0,
+            es: leaf.eax & (1 << 3) != 0,
+            snp: leaf.eax & (1 << 4) != 0,
+            asids: leaf.ecx,
+            min_sev_asid: leaf.edx,
+            cbitpos: leaf.ebx & 0x3f,
+            reduced_phys_bits: (leaf.ebx >> 6) & 0x3f,
+        })
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn cpuid() -> Option<Cpuid> {
+    None
+}
+
+/// The name of the host.
+pub fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer outlives the call and its size is given.
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned()).filter(|h| !h.is_empty())
+}
+
+/// The CPU vendor reported by CPUID leaf 0, e.g. `AuthenticAMD`.
+#[cfg(target_arch = "x86_64")]
+pub fn cpu_vendor() -> Option<String> {
+    // SAFETY: CPUID is available on every x86_64 CPU.
+    let leaf = unsafe { std::arch::x86_64::__cpuid(0) };
+
+    let bytes = [leaf.ebx, leaf.edx, leaf.ecx]
+        .iter()
+        .flat_map(|r| r.to_le_bytes())
+        .collect::<Vec<_>>();
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn cpu_vendor() -> Option<String> {
+    None
+}
+
+/// The CPU family and model reported by CPUID leaf 1, e.g. `(0x19, 0x01)`.
+#[cfg(target_arch = "x86_64")]
+pub fn cpu_model() -> Option<(u32, u32)> {
+    // SAFETY: CPUID is available on every x86_64 CPU.
+    let eax = unsafe { std::arch::x86_64::__cpuid(1) }.eax;
+
+    let family = (eax >> 8) & 0xf;
+    let model = (eax >> 4) & 0xf;
+    Some(match family {
+        0xf => (family + ((eax >> 20) & 0xff), model | ((eax >> 12) & 0xf0)),
+        _ => (family, model),
+    })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn cpu_model() -> Option<(u32, u32)> {
+    None
+}
+
+/// The processor generations, for `possible_values`.
+pub const GENERATIONS: &[&str] = &["Naples", "Rome", "Milan", "Genoa", "Turin"];
+
+/// An EPYC processor generation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Generation {
+    Naples,
+    Rome,
+    Milan,
+    Genoa,
+    Turin,
+}
+
+impl std::str::FromStr for Generation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "naples" => Ok(Generation::Naples),
+            "rome" => Ok(Generation::Rome),
+            "milan" => Ok(Generation::Milan),
+            "genoa" => Ok(Generation::Genoa),
+            "turin" => Ok(Generation::Turin),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unknown generation {} (expected {})",
+                    s,
+                    GENERATIONS.join(", ")
+                ),
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Generation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Generation::Naples => "Naples",
+            Generation::Rome => "Rome",
+            Generation::Milan => "Milan",
+            Generation::Genoa => "Genoa",
+            Generation::Turin => "Turin",
+        })
+    }
+}
+
+impl Generation {
+    /// The generation of the local processor, from its family and model.
+    pub fn local() -> Option<Self> {
+        match cpu_model()? {
+            (0x17, 0x00..=0x0f) => Some(Generation::Naples),
+            (0x17, 0x30..=0x3f) => Some(Generation::Rome),
+            (0x19, 0x00..=0x0f) => Some(Generation::Milan),
+            (0x19, 0x10..=0x1f) | (0x19, 0xa0..=0xaf) => Some(Generation::Genoa),
+            (0x1a, 0x00..=0x1f) => Some(Generation::Turin),
+            _ => None,
+        }
+    }
+
+    /// `given`, or the generation of the local processor. `option` is the flag
+    /// giving it, for the error when neither is known.
+    pub fn or_local(given: Option<Self>, option: &str) -> Result<Self> {
+        given.or_else(Self::local).ok_or_else(|| {
+            error::Context::new(
+                "unable to tell the processor generation",
+                Box::new(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("give {}", option),
+                )),
+            )
+        })
+    }
+
+    /// The name of the generation at the KDS, which only serves SEV-SNP
+    /// certificates.
+    pub fn product(self) -> Option<kds::Product> {
+        match self {
+            Generation::Naples | Generation::Rome => None,
+            Generation::Milan => Some(kds::Product::Milan),
+            Generation::Genoa => Some(kds::Product::Genoa),
+            Generation::Turin => Some(kds::Product::Turin),
+        }
+    }
+}
+
+/// Reads a model-specific register of CPU 0 through the msr driver.
+pub fn msr(index: u32) -> std::io::Result<u64> {
+    use std::os::unix::fs::FileExt;
+
+    let file = std::fs::File::open("/dev/cpu/0/msr")?;
+    let mut value = [0u8; 8];
+    file.read_exact_at(&mut value, index.into())?;
+    Ok(u64::from_le_bytes(value))
+}
+
+/// The physical addresses of the first and last byte of the RMP, both zero if
+/// the BIOS did not reserve one.
+pub fn rmp_range() -> std::io::Result<(u64, u64)> {
+    Ok((msr(MSR_RMP_BASE)?, msr(MSR_RMP_END)?))
+}
+
+/// The end of the highest System RAM range of /proc/iomem, which shows addresses
+/// only to root.
+pub fn system_ram_end() -> Option<u64> {
+    std::fs::read_to_string("/proc/iomem")
+        .ok()?
+        .lines()
+        .filter(|line| !line.starts_with(' '))
+        .filter_map(|line| {
+            let (range, name) = line.split_once(" : ")?;
+            if name != "System RAM" {
+                return None;
+            }
+            u64::from_str_radix(range.split_once('-')?.1, 16).ok()
+        })
+        .max()
+        .filter(|end| *end != 0)
+        .map(|end| end + 1)
+}
+
+/// Whether a boolean `kvm_amd` module parameter (e.g. `sev_es`) is enabled.
+pub fn kvm_param(name: &str) -> bool {
+    let path = Path::new("/sys/module/kvm_amd/parameters").join(name);
+    matches!(
+        std::fs::read_to_string(path).as_deref().map(str::trim),
+        Ok("Y") | Ok("y") | Ok("1")
+    )
+}
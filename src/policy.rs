@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding of SEV and SEV-SNP guest policies into their individual flags.
+
+use super::*;
+use colorful::*;
+
+#[derive(StructOpt)]
+pub enum Policy {
+    #[structopt(about = "Decode a guest policy value into its individual flags")]
+    Explain {
+        #[structopt(long, help = "Interpret the value as a 64-bit SEV-SNP guest policy")]
+        snp: bool,
+
+        #[structopt(help = "Policy value (decimal or 0x-prefixed hexadecimal)")]
+        value: String,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SnpPolicy {
+    #[structopt(about = "Build a 64-bit SEV-SNP guest policy from named flags")]
+    Build {
+        #[structopt(long, help = "Allow SMT")]
+        smt: bool,
+
+        #[structopt(long, help = "Allow association with a migration agent")]
+        migrate_ma: bool,
+
+        #[structopt(long, conflicts_with = "no-debug", help = "Allow debugging")]
+        debug: bool,
+
+        #[structopt(long, help = "Disallow debugging (the default)")]
+        no_debug: bool,
+
+        #[structopt(long, help = "Only allow activation on one socket")]
+        single_socket: bool,
+
+        #[structopt(long, help = "Allow CXL to be populated with guest memory")]
+        cxl_allow: bool,
+
+        #[structopt(long, help = "Require AES-256-XTS for memory encryption")]
+        mem_aes_256_xts: bool,
+
+        #[structopt(long, help = "Require Running Average Power Limit to be disabled")]
+        rapl_dis: bool,
+
+        #[structopt(long, help = "Require ciphertext hiding")]
+        ciphertext_hiding: bool,
+
+        #[structopt(
+            long,
+            default_value = "0.0",
+            help = "Minimum firmware ABI version (major.minor)"
+        )]
+        min_abi: String,
+    },
+
+    #[structopt(about = "Decode an SEV-SNP guest policy into its individual flags")]
+    Explain {
+        #[structopt(help = "Policy value (decimal or 0x-prefixed hexadecimal)")]
+        value: String,
+    },
+}
+
+/// A single-bit field of a guest policy.
+struct Bit {
+    bit: u32,
+    name: &'static str,
+    desc: &'static str,
+
+    /// A security implication printed when the bit has the given value.
+    warn: Option<(bool, &'static str)>,
+}
+
+const SEV_BITS: &[Bit] = &[
+    Bit {
+        bit: 0,
+        name: "NODBG",
+        desc: "Debugging of the guest is disallowed",
+        warn: Some((
+            false,
+            "debugging is allowed: the hypervisor may decrypt guest memory",
+        )),
+    },
+    Bit {
+        bit: 1,
+        name: "NOKS",
+        desc: "Sharing keys with other guests is disallowed",
+        warn: Some((false, "the guest may share its memory encryption key")),
+    },
+    Bit {
+        bit: 2,
+        name: "ES",
+        desc: "SEV-ES is required",
+        warn: Some((false, "register state is not protected from the hypervisor")),
+    },
+    Bit {
+        bit: 3,
+        name: "NOSEND",
+        desc: "Sending the guest to another platform is disallowed",
+        warn: None,
+    },
+    Bit {
+        bit: 4,
+        name: "DOMAIN",
+        desc: "The guest must not leave the domain",
+        warn: None,
+    },
+    Bit {
+        bit: 5,
+        name: "SEV",
+        desc: "The guest must not be sent to a non-SEV platform",
+        warn: None,
+    },
+];
+
+/// Bits 6 to 15 of an SEV policy are reserved.
+const SEV_RESERVED: u64 = 0xffc0;
+
+const SNP_BITS: &[Bit] = &[
+    Bit {
+        bit: 16,
+        name: "SMT",
+        desc: "SMT is allowed",
+        warn: None,
+    },
+    Bit {
+        bit: 17,
+        name: "RESERVED",
+        desc: "Reserved, must be one",
+        warn: Some((false, "reserved bit 17 must be set or launch will fail")),
+    },
+    Bit {
+        bit: 18,
+        name: "MIGRATE_MA",
+        desc: "Association with a migration agent is allowed",
+        warn: None,
+    },
+    Bit {
+        bit: 19,
+        name: "DEBUG",
+        desc: "Debugging is allowed",
+        warn: Some((
+            true,
+            "debugging is allowed: the hypervisor may decrypt guest memory",
+        )),
+    },
+    Bit {
+        bit: 20,
+        name: "SINGLE_SOCKET",
+        desc: "The guest may only be activated on one socket",
+        warn: None,
+    },
+    Bit {
+        bit: 21,
+        name: "CXL_ALLOW",
+        desc: "CXL may be populated with guest memory",
+        warn: None,
+    },
+    Bit {
+        bit: 22,
+        name: "MEM_AES_256_XTS",
+        desc: "AES-256-XTS is required for memory encryption",
+        warn: None,
+    },
+    Bit {
+        bit: 23,
+        name: "RAPL_DIS",
+        desc: "Running Average Power Limit must be disabled",
+        warn: None,
+    },
+    Bit {
+        bit: 24,
+        name: "CIPHERTEXT_HIDING",
+        desc: "Ciphertext hiding must be enabled",
+        warn: None,
+    },
+];
+
+/// Bits 25 to 63 of an SNP policy are reserved and must be zero.
+const SNP_RESERVED: u64 = !0x1ff_ffff;
+
+pub fn cmd(policy: Policy) -> Result<()> {
+    match policy {
+        Policy::Explain { snp, value } => {
+            let value = parse(&value)?;
+
+            if snp || value > u32::MAX as u64 {
+                output::print(&explain_snp(value));
+            } else {
+                output::print(&explain_sev(value));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn snp_cmd(policy: SnpPolicy) -> Result<()> {
+    match policy {
+        SnpPolicy::Build {
+            smt,
+            migrate_ma,
+            debug,
+            no_debug,
+            single_socket,
+            cxl_allow,
+            mem_aes_256_xts,
+            rapl_dis,
+            ciphertext_hiding,
+            min_abi,
+        } => {
+            let invalid = || {
+                error::Context::new(
+                    &format!("invalid ABI version {} (expected major.minor)", min_abi),
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                )
+            };
+            let (major, minor) = min_abi.split_once('.').ok_or_else(invalid)?;
+            let major: u8 = major.parse().map_err(|_| invalid())?;
+            let minor: u8 = minor.parse().map_err(|_| invalid())?;
+
+            let mut value = (major as u64) << 8 | minor as u64;
+            for (set, name) in &[
+                (smt, "SMT"),
+                // Bit 17 must be one.
+                (true, "RESERVED"),
+                (migrate_ma, "MIGRATE_MA"),
+                // --no-debug states the default; structopt rejects it with --debug.
+                (debug && !no_debug, "DEBUG"),
+                (single_socket, "SINGLE_SOCKET"),
+                (cxl_allow, "CXL_ALLOW"),
+                (mem_aes_256_xts, "MEM_AES_256_XTS"),
+                (rapl_dis, "RAPL_DIS"),
+                (ciphertext_hiding, "CIPHERTEXT_HIDING"),
+            ] {
+                if *set {
+                    value |= snp_flag(name).unwrap();
+                }
+            }
+
+            output::print(&schema::SnpPolicy {
+                policy: format!("{:#x}", value),
+                flags: snp_flags(value).iter().map(|f| f.to_string()).collect(),
+                min_abi: format!("{}.{}", major, minor),
+            });
+        }
+
+        SnpPolicy::Explain { value } => output::print(&explain_snp(parse(&value)?)),
+    }
+
+    Ok(())
+}
+
+impl output::Document for schema::SnpPolicy {
+    fn table(&self) -> String {
+        format!("{}\n", self.policy)
+    }
+}
+
+/// Parses a policy given in decimal or as `0x`-prefixed hex.
+pub fn parse(value: &str) -> Result<u64> {
+    parse_u64(value).context(format!("invalid policy value: {}", value))
+}
+
+/// Decodes an SEV guest policy.
+pub fn explain_sev(value: u64) -> schema::PolicyExplanation {
+    let (flags, mut warnings) = flags(value, SEV_BITS);
+    let reserved = Some(value & SEV_RESERVED)
+        .filter(|r| *r != 0)
+        .map(|r| format!("{:#06x}", r));
+    warnings.extend(
+        reserved
+            .iter()
+            .map(|r| format!("reserved bits are set: {}", r)),
+    );
+
+    schema::PolicyExplanation {
+        kind: "sev".to_string(),
+        policy: format!("{:#010x}", value),
+        min_version: format!("{}.{}", (value >> 16) & 0xff, (value >> 24) & 0xff),
+        flags,
+        reserved,
+        warnings,
+    }
+}
+
+/// Decodes an SEV-SNP guest policy.
+pub fn explain_snp(value: u64) -> schema::PolicyExplanation {
+    let (flags, mut warnings) = flags(value, SNP_BITS);
+    let reserved = Some(value & SNP_RESERVED)
+        .filter(|r| *r != 0)
+        .map(|r| format!("{:#018x}", r));
+    warnings.extend(
+        reserved
+            .iter()
+            .map(|r| format!("reserved bits are set: {}", r)),
+    );
+
+    schema::PolicyExplanation {
+        kind: "snp".to_string(),
+        policy: format!("{:#018x}", value),
+        min_version: format!("{}.{}", (value >> 8) & 0xff, value & 0xff),
+        flags,
+        reserved,
+        warnings,
+    }
+}
+
+impl output::Document for schema::PolicyExplanation {
+    fn table(&self) -> String {
+        let (kind, version) = match self.kind.as_str() {
+            "snp" => ("SEV-SNP", "ABI"),
+            _ => ("SEV", "API"),
+        };
+
+        let mut out = format!("{} guest policy {}\n", kind, self.policy);
+        for flag in &self.flags {
+            out += &format!(
+                "  [{}] {:<18} {}\n",
+                if flag.set { "x" } else { " " },
+                flag.name,
+                flag.description
+            );
+        }
+        out += &format!(
+            "  minimum firmware {} version: {}\n",
+            version, self.min_version
+        );
+        for w in &self.warnings {
+            out += &format!("{} {}\n", "warning:".red(), w);
+        }
+        out
+    }
+
+    fn csv(&self) -> String {
+        output::csv(
+            &["flag", "set", "description"],
+            self.flags
+                .iter()
+                .map(|f| vec![f.name.clone(), f.set.to_string(), f.description.clone()])
+                .collect(),
+        )
+    }
+}
+
+/// The names of the flags set in an SEV policy, e.g. `NOSEND`.
+pub fn sev_flags(value: u64) -> Vec<&'static str> {
+    SEV_BITS
+        .iter()
+        .filter(|b| value & (1 << b.bit) != 0)
+        .map(|b| b.name)
+        .collect()
+}
+
+/// The mask of the SEV policy flag `name`, ignoring case.
+pub fn sev_flag(name: &str) -> Option<u64> {
+    SEV_BITS
+        .iter()
+        .find(|b| b.name.eq_ignore_ascii_case(name))
+        .map(|b| 1 << b.bit)
+}
+
+/// The names of the flags set in an SEV-SNP policy, e.g. `DEBUG`.
+pub fn snp_flags(value: u64) -> Vec<&'static str> {
+    SNP_BITS
+        .iter()
+        .filter(|b| value & (1 << b.bit) != 0)
+        .map(|b| b.name)
+        .collect()
+}
+
+/// The mask of the SEV-SNP policy flag `name`, ignoring case.
+pub fn snp_flag(name: &str) -> Option<u64> {
+    SNP_BITS
+        .iter()
+        .find(|b| b.name.eq_ignore_ascii_case(name))
+        .map(|b| 1 << b.bit)
+}
+
+/// The reserved bits set in an SEV-SNP policy.
+pub fn snp_reserved(value: u64) -> u64 {
+    value & SNP_RESERVED
+}
+
+/// An SEV-SNP policy on one line: the minimum ABI version and the names of the set bits.
+pub fn describe_snp(value: u64) -> String {
+    let mut parts = vec![format!("ABI {}.{}", (value >> 8) & 0xff, value & 0xff)];
+    parts.extend(snp_flags(value).iter().map(|name| name.to_string()));
+    if snp_reserved(value) != 0 {
+        parts.push(format!("reserved {:#x}", snp_reserved(value)));
+    }
+    parts.join(", ")
+}
+
+/// Every bit of `bits` with whether `value` sets it, and the warnings they imply.
+fn flags(value: u64, bits: &[Bit]) -> (Vec<schema::PolicyFlag>, Vec<String>) {
+    let mut flags = Vec::new();
+    let mut warnings = Vec::new();
+
+    for b in bits {
+        let set = value & (1 << b.bit) != 0;
+        flags.push(schema::PolicyFlag {
+            name: b.name.to_string(),
+            description: b.desc.to_string(),
+            set,
+        });
+
+        if let Some((when, msg)) = b.warn {
+            if when == set {
+                warnings.push(msg.to_string());
+            }
+        }
+    }
+
+    (flags, warnings)
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Progress indicators for the operations that can take a while, such as KDS
+//! downloads, firmware updates and provisioning, so that a pause is not taken for
+//! a hang.
+//!
+//! They are drawn on stderr only when it is a terminal, and neither with `--quiet`
+//! nor while logging, whose lines they would garble. One is shown at a time: an
+//! indicator started while another is shown, e.g. by a nested download, is not.
+
+use super::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// The spinner's frames.
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the indicator is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+/// The width of the progress bar, in characters.
+const WIDTH: usize = 24;
+
+/// Whether indicators may be drawn.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether an indicator is shown.
+static SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Allows indicators if `enabled` and stderr is a terminal.
+pub fn init(enabled: bool) {
+    // SAFETY: isatty only inspects the descriptor.
+    let tty = unsafe { libc::isatty(libc::STDERR_FILENO) } == 1;
+    ENABLED.store(enabled && tty, Ordering::Relaxed);
+}
+
+/// What the indicator shows.
+struct State {
+    message: String,
+
+    /// The bytes transferred, and how many there are if known.
+    bytes: Option<(u64, Option<u64>)>,
+}
+
+/// An indicator, shown from [`start`] until dropped.
+pub struct Progress {
+    shown: Option<Shown>,
+}
+
+struct Shown {
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Shows a spinner with `message`, e.g. `Downloading the VCEK`.
+pub fn start(message: &str) -> Progress {
+    if !ENABLED.load(Ordering::Relaxed) || SHOWN.swap(true, Ordering::AcqRel) {
+        return Progress { shown: None };
+    }
+
+    let state = Arc::new(Mutex::new(State {
+        message: message.to_string(),
+        bytes: None,
+    }));
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let state = state.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            for frame in FRAMES.iter().cycle() {
+                if stop.load(Ordering::Acquire) {
+                    break;
+                }
+                draw(*frame, &state.lock().unwrap());
+                std::thread::sleep(TICK);
+            }
+        })
+    };
+
+    Progress {
+        shown: Some(Shown {
+            state,
+            stop,
+            thread: Some(thread),
+        }),
+    }
+}
+
+impl Progress {
+    /// Replaces the message, for the next step of an operation.
+    pub fn step(&self, message: &str) {
+        if let Some(shown) = &self.shown {
+            let mut state = shown.state.lock().unwrap();
+            state.message = message.to_string();
+            state.bytes = None;
+        }
+    }
+
+    /// Shows that `done` bytes of `total`, if known, are transferred.
+    pub fn bytes(&self, done: u64, total: Option<u64>) {
+        if let Some(shown) = &self.shown {
+            shown.state.lock().unwrap().bytes = Some((done, total));
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(shown) = &mut self.shown {
+            shown.stop.store(true, Ordering::Release);
+            if let Some(thread) = shown.thread.take() {
+                let _ = thread.join();
+            }
+            eprint!("\r\x1b[2K");
+            SHOWN.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Redraws the line of the indicator.
+fn draw(frame: char, state: &State) {
+    let mut line = format!("\r\x1b[2K{} {}", frame, state.message);
+    match state.bytes {
+        Some((done, Some(total))) if total > 0 => {
+            let filled = (done.min(total) * WIDTH as u64 / total) as usize;
+            line += &format!(
+                " [{}{}] {}% of {}",
+                "#".repeat(filled),
+                "-".repeat(WIDTH - filled),
+                done.min(total) * 100 / total,
+                size(total)
+            );
+        }
+        Some((done, _)) => line += &format!(" {}", size(done)),
+        None => (),
+    }
+    eprint!("{}", line);
+}
+
+/// A byte count for people, e.g. `12.5 KiB`.
+fn size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal client for the QEMU Machine Protocol (QMP).
+
+use super::*;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+
+/// How long to wait for QEMU to accept a command or respond to it.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The launch state of an SEV guest, as returned by `query-sev`.
+pub struct SevInfo {
+    pub enabled: bool,
+    pub state: String,
+    pub handle: u32,
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u8,
+    pub policy: u32,
+}
+
+/// A QMP connection over a UNIX socket (`-qmp unix:/path,server`), for async callers.
+pub struct AsyncQmp {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl AsyncQmp {
+    /// Connects to the socket and leaves capabilities negotiation mode.
+    pub async fn connect(path: &Path) -> Result<Self> {
+        tracing::info!(path = %path.display(), "connecting to QMP");
+        let stream = match timeout(TIMEOUT, UnixStream::connect(path)).await {
+            Ok(stream) => stream,
+            Err(_) => Err(timed_out()),
+        }
+        .context(format!(
+            "unable to connect to QMP socket {}",
+            path.display()
+        ))?;
+        let (reader, writer) = stream.into_split();
+
+        let mut qmp = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+
+        let greeting = qmp.receive().await?;
+        if greeting.get("QMP").is_none() {
+            return Err(error::Context::new(
+                "unexpected QMP greeting",
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+
+        qmp.execute("qmp_capabilities", None).await?;
+        Ok(qmp)
+    }
+
+    /// Executes a command and returns its result, skipping asynchronous events.
+    pub async fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+
+        tracing::debug!(command, "executing QMP command");
+        let line = format!("{}\n", request);
+        match timeout(TIMEOUT, self.writer.write_all(line.as_bytes())).await {
+            Ok(written) => written,
+            Err(_) => Err(timed_out()),
+        }
+        .context("unable to send QMP command")?;
+
+        loop {
+            let mut response = self.receive().await?;
+            if response.get("event").is_some() {
+                continue;
+            }
+
+            if let Some(error) = response.get("error") {
+                let desc = error["desc"].as_str().unwrap_or("unknown error");
+                return Err(error::Context::new(
+                    &format!("QMP command {} failed: {}", command, desc),
+                    Box::<Error>::new(ErrorKind::Other.into()),
+                ));
+            }
+
+            return match response.get_mut("return") {
+                Some(value) => Ok(value.take()),
+                None => Err(error::Context::new(
+                    "unexpected QMP response",
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                )),
+            };
+        }
+    }
+
+    /// Queries the guest's SEV state.
+    pub async fn query_sev(&mut self) -> Result<SevInfo> {
+        let info = self.execute("query-sev", None).await?;
+        let field = |name: &str| {
+            info[name].as_u64().ok_or_else(|| {
+                error::Context::new(
+                    &format!("query-sev returned no {}", name),
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                )
+            })
+        };
+
+        Ok(SevInfo {
+            enabled: info["enabled"].as_bool().unwrap_or(false),
+            state: info["state"].as_str().unwrap_or("unknown").to_string(),
+            handle: field("handle")? as u32,
+            api_major: field("api-major")? as u8,
+            api_minor: field("api-minor")? as u8,
+            build_id: field("build-id")? as u8,
+            policy: field("policy")? as u32,
+        })
+    }
+
+    /// Queries the base64-encoded launch measurement and nonce.
+    pub async fn query_sev_launch_measure(&mut self) -> Result<String> {
+        let measure = self.execute("query-sev-launch-measure", None).await?;
+        match measure["data"].as_str() {
+            Some(data) => Ok(data.to_string()),
+            None => Err(error::Context::new(
+                "query-sev-launch-measure returned no data",
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            )),
+        }
+    }
+
+    /// Requests an attestation report of the running guest binding `mnonce`.
+    pub async fn query_sev_attestation_report(&mut self, mnonce: &[u8]) -> Result<Vec<u8>> {
+        let report = self
+            .execute(
+                "query-sev-attestation-report",
+                Some(json!({ "mnonce": base64::encode(mnonce) })),
+            )
+            .await?;
+        report["data"]
+            .as_str()
+            .and_then(|data| base64::decode(data).ok())
+            .ok_or_else(|| {
+                error::Context::new(
+                    "query-sev-attestation-report returned no data",
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                )
+            })
+    }
+
+    /// Injects a launch secret packet into the guest's memory.
+    pub async fn sev_inject_launch_secret(&mut self, header: &[u8], secret: &[u8]) -> Result<()> {
+        self.execute(
+            "sev-inject-launch-secret",
+            Some(json!({
+                "packet-header": base64::encode(header),
+                "secret": base64::encode(secret),
+            })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Resumes the guest.
+    pub async fn cont(&mut self) -> Result<()> {
+        self.execute("cont", None).await?;
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if match timeout(TIMEOUT, self.reader.read_line(&mut line)).await {
+            Ok(read) => read,
+            Err(_) => Err(timed_out()),
+        }
+        .context("unable to read from QMP socket")?
+            == 0
+        {
+            return Err(error::Context::new(
+                "QMP socket closed",
+                Box::<Error>::new(ErrorKind::UnexpectedEof.into()),
+            ));
+        }
+
+        serde_json::from_str(&line).context("invalid QMP message")
+    }
+}
+
+/// An [`AsyncQmp`] connection for blocking callers, driven by a runtime of its own.
+pub struct Qmp {
+    runtime: tokio::runtime::Runtime,
+    inner: AsyncQmp,
+}
+
+impl Qmp {
+    /// Connects to the socket and leaves capabilities negotiation mode.
+    pub fn connect(path: &Path) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("unable to start async runtime")?;
+        let inner = runtime.block_on(AsyncQmp::connect(path))?;
+        Ok(Self { runtime, inner })
+    }
+
+    /// Executes a command and returns its result, skipping asynchronous events.
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        self.runtime
+            .block_on(self.inner.execute(command, arguments))
+    }
+
+    /// Queries the guest's SEV state.
+    pub fn query_sev(&mut self) -> Result<SevInfo> {
+        self.runtime.block_on(self.inner.query_sev())
+    }
+
+    /// Queries the base64-encoded launch measurement and nonce.
+    pub fn query_sev_launch_measure(&mut self) -> Result<String> {
+        self.runtime.block_on(self.inner.query_sev_launch_measure())
+    }
+
+    /// Requests an attestation report of the running guest binding `mnonce`.
+    pub fn query_sev_attestation_report(&mut self, mnonce: &[u8]) -> Result<Vec<u8>> {
+        self.runtime
+            .block_on(self.inner.query_sev_attestation_report(mnonce))
+    }
+
+    /// Injects a launch secret packet into the guest's memory.
+    pub fn sev_inject_launch_secret(&mut self, header: &[u8], secret: &[u8]) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.sev_inject_launch_secret(header, secret))
+    }
+
+    /// Resumes the guest.
+    pub fn cont(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.cont())
+    }
+}
+
+fn timed_out() -> Error {
+    Error::new(
+        ErrorKind::TimedOut,
+        format!("no response from QEMU within {}s", TIMEOUT.as_secs()),
+    )
+}
@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording and replaying the firmware commands of a run, so that what a
+//! command did on a platform can be reproduced without it.
+//!
+//! `--record` writes every firmware command issued and what the firmware answered
+//! to a file, in the helper's protocol. `--replay` then answers the same commands
+//! from that file, in the same order, and fails as soon as the run asks for
+//! anything else.
+
+use super::*;
+use device::Device;
+use schema::{Recorded, Recording};
+use serde_json::Value;
+use std::sync::Mutex;
+
+enum Mode {
+    Record {
+        path: PathBuf,
+        recording: Recording,
+    },
+    Replay {
+        path: PathBuf,
+        commands: std::vec::IntoIter<Recorded>,
+    },
+}
+
+static MODE: Mutex<Option<Mode>> = Mutex::new(None);
+
+/// Records the firmware commands issued afterwards to `path`.
+pub fn record(path: PathBuf) {
+    *MODE.lock().unwrap() = Some(Mode::Record {
+        path,
+        recording: Recording {
+            sevctl_version: VERSION.to_string(),
+            commands: Vec::new(),
+        },
+    });
+}
+
+/// Answers the firmware commands issued afterwards from the recording at `path`.
+pub fn replay(path: PathBuf) -> Result<()> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let text =
+        std::fs::read_to_string(&path).context(format!("unable to read {}", path.display()))?;
+    let recording: schema::Versioned<Recording> =
+        serde_json::from_str(&text).context(format!("invalid recording {}", path.display()))?;
+
+    *MODE.lock().unwrap() = Some(Mode::Replay {
+        path,
+        commands: recording.document.commands.into_iter(),
+    });
+    Ok(())
+}
+
+/// The firmware as recorded, when replaying.
+pub fn player() -> Option<Box<dyn Device>> {
+    match MODE.lock().unwrap().as_ref() {
+        Some(Mode::Replay { .. }) => Some(Box::new(helper::Client::with(Player))),
+        _ => None,
+    }
+}
+
+/// Wraps the firmware so that its commands are recorded, when recording.
+pub fn wrap(fw: Box<dyn Device>) -> Box<dyn Device> {
+    match MODE.lock().unwrap().as_ref() {
+        Some(Mode::Record { .. }) => Box::new(helper::Client::with(Recorder(fw))),
+        _ => fw,
+    }
+}
+
+/// Issues requests to the firmware, recording them with their responses.
+struct Recorder(Box<dyn Device>);
+
+impl helper::Transport for Recorder {
+    fn call(&mut self, request: Value) -> std::io::Result<Value> {
+        let result = helper::serve(&mut *self.0, &request);
+
+        if let Some(Mode::Record { path, recording }) = MODE.lock().unwrap().as_mut() {
+            recording.commands.push(Recorded {
+                request,
+                response: helper::response(&result),
+            });
+
+            // Rewritten after every command, so that runs ending early are recorded too.
+            let document = schema::Versioned::new(&*recording).to_string_pretty();
+            session::write(path, document.as_bytes(), 0o600)
+                .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        }
+
+        result
+    }
+}
+
+/// Answers requests from a recording.
+struct Player;
+
+impl helper::Transport for Player {
+    fn call(&mut self, request: Value) -> std::io::Result<Value> {
+        let mut mode = MODE.lock().unwrap();
+        let (path, commands) = match mode.as_mut() {
+            Some(Mode::Replay { path, commands }) => (path, commands),
+            _ => unreachable!("replaying without a recording"),
+        };
+
+        match commands.next() {
+            Some(recorded) if recorded.request == request => helper::result(recorded.response),
+            Some(recorded) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the run departs from {}: it issued {} where {} was recorded",
+                    path.display(),
+                    request,
+                    recorded.request
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "the run departs from {}: it issued {} after the last recorded command",
+                    path.display(),
+                    request
+                ),
+            )),
+        }
+    }
+}
@@ -0,0 +1,619 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! SEV-SNP attestation reports, as requested by a guest from the platform.
+//!
+//! Guests request reports through configfs-tsm, or with the SNP_GET_REPORT ioctl of
+//! the SEV guest device on kernels predating it. The device also derives sealing keys
+//! bound to the guest and the platform (SNP_GET_DERIVED_KEY).
+
+use super::*;
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::{self, MessageDigest};
+use openssl::x509::X509;
+use serde::Serialize;
+use std::convert::TryInto;
+use std::os::unix::io::AsRawFd;
+use zeroize::Zeroize;
+
+/// Where the kernel exposes the guest's trusted security module (configfs-tsm).
+const TSM_REPORT: &str = "/sys/kernel/config/tsm/report";
+
+/// The SEV guest device.
+pub const SEV_GUEST: &str = "/dev/sev-guest";
+
+/// The SNP_GET_REPORT ioctl: `_IOWR('S', 0x0, struct snp_guest_request_ioctl)`.
+const SNP_GET_REPORT: libc::c_ulong = 0xc020_5300;
+
+/// The SNP_GET_DERIVED_KEY ioctl: `_IOWR('S', 0x1, struct snp_guest_request_ioctl)`.
+const SNP_GET_DERIVED_KEY: libc::c_ulong = 0xc020_5301;
+
+/// The SNP_GET_EXT_REPORT ioctl: `_IOWR('S', 0x2, struct snp_guest_request_ioctl)`.
+const SNP_GET_EXT_REPORT: libc::c_ulong = 0xc020_5302;
+
+/// The GUIDs of the certificates in the table of an extended report.
+pub const VCEK_GUID: &str = "63da758d-e664-4564-adc5-f4b93be8accd";
+pub const ASK_GUID: &str = "4ab7b379-bbac-4fe4-a02f-05aef327c782";
+pub const ARK_GUID: &str = "c0b406a4-a803-4952-9743-3fb6014cd0ae";
+pub const VLEK_GUID: &str = "a8074bc2-a25a-483e-aae6-39c045a0b8a1";
+
+/// The names of the certificates of an extended report by GUID, as written to files.
+pub const CERT_NAMES: &[(&str, &str)] = &[
+    (VCEK_GUID, "vcek"),
+    (ASK_GUID, "ask"),
+    (ARK_GUID, "ark"),
+    (VLEK_GUID, "vlek"),
+];
+
+/// The size of a certificate table buffer to start with; the kernel asks for more if needed.
+const CERTS_SIZE: usize = 4 * 4096;
+
+/// The version of the guest messages sevctl sends.
+const MSG_VERSION: u8 = 1;
+
+/// The size of the response buffer the kernel fills.
+const RESPONSE_SIZE: usize = 4000;
+
+/// Where the report or key starts in a response message, after the status.
+const RESPONSE_HEADER: usize = 0x20;
+
+/// The size of an attestation report.
+pub const REPORT_SIZE: usize = 0x4a0;
+
+/// The part of the report covered by its signature.
+const SIGNED_SIZE: usize = 0x2a0;
+
+/// The size of each little-endian signature component, zero-padded beyond P-384's 48 bytes.
+const COMPONENT_SIZE: usize = 72;
+
+/// The bits of PLATFORM_INFO.
+const PLAT_INFO: &[(u32, &str)] = &[
+    (0, "SMT enabled"),
+    (1, "TSME enabled"),
+    (2, "ECC memory"),
+    (3, "RAPL disabled"),
+    (4, "ciphertext hiding enabled"),
+];
+
+/// The argument of the guest request ioctls.
+#[repr(C)]
+struct GuestRequest {
+    msg_version: u8,
+    req_data: u64,
+    resp_data: u64,
+
+    /// The firmware status in the low half, the hypervisor's in the high half.
+    exitinfo2: u64,
+}
+
+/// The MSG_REPORT_REQ message.
+#[repr(C)]
+struct ReportRequest {
+    user_data: [u8; 64],
+    vmpl: u32,
+    reserved: [u8; 28],
+}
+
+/// The argument of SNP_GET_EXT_REPORT: the report request and the certificate buffer.
+#[repr(C)]
+struct ExtReportRequest {
+    data: ReportRequest,
+    certs_address: u64,
+    certs_len: u32,
+}
+
+/// The MSG_KEY_REQ message.
+#[repr(C)]
+struct DerivedKeyRequest {
+    root_key_select: u32,
+    reserved: u32,
+
+    /// Bit 0 selects the policy, then the image ID, family ID, measurement, guest
+    /// SVN and TCB version.
+    guest_field_select: u64,
+    vmpl: u32,
+    guest_svn: u32,
+    tcb_version: u64,
+}
+
+/// Pads report data to the 64 bytes a report carries.
+pub fn data(data: &[u8]) -> Result<[u8; 64]> {
+    let mut padded = [0u8; 64];
+    if data.len() > padded.len() {
+        return Err(error::Context::new(
+            &format!(
+                "report data is {} bytes, at most 64 are allowed",
+                data.len()
+            ),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    padded[..data.len()].copy_from_slice(data);
+    Ok(padded)
+}
+
+/// The SHA-512 digest of a file, which fills the report data exactly, e.g. to bind
+/// a public key or a nonce transcript of any size.
+pub fn data_sha512(path: &Path) -> Result<[u8; 64]> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let bytes = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+    let digest = hash::hash(MessageDigest::sha512(), &bytes).context("unable to hash")?;
+
+    let mut data = [0u8; 64];
+    data.copy_from_slice(&digest);
+    Ok(data)
+}
+
+/// Requests an attestation report carrying `data` from the platform.
+pub fn request(data: &[u8; 64]) -> Result<Report> {
+    let dir = Path::new(TSM_REPORT).join(format!("sevctl-{}", std::process::id()));
+    std::fs::create_dir(&dir).context(format!(
+        "unable to create {} (is this an SEV-SNP guest with configfs-tsm?)",
+        dir.display()
+    ))?;
+
+    let report = std::fs::write(dir.join("inblob"), &data[..])
+        .context("unable to write report data")
+        .and_then(|_| std::fs::read(dir.join("outblob")).context("unable to read report"));
+
+    // The report entry only exists for this request.
+    let _ = std::fs::remove_dir(&dir);
+
+    Report::new(report?)
+}
+
+/// Requests an attestation report carrying `data` for the given VMPL with
+/// SNP_GET_REPORT on the SEV guest device.
+pub fn request_device(data: &[u8; 64], vmpl: u32) -> Result<Report> {
+    let mut request = ReportRequest {
+        user_data: *data,
+        vmpl,
+        reserved: [0; 28],
+    };
+    let mut response = [0u8; RESPONSE_SIZE];
+    guest_request(SNP_GET_REPORT, &mut request, &mut response)
+        .map_err(|e| error::Context::new("unable to request an attestation report", Box::new(e)))?;
+
+    let size = u32::from_le_bytes(response[4..8].try_into().unwrap()) as usize;
+    let end = RESPONSE_HEADER + size.min(response.len() - RESPONSE_HEADER);
+    Report::new(response[RESPONSE_HEADER..end].to_vec())
+}
+
+/// The name of a certificate of an extended report, e.g. `vcek`, if its GUID is known.
+///
+/// Hosts differ in whether they store the GUIDs in EFI byte order or as RFC 4122
+/// bytes, so both are recognized.
+pub fn cert_name(guid: &guid::Guid) -> Option<&'static str> {
+    let mut rfc = *guid.as_bytes();
+    rfc[0..4].reverse();
+    rfc[4..6].reverse();
+    rfc[6..8].reverse();
+
+    CERT_NAMES
+        .iter()
+        .find(|(g, _)| {
+            let known = guid::Guid::from_static(g);
+            *known.as_bytes() == *guid.as_bytes() || *known.as_bytes() == rfc
+        })
+        .map(|(_, name)| *name)
+}
+
+/// The certificates of an extended report with their GUIDs.
+pub type CertTable = Vec<(guid::Guid, Vec<u8>)>;
+
+/// Requests an attestation report with SNP_GET_EXT_REPORT, along with the
+/// certificates the host provides, e.g. the VCEK, ASK and ARK, by GUID.
+pub fn request_extended(data: &[u8; 64], vmpl: u32) -> Result<(Report, CertTable)> {
+    let context = |e| error::Context::new("unable to request an extended attestation report", e);
+
+    let mut certs = vec![0u8; CERTS_SIZE];
+    let mut response = [0u8; RESPONSE_SIZE];
+    loop {
+        let mut request = ExtReportRequest {
+            data: ReportRequest {
+                user_data: *data,
+                vmpl,
+                reserved: [0; 28],
+            },
+            certs_address: certs.as_mut_ptr() as u64,
+            certs_len: certs.len() as u32,
+        };
+        match guest_request(SNP_GET_EXT_REPORT, &mut request, &mut response) {
+            Ok(()) => break,
+
+            // The kernel reports the size of a table that did not fit.
+            Err(_) if request.certs_len as usize > certs.len() => {
+                certs.resize(request.certs_len as usize, 0);
+            }
+            Err(e) => return Err(context(Box::new(e))),
+        }
+    }
+
+    let size = u32::from_le_bytes(response[4..8].try_into().unwrap()) as usize;
+    let end = RESPONSE_HEADER + size.min(response.len() - RESPONSE_HEADER);
+    let report = Report::new(response[RESPONSE_HEADER..end].to_vec())?;
+
+    let invalid = || {
+        context(Box::new(Error::new(
+            ErrorKind::InvalidData,
+            "invalid certificate table",
+        )))
+    };
+    let mut table = Vec::new();
+    for entry in certs.chunks_exact(24) {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&entry[..16]);
+        let offset = u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize;
+
+        // The table ends with an entry of zeros.
+        if bytes == [0; 16] && offset == 0 && len == 0 {
+            break;
+        }
+        let cert = certs.get(offset..offset + len).ok_or_else(invalid)?;
+        table.push((guid::Guid::from_bytes(bytes), cert.to_vec()));
+    }
+
+    Ok((report, table))
+}
+
+/// The fields of the guest and platform a derived key is bound to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeySelect {
+    /// Derive from the VM root key rather than the VCEK.
+    pub vmrk: bool,
+
+    pub policy: bool,
+    pub image_id: bool,
+    pub family_id: bool,
+    pub measurement: bool,
+
+    /// The guest SVN to bind, at most the guest's.
+    pub guest_svn: Option<u32>,
+
+    /// The TCB version to bind, at most the platform's.
+    pub tcb: Option<platform::TcbVersion>,
+
+    pub vmpl: u32,
+}
+
+/// Requests a key derived from the selected fields with SNP_GET_DERIVED_KEY on the
+/// SEV guest device.
+pub fn derived_key(select: KeySelect) -> Result<secure::Secret> {
+    let fields = [
+        select.policy,
+        select.image_id,
+        select.family_id,
+        select.measurement,
+        select.guest_svn.is_some(),
+        select.tcb.is_some(),
+    ];
+    let mut request = DerivedKeyRequest {
+        root_key_select: select.vmrk as u32,
+        reserved: 0,
+        guest_field_select: fields
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (bit, set)| mask | ((*set as u64) << bit)),
+        vmpl: select.vmpl,
+        guest_svn: select.guest_svn.unwrap_or(0),
+        tcb_version: select.tcb.map_or(0, u64::from),
+    };
+    let mut response = [0u8; RESPONSE_SIZE];
+    guest_request(SNP_GET_DERIVED_KEY, &mut request, &mut response)
+        .map_err(|e| error::Context::new("unable to request a derived key", Box::new(e)))?;
+
+    let mut key = secure::Secret::new(32);
+    key.copy_from_slice(&response[RESPONSE_HEADER..RESPONSE_HEADER + 32]);
+    response.zeroize();
+    Ok(key)
+}
+
+/// Sends a guest request to the firmware through the SEV guest device; the
+/// response starts with the firmware status. The kernel may update the request.
+fn guest_request<T>(
+    ioctl: libc::c_ulong,
+    request: &mut T,
+    response: &mut [u8; RESPONSE_SIZE],
+) -> std::io::Result<()> {
+    tracing::debug!(path = SEV_GUEST, "opening the SEV guest device");
+    let dev = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(SEV_GUEST)
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "unable to open {} (is this an SEV-SNP guest?): {}",
+                    SEV_GUEST, e
+                ),
+            )
+        })?;
+
+    let mut arg = GuestRequest {
+        msg_version: MSG_VERSION,
+        req_data: request as *mut T as u64,
+        resp_data: response.as_mut_ptr() as u64,
+        exitinfo2: 0,
+    };
+
+    // SAFETY: `arg` is a valid snp_guest_request_ioctl whose buffers outlive the call.
+    let ret = unsafe { libc::ioctl(dev.as_raw_fd(), ioctl, &mut arg as *mut GuestRequest) };
+    if ret == -1 {
+        let cause = Error::last_os_error();
+        return Err(match arg.exitinfo2 as u32 {
+            0 => cause,
+            status => Error::new(cause.kind(), error::Firmware { status, cause }),
+        });
+    }
+
+    match u32::from_le_bytes(response[..4].try_into().unwrap()) {
+        0 => Ok(()),
+        status => Err(Error::new(
+            ErrorKind::Other,
+            error::Firmware {
+                status,
+                cause: Error::new(ErrorKind::Other, "the firmware rejected the request"),
+            },
+        )),
+    }
+}
+
+/// An attestation report.
+pub struct Report(Vec<u8>);
+
+impl Report {
+    pub fn new(bytes: Vec<u8>) -> Result<Self> {
+        if bytes.len() < REPORT_SIZE {
+            return Err(error::Context::new(
+                &format!(
+                    "attestation report is {} bytes, expected {}",
+                    bytes.len(),
+                    REPORT_SIZE
+                ),
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+
+        Ok(Self(bytes))
+    }
+
+    /// The raw report.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Checks the ECDSA P-384 signature of the report with the VCEK.
+    pub fn verify(&self, vcek: &X509) -> Result<bool> {
+        let component = |off: usize| {
+            let mut be = self.0[off..off + COMPONENT_SIZE].to_vec();
+            be.reverse();
+            BigNum::from_slice(&be).context("invalid report signature")
+        };
+        let sig = EcdsaSig::from_private_components(
+            component(SIGNED_SIZE)?,
+            component(SIGNED_SIZE + COMPONENT_SIZE)?,
+        )
+        .context("invalid report signature")?;
+
+        let key = vcek
+            .public_key()
+            .and_then(|key| key.ec_key())
+            .context("the VCEK holds no EC public key")?;
+        let digest = hash::hash(MessageDigest::sha384(), &self.0[..SIGNED_SIZE])
+            .context("unable to hash the report")?;
+        sig.verify(&digest, &key)
+            .context("unable to check the report signature")
+    }
+
+    /// The fields of the report.
+    pub fn document(&self) -> schema::AttestationReport {
+        let b = &self.0;
+        let bytes = |off: usize, len: usize| b[off..off + len].to_vec();
+        let le = |off: usize, len: usize| {
+            let mut v = [0u8; 8];
+            v[..len].copy_from_slice(&b[off..off + len]);
+            u64::from_le_bytes(v)
+        };
+        let u32 = |off: usize| le(off, 4) as u32;
+        let tcb = |off: usize| schema::Tcb {
+            bootloader: b[off],
+            tee: b[off + 1],
+            _reserved: bytes(off + 2, 4),
+            snp: b[off + 6],
+            microcode: b[off + 7],
+        };
+
+        schema::AttestationReport {
+            version: u32(0x00),
+            guest_svn: u32(0x04),
+            policy: le(0x08, 8),
+            family_id: bytes(0x10, 16),
+            image_id: bytes(0x20, 16),
+            vmpl: u32(0x30),
+            sig_algo: u32(0x34),
+            current_tcb: tcb(0x38),
+            plat_info: le(0x40, 8),
+            _author_key_en: u32(0x48),
+            _reserved_0: u32(0x4c),
+            report_data: bytes(0x50, 64),
+            measurement: bytes(0x90, 48),
+            host_data: bytes(0xc0, 32),
+            id_key_digest: bytes(0xe0, 48),
+            author_key_digest: bytes(0x110, 48),
+            report_id: bytes(0x140, 32),
+            report_id_ma: bytes(0x160, 32),
+            reported_tcb: tcb(0x180),
+            _reserved_1: bytes(0x188, 24),
+            chip_id: bytes(0x1a0, 64),
+            committed_tcb: tcb(0x1e0),
+            current_build: b[0x1e8],
+            current_minor: b[0x1e9],
+            current_major: b[0x1ea],
+            _reserved_2: b[0x1eb],
+            committed_build: b[0x1ec],
+            committed_minor: b[0x1ed],
+            committed_major: b[0x1ee],
+            _reserved_3: b[0x1ef],
+            launch_tcb: tcb(0x1f0),
+            _reserved_4: bytes(0x1f8, 168),
+            signature: schema::Signature {
+                r: bytes(0x2a0, 72),
+                s: bytes(0x2a0 + 72, 72),
+                _reserved: bytes(0x2a0 + 144, 368),
+            },
+        }
+    }
+}
+
+impl From<&schema::Tcb> for platform::TcbVersion {
+    fn from(tcb: &schema::Tcb) -> Self {
+        Self {
+            bootloader: tcb.bootloader,
+            tee: tcb.tee,
+            snp: tcb.snp,
+            microcode: tcb.microcode,
+        }
+    }
+}
+
+impl output::Document for schema::AttestationReport {
+    fn table(&self) -> String {
+        let tcb = |tcb: &schema::Tcb| {
+            format!(
+                "bootloader {}, tee {}, snp {}, microcode {}",
+                tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode
+            )
+        };
+
+        format!(
+            "version: {}\nguest svn: {}\npolicy: {:#x}\nvmpl: {}\nmeasurement: {}\n\
+             report data: {}\nhost data: {}\nchip id: {}\nreported tcb: {}\n",
+            self.version,
+            self.guest_svn,
+            self.policy,
+            self.vmpl,
+            hex(&self.measurement),
+            hex(&self.report_data),
+            hex(&self.host_data),
+            hex(&self.chip_id),
+            tcb(&self.reported_tcb)
+        )
+    }
+}
+
+/// An attestation report shown field by field, with the meaning of each.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub struct Details(pub schema::AttestationReport);
+
+impl output::Document for Details {
+    fn table(&self) -> String {
+        let r = &self.0;
+        let tcb = |tcb: &schema::Tcb| {
+            let raw =
+                u64::from_le_bytes([tcb.bootloader, tcb.tee, 0, 0, 0, 0, tcb.snp, tcb.microcode]);
+            format!(
+                "{:#018x} (bootloader {}, tee {}, snp {}, microcode {})",
+                raw, tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode
+            )
+        };
+        let digest = |bytes: &[u8]| match bytes.iter().all(|b| *b == 0) {
+            true => format!("{} (none)", hex(bytes)),
+            false => hex(bytes),
+        };
+
+        let mut plat_info = Vec::new();
+        for (bit, name) in PLAT_INFO {
+            if r.plat_info & (1 << bit) != 0 {
+                plat_info.push(*name);
+            }
+        }
+
+        let key_info = r._author_key_en;
+        let signing_key = match (key_info >> 2) & 0x7 {
+            0 => "VCEK",
+            1 => "VLEK",
+            7 => "none",
+            _ => "reserved",
+        };
+
+        let mut out = String::new();
+        let mut line = |name: &str, value: String| out += &format!("{:<20} {}\n", name, value);
+        line("version:", r.version.to_string());
+        line("guest svn:", r.guest_svn.to_string());
+        line(
+            "policy:",
+            format!("{:#018x} ({})", r.policy, policy::describe_snp(r.policy)),
+        );
+        line("family id:", hex(&r.family_id));
+        line("image id:", hex(&r.image_id));
+        line("vmpl:", r.vmpl.to_string());
+        line(
+            "signature algorithm:",
+            match r.sig_algo {
+                1 => "1 (ECDSA P-384 with SHA-384)".to_string(),
+                n => format!("{} (unknown)", n),
+            },
+        );
+        line("current tcb:", tcb(&r.current_tcb));
+        line(
+            "platform info:",
+            format!("{:#x} ({})", r.plat_info, plat_info.join(", ")),
+        );
+        line(
+            "key info:",
+            format!(
+                "{:#x} (author key {}, chip key {}, signed by {})",
+                key_info,
+                if key_info & 1 != 0 {
+                    "enabled"
+                } else {
+                    "disabled"
+                },
+                if key_info & 2 != 0 {
+                    "masked"
+                } else {
+                    "unmasked"
+                },
+                signing_key
+            ),
+        );
+        line("report data:", hex(&r.report_data));
+        line("measurement:", hex(&r.measurement));
+        line("host data:", digest(&r.host_data));
+        line("id key digest:", digest(&r.id_key_digest));
+        line("author key digest:", digest(&r.author_key_digest));
+        line("report id:", hex(&r.report_id));
+        line("report id ma:", digest(&r.report_id_ma));
+        line("reported tcb:", tcb(&r.reported_tcb));
+        line(
+            "chip id:",
+            match r.chip_id.iter().all(|b| *b == 0) {
+                true => format!("{} (masked)", hex(&r.chip_id)),
+                false => hex(&r.chip_id),
+            },
+        );
+        line("committed tcb:", tcb(&r.committed_tcb));
+        line(
+            "current version:",
+            format!(
+                "{}.{} build {}",
+                r.current_major, r.current_minor, r.current_build
+            ),
+        );
+        line(
+            "committed version:",
+            format!(
+                "{}.{} build {}",
+                r.committed_major, r.committed_minor, r.committed_build
+            ),
+        );
+        line("launch tcb:", tcb(&r.launch_tcb));
+        line("signature r:", hex(&r.signature.r));
+        line("signature s:", hex(&r.signature.s));
+        out
+    }
+}
@@ -0,0 +1,234 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Confinement of the parsers of untrusted input.
+//!
+//! Certificates and attestation reports often come from the network or from the
+//! host of a guest, and OpenSSL's ASN.1 and X.509 parsers are large C code. They
+//! run in a forked child that may only allocate memory and write its result, as
+//! JSON, to a pipe back to sevctl: a seccomp filter kills it on any other system
+//! call, so a parser exploited by a crafted certificate can neither open files nor
+//! sockets, nor issue firmware commands, nor start processes.
+//!
+//! The SEV certificates and the fields of attestation reports are fixed-size
+//! records read by Rust code with bounds checks, and are decoded in place.
+
+use super::*;
+use schema::ErrorReport;
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+
+/// The audit architecture of the system call numbers in the filter.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// The system calls the child may make with any arguments: memory management,
+/// locks, OpenSSL's fork and entropy checks, and exiting.
+const ALLOWED: &[libc::c_long] = &[
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_clock_gettime,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+/// Runs `parse`, which handles the untrusted `what`, in a confined child and
+/// returns its result.
+pub fn run<T, F>(what: &str, parse: F) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce() -> Result<T>,
+{
+    let _span = tracing::debug_span!("sandbox", what).entered();
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(Error::last_os_error()).context("unable to create the sandbox pipe");
+    }
+    let (read, write) = (fds[0], fds[1]);
+
+    // SAFETY: the child only runs `parse` and exits without returning; sevctl
+    // parses on the thread that forks.
+    match unsafe { libc::fork() } {
+        -1 => {
+            let err = Error::last_os_error();
+            // SAFETY: both ends are ours.
+            unsafe {
+                libc::close(read);
+                libc::close(write);
+            }
+            Err(err).context("unable to start the sandbox")
+        }
+        0 => {
+            // SAFETY: the read end is ours.
+            unsafe { libc::close(read) };
+            child(write, parse)
+        }
+        pid => {
+            // SAFETY: the write end is ours, and the read end is now owned by `pipe`.
+            let mut pipe = unsafe {
+                libc::close(write);
+                File::from_raw_fd(read)
+            };
+            let mut output = Vec::new();
+            let read = pipe.read_to_end(&mut output);
+
+            let mut status = 0;
+            // SAFETY: `pid` is our child.
+            while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+                if Error::last_os_error().kind() != ErrorKind::Interrupted {
+                    return Err(Error::last_os_error()).context("unable to wait for the sandbox");
+                }
+            }
+            read.context("unable to read from the sandbox")?;
+
+            outcome(what, status, &output)
+        }
+    }
+}
+
+/// The result the child sent, or why it sent none.
+fn outcome<T: DeserializeOwned>(what: &str, status: libc::c_int, output: &[u8]) -> Result<T> {
+    if libc::WIFSIGNALED(status) {
+        let reason = match libc::WTERMSIG(status) {
+            libc::SIGSYS => "it made a forbidden system call".to_string(),
+            signal => format!("it was killed by signal {}", signal),
+        };
+        return Err(error::Context::new(
+            &format!("the parser of the {} was stopped", what),
+            Box::new(Error::new(ErrorKind::InvalidData, reason)),
+        ));
+    }
+
+    let result: std::result::Result<T, ErrorReport> =
+        serde_json::from_slice(output).context("invalid result from the sandbox")?;
+    result.map_err(|report| {
+        error::Context::new(
+            &report.message,
+            Box::new(Error::new(ErrorKind::InvalidData, report.causes.join(": "))),
+        )
+        .with_kind(report.kind)
+    })
+}
+
+/// Confines the child, runs `parse` and writes its result to `pipe`.
+fn child<T: Serialize>(pipe: libc::c_int, parse: impl FnOnce() -> Result<T>) -> ! {
+    // A panic message would be a write to stderr.
+    std::panic::set_hook(Box::new(|_| {}));
+    openssl::init();
+
+    let result = match confine(pipe) {
+        Ok(()) => {
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(parse)).unwrap_or_else(|_| {
+                Err(error::Context::new(
+                    "the parser panicked",
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                ))
+            })
+        }
+        Err(err) => Err(err),
+    };
+    let json = serde_json::to_vec(&result.map_err(|e| e.report())).unwrap_or_default();
+
+    let mut written = 0;
+    while written < json.len() {
+        // SAFETY: the range is within `json`.
+        let n = unsafe {
+            libc::write(
+                pipe,
+                json[written..].as_ptr() as *const libc::c_void,
+                json.len() - written,
+            )
+        };
+        match n {
+            n if n > 0 => written += n as usize,
+            _ if Error::last_os_error().kind() == ErrorKind::Interrupted => (),
+            _ => break,
+        }
+    }
+
+    // SAFETY: exiting without running the parent's destructors or exit handlers.
+    unsafe { libc::_exit(0) }
+}
+
+/// Installs the seccomp filter: the calls in `ALLOWED`, and writes to `pipe`.
+fn confine(pipe: libc::c_int) -> Result<()> {
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jeq = |k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+    let ret = |action: u32| stmt(libc::BPF_RET | libc::BPF_K, action);
+
+    let arch = memoffset(|d| &d.arch);
+    let nr = memoffset(|d| &d.nr);
+    // The low half of the first argument, on little-endian targets.
+    let fd = memoffset(|d| &d.args[0]);
+
+    let mut filter = vec![
+        load(arch),
+        jeq(AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(nr),
+    ];
+    for (i, call) in ALLOWED.iter().enumerate() {
+        // Jump to the final ALLOW past the remaining comparisons and the write check.
+        let skip = (ALLOWED.len() - i - 1 + 4) as u8;
+        filter.push(jeq(*call as u32, skip, 0));
+    }
+    filter.extend(vec![
+        jeq(libc::SYS_write as u32, 0, 2),
+        load(fd),
+        jeq(pipe as u32, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        ret(libc::SECCOMP_RET_ALLOW),
+    ]);
+
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    // SAFETY: `program` points at `filter`, which outlives the calls; the kernel
+    // copies it.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &program as *const libc::sock_fprog,
+            ) != 0
+        {
+            return Err(Error::last_os_error()).context("unable to confine the parser");
+        }
+    }
+
+    Ok(())
+}
+
+/// The offset of a field of `seccomp_data`.
+fn memoffset<T>(field: impl Fn(&libc::seccomp_data) -> &T) -> usize {
+    // SAFETY: seccomp_data is plain data.
+    let data: libc::seccomp_data = unsafe { std::mem::zeroed() };
+    field(&data) as *const T as usize - &data as *const _ as usize
+}
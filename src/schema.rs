@@ -0,0 +1,1206 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The JSON documents sevctl writes, as serde types.
+//!
+//! Every document carries a `schema_version`, which is increased whenever a field
+//! is removed or changes its meaning; new fields may be added without it. Parsers
+//! should check it before relying on the rest of the document.
+
+use super::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The version of the documents' schemas.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(StructOpt)]
+pub enum Schema {
+    #[structopt(about = "The SEV attestation report written by attestation request")]
+    AttestationRequest,
+
+    #[structopt(about = "The verdicts written by attestation verify")]
+    AttestationVerify,
+
+    #[structopt(about = "The entries of the audit log, one per line")]
+    Audit,
+
+    #[structopt(about = "The firmware command latencies written by bench")]
+    Bench,
+
+    #[structopt(about = "The files written by certs sync")]
+    CertsSync,
+
+    #[structopt(about = "The diagnosis written by doctor")]
+    Doctor,
+
+    #[structopt(about = "The attestation report written by guest-report")]
+    GuestReport,
+
+    #[structopt(about = "The firmware status code written by error")]
+    Error,
+
+    #[structopt(about = "The launch security element written by export --libvirt")]
+    ExportLibvirt,
+
+    #[structopt(about = "The error written by any command failing with --output json or yaml")]
+    Failure,
+
+    #[structopt(about = "The advisory verdicts written by firmware check")]
+    FirmwareCheck,
+
+    #[structopt(about = "The installed image written by firmware update")]
+    FirmwareUpdate,
+
+    #[structopt(about = "The state file written by init-ex show, init, backup and restore")]
+    InitEx,
+
+    #[structopt(about = "The node capabilities written by k8s resources")]
+    K8sResources,
+
+    #[structopt(about = "The steps written by launch attest")]
+    LaunchAttest,
+
+    #[structopt(about = "The domains written by libvirt list")]
+    LibvirtList,
+
+    #[structopt(about = "The launch measurement written by libvirt measurement")]
+    LibvirtMeasurement,
+
+    #[structopt(about = "The verdict written by measurement verify")]
+    MeasurementVerify,
+
+    #[structopt(about = "The bundle written by migration bundle")]
+    MigrationBundle,
+
+    #[structopt(about = "The guest policy written by migration policy")]
+    MigrationPolicy,
+
+    #[structopt(about = "The verdicts written by migration verify")]
+    MigrationVerify,
+
+    #[structopt(about = "The nonce written by nonce new, and kept with --store")]
+    NonceNew,
+
+    #[structopt(about = "The stored nonces written by nonce list")]
+    NonceList,
+
+    #[structopt(about = "The check results written by ok")]
+    Ok,
+
+    #[structopt(about = "The tables written by ovmf show")]
+    OvmfShow,
+
+    #[structopt(
+        about = "The decoded guest policy written by policy explain and snp policy explain"
+    )]
+    PolicyExplain,
+
+    #[structopt(about = "The firmware commands written by --record")]
+    Recording,
+
+    #[structopt(about = "The manifest of the tarball written by report-bundle")]
+    ReportBundle,
+
+    #[structopt(about = "The launch digest written by measurement build and snp measurement")]
+    LaunchDigest,
+
+    #[structopt(about = "The secret packet written by secret build")]
+    SecretBuild,
+
+    #[structopt(about = "The injected secret written by secret inject")]
+    SecretInject,
+
+    #[structopt(about = "The decoded secret packet written by secret show")]
+    SecretShow,
+
+    #[structopt(
+        about = "The guest launch state served by serve at /sev/guests/<guest>/launch-measure"
+    )]
+    ServeLaunchMeasure,
+
+    #[structopt(about = "The platform status served by serve at /sev/status")]
+    ServeStatus,
+
+    #[structopt(about = "The session blob fields written by session show")]
+    SessionShow,
+
+    #[structopt(about = "The platform flags written by show flags")]
+    ShowFlags,
+
+    #[structopt(about = "The guest state written by show guest")]
+    ShowGuest,
+
+    #[structopt(about = "The number of guests written by show guests")]
+    ShowGuests,
+
+    #[structopt(about = "The firmware version written by show version")]
+    ShowVersion,
+
+    #[structopt(about = "The ID block written by snp id-block")]
+    SnpIdBlock,
+
+    #[structopt(about = "The SEV-SNP configuration written by snp config set")]
+    SnpConfigSet,
+
+    #[structopt(about = "The guest policy written by snp policy build")]
+    SnpPolicyBuild,
+
+    #[structopt(about = "The verdicts written by snp report verify")]
+    SnpReportVerify,
+
+    #[structopt(about = "The RMP layout written by snp rmp")]
+    SnpRmp,
+
+    #[structopt(about = "The SEV-SNP platform status written by snp show")]
+    SnpShow,
+
+    #[structopt(about = "The TCB versions written by snp tcb")]
+    SnpTcb,
+
+    #[structopt(about = "The signature checks written by verify")]
+    Verify,
+
+    #[structopt(about = "The VMSA files written by vmsa build-set")]
+    VmsaBuildSet,
+
+    #[structopt(about = "The differences written by vmsa diff")]
+    VmsaDiff,
+
+    #[structopt(about = "The VMSA fields written by vmsa show")]
+    VmsaShow,
+}
+
+pub fn cmd(schema: Schema) -> Result<()> {
+    let schema = match schema {
+        Schema::AttestationRequest => schemars::schema_for!(Versioned<SevAttestationReport>),
+        Schema::AttestationVerify => schemars::schema_for!(Versioned<Checks>),
+        Schema::Audit => schemars::schema_for!(Versioned<AuditEntry>),
+        Schema::Bench => schemars::schema_for!(Versioned<Benchmark>),
+        Schema::CertsSync => schemars::schema_for!(Versioned<CertsSync>),
+        Schema::Doctor => schemars::schema_for!(Versioned<Diagnosis>),
+        Schema::Error => schemars::schema_for!(Versioned<FirmwareStatus>),
+        Schema::ExportLibvirt => schemars::schema_for!(Versioned<LibvirtLaunchSecurity>),
+        Schema::Failure => schemars::schema_for!(Versioned<ErrorReport>),
+        Schema::FirmwareCheck => schemars::schema_for!(Versioned<Checks>),
+        Schema::FirmwareUpdate => schemars::schema_for!(Versioned<FirmwareUpdate>),
+        Schema::GuestReport => schemars::schema_for!(Versioned<AttestationReport>),
+        Schema::InitEx => schemars::schema_for!(Versioned<InitExState>),
+        Schema::K8sResources => schemars::schema_for!(Versioned<NodeResources>),
+        Schema::LaunchAttest => schemars::schema_for!(Versioned<LaunchAttest>),
+        Schema::LibvirtList => schemars::schema_for!(Versioned<LibvirtDomains>),
+        Schema::LibvirtMeasurement => schemars::schema_for!(Versioned<LibvirtMeasurement>),
+        Schema::MeasurementVerify => schemars::schema_for!(Versioned<MeasurementVerification>),
+        Schema::MigrationBundle => schemars::schema_for!(Versioned<MigrationBundle>),
+        Schema::MigrationPolicy => schemars::schema_for!(Versioned<SevPolicy>),
+        Schema::MigrationVerify => schemars::schema_for!(Versioned<Checks>),
+        Schema::NonceNew => schemars::schema_for!(Versioned<Nonce>),
+        Schema::NonceList => schemars::schema_for!(Versioned<Nonces>),
+        Schema::Ok => schemars::schema_for!(Versioned<Checks>),
+        Schema::OvmfShow => schemars::schema_for!(Versioned<OvmfImage>),
+        Schema::PolicyExplain => schemars::schema_for!(Versioned<PolicyExplanation>),
+        Schema::Recording => schemars::schema_for!(Versioned<Recording>),
+        Schema::ReportBundle => schemars::schema_for!(Versioned<ReportBundle>),
+        Schema::LaunchDigest => schemars::schema_for!(Versioned<LaunchDigest>),
+        Schema::SecretBuild => schemars::schema_for!(Versioned<SecretPacket>),
+        Schema::SecretInject => schemars::schema_for!(Versioned<SecretInjection>),
+        Schema::SecretShow => schemars::schema_for!(Versioned<SecretDetails>),
+        Schema::ServeLaunchMeasure => schemars::schema_for!(Versioned<LaunchMeasure>),
+        Schema::ServeStatus => schemars::schema_for!(Versioned<PlatformStatus>),
+        Schema::SessionShow => schemars::schema_for!(Versioned<SessionBlob>),
+        Schema::ShowFlags => schemars::schema_for!(Versioned<PlatformFlags>),
+        Schema::ShowGuest => schemars::schema_for!(Versioned<GuestState>),
+        Schema::ShowGuests => schemars::schema_for!(Versioned<Guests>),
+        Schema::ShowVersion => schemars::schema_for!(Versioned<FirmwareVersion>),
+        Schema::SnpIdBlock => schemars::schema_for!(Versioned<IdBlock>),
+        Schema::SnpConfigSet => schemars::schema_for!(Versioned<SnpConfig>),
+        Schema::SnpPolicyBuild => schemars::schema_for!(Versioned<SnpPolicy>),
+        Schema::SnpReportVerify => schemars::schema_for!(Versioned<Checks>),
+        Schema::SnpRmp => schemars::schema_for!(Versioned<Rmp>),
+        Schema::SnpShow => schemars::schema_for!(Versioned<SnpPlatformStatus>),
+        Schema::SnpTcb => schemars::schema_for!(Versioned<TcbStatus>),
+        Schema::Verify => schemars::schema_for!(Versioned<ChainVerification>),
+        Schema::VmsaBuildSet => schemars::schema_for!(Versioned<VmsaBuildSet>),
+        Schema::VmsaDiff => schemars::schema_for!(Versioned<VmsaDiff>),
+        Schema::VmsaShow => schemars::schema_for!(Versioned<Vmsa>),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+    Ok(())
+}
+
+/// A document with its schema version.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Versioned<T> {
+    pub schema_version: u32,
+
+    /// Where and when the document was written, with `--with-metadata`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
+    #[serde(flatten)]
+    pub document: T,
+}
+
+impl<T: Serialize> Versioned<T> {
+    pub fn new(document: T) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            metadata: None,
+            document,
+        }
+    }
+
+    /// The document as JSON, for printing.
+    pub fn to_string_pretty(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The document as a JSON value, for serving.
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap()
+    }
+}
+
+/// The host a document comes from, to tell the documents of many hosts apart.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Metadata {
+    pub hostname: Option<String>,
+
+    /// When the document was written, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    pub sevctl_version: String,
+
+    /// The SEV firmware version as `major.minor.build`, if the firmware answered.
+    pub firmware_version: Option<String>,
+}
+
+/// The SEV platform status.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlatformStatus {
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u8,
+
+    /// `uninitialized`, `initialized` or `working`.
+    pub state: String,
+
+    /// Whether the platform is owned by an external OCA.
+    pub owned: bool,
+
+    /// Whether SEV-ES is supported.
+    pub es: bool,
+
+    pub guests: u32,
+}
+
+/// An SEV firmware image installed by firmware update.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct FirmwareUpdate {
+    /// Where the image was installed.
+    pub path: PathBuf,
+
+    /// Whether the image differed from the one installed there before.
+    pub changed: bool,
+
+    /// The firmware version in the image header.
+    pub image_version: String,
+
+    /// The version of the running firmware, if known.
+    pub current_version: Option<String>,
+
+    /// The version of the committed firmware, if an attestation report was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub committed_version: Option<String>,
+
+    /// Whether SEV-SNP is initialized, so that rollback protection applies.
+    pub snp: bool,
+
+    /// Why the image might not take effect as expected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// An INIT_EX state file.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct InitExState {
+    pub path: PathBuf,
+
+    /// The SHA-256 digest of the file, in hex.
+    pub sha256: String,
+
+    /// `uninitialized`, `initialized` or `working`, if the SEV device is available.
+    pub platform_state: Option<String>,
+}
+
+/// The SEV firmware version.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct FirmwareVersion {
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u8,
+}
+
+/// The SEV-SNP platform status.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnpPlatformStatus {
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u32,
+
+    /// `uninitialized` or `initialized`.
+    pub state: String,
+
+    pub rmp_initialized: bool,
+
+    /// Whether the chip ID is zeroed in attestation reports.
+    pub mask_chip_id: bool,
+
+    pub guests: u32,
+
+    /// The TCB version of the installed firmware.
+    pub current_tcb: TcbVersion,
+
+    /// The TCB version attestation reports are signed with.
+    pub reported_tcb: TcbVersion,
+}
+
+/// The certificate chains and guest policy of a migration, handed to migration
+/// agents and orchestrators.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct MigrationBundle {
+    /// The guest policy, in hex.
+    pub policy: String,
+
+    /// The source platform's SEV and CA certificate chains, in base64.
+    pub source: String,
+
+    /// The target platform's SEV and CA certificate chains, in base64.
+    pub target: String,
+}
+
+/// The firmware commands of a run, in the order they were issued.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Recording {
+    /// The version of sevctl that recorded them.
+    pub sevctl_version: String,
+
+    pub commands: Vec<Recorded>,
+}
+
+/// The contents of a support bundle, kept in it as `manifest.json`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReportBundle {
+    /// The version of sevctl that gathered it.
+    pub sevctl_version: String,
+
+    /// When it was gathered, in seconds since the Unix epoch.
+    pub created: u64,
+
+    pub files: Vec<BundleFile>,
+}
+
+/// A file of a support bundle.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct BundleFile {
+    pub name: String,
+
+    /// Why it could not be gathered, in which case it is missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The certificates of a directory synchronized with the platform's.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct CertsSync {
+    pub dir: PathBuf,
+    pub files: Vec<SyncedFile>,
+}
+
+/// A certificate file, relative to the synchronized directory.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SyncedFile {
+    pub name: String,
+    pub state: SyncState,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncState {
+    /// Present and valid, so left untouched.
+    Current,
+
+    /// Missing, and downloaded.
+    Fetched,
+
+    /// Stale or damaged, and downloaded anew.
+    Replaced,
+
+    /// A VCEK of the chip at another TCB version, removed with `--prune`.
+    Removed,
+}
+
+/// A firmware command and what the firmware answered, as in the helper's protocol.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Recorded {
+    pub request: serde_json::Value,
+    pub response: serde_json::Value,
+}
+
+/// A command changing the platform, as recorded in the audit log.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditEntry {
+    /// When the command completed, in seconds since the Unix epoch.
+    pub time: u64,
+
+    pub uid: u32,
+    pub user: Option<String>,
+    pub pid: u32,
+
+    /// The sevctl command, or the firmware command the helper issued.
+    pub command: String,
+
+    /// The command line, or the request the helper received.
+    pub args: Vec<String>,
+
+    /// Whether the command succeeded.
+    pub ok: bool,
+
+    /// Why the command failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The status code, if the firmware rejected a command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_status: Option<u32>,
+}
+
+/// A nonce for a report to bind, and its state if it is stored.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Nonce {
+    /// The name it is stored under, for `--nonce-id`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The nonce, in hex.
+    pub nonce: String,
+
+    /// When it was generated, in seconds since the Unix epoch.
+    pub created: u64,
+
+    /// When it stops being accepted, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+
+    /// When a report binding it was verified, after which it is not accepted again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used: Option<u64>,
+}
+
+/// The stored nonces.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Nonces {
+    pub nonces: Vec<Nonce>,
+}
+
+/// The latency of firmware commands.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Benchmark {
+    /// The times each command was issued.
+    pub iterations: u32,
+
+    pub commands: Vec<CommandLatency>,
+}
+
+/// The latency of one firmware command.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandLatency {
+    pub command: String,
+
+    /// Absent if the command failed.
+    pub latency: Option<Latency>,
+
+    /// Why the command failed.
+    pub error: Option<String>,
+}
+
+/// Latency statistics, in microseconds.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Latency {
+    pub min_us: u64,
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// An SEV guest policy.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SevPolicy {
+    /// The value, in hex.
+    pub policy: String,
+
+    /// The names of the flags set, e.g. `NODBG`.
+    pub flags: Vec<String>,
+
+    /// The minimum firmware API version, e.g. `0.17`.
+    pub min_api: String,
+}
+
+/// An SEV-SNP guest policy.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnpPolicy {
+    /// The value, in hex.
+    pub policy: String,
+
+    /// The names of the flags set, e.g. `SMT`.
+    pub flags: Vec<String>,
+
+    /// The minimum firmware ABI version, e.g. `1.51`.
+    pub min_abi: String,
+}
+
+/// A guest policy decoded into its flags.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyExplanation {
+    /// `sev` or `snp`.
+    pub kind: String,
+
+    /// The value, in hex.
+    pub policy: String,
+
+    /// The minimum firmware API (SEV) or ABI (SEV-SNP) version, e.g. `1.51`.
+    pub min_version: String,
+
+    pub flags: Vec<PolicyFlag>,
+
+    /// The reserved bits set, in hex, if any.
+    pub reserved: Option<String>,
+
+    /// The security implications of the policy, e.g. that debugging is allowed.
+    pub warnings: Vec<String>,
+}
+
+/// A single-bit flag of a guest policy.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyFlag {
+    /// The flag's name, e.g. `NODBG`.
+    pub name: String,
+
+    pub description: String,
+
+    pub set: bool,
+}
+
+/// The SEV-SNP configuration set with SNP_SET_CONFIG.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnpConfig {
+    /// The TCB version attestation reports are signed with.
+    pub reported_tcb: TcbVersion,
+
+    /// Whether the chip ID is zeroed in attestation reports.
+    pub mask_chip_id: bool,
+}
+
+/// The location of the RMP and the memory it covers, in bytes.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct Rmp {
+    /// The physical address of the first byte of the RMP.
+    pub base: u64,
+
+    /// The physical address of the last byte of the RMP.
+    pub end: u64,
+
+    pub size: u64,
+
+    /// The number of 4 KiB pages the RMP describes.
+    pub entries: u64,
+
+    /// The memory the RMP covers, from address zero.
+    pub covered: u64,
+
+    /// The end of the highest system RAM, if known.
+    pub system_ram: Option<u64>,
+
+    /// Whether the RMP covers all system RAM, as SEV-SNP requires.
+    pub covers_system_ram: Option<bool>,
+}
+
+/// The TCB versions relevant to rollback protection.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct TcbStatus {
+    /// The TCB version of the installed firmware.
+    pub current_tcb: TcbVersion,
+
+    /// The TCB version attestation reports are signed with.
+    pub reported_tcb: TcbVersion,
+
+    /// The oldest TCB version the platform accepts, if known.
+    pub committed_tcb: Option<TcbVersion>,
+
+    /// The version of the installed firmware, e.g. `1.55.21`.
+    pub current_version: String,
+
+    /// The version of the committed firmware, if known.
+    pub committed_version: Option<String>,
+
+    /// The conditions weakening rollback protection.
+    pub warnings: Vec<String>,
+}
+
+/// The security version numbers of the SEV-SNP firmware components.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct TcbVersion {
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+/// The number of active SEV guests.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Guests {
+    pub guests: u32,
+}
+
+/// The SEV platform flags.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct PlatformFlags {
+    /// Whether the platform is owned by an external OCA.
+    pub owned: bool,
+
+    /// Whether SEV-ES is supported.
+    pub es: bool,
+}
+
+/// The SEV state of a running guest, as reported by QEMU.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct GuestState {
+    pub enabled: bool,
+    pub state: String,
+    pub handle: u32,
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u8,
+    pub policy: u32,
+
+    /// The base64 launch measurement and nonce, only available in the
+    /// `launch-secret` state.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement: Option<String>,
+}
+
+/// A firmware status code with its meaning.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct FirmwareStatus {
+    pub code: u32,
+    pub name: String,
+    pub description: String,
+
+    /// The usual causes of the status.
+    pub causes: Vec<String>,
+}
+
+/// A failed command, written instead of its result with `--output json` or `yaml`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct ErrorReport {
+    /// The stable code of the kind.
+    pub code: u32,
+
+    pub kind: error::Kind,
+    pub message: String,
+
+    /// The causes of the error, outermost first.
+    pub causes: Vec<String>,
+
+    /// The status code, if the firmware rejected a command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware_status: Option<u32>,
+}
+
+/// The results of readiness checks.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Checks {
+    pub checks: Vec<CheckResult>,
+}
+
+/// The readiness checks of a host, and the causes of their failures.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Diagnosis {
+    pub checks: Vec<CheckResult>,
+
+    /// In priority order, the root causes first.
+    pub findings: Vec<Finding>,
+}
+
+/// A cause of failed checks, or a known issue of the host.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Finding {
+    /// The ID of the rule that found it, or of the check no rule explains.
+    pub id: String,
+
+    pub cause: String,
+
+    /// The IDs of the failed checks it explains.
+    pub explains: Vec<String>,
+
+    /// What to do about it, in order.
+    pub steps: Vec<String>,
+}
+
+/// The result of a readiness check.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct CheckResult {
+    /// The ID of the check, stable across releases, e.g. `cpu.sev-es`.
+    pub id: String,
+
+    pub name: String,
+    pub state: CheckState,
+
+    /// What was found, or why the check failed or was skipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckState {
+    Pass,
+    Fail,
+
+    /// Not run, because a check it requires did not pass.
+    Skip,
+}
+
+/// The result of checking every signature of a SEV and CA certificate chain.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ChainVerification {
+    /// Whether every signature is valid.
+    pub valid: bool,
+
+    /// The PDH, which the chain certifies.
+    pub pdh: String,
+
+    /// The signers, from the PEK up to the ARK.
+    pub signatures: Vec<SignatureCheck>,
+}
+
+/// A certificate's signature of another.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct SignatureCheck {
+    /// The signing certificate.
+    pub signer: String,
+
+    /// The usage of the signed certificate, e.g. `PDH`.
+    pub signee: String,
+
+    pub valid: bool,
+
+    /// Whether the signer's self signature is valid, for the OCA and ARK.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_signature_valid: Option<bool>,
+}
+
+/// A launch digest, and the launch measurement if a TIK was given, in the
+/// requested encoding.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LaunchDigest {
+    pub launch_digest: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement: Option<String>,
+}
+
+/// The `<launchSecurity>` element of a libvirt domain, as written by
+/// `export --libvirt`, and the files it refers to.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibvirtLaunchSecurity {
+    /// The platform's full certificate chain, written for the guest owner.
+    pub chain: PathBuf,
+
+    pub cbitpos: u32,
+    pub reduced_phys_bits: u32,
+
+    /// The guest policy, in hex.
+    pub policy: String,
+
+    /// The guest owner's base64 PDH, once `session create` has written it.
+    pub dh_cert: Option<String>,
+
+    /// The base64 launch session blob, once `session create` has written it.
+    pub session: Option<String>,
+
+    pub dh_cert_path: PathBuf,
+    pub session_path: PathBuf,
+}
+
+/// Whether a launch measurement is the expected one and, if not, the launch
+/// parameters that would explain it.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct MeasurementVerification {
+    pub matches: bool,
+
+    /// Each a single launch parameter that, changed, reproduces the measurement.
+    pub likely_causes: Vec<String>,
+}
+
+/// The SEV-related tables of an OVMF firmware image.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct OvmfImage {
+    /// The size of the image in bytes.
+    pub size: usize,
+
+    /// The GUIDed structure table, empty if the image has none.
+    pub table: Vec<OvmfEntry>,
+
+    /// The SEV metadata sections, if the image has SEV metadata.
+    pub sections: Option<Vec<OvmfSection>>,
+}
+
+/// An entry of the GUIDed structure table of an OVMF image.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct OvmfEntry {
+    pub guid: String,
+
+    /// What the entry holds, if sevctl knows its GUID.
+    pub name: Option<String>,
+
+    /// The entry decoded, e.g. `0x0080c000 (0x400 bytes)`, or its size if unknown.
+    pub value: String,
+}
+
+/// A memory region described by the SEV metadata of an OVMF image.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct OvmfSection {
+    pub gpa: u32,
+    pub size: u32,
+    pub kind: u32,
+
+    /// The section type's name, e.g. `SNP_SECRETS`.
+    pub kind_name: String,
+}
+
+/// An SEV-SNP ID block and its authentication information, base64 encoded.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct IdBlock {
+    pub id_block: String,
+    pub id_auth: String,
+    pub author_key_enabled: bool,
+}
+
+/// The base64 arguments of QEMU's `sev-inject-launch-secret`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretPacket {
+    pub packet_header: String,
+    pub secret: String,
+}
+
+/// A secret injected into a guest by `secret inject`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretInjection {
+    /// The base64 launch measurement and nonce the secret was bound to.
+    pub measurement: String,
+}
+
+/// A secret packet decoded by `secret show`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretDetails {
+    pub flags: u32,
+    pub iv: String,
+    pub mac: String,
+
+    /// The size of the encrypted secret in bytes.
+    pub length: usize,
+
+    /// Whether flags other than the compression flag are set.
+    pub reserved_flags: bool,
+
+    /// Whether the MAC is valid for the given TIK and measurement, if they were given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_valid: Option<bool>,
+
+    /// The decrypted secret, if the TEK was given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plaintext: Option<SecretPlaintext>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretPlaintext {
+    /// The size of the decrypted secret in bytes.
+    pub size: usize,
+
+    /// The OVMF secret table the secret holds, if it is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<SecretTable>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretTable {
+    /// The length of the table in bytes, including its header.
+    pub length: usize,
+
+    /// Whether the length fits the decrypted secret.
+    pub length_valid: bool,
+
+    pub entries: Vec<SecretTableEntry>,
+
+    /// The offset of a malformed entry, which ends the table.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub malformed_at: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecretTableEntry {
+    pub guid: String,
+
+    /// What a well-known GUID identifies, e.g. `disk (LUKS) passphrase`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// The size of the entry's data in bytes, without its header.
+    pub size: usize,
+}
+
+/// The fields of a launch session blob, shown by `session show`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionBlob {
+    pub nonce: String,
+    pub wrap_tk: String,
+    pub wrap_iv: String,
+    pub wrap_mac: String,
+    pub policy_mac: String,
+
+    /// The policy the MAC was checked against with `--tik` and `--policy`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<u32>,
+}
+
+/// The steps taken by `launch attest`, as appended to the launch log.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LaunchAttest {
+    /// The firmware handle of the guest.
+    pub handle: u32,
+
+    pub steps: Vec<String>,
+}
+
+/// The launch measurement of a domain, fetched by `libvirt measurement`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibvirtMeasurement {
+    pub domain: String,
+
+    /// The base64 launch measurement and nonce.
+    pub measurement: String,
+}
+
+/// The domains configured for SEV, listed by `libvirt list`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibvirtDomains {
+    pub domains: Vec<LibvirtDomain>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LibvirtDomain {
+    pub name: String,
+
+    /// The policy of the domain's `<launchSecurity>` element, as written there.
+    pub policy: String,
+}
+
+/// The launch state of a guest as reported by QEMU, which `measurement verify`
+/// accepts as `--platform-info`.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct LaunchMeasure {
+    pub enabled: bool,
+    pub state: String,
+    pub handle: u32,
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u8,
+    pub policy: u32,
+
+    /// The base64 launch measurement and nonce, as returned by query-sev-launch-measure.
+    pub data: String,
+}
+
+/// A node's SEV capabilities, for Kubernetes.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct NodeResources {
+    /// Node Feature Discovery labels.
+    pub labels: BTreeMap<String, String>,
+
+    pub resources: Resources,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Resources {
+    /// The ASIDs of the node.
+    pub capacity: Asids,
+
+    /// The ASIDs not in use, if the kernel reports usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<Asids>,
+}
+
+/// ASIDs by extended resource name.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Asids {
+    #[serde(rename = "amd.com/sev")]
+    pub sev: u64,
+
+    #[serde(rename = "amd.com/sev-es")]
+    pub sev_es: u64,
+
+    #[serde(rename = "amd.com/sev-snp")]
+    pub sev_snp: u64,
+}
+
+/// The fields of a VMSA by name, as hex strings so that 64-bit values stay exact.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Vmsa {
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// The fields in which two VMSAs differ, in offset order within the named
+/// fields, then the reserved bytes.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct VmsaDiff {
+    pub differences: Vec<VmsaDifference>,
+}
+
+/// A field, or a reserved byte, that differs between two VMSAs.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct VmsaDifference {
+    /// The field's name, or `reserved` for a byte outside the named fields.
+    pub field: String,
+
+    /// The offset in the VMSA page.
+    pub offset: usize,
+
+    /// The value in the first VMSA, in hex.
+    pub a: String,
+
+    /// The value in the second VMSA, in hex.
+    pub b: String,
+}
+
+/// The VMSAs written by `vmsa build-set`, in launch order.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct VmsaBuildSet {
+    pub files: Vec<PathBuf>,
+}
+
+/// An SEV attestation report of a running guest, with the nonce and digest in hex.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct SevAttestationReport {
+    pub mnonce: String,
+    pub launch_digest: String,
+    pub policy: u32,
+
+    /// The usage of the signing key, the PEK's (0x1002).
+    pub sig_usage: u32,
+
+    /// The signature algorithm, ECDSA with SHA-256 (0x2).
+    pub sig_algo: u32,
+}
+
+/// An SEV-SNP attestation report, with the fields named as in the `sev` crate so
+/// that attestation services accept it.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AttestationReport {
+    pub version: u32,
+    pub guest_svn: u32,
+    pub policy: u64,
+    pub family_id: Vec<u8>,
+    pub image_id: Vec<u8>,
+    pub vmpl: u32,
+    pub sig_algo: u32,
+    pub current_tcb: Tcb,
+    pub plat_info: u64,
+    pub _author_key_en: u32,
+    pub _reserved_0: u32,
+    pub report_data: Vec<u8>,
+    pub measurement: Vec<u8>,
+    pub host_data: Vec<u8>,
+    pub id_key_digest: Vec<u8>,
+    pub author_key_digest: Vec<u8>,
+    pub report_id: Vec<u8>,
+    pub report_id_ma: Vec<u8>,
+    pub reported_tcb: Tcb,
+    pub _reserved_1: Vec<u8>,
+    pub chip_id: Vec<u8>,
+    pub committed_tcb: Tcb,
+    pub current_build: u8,
+    pub current_minor: u8,
+    pub current_major: u8,
+    pub _reserved_2: u8,
+    pub committed_build: u8,
+    pub committed_minor: u8,
+    pub committed_major: u8,
+    pub _reserved_3: u8,
+    pub launch_tcb: Tcb,
+    pub _reserved_4: Vec<u8>,
+    pub signature: Signature,
+}
+
+/// The security version numbers of the firmware components.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Tcb {
+    pub bootloader: u8,
+    pub tee: u8,
+    pub _reserved: Vec<u8>,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+/// The ECDSA P-384 signature of the report.
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Signature {
+    pub r: Vec<u8>,
+    pub s: Vec<u8>,
+    pub _reserved: Vec<u8>,
+}
@@ -0,0 +1,698 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Construction of LAUNCH_SECRET packets for injecting secrets into a measured guest.
+
+use super::*;
+use openssl::{rand, symm};
+use zeroize::Zeroizing;
+
+#[derive(StructOpt)]
+#[allow(clippy::large_enum_variant)]
+pub enum Secret {
+    #[structopt(about = "Encrypt a secret for injection with LAUNCH_SECRET")]
+    Build {
+        #[structopt(long, parse(from_os_str), help = "TEK from the launch session")]
+        tek: PathBuf,
+
+        #[structopt(long, parse(from_os_str), help = "TIK from the launch session")]
+        tik: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Launch measurement and nonce, as returned by query-sev-launch-measure"
+        )]
+        measurement: String,
+
+        #[structopt(
+            long,
+            default_value = "auto",
+            possible_values = encoding::INPUT_FORMS,
+            help = "Encoding of the measurement, TEK and TIK: auto, hex, base64 or raw"
+        )]
+        in_form: encoding::Form,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "OVMF firmware image whose secret area the secret must fit in"
+        )]
+        firmware: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        secret: SecretArgs,
+    },
+
+    #[structopt(about = "Verify a guest's launch measurement, inject a secret and resume it")]
+    Inject {
+        #[structopt(long, parse(from_os_str), help = "QMP socket of the guest")]
+        qmp: PathBuf,
+
+        #[structopt(long, parse(from_os_str), help = "TEK from the launch session")]
+        tek: PathBuf,
+
+        #[structopt(flatten)]
+        inputs: measurement::Inputs,
+
+        #[structopt(flatten)]
+        domain: domain::DomainArgs,
+
+        #[structopt(flatten)]
+        context: measurement::ContextArgs,
+
+        #[structopt(flatten)]
+        secret: SecretArgs,
+    },
+
+    #[structopt(about = "Display, validate and decrypt a secret packet")]
+    Show {
+        #[structopt(long, parse(from_os_str), help = "TEK to decrypt the secret with")]
+        tek: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "measurement",
+            help = "TIK to validate the MAC with"
+        )]
+        tik: Option<PathBuf>,
+
+        #[structopt(long, help = "Launch measurement and nonce the packet was built for")]
+        measurement: Option<String>,
+
+        #[structopt(
+            long,
+            default_value = "auto",
+            possible_values = encoding::INPUT_FORMS,
+            help = "Encoding of the measurement, TEK and TIK: auto, hex, base64 or raw"
+        )]
+        in_form: encoding::Form,
+
+        #[structopt(parse(from_os_str), help = "Packet as written by secret build")]
+        packet: PathBuf,
+    },
+
+    #[structopt(about = "Write a secret table holding a disk (LUKS) passphrase")]
+    LuksPassphrase {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Read the passphrase from a file instead of prompting"
+        )]
+        passphrase_file: Option<PathBuf>,
+
+        #[structopt(parse(from_os_str), help = "Path to write the secret table to")]
+        output: PathBuf,
+    },
+}
+
+/// The secret to inject: a file, or the entries of a secret table.
+#[derive(StructOpt)]
+pub struct SecretArgs {
+    #[structopt(
+        long = "secret",
+        number_of_values = 1,
+        help = "Entry of an OVMF secret table, as <guid>:<file> (may be repeated); the GUID may be \
+                given by name: luks, coco-offline-kbc or coco-online-kbc"
+    )]
+    entries: Vec<String>,
+
+    #[structopt(
+        parse(from_os_str),
+        required_unless = "entries",
+        conflicts_with = "entries",
+        help = "File holding the secret"
+    )]
+    file: Option<PathBuf>,
+}
+
+impl SecretArgs {
+    /// The plaintext to encrypt, checked to fit the firmware's secret area if given.
+    pub fn data(&self, firmware: Option<&Path>) -> Result<secure::Secret> {
+        let data = match &self.file {
+            Some(file) => secure::Secret::read(file)?,
+            None => table(
+                &self
+                    .entries
+                    .iter()
+                    .map(|e| entry(e))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+        };
+
+        if let Some(firmware) = firmware {
+            let (_, size) = ovmf::Ovmf::load(firmware)?.secret_area().ok_or_else(|| {
+                error::Context::new(
+                    "firmware has no secret area",
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                )
+            })?;
+
+            if data.len() > size as usize {
+                return Err(error::Context::new(
+                    &format!(
+                        "secret is {} bytes, but the firmware's secret area holds {}",
+                        data.len(),
+                        size
+                    ),
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                ));
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+pub fn cmd(secret: Secret) -> Result<()> {
+    match secret {
+        Secret::Build {
+            tek,
+            tik,
+            measurement,
+            in_form,
+            firmware,
+            secret,
+        } => {
+            // Only the packet is written: secret inject releases a secret to a guest
+            // after checking its measurement.
+            let measurement = encoding::decode(
+                in_form,
+                measurement.as_bytes(),
+                measurement::LAUNCH_MEASURE_LEN,
+                "launch measurement",
+            )?;
+
+            let (header, data) = build(
+                &session::read_key(&tek, in_form)?,
+                &session::read_key(&tik, in_form)?,
+                &measurement[..32],
+                &secret.data(firmware.as_deref())?,
+            )?;
+
+            output::print(&schema::SecretPacket {
+                packet_header: base64::encode(&header[..]),
+                secret: base64::encode(&data[..]),
+            });
+            Ok(())
+        }
+
+        Secret::Inject {
+            qmp,
+            tek,
+            mut inputs,
+            domain,
+            mut context,
+            secret,
+        } => {
+            let tik = match &context.tik {
+                Some(tik) => session::read_key(tik, context.in_form)?,
+                None => {
+                    return Err(error::Context::new(
+                        "--tik is required to inject a secret",
+                        Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                    ))
+                }
+            };
+            let tek = session::read_key(&tek, context.in_form)?;
+            let secret = secret.data(inputs.firmware.as_deref())?;
+
+            domain.apply(&mut inputs, &mut context)?;
+            context.platform()?;
+
+            let mut qmp = qmp::Qmp::connect(&qmp)?;
+            let measurement = context.query(&mut qmp)?;
+            tracing::info!(%measurement, "queried launch measurement");
+
+            // Refuse to release the secret to a guest that is not the expected one.
+            measurement::check(&measurement, &inputs, &context)?;
+
+            let measure = encoding::decode(
+                encoding::Form::Base64,
+                measurement.as_bytes(),
+                measurement::LAUNCH_MEASURE_LEN,
+                "launch measurement",
+            )?;
+
+            let (header, data) = build(&tek, &tik, &measure[..32], &secret)?;
+            qmp.sev_inject_launch_secret(&header, &data)?;
+            tracing::info!("secret injected");
+
+            qmp.cont()?;
+            output::print(&schema::SecretInjection { measurement });
+            Ok(())
+        }
+
+        Secret::Show {
+            tek,
+            tik,
+            measurement,
+            in_form,
+            packet,
+        } => {
+            let text = String::from_utf8(read(&packet)?).context("packet is not text")?;
+            let packet = packet_fields(&text)?;
+            let header = base64::decode(&packet.packet_header)
+                .context("packet-header is not valid base64")?;
+            let data = base64::decode(&packet.secret).context("secret is not valid base64")?;
+
+            let tek = match tek {
+                Some(tek) => Some(session::read_key(&tek, in_form)?),
+                None => None,
+            };
+
+            let tik = match (tik, measurement) {
+                (Some(tik), Some(measurement)) => Some((
+                    session::read_key(&tik, in_form)?,
+                    encoding::decode(
+                        in_form,
+                        measurement.as_bytes(),
+                        measurement::LAUNCH_MEASURE_LEN,
+                        "launch measurement",
+                    )?,
+                )),
+                _ => None,
+            };
+
+            output::print(&show(
+                &header,
+                &data,
+                tek.as_deref(),
+                tik.as_ref().map(|(tik, m)| (&tik[..], &m[..32])),
+            )?);
+            Ok(())
+        }
+
+        Secret::LuksPassphrase {
+            passphrase_file,
+            output,
+        } => {
+            let passphrase = match passphrase_file {
+                Some(path) => {
+                    let data = secure::Secret::read(&path)?;
+                    let text =
+                        std::str::from_utf8(&data).context("passphrase is not valid UTF-8")?;
+                    Zeroizing::new(
+                        text.trim_end_matches(|c| c == '\n' || c == '\r')
+                            .to_string(),
+                    )
+                }
+                None => {
+                    let passphrase = Zeroizing::new(
+                        rpassword::read_password_from_tty(Some("Passphrase: "))
+                            .context("unable to read passphrase")?,
+                    );
+                    let again = Zeroizing::new(
+                        rpassword::read_password_from_tty(Some("Repeat passphrase: "))
+                            .context("unable to read passphrase")?,
+                    );
+                    if passphrase != again {
+                        return Err(error::Context::new(
+                            "passphrases do not match",
+                            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                        ));
+                    }
+                    passphrase
+                }
+            };
+
+            if passphrase.is_empty() {
+                return Err(error::Context::new(
+                    "passphrase is empty",
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                ));
+            }
+
+            // The guest reads the passphrase as a NUL-terminated string.
+            let mut data = secure::Secret::new(passphrase.len() + 1);
+            data[..passphrase.len()].copy_from_slice(passphrase.as_bytes());
+
+            let table = table(&[(guid::Guid::from_static(LUKS_PASSPHRASE), data)]);
+            session::write(&output, &table, 0o600)
+        }
+    }
+}
+
+/// Identifies the disk passphrase used to unlock an encrypted root file system.
+const LUKS_PASSPHRASE: &str = "736869e5-84f0-4973-92ec-06879ce3da0b";
+
+/// Well-known secret table entries as (name, GUID, description).
+const KNOWN_SECRETS: &[(&str, &str, &str)] = &[
+    ("luks", LUKS_PASSPHRASE, "disk (LUKS) passphrase"),
+    (
+        "coco-offline-kbc",
+        "e6f5a162-d67f-4750-a67c-5d065f2a9910",
+        "Confidential Containers offline KBC keys",
+    ),
+    (
+        "coco-online-kbc",
+        "1ee27366-0c87-43a6-af48-28543eaf7cb0",
+        "Confidential Containers online KBC connection",
+    ),
+];
+
+/// Resolves the name of a well-known secret or parses a GUID.
+fn secret_guid(name: &str) -> Result<guid::Guid> {
+    match KNOWN_SECRETS.iter().find(|(n, ..)| *n == name) {
+        Some((_, guid, _)) => Ok(guid::Guid::from_static(guid)),
+        None => name.parse().context(format!(
+            "invalid secret GUID {} (or unknown name; known names: {})",
+            name,
+            KNOWN_SECRETS
+                .iter()
+                .map(|(n, ..)| *n)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+impl output::Document for schema::SecretPacket {
+    fn table(&self) -> String {
+        format!(
+            "packet-header: {}\nsecret: {}\n",
+            self.packet_header, self.secret
+        )
+    }
+}
+
+impl output::Document for schema::SecretInjection {
+    fn table(&self) -> String {
+        format!(
+            "launch measurement: {}\nsecret injected\nguest resumed\n",
+            self.measurement
+        )
+    }
+}
+
+/// Parses a packet written by `secret build`: a JSON document, or the `packet-header:`
+/// and `secret:` lines of the table and YAML forms.
+fn packet_fields(text: &str) -> Result<schema::SecretPacket> {
+    if let Ok(packet) = serde_json::from_str(text) {
+        return Ok(packet);
+    }
+
+    let field = |name: &str| -> Result<String> {
+        text.lines()
+            .find(|l| l.starts_with(name) && l[name.len()..].starts_with(':'))
+            .map(|l| {
+                l[name.len() + 1..]
+                    .trim()
+                    .trim_matches(&['"', '\''][..])
+                    .to_string()
+            })
+            .ok_or_else(|| {
+                error::Context::new(
+                    &format!("packet has no {}", name),
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                )
+            })
+    };
+
+    Ok(schema::SecretPacket {
+        packet_header: field("packet-header")?,
+        secret: field("secret")?,
+    })
+}
+
+/// Marks the start of an OVMF secret table.
+const TABLE_HEADER: &str = "1e74f542-71dd-4d66-963e-ef4287ff173b";
+
+/// Every table entry, and the table itself, starts with a GUID and a 32-bit length.
+const ENTRY_HEADER_SIZE: usize = 16 + 4;
+
+/// Parses a `<guid>:<file>` secret table entry.
+fn entry(entry: &str) -> Result<(guid::Guid, secure::Secret)> {
+    let colon = entry.find(':').ok_or_else(|| {
+        error::Context::new(
+            &format!("invalid secret {}, expected <guid>:<file>", entry),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        )
+    })?;
+
+    let guid = secret_guid(&entry[..colon])?;
+    Ok((guid, secure::Secret::read(Path::new(&entry[colon + 1..]))?))
+}
+
+/// Builds an OVMF secret table.
+///
+/// The lengths of the table and of each entry include their headers; the table is
+/// then padded to a whole number of AES blocks.
+fn table(entries: &[(guid::Guid, secure::Secret)]) -> secure::Secret {
+    let len = ENTRY_HEADER_SIZE
+        + entries
+            .iter()
+            .map(|(_, data)| ENTRY_HEADER_SIZE + data.len())
+            .sum::<usize>();
+
+    // Built in place, as the entries are secrets.
+    let mut table = secure::Secret::new((len + 15) / 16 * 16);
+    let mut at = 0;
+    let mut put = |bytes: &[u8]| {
+        table[at..at + bytes.len()].copy_from_slice(bytes);
+        at += bytes.len();
+    };
+
+    put(guid::Guid::from_static(TABLE_HEADER).as_bytes());
+    put(&(len as u32).to_le_bytes());
+    for (guid, data) in entries {
+        put(guid.as_bytes());
+        put(&((ENTRY_HEADER_SIZE + data.len()) as u32).to_le_bytes());
+        put(data);
+    }
+    table
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    tracing::debug!(path = %path.display(), "reading file");
+    std::fs::read(path).context(format!("unable to read {}", path.display()))
+}
+
+/// The only defined header flag: the secret is compressed.
+const FLAG_COMPRESSED: u32 = 1;
+
+/// The packet header holds the flags, the IV and the MAC.
+const HEADER_LEN: usize = 4 + 16 + 32;
+
+/// Encrypts a secret, returning the packet header and the ciphertext.
+pub fn build(tek: &[u8], tik: &[u8], measure: &[u8], secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let flags = 0u32;
+
+    let mut iv = [0u8; 16];
+    rand::rand_bytes(&mut iv).context("unable to generate IV")?;
+
+    let data = symm::encrypt(symm::Cipher::aes_128_ctr(), tek, Some(&iv), secret)
+        .context("unable to encrypt secret")?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&flags.to_le_bytes());
+    header.extend_from_slice(&iv);
+    header.extend_from_slice(&packet_mac(tik, flags, &iv, &data, measure)?);
+
+    Ok((header, data))
+}
+
+/// Computes the MAC binding the encrypted secret to the launch measurement.
+fn packet_mac(tik: &[u8], flags: u32, iv: &[u8], data: &[u8], measure: &[u8]) -> Result<[u8; 32]> {
+    let len = (data.len() as u32).to_le_bytes();
+    let mut msg = Vec::with_capacity(1 + 4 + 16 + 4 + 4 + data.len() + 32);
+    msg.push(0x01);
+    msg.extend_from_slice(&flags.to_le_bytes());
+    msg.extend_from_slice(iv);
+    msg.extend_from_slice(&len); // guest length
+    msg.extend_from_slice(&len); // transport length
+    msg.extend_from_slice(data);
+    msg.extend_from_slice(measure);
+
+    session::mac(tik, &msg)
+}
+
+/// Decodes a packet, validating its MAC and decrypting it when the keys are given.
+fn show(
+    header: &[u8],
+    data: &[u8],
+    tek: Option<&[u8]>,
+    tik: Option<(&[u8], &[u8])>,
+) -> Result<schema::SecretDetails> {
+    if header.len() != HEADER_LEN {
+        return Err(error::Context::new(
+            &format!(
+                "packet header is {} bytes, expected {}",
+                header.len(),
+                HEADER_LEN
+            ),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        ));
+    }
+
+    let flags = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let (iv, mac) = (&header[4..20], &header[20..]);
+
+    let mac_valid = match tik {
+        Some((tik, measure)) => Some(packet_mac(tik, flags, iv, data, measure)?[..] == *mac),
+        None => None,
+    };
+
+    let plaintext = match tek {
+        Some(tek) => Some(plaintext(tek, iv, data)?),
+        None => None,
+    };
+
+    Ok(schema::SecretDetails {
+        flags,
+        iv: hex(iv),
+        mac: hex(mac),
+        length: data.len(),
+        reserved_flags: flags & !FLAG_COMPRESSED != 0,
+        mac_valid,
+        plaintext,
+    })
+}
+
+/// Decrypts a secret and lists the entries of the secret table it holds.
+fn plaintext(tek: &[u8], iv: &[u8], data: &[u8]) -> Result<schema::SecretPlaintext> {
+    let plain = symm::decrypt(symm::Cipher::aes_128_ctr(), tek, Some(iv), data)
+        .map(secure::Secret::take)
+        .context("unable to decrypt secret")?;
+
+    let mut secret = schema::SecretPlaintext {
+        size: plain.len(),
+        table: None,
+    };
+
+    if plain.len() < ENTRY_HEADER_SIZE
+        || plain[..16] != guid::Guid::from_static(TABLE_HEADER).as_bytes()[..]
+    {
+        return Ok(secret);
+    }
+
+    let u32_at = |at: usize| {
+        u32::from_le_bytes([plain[at], plain[at + 1], plain[at + 2], plain[at + 3]]) as usize
+    };
+
+    let len = u32_at(16);
+    let table = secret.table.get_or_insert(schema::SecretTable {
+        length: len,
+        length_valid: len >= ENTRY_HEADER_SIZE && len <= plain.len(),
+        entries: Vec::new(),
+        malformed_at: None,
+    });
+    if !table.length_valid {
+        return Ok(secret);
+    }
+
+    let mut at = ENTRY_HEADER_SIZE;
+    while at + ENTRY_HEADER_SIZE <= len {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&plain[at..at + 16]);
+        let size = u32_at(at + 16);
+        if size < ENTRY_HEADER_SIZE || at + size > len {
+            table.malformed_at = Some(at);
+            break;
+        }
+
+        let guid = guid::Guid::from_bytes(bytes);
+        table.entries.push(schema::SecretTableEntry {
+            guid: guid.to_string(),
+            description: KNOWN_SECRETS
+                .iter()
+                .find(|(_, g, _)| guid::Guid::from_static(g) == guid)
+                .map(|(_, _, desc)| desc.to_string()),
+            size: size - ENTRY_HEADER_SIZE,
+        });
+        at += size;
+    }
+
+    Ok(secret)
+}
+
+impl output::Document for schema::SecretDetails {
+    fn table(&self) -> String {
+        let mut out = format!(
+            "flags:  {:#010x}\niv:     {}\nmac:    {}\nlength: {}\n",
+            self.flags, self.iv, self.mac, self.length
+        );
+
+        if self.reserved_flags {
+            out += "warning: reserved flags are set\n";
+        }
+
+        match self.mac_valid {
+            Some(true) => out += "mac is valid for this measurement\n",
+            Some(false) => out += "mac is NOT valid for this measurement and TIK\n",
+            None => (),
+        }
+
+        let secret = match &self.plaintext {
+            Some(secret) => secret,
+            None => return out,
+        };
+
+        let table = match &secret.table {
+            Some(table) => table,
+            None => {
+                return out + &format!("secret is not a secret table ({} bytes)\n", secret.size)
+            }
+        };
+
+        if !table.length_valid {
+            return out
+                + &format!(
+                    "secret table has an invalid length of {} bytes\n",
+                    table.length
+                );
+        }
+
+        out += &format!("secret table ({} bytes):\n", table.length);
+        for entry in &table.entries {
+            let known = entry
+                .description
+                .as_ref()
+                .map(|desc| format!(" ({})", desc))
+                .unwrap_or_default();
+            out += &format!("  {}{}: {} bytes\n", entry.guid, known, entry.size);
+        }
+        if let Some(at) = table.malformed_at {
+            out += &format!("  malformed entry at offset {}\n", at);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_packet() {
+        let (tek, tik, measure) = ([1u8; 16], [2u8; 16], [3u8; 32]);
+        let data = secure::Secret::take(b"passphrase".to_vec());
+        let secret = table(&[(guid::Guid::from_static(LUKS_PASSPHRASE), data)]);
+        let (header, data) = build(&tek, &tik, &measure, &secret).unwrap();
+
+        // secret show reads both the table and the JSON forms of secret build.
+        let packet = schema::SecretPacket {
+            packet_header: base64::encode(&header),
+            secret: base64::encode(&data),
+        };
+        let json = schema::Versioned::new(packet).to_string_pretty();
+        let table = output::Document::table(&packet_fields(&json).unwrap());
+        let packet = packet_fields(&table).unwrap();
+        assert_eq!(base64::decode(&packet.packet_header).unwrap(), header);
+        assert_eq!(base64::decode(&packet.secret).unwrap(), data);
+
+        let details = show(&header, &data, Some(&tek), Some((&tik, &measure))).unwrap();
+        assert_eq!(details.mac_valid, Some(true));
+        assert!(!details.reserved_flags);
+
+        let table = details.plaintext.unwrap().table.unwrap();
+        assert!(table.length_valid);
+        assert_eq!(table.malformed_at, None);
+        assert_eq!(table.entries.len(), 1);
+        assert_eq!(table.entries[0].guid, LUKS_PASSPHRASE);
+        assert_eq!(table.entries[0].size, 10);
+
+        let details = show(&header, &data, None, Some((&tik, &[4u8; 32]))).unwrap();
+        assert_eq!(details.mac_valid, Some(false));
+        assert!(details.plaintext.is_none());
+    }
+}
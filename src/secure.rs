@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Memory for key material: transport keys, private keys and secrets.
+//!
+//! A [`Secret`] lives in pages of its own, zeroed when it is dropped. Where the
+//! kernel supports memfd_secret, the pages are not even mapped by the kernel;
+//! otherwise they are locked, if the memlock limit allows, and left out of core
+//! dumps, so that key material does not linger in memory nor reach swap.
+
+use super::*;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use zeroize::Zeroize;
+
+/// Bytes of key material.
+pub struct Secret {
+    ptr: NonNull<u8>,
+    len: usize,
+
+    /// The size of the mapping, a whole number of pages.
+    size: usize,
+}
+
+// SAFETY: a Secret owns its mapping like a Vec owns its allocation.
+unsafe impl Send for Secret {}
+unsafe impl Sync for Secret {}
+
+impl Secret {
+    /// `len` zero bytes.
+    pub fn new(len: usize) -> Self {
+        // SAFETY: sysconf has no preconditions.
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let size = (len.max(1) + page - 1) / page * page;
+
+        let ptr = secret_pages(size).unwrap_or_else(|| locked_pages(size));
+        Self { ptr, len, size }
+    }
+
+    /// Moves `data` into a Secret, zeroing what it leaves behind.
+    pub fn take(mut data: Vec<u8>) -> Self {
+        let mut secret = Self::new(data.len());
+        secret.copy_from_slice(&data);
+        data.zeroize();
+        secret
+    }
+
+    /// Reads a file holding key material.
+    pub fn read(path: &Path) -> Result<Self> {
+        tracing::debug!(path = %path.display(), "reading file");
+
+        // Regular files are read in a single allocation of their size.
+        let data = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+        Ok(Self::take(data))
+    }
+}
+
+/// Pages from memfd_secret, if the kernel supports it.
+fn secret_pages(size: usize) -> Option<NonNull<u8>> {
+    // SAFETY: memfd_secret takes flags and returns a new file descriptor.
+    let fd = unsafe { libc::syscall(libc::SYS_memfd_secret, 0) } as libc::c_int;
+    if fd < 0 {
+        return None;
+    }
+
+    // SAFETY: `fd` is ours; mapping it shared is the only way to use its pages.
+    let ptr = unsafe {
+        let ptr = match libc::ftruncate(fd, size as libc::off_t) {
+            0 => libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            ),
+            _ => libc::MAP_FAILED,
+        };
+        libc::close(fd);
+        ptr
+    };
+
+    match ptr {
+        libc::MAP_FAILED => None,
+        ptr => NonNull::new(ptr as *mut u8),
+    }
+}
+
+/// Anonymous pages, locked and left out of core dumps where possible.
+fn locked_pages(size: usize) -> NonNull<u8> {
+    // SAFETY: a new private anonymous mapping aliases nothing.
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        std::alloc::handle_alloc_error(std::alloc::Layout::from_size_align(size, 1).unwrap());
+    }
+
+    // Without them, the key material is still zeroed.
+    // SAFETY: `ptr` is a mapping of `size` bytes.
+    unsafe {
+        if libc::mlock(ptr, size) != 0 {
+            tracing::debug!("unable to lock key material in memory");
+        }
+        libc::madvise(ptr, size, libc::MADV_DONTDUMP);
+    }
+
+    NonNull::new(ptr as *mut u8).unwrap()
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.deref_mut().zeroize();
+
+        // SAFETY: the mapping is ours and nothing refers to it anymore. Unmapping
+        // unlocks it.
+        unsafe { libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, self.size) };
+    }
+}
+
+impl Deref for Secret {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping holds at least `len` initialized bytes.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for Secret {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the mapping holds at least `len` initialized bytes, only borrowed
+        // through `self`.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        let mut secret = Self::new(self.len);
+        secret.copy_from_slice(self);
+        secret
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Secret({} bytes)", self.len)
+    }
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! An HTTPS service exposing the platform's attestation material to guest owners.
+
+use super::*;
+use http::{Request, Response};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(StructOpt)]
+pub struct ServeArgs {
+    #[structopt(long, default_value = "0.0.0.0:8443", help = "Address to listen on")]
+    listen: String,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "TLS certificate (chain) in PEM format"
+    )]
+    cert: PathBuf,
+
+    #[structopt(long, parse(from_os_str), help = "TLS private key in PEM format")]
+    key: PathBuf,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "File holding the bearer token required by all endpoints but the certificate chain"
+    )]
+    token_file: PathBuf,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory of guest QMP sockets, named <guest>.qmp"
+    )]
+    qmp_dir: Option<PathBuf>,
+}
+
+pub fn cmd(args: ServeArgs) -> Result<()> {
+    let token = std::fs::read_to_string(&args.token_file)
+        .context(format!("unable to read {}", args.token_file.display()))?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        return Err(error::Context::new(
+            &format!("{} holds no token", args.token_file.display()),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    let tls = http::acceptor(&args.cert, &args.key)?;
+    let service = Arc::new(Service {
+        token,
+        qmp_dir: args.qmp_dir,
+        chain: Mutex::new(None),
+    });
+
+    http::serve(&args.listen, Some(tls), move |request| {
+        let service = service.clone();
+        async move { service.handle(&request).await }
+    })
+}
+
+struct Service {
+    token: String,
+    qmp_dir: Option<PathBuf>,
+
+    /// The encoded certificate chain, downloaded on first use.
+    chain: Mutex<Option<Vec<u8>>>,
+}
+
+impl Service {
+    async fn handle(&self, request: &Request) -> Response {
+        let path = request.path.split('?').next().unwrap_or("");
+
+        // The certificate chain is public, so `session create --host` needs no token.
+        if path == session::CHAIN_PATH {
+            return self.chain().await.unwrap_or_else(internal);
+        }
+
+        if !self.authorized(request) {
+            return Response::error(401, "missing or invalid bearer token");
+        }
+
+        let guest = path
+            .strip_prefix("/sev/guests/")
+            .and_then(|p| p.strip_suffix("/launch-measure"));
+
+        match (path, guest) {
+            ("/sev/status", _) => status().await.unwrap_or_else(internal),
+            (_, Some(guest)) => self.launch_measure(guest).await.unwrap_or_else(internal),
+            _ => Response::error(404, "not found"),
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let token = match request.header("Authorization") {
+            Some(value) => value.strip_prefix("Bearer ").unwrap_or("").trim(),
+            None => return false,
+        };
+
+        token.len() == self.token.len()
+            && openssl::memcmp::eq(token.as_bytes(), self.token.as_bytes())
+    }
+
+    async fn chain(&self) -> Result<Response> {
+        let mut cached = self.chain.lock().await;
+        if cached.is_none() {
+            let mut buf = Vec::new();
+            chain_async()
+                .await?
+                .encode(&mut buf, ())
+                .context("certificate chain encoding failed")?;
+            *cached = Some(buf);
+        }
+
+        Ok(Response::ok(
+            "application/octet-stream",
+            cached.clone().unwrap(),
+        ))
+    }
+
+    /// The guest's `query-sev` state and launch measurement, usable as
+    /// `measurement verify --platform-info`.
+    async fn launch_measure(&self, guest: &str) -> Result<Response> {
+        let dir = match &self.qmp_dir {
+            Some(dir) => dir,
+            None => return Ok(Response::error(404, "guest queries are not enabled")),
+        };
+
+        let valid = |c: char| c.is_ascii_alphanumeric() || "-_.".contains(c);
+        if guest.is_empty() || guest.starts_with('.') || !guest.chars().all(valid) {
+            return Ok(Response::error(400, "invalid guest name"));
+        }
+
+        let socket = dir.join(format!("{}.qmp", guest));
+        if !socket.exists() {
+            return Ok(Response::error(404, "no such guest"));
+        }
+
+        let mut qmp = qmp::AsyncQmp::connect(&socket).await?;
+        let info = qmp.query_sev().await?;
+
+        let document = schema::LaunchMeasure {
+            data: qmp.query_sev_launch_measure().await?,
+            enabled: info.enabled,
+            state: info.state,
+            handle: info.handle,
+            api_major: info.api_major,
+            api_minor: info.api_minor,
+            build_id: info.build_id,
+            policy: info.policy,
+        };
+
+        Ok(Response::json(&schema::Versioned::new(document).to_value()))
+    }
+}
+
+async fn status() -> Result<Response> {
+    let status = tokio::task::spawn_blocking(platform_status)
+        .await
+        .context("firmware task failed")??;
+
+    let document = schema::PlatformStatus {
+        api_major: status.build.version.major,
+        api_minor: status.build.version.minor,
+        build_id: status.build.build,
+        state: format!("{:?}", status.state).to_lowercase(),
+        owned: status.flags.contains(::sev::firmware::Flags::OWNED),
+        es: status
+            .flags
+            .contains(::sev::firmware::Flags::ENCRYPTED_STATE),
+        guests: status.guests,
+    };
+
+    Ok(Response::json(&schema::Versioned::new(document).to_value()))
+}
+
+/// Logs an error with its causes and hides the details from the client.
+fn internal(e: error::Context) -> Response {
+    let mut message = e.to_string();
+    let mut err: &(dyn std::error::Error + 'static) = &e;
+    while let Some(cause) = err.source() {
+        message += &format!(": {}", cause);
+        err = cause;
+    }
+
+    eprintln!("error: {}", message);
+    Response::error(500, "internal error, see the server log")
+}
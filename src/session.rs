@@ -0,0 +1,792 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Handling of the launch session artifacts exchanged between the guest owner and the platform.
+
+use super::*;
+use ::sev::launch;
+use colorful::*;
+use openssl::{bn, derive, ec, hash, nid, pkey, rand, sign, symm};
+use secure::Secret;
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use zeroize::Zeroize;
+
+#[derive(StructOpt)]
+pub enum Session {
+    #[structopt(about = "Generate the launch session artifacts for a target platform")]
+    Create {
+        #[structopt(long, help = "Prefix for the generated file names")]
+        name: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            default_value = ".",
+            help = "Directory to write the generated files to"
+        )]
+        outdir: PathBuf,
+
+        #[structopt(
+            long,
+            conflicts_with_all = &["chain", "url"],
+            help = "Fetch the certificate chain from a host running sevctl"
+        )]
+        host: Option<String>,
+
+        #[structopt(
+            long,
+            conflicts_with = "chain",
+            help = "Download the certificate chain from a URL"
+        )]
+        url: Option<String>,
+
+        #[structopt(help = "Guest policy (decimal or 0x-prefixed hexadecimal)")]
+        policy: String,
+
+        #[structopt(
+            parse(from_os_str),
+            required_unless_one = &["host", "url"],
+            help = "Path to the target platform's certificate chain, or - for stdin"
+        )]
+        chain: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        roots: Roots,
+    },
+
+    #[structopt(about = "Decode and validate a launch session blob")]
+    Show {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "policy",
+            help = "TIK used to validate the policy MAC"
+        )]
+        tik: Option<PathBuf>,
+
+        #[structopt(long, help = "Guest policy the session is expected to carry")]
+        policy: Option<String>,
+
+        #[structopt(parse(from_os_str), help = "Path to the base64-encoded session blob")]
+        session: PathBuf,
+    },
+}
+
+/// The path under which a host running sevctl serves its SEV certificate chain.
+pub const CHAIN_PATH: &str = "/sev/chain";
+
+/// The size of the LAUNCH_START session buffer.
+const SESSION_LEN: usize = 128;
+
+/// The size of the transport integrity and encryption keys.
+const KEY_LEN: usize = 16;
+
+pub fn cmd(session: Session) -> Result<()> {
+    match session {
+        Session::Create {
+            name,
+            outdir,
+            host,
+            url,
+            policy,
+            chain,
+            roots,
+        } => create(
+            name,
+            outdir,
+            target_pdh(&read_chain(chain, host, url)?, roots.load()?)?,
+            policy,
+        ),
+
+        Session::Show {
+            tik,
+            policy,
+            session,
+        } => show(session, tik, policy),
+    }
+}
+
+/// Reads a target platform's certificate chain from a file (or `-` for stdin), a host
+/// running sevctl, or a URL.
+pub fn read_chain(
+    chain: Option<PathBuf>,
+    host: Option<String>,
+    url: Option<String>,
+) -> Result<Vec<u8>> {
+    Ok(match (chain, host, url) {
+        (Some(path), _, _) if path.as_os_str() == "-" => {
+            let mut buf = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut buf)
+                .context("unable to read certificate chain from stdin")?;
+            buf
+        }
+        (Some(path), _, _) => {
+            tracing::debug!(path = %path.display(), "reading file");
+            let mut buf = Vec::new();
+            File::open(&path)
+                .context(format!("unable to open {}", path.display()))?
+                .read_to_end(&mut buf)
+                .context("unable to read certificate chain")?;
+            buf
+        }
+        (None, Some(host), _) => fetch(
+            &format!("https://{}{}", host, CHAIN_PATH),
+            "certificate chain",
+        )?,
+        (None, None, Some(url)) => fetch(&url, "certificate chain")?,
+        (None, None, None) => unreachable!(),
+    })
+}
+
+/// The AMD roots a target platform's certificate chain must lead to.
+#[derive(StructOpt)]
+pub struct Roots {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "AMD CA chain (ASK and ARK) to trust instead of the built-in ones"
+    )]
+    ca: Option<PathBuf>,
+}
+
+impl Roots {
+    /// The CA chain given, or else those built into sevctl.
+    pub fn load(&self) -> Result<Vec<Root>> {
+        match &self.ca {
+            Some(path) => Ok(vec![read_root(path.clone())?]),
+            None => Ok(roots_builtin()),
+        }
+    }
+}
+
+/// Verifies a target platform's SEV (or full) certificate chain up to one of
+/// `roots` and returns its PDH.
+#[tracing::instrument(skip_all, err)]
+pub fn target_pdh(buf: &[u8], roots: Vec<Root>) -> Result<sev::Certificate> {
+    decode_chain(buf, roots)?
+        .verify()
+        .context("target platform's certificate chain is invalid")
+}
+
+/// Verifies a target platform's SEV (or full) certificate chain up to one of
+/// `roots` and returns it.
+pub fn target_chain(buf: &[u8], roots: Vec<Root>) -> Result<Chain> {
+    let chain = decode_chain(buf, roots)?;
+
+    // Verifying consumes the chain, so a copy is.
+    let mut copy = Vec::new();
+    chain
+        .encode(&mut copy, ())
+        .context("unable to encode certificate chain")?;
+    Chain::decode(&mut &copy[..], ())
+        .context("unable to decode certificate chain")?
+        .verify()
+        .context("target platform's certificate chain is invalid")?;
+    Ok(chain)
+}
+
+/// Decodes an SEV certificate chain and pairs it with the root among `roots` whose
+/// ASK signs its CEK. A CA chain following the SEV chain is only accepted if its ARK
+/// is that root's: whoever sends the chain could otherwise make up their own.
+pub fn decode_chain(buf: &[u8], roots: Vec<Root>) -> Result<Chain> {
+    let mut rdr = buf;
+    let sev = sev::Chain::decode(&mut rdr, ()).context("unable to decode SEV certificate chain")?;
+    let root = select_root(&sev, roots)?;
+
+    if !rdr.is_empty() {
+        let ca =
+            ca::Chain::decode(&mut rdr, ()).context("unable to decode CA certificate chain")?;
+        if ca.ark != root.chain.ark {
+            return Err(error::Context::new(
+                &format!(
+                    "the CA chain given ends in an ARK other than {}'s",
+                    root.name
+                ),
+                Box::new(Error::new(
+                    ErrorKind::InvalidData,
+                    "only the built-in AMD roots or those given with --ca are trusted",
+                )),
+            ));
+        }
+    }
+
+    Ok(Chain {
+        ca: root.chain,
+        sev,
+    })
+}
+
+fn create(
+    name: Option<String>,
+    outdir: PathBuf,
+    pdh: sev::Certificate,
+    policy: String,
+) -> Result<()> {
+    let artifacts = Artifacts::generate(&pdh, sev_policy(&policy)?)?;
+
+    std::fs::create_dir_all(&outdir).context(format!("unable to create {}", outdir.display()))?;
+
+    let path = |file: &str| artifact(&outdir, name.as_deref(), file);
+
+    write(
+        &path("godh.b64"),
+        base64::encode(&artifacts.godh).as_bytes(),
+        0o644,
+    )?;
+    write(
+        &path("session.b64"),
+        base64::encode(&artifacts.session).as_bytes(),
+        0o644,
+    )?;
+    write(&path("tek.bin"), &artifacts.tek, 0o600)?;
+    write(&path("tik.bin"), &artifacts.tik, 0o600)?;
+
+    Ok(())
+}
+
+/// The launch session artifacts for one guest.
+pub struct Artifacts {
+    /// The encoded guest owner's Diffie-Hellman certificate.
+    pub godh: Vec<u8>,
+
+    /// The LAUNCH_START session buffer.
+    pub session: Vec<u8>,
+
+    pub tek: Secret,
+    pub tik: Secret,
+}
+
+impl Artifacts {
+    /// Generates fresh transport keys and wraps them for the platform owning `pdh`.
+    pub fn generate(pdh: &sev::Certificate, policy: u32) -> Result<Self> {
+        let (godh, prv) =
+            sev::Certificate::generate(sev::Usage::PDH).context("unable to generate GODH key")?;
+        let z = shared_secret(&prv, pdh)?;
+
+        let tek = random_key()?;
+        let tik = random_key()?;
+        let session = start(&z, &tek, &tik, policy)?;
+
+        let mut godh_buf = Vec::new();
+        godh.encode(&mut godh_buf, ())
+            .context("unable to encode GODH certificate")?;
+
+        Ok(Self {
+            godh: godh_buf,
+            session: encode_session(&session),
+            tek,
+            tik,
+        })
+    }
+}
+
+/// The path of a session file, prefixed with the session's name if it has one.
+pub fn artifact(dir: &Path, name: Option<&str>, file: &str) -> PathBuf {
+    match name {
+        Some(name) => dir.join(format!("{}_{}", name, file)),
+        None => dir.join(file),
+    }
+}
+
+fn show(path: PathBuf, tik: Option<PathBuf>, policy: Option<String>) -> Result<()> {
+    let session = read_session(&path)?;
+
+    let mut blob = schema::SessionBlob {
+        nonce: hex(&session.nonce),
+        wrap_tk: hex(&session.wrap_tk),
+        wrap_iv: hex(&session.wrap_iv),
+        wrap_mac: hex(&session.wrap_mac),
+        policy_mac: hex(&session.policy_mac),
+        policy: None,
+    };
+
+    if let (Some(tik), Some(policy)) = (tik, policy) {
+        let tik = read_key(&tik, encoding::Form::Auto)?;
+        let policy = sev_policy(&policy)?;
+
+        let mac = mac(&tik, &policy.to_le_bytes())?;
+        if mac != session.policy_mac {
+            return Err(error::Context::new(
+                &format!("policy MAC does not match policy {:#010x}", policy),
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+        blob.policy = Some(policy);
+    }
+
+    output::print(&blob);
+    Ok(())
+}
+
+impl output::Document for schema::SessionBlob {
+    fn table(&self) -> String {
+        let mut out = format!(
+            "nonce:      {}\nwrap_tk:    {}\nwrap_iv:    {}\nwrap_mac:   {}\npolicy_mac: {}\n",
+            self.nonce, self.wrap_tk, self.wrap_iv, self.wrap_mac, self.policy_mac
+        );
+        if let Some(policy) = self.policy {
+            out += &format!(
+                "\n{} policy MAC matches policy {:#010x}\n",
+                "✓".green(),
+                policy
+            );
+        }
+        out
+    }
+}
+
+/// Reads a base64-encoded LAUNCH_START session buffer.
+fn read_session(path: &Path) -> Result<launch::Session> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let mut b64 = String::new();
+    File::open(path)
+        .context(format!("unable to open {}", path.display()))?
+        .read_to_string(&mut b64)
+        .context("unable to read session blob")?;
+
+    let buf = base64::decode(b64.trim()).context("session blob is not valid base64")?;
+    decode_session(&buf)
+}
+
+/// Parses a guest policy, which is limited to 32 bits for SEV and SEV-ES.
+pub fn sev_policy(value: &str) -> Result<u32> {
+    let policy = policy::parse(value)?;
+    if policy > u32::MAX as u64 {
+        return Err(error::Context::new(
+            "session policies are 32 bits wide",
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    Ok(policy as u32)
+}
+
+fn decode_session(buf: &[u8]) -> Result<launch::Session> {
+    if buf.len() != SESSION_LEN {
+        return Err(error::Context::new(
+            &format!(
+                "session blob is {} bytes, expected {}",
+                buf.len(),
+                SESSION_LEN
+            ),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        ));
+    }
+
+    let mut session = launch::Session {
+        nonce: [0u8; 16],
+        wrap_tk: [0u8; 32],
+        wrap_iv: [0u8; 16],
+        wrap_mac: [0u8; 32],
+        policy_mac: [0u8; 32],
+    };
+
+    session.nonce.copy_from_slice(&buf[0..16]);
+    session.wrap_tk.copy_from_slice(&buf[16..48]);
+    session.wrap_iv.copy_from_slice(&buf[48..64]);
+    session.wrap_mac.copy_from_slice(&buf[64..96]);
+    session.policy_mac.copy_from_slice(&buf[96..128]);
+
+    Ok(session)
+}
+
+fn encode_session(session: &launch::Session) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(SESSION_LEN);
+    buf.extend_from_slice(&session.nonce);
+    buf.extend_from_slice(&session.wrap_tk);
+    buf.extend_from_slice(&session.wrap_iv);
+    buf.extend_from_slice(&session.wrap_mac);
+    buf.extend_from_slice(&session.policy_mac);
+    buf
+}
+
+/// Builds the LAUNCH_START session buffer wrapping the TEK and TIK for the platform.
+fn start(z: &[u8], tek: &[u8], tik: &[u8], policy: u32) -> Result<launch::Session> {
+    let mut nonce = [0u8; 16];
+    let mut iv = [0u8; 16];
+    rand::rand_bytes(&mut nonce).context("unable to generate nonce")?;
+    rand::rand_bytes(&mut iv).context("unable to generate IV")?;
+    wrap(z, nonce, iv, tek, tik, policy)
+}
+
+/// Wraps the TEK and TIK under the keys derived from the shared secret and nonce.
+fn wrap(
+    z: &[u8],
+    nonce: [u8; 16],
+    iv: [u8; 16],
+    tek: &[u8],
+    tik: &[u8],
+    policy: u32,
+) -> Result<launch::Session> {
+    let master = kdf(z, KEY_LEN, &nonce, "sev-master-secret")?;
+    let kek = kdf(&master, KEY_LEN, &[], "sev-kek")?;
+    let kik = kdf(&master, KEY_LEN, &[], "sev-kik")?;
+
+    let mut keys = Secret::new(2 * KEY_LEN);
+    keys[..KEY_LEN].copy_from_slice(tek);
+    keys[KEY_LEN..].copy_from_slice(tik);
+    let wrapped = symm::encrypt(symm::Cipher::aes_128_ctr(), &kek, Some(&iv), &keys)
+        .context("unable to wrap transport keys")?;
+
+    let mut wrap_tk = [0u8; 32];
+    wrap_tk.copy_from_slice(&wrapped);
+
+    Ok(launch::Session {
+        nonce,
+        wrap_tk,
+        wrap_iv: iv,
+        wrap_mac: mac(&kik, &wrap_tk)?,
+        policy_mac: mac(tik, &policy.to_le_bytes())?,
+    })
+}
+
+/// Computes the ECDH shared secret between the GODH private key and the platform's PDH.
+fn shared_secret(prv: &PrivateKey<sev::Usage>, pdh: &sev::Certificate) -> Result<Secret> {
+    let mut der = Vec::new();
+    prv.encode(&mut der, ())
+        .context("unable to encode GODH private key")?;
+    let der = Secret::take(der);
+    let prv = pkey::PKey::private_key_from_der(&der).context("unable to load GODH key")?;
+    let pdh = public_key(pdh, "PDH", ECDH)?;
+
+    let mut deriver = derive::Deriver::new(&prv).context("unable to create ECDH context")?;
+    deriver
+        .set_peer(&pdh)
+        .context("unable to use PDH public key")?;
+    deriver
+        .derive_to_vec()
+        .map(Secret::take)
+        .context("unable to derive shared secret")
+}
+
+/// The algorithms of the EC keys of SEV certificates, with SHA-256 or SHA-384.
+pub const ECDH: (&str, [u32; 2]) = ("ECDH", [0x0003, 0x0103]);
+pub const ECDSA: (&str, [u32; 2]) = ("ECDSA", [0x0002, 0x0102]);
+
+/// Extracts the EC public key of an algorithm from an encoded certificate.
+pub fn public_key(
+    cert: &sev::Certificate,
+    what: &str,
+    (algorithm, ids): (&str, [u32; 2]),
+) -> Result<pkey::PKey<pkey::Public>> {
+    let invalid = || {
+        error::Context::new(
+            &format!("{} certificate does not hold an {} key", what, algorithm),
+            Box::<Error>::new(ErrorKind::InvalidData.into()),
+        )
+    };
+
+    let mut buf = Vec::new();
+    cert.encode(&mut buf, ())
+        .context(format!("unable to encode {} certificate", what))?;
+
+    let u32_at = |off: usize| {
+        let mut v = [0u8; 4];
+        v.copy_from_slice(&buf[off..off + 4]);
+        u32::from_le_bytes(v)
+    };
+
+    if !ids.contains(&u32_at(12)) {
+        return Err(invalid());
+    }
+
+    let (nid, size) = match u32_at(16) {
+        1 => (nid::Nid::X9_62_PRIME256V1, 32),
+        2 => (nid::Nid::SECP384R1, 48),
+        _ => return Err(invalid()),
+    };
+
+    // Coordinates are stored little-endian in 72-byte fields.
+    let coord = |off: usize| {
+        let mut be = buf[off..off + size].to_vec();
+        be.reverse();
+        bn::BigNum::from_slice(&be)
+    };
+
+    let group = ec::EcGroup::from_curve_name(nid).context("unsupported curve")?;
+    let invalid_key = format!("invalid {} public key", what);
+    let x = coord(20).context(&invalid_key)?;
+    let y = coord(92).context(&invalid_key)?;
+    let key =
+        ec::EcKey::from_public_key_affine_coordinates(&group, &x, &y).context(&invalid_key)?;
+    pkey::PKey::from_ec_key(key).context(&invalid_key)
+}
+
+/// Reads a raw transport key (TIK or TEK).
+pub fn read_key(path: &Path, form: encoding::Form) -> Result<Secret> {
+    let key = Secret::read(path)?;
+    encoding::decode(form, &key, KEY_LEN, "key").map(Secret::take)
+}
+
+fn random_key() -> Result<Secret> {
+    let mut key = Secret::new(KEY_LEN);
+    rand::rand_bytes(&mut key).context("unable to generate transport key")?;
+    Ok(key)
+}
+
+/// Writes a session file, readable only as `mode` allows.
+pub fn write(path: &Path, data: &[u8], mode: u32) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .context(format!("unable to create {}", path.display()))?;
+
+    // The mode only applies to new files; a key must not stay readable in an old one.
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+        .context(format!("unable to set the mode of {}", path.display()))?;
+    file.write_all(data)
+        .context(format!("unable to write {}", path.display()))
+}
+
+/// NIST SP 800-108 key derivation in counter mode with HMAC-SHA256.
+fn kdf(key: &[u8], size: usize, ctx: &[u8], label: &str) -> Result<Secret> {
+    let bits = (size as u32 * 8).to_le_bytes();
+    let mut out = Vec::with_capacity(size + 32);
+
+    let mut i = 1u32;
+    while out.len() < size {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&i.to_le_bytes());
+        msg.extend_from_slice(label.as_bytes());
+        msg.push(0);
+        msg.extend_from_slice(ctx);
+        msg.extend_from_slice(&bits);
+
+        let mut block = mac(key, &msg)?;
+        out.extend_from_slice(&block);
+        block.zeroize();
+        i += 1;
+    }
+
+    out.truncate(size);
+    Ok(Secret::take(out))
+}
+
+/// HMAC-SHA256 as used throughout the SEV key schedule.
+pub fn mac(key: &[u8], data: &[u8]) -> Result<[u8; 32]> {
+    let key = pkey::PKey::hmac(key).context("unable to create HMAC key")?;
+    let mut sig =
+        sign::Signer::new(hash::MessageDigest::sha256(), &key).context("unable to create HMAC")?;
+    sig.update(data).context("unable to compute HMAC")?;
+
+    let mut out = [0u8; 32];
+    sig.sign(&mut out).context("unable to compute HMAC")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+
+    /// RFC 4231 test cases 1 and 2.
+    #[test]
+    fn hmac_sha256() {
+        let mac1 = mac(&[0x0b; 20], b"Hi There").unwrap();
+        assert_eq!(
+            hex(&mac1),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+
+        let mac2 = mac(b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            hex(&mac2),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn kdf_counter_mode() {
+        let z: Vec<u8> = (0..48).collect();
+        let nonce: Vec<u8> = (0xa0..0xb0).collect();
+
+        let master = kdf(&z, KEY_LEN, &nonce, "sev-master-secret").unwrap();
+        assert_eq!(hex(&master), "4039d2f8212ac953f32d1386f0f6cc7c");
+
+        // The output length is part of every block's input, so longer keys are
+        // not extensions of shorter ones.
+        let long = kdf(&z, 48, &nonce, "sev-master-secret").unwrap();
+        assert_eq!(
+            hex(&long),
+            "17e51fd351ef3103a1e3793bbbe09534de2da30e0877b8017050e85b\
+             6370313b13932904841d3aa343d184d5678e7fce"
+        );
+
+        let kek = kdf(&master, KEY_LEN, &[], "sev-kek").unwrap();
+        let kik = kdf(&master, KEY_LEN, &[], "sev-kik").unwrap();
+        assert_eq!(hex(&kek), "2c229c5221c46911f44e7a572661e755");
+        assert_eq!(hex(&kik), "76d204e620f1d36209e11640cdc6284a");
+    }
+
+    #[test]
+    fn wrap_transport_keys() {
+        let z: Vec<u8> = (0..48).collect();
+        let mut nonce = [0u8; 16];
+        let mut iv = [0u8; 16];
+        nonce.iter_mut().zip(0xa0..).for_each(|(b, v)| *b = v);
+        iv.iter_mut().zip(0xb0..).for_each(|(b, v)| *b = v);
+
+        let session = wrap(&z, nonce, iv, &[0x11; 16], &[0x22; 16], 0x05).unwrap();
+        assert_eq!(session.nonce, nonce);
+        assert_eq!(session.wrap_iv, iv);
+        assert_eq!(
+            hex(&session.wrap_tk),
+            "dfaaa5a783063044fbfc8c34999c7060538ce0a490d43e59f3c7146b97271a5e"
+        );
+        assert_eq!(
+            hex(&session.wrap_mac),
+            "9547eb62fe0483dcfb9020438430f20c3905bd1e81554be188ae12f2172e9d37"
+        );
+        assert_eq!(
+            hex(&session.policy_mac),
+            "94c92c58b583c882cce26cc7977d1c4ff7242fafaefbea944729ebdf457c5aab"
+        );
+
+        let blob = encode_session(&session);
+        assert_eq!(blob.len(), SESSION_LEN);
+        assert_eq!(encode_session(&decode_session(&blob).unwrap()), blob);
+    }
+
+    /// A Rome platform's SEV certificate chain, signed by AMD.
+    const ROME: &[u8] = include_bytes!("mock.chain");
+
+    /// The RSA-PSS signature of `data`, big-endian and as long as the modulus.
+    fn pss(key: &pkey::PKey<pkey::Private>, data: &[u8]) -> Vec<u8> {
+        loop {
+            let mut signer = sign::Signer::new(hash::MessageDigest::sha256(), key).unwrap();
+            signer
+                .set_rsa_padding(openssl::rsa::Padding::PKCS1_PSS)
+                .unwrap();
+            signer
+                .set_rsa_pss_saltlen(sign::RsaPssSaltlen::DIGEST_LENGTH)
+                .unwrap();
+            signer.update(data).unwrap();
+            let sig = signer.sign_to_vec().unwrap();
+            // The sev crate drops leading zeros of SEV certificate signatures.
+            if sig[0] != 0 {
+                return sig;
+            }
+        }
+    }
+
+    fn le(n: &bn::BigNumRef, len: usize) -> Vec<u8> {
+        let mut bytes = n.to_vec();
+        bytes.reverse();
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    /// An AMD CA certificate for `key` of `usage` (0 for an ARK, 0x13 for an ASK),
+    /// signed by `signer`.
+    fn ca_cert(
+        usage: u32,
+        kid: [u8; 16],
+        sid: [u8; 16],
+        key: &Rsa<pkey::Private>,
+        signer: &pkey::PKey<pkey::Private>,
+    ) -> ca::Certificate {
+        let mut cert = 1u32.to_le_bytes().to_vec();
+        cert.extend_from_slice(&kid);
+        cert.extend_from_slice(&sid);
+        cert.extend_from_slice(&usage.to_le_bytes());
+        cert.extend_from_slice(&[0; 16]);
+        cert.extend_from_slice(&2048u32.to_le_bytes());
+        cert.extend_from_slice(&2048u32.to_le_bytes());
+        cert.extend(le(key.e(), 256));
+        cert.extend(le(key.n(), 256));
+        let sig = pss(signer, &cert);
+        cert.extend(sig.iter().rev());
+        ca::Certificate::decode(&mut &cert[..], ()).unwrap()
+    }
+
+    /// A complete chain, self-consistent but made up from its ARK down.
+    fn forged() -> Chain {
+        let ark_key = Rsa::generate(2048).unwrap();
+        let ask_key = Rsa::generate(2048).unwrap();
+        let ark = ca_cert(
+            0,
+            [1; 16],
+            [1; 16],
+            &ark_key,
+            &pkey::PKey::from_rsa(ark_key.clone()).unwrap(),
+        );
+        let ask = ca_cert(
+            0x13,
+            [2; 16],
+            [1; 16],
+            &ask_key,
+            &pkey::PKey::from_rsa(ark_key).unwrap(),
+        );
+
+        // The sev crate only signs with SEV keys, so the ASK's signature of the CEK
+        // goes into its first slot by hand: usage, algorithm, then the little-endian
+        // signature.
+        let (cek, cek_prv) = sev::Certificate::generate(sev::Usage::CEK).unwrap();
+        let mut buf = Vec::new();
+        cek.encode(&mut buf, ()).unwrap();
+        let body = buf.len() - 2 * (8 + 512);
+        let sig = pss(&pkey::PKey::from_rsa(ask_key).unwrap(), &buf[..body]);
+        buf[body..body + 4].copy_from_slice(&0x13u32.to_le_bytes());
+        buf[body + 4..body + 8].copy_from_slice(&1u32.to_le_bytes());
+        let mut sig: Vec<u8> = sig.into_iter().rev().collect();
+        sig.resize(512, 0);
+        buf[body + 8..body + 8 + 512].copy_from_slice(&sig);
+        let cek = sev::Certificate::decode(&mut &buf[..], ()).unwrap();
+
+        let (mut oca, oca_prv) = sev::Certificate::generate(sev::Usage::OCA).unwrap();
+        oca_prv.sign(&mut oca).unwrap();
+        let (mut pek, pek_prv) = sev::Certificate::generate(sev::Usage::PEK).unwrap();
+        cek_prv.sign(&mut pek).unwrap();
+        oca_prv.sign(&mut pek).unwrap();
+        let (mut pdh, _) = sev::Certificate::generate(sev::Usage::PDH).unwrap();
+        pek_prv.sign(&mut pdh).unwrap();
+
+        Chain {
+            ca: ca::Chain { ask, ark },
+            sev: sev::Chain { pdh, pek, oca, cek },
+        }
+    }
+
+    fn encode(chain: &impl codicon::Encoder<(), Error = Error>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        chain.encode(&mut buf, ()).unwrap();
+        buf
+    }
+
+    #[test]
+    fn forged_ark() {
+        let buf = encode(&forged());
+
+        // The chain verifies against the ARK it carries, which anyone can make up.
+        Chain::decode(&mut &buf[..], ()).unwrap().verify().unwrap();
+        assert!(target_pdh(&buf, roots_builtin()).is_err());
+        assert!(target_chain(&buf, roots_builtin()).is_err());
+    }
+
+    #[test]
+    fn forged_ark_after_genuine_chain() {
+        let mut buf = ROME.to_vec();
+        buf.extend(encode(&forged().ca));
+        assert!(target_pdh(&buf, roots_builtin()).is_err());
+    }
+
+    #[test]
+    fn genuine_chain() {
+        target_pdh(ROME, roots_builtin()).unwrap();
+
+        let mut buf = ROME.to_vec();
+        buf.extend(encode(&ca::Chain::from(Generation::Rome)));
+        let chain = target_chain(&buf, roots_builtin()).unwrap();
+        assert!(chain.ca == ca::Chain::from(Generation::Rome));
+
+        let naples = roots_builtin()
+            .into_iter()
+            .filter(|root| root.name == "Naples")
+            .collect();
+        assert!(target_pdh(ROME, naples).is_err());
+    }
+}
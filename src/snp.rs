@@ -0,0 +1,1447 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Utilities for SEV-SNP guests.
+//!
+//! The SNP launch digest is a chain of SHA-384 hashes: every page added to the guest
+//! during the launch (SNP_LAUNCH_UPDATE) is described by a PAGE_INFO structure that
+//! includes the previous digest, and the new digest is the hash of that structure.
+
+use super::*;
+use colorful::*;
+use measurement::DirectBoot;
+use openssl::x509::X509;
+use openssl::{bn, ec, ecdsa, hash, nid, pkey};
+use std::io::Write;
+
+#[derive(StructOpt)]
+pub enum Snp {
+    #[structopt(about = "Compute the expected SEV-SNP launch digest")]
+    Measurement {
+        #[structopt(long, parse(from_os_str), help = "OVMF firmware image")]
+        firmware: PathBuf,
+
+        #[structopt(long, parse(from_os_str), help = "Kernel for measured direct boot")]
+        kernel: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "kernel",
+            help = "Initrd for measured direct boot"
+        )]
+        initrd: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            requires = "kernel",
+            help = "Kernel command line for measured direct boot"
+        )]
+        cmdline: Option<String>,
+
+        #[structopt(flatten)]
+        vcpus: vcpu::VcpuArgs,
+
+        #[structopt(
+            long,
+            default_value = "hex",
+            possible_values = encoding::OUTPUT_FORMS,
+            help = "Encoding of the output: hex, base64 or raw"
+        )]
+        out_form: encoding::Form,
+    },
+
+    #[structopt(about = "Derive a sealing key inside an SEV-SNP guest")]
+    DerivedKey {
+        #[structopt(long, help = "Bind the key to the guest policy")]
+        policy: bool,
+
+        #[structopt(long, help = "Bind the key to the image ID")]
+        image_id: bool,
+
+        #[structopt(long, help = "Bind the key to the family ID")]
+        family_id: bool,
+
+        #[structopt(long, help = "Bind the key to the launch measurement")]
+        measurement: bool,
+
+        #[structopt(long, help = "Bind the key to this guest SVN, at most the guest's")]
+        guest_svn: Option<u32>,
+
+        #[structopt(
+            long,
+            help = "Bind the key to this TCB version, at most the platform's: a number as in \
+                    attestation reports, or bootloader=N,tee=N,snp=N,microcode=N"
+        )]
+        tcb: Option<String>,
+
+        #[structopt(long, help = "Derive from the VM root key instead of the VCEK")]
+        vmrk: bool,
+
+        #[structopt(long, default_value = "0", help = "VMPL the key is derived for")]
+        vmpl: u32,
+
+        #[structopt(
+            parse(from_os_str),
+            help = "Key output file path (created with mode 0600)"
+        )]
+        destination: PathBuf,
+    },
+
+    #[structopt(about = "Download the VCEK of a chip from the KDS")]
+    FetchVcek {
+        #[structopt(
+            long,
+            help = "Chip ID (64 bytes, hex), defaults to the local platform's"
+        )]
+        id: Option<String>,
+
+        #[structopt(long, help = "Bootloader SVN, defaults to the local reported TCB's")]
+        bl: Option<u8>,
+
+        #[structopt(long, help = "TEE SVN, defaults to the local reported TCB's")]
+        tee: Option<u8>,
+
+        #[structopt(long, help = "SNP firmware SVN, defaults to the local reported TCB's")]
+        snp: Option<u8>,
+
+        #[structopt(long, help = "Microcode SVN, defaults to the local reported TCB's")]
+        ucode: Option<u8>,
+
+        #[structopt(
+            long,
+            possible_values = kds::PRODUCTS,
+            case_insensitive = true,
+            help = "Processor generation, defaults to the local processor's"
+        )]
+        product: Option<kds::Product>,
+
+        #[structopt(long, help = "Write PEM instead of DER")]
+        pem: bool,
+
+        #[structopt(parse(from_os_str), help = "VCEK output file path")]
+        destination: PathBuf,
+    },
+
+    #[structopt(about = "Build and sign the ID block and ID authentication information")]
+    IdBlock {
+        #[structopt(long, help = "Expected launch digest")]
+        digest: String,
+
+        #[structopt(
+            long,
+            default_value = "0x30000",
+            help = "Guest policy (decimal or 0x-prefixed hexadecimal)"
+        )]
+        policy: String,
+
+        #[structopt(long, help = "Family ID (16 bytes), defaults to zero")]
+        family_id: Option<String>,
+
+        #[structopt(long, help = "Image ID (16 bytes), defaults to zero")]
+        image_id: Option<String>,
+
+        #[structopt(long, default_value = "0", help = "Guest security version number")]
+        guest_svn: u32,
+
+        #[structopt(long, parse(from_os_str), help = "ID key (PEM, ECDSA P-384)")]
+        id_key: PathBuf,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Author key signing the ID key (PEM, ECDSA P-384)"
+        )]
+        author_key: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            default_value = "auto",
+            possible_values = &["auto", "hex", "base64"],
+            help = "Encoding of the digest and IDs: auto, hex or base64"
+        )]
+        in_form: encoding::Form,
+    },
+
+    #[structopt(about = "Commit the installed firmware and reported TCB, preventing rollback")]
+    Commit {
+        #[structopt(long, help = "Confirm the commit, which cannot be undone")]
+        yes: bool,
+    },
+
+    #[structopt(about = "Change the SEV-SNP platform configuration")]
+    Config {
+        #[structopt(subcommand)]
+        cmd: SnpConfig,
+    },
+
+    #[structopt(about = "Check that the host is ready to run SEV-SNP guests")]
+    Ok,
+
+    #[structopt(about = "Build and explain SEV-SNP guest policies")]
+    Policy {
+        #[structopt(subcommand)]
+        cmd: policy::SnpPolicy,
+    },
+
+    #[structopt(
+        about = "Request an attestation report inside an SEV-SNP guest",
+        setting = structopt::clap::AppSettings::SubcommandsNegateReqs
+    )]
+    Report {
+        #[structopt(subcommand)]
+        cmd: Option<SnpReport>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            required_unless_one = &["random", "report-data-sha512"],
+            conflicts_with_all = &["random", "report-data-sha512"],
+            help = "File of up to 64 bytes (e.g. a nonce or key hash) to bind into the report"
+        )]
+        data: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with = "random",
+            help = "File of any size (e.g. a public key) whose SHA-512 digest to bind into the report"
+        )]
+        report_data_sha512: Option<PathBuf>,
+
+        #[structopt(long, help = "Bind 64 random bytes into the report")]
+        random: bool,
+
+        #[structopt(long, default_value = "0", help = "VMPL the report is requested for")]
+        vmpl: u32,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Request an extended report and write the host's certificates to this directory"
+        )]
+        certs: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Request an extended report and verify it with the host's certificates"
+        )]
+        verify: bool,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            requires = "verify",
+            help = "Appraisal policy (TOML) to apply with --verify"
+        )]
+        policy: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        arks: appraisal::TrustedArks,
+
+        #[structopt(parse(from_os_str), help = "Attestation report output file path")]
+        destination: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Show the size of the RMP and the memory it covers")]
+    Rmp,
+
+    #[structopt(about = "Show the SEV-SNP platform status")]
+    Show,
+
+    #[structopt(about = "Compare the current, committed and reported TCB versions")]
+    Tcb {
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Attestation report to take the versions from, including the committed ones"
+        )]
+        report: Option<PathBuf>,
+    },
+
+    #[structopt(about = "Check the TCB versions against the minimums AMD recommends")]
+    TcbCheck {
+        #[structopt(
+            long,
+            help = "Table of minimums (TOML), as a file or URL, instead of the bundled one"
+        )]
+        minimums: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "Attestation report to take the versions from, including the committed ones"
+        )]
+        report: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            possible_values = kds::PRODUCTS,
+            case_insensitive = true,
+            help = "Processor generation, defaults to the local processor's"
+        )]
+        product: Option<kds::Product>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SnpReport {
+    #[structopt(about = "Decode every field of an attestation report")]
+    Show {
+        #[structopt(parse(from_os_str), help = "Attestation report file")]
+        report: PathBuf,
+    },
+
+    #[structopt(about = "Verify the signature of an attestation report and appraise it")]
+    Verify {
+        #[structopt(parse(from_os_str), help = "Attestation report file")]
+        report: PathBuf,
+
+        #[structopt(long, parse(from_os_str), help = "Appraisal policy (TOML)")]
+        policy: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "File whose SHA-512 digest the report data must be"
+        )]
+        report_data_sha512: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            conflicts_with = "report-data-sha512",
+            help = "ID of the stored nonce the report data must be (see nonce new --store)"
+        )]
+        nonce_id: Option<String>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "VCEK (PEM or DER), downloaded from the KDS by default"
+        )]
+        vcek: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "ASK and ARK (PEM), downloaded from the KDS by default"
+        )]
+        chain: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            possible_values = kds::PRODUCTS,
+            case_insensitive = true,
+            help = "Processor generation, defaults to the VCEK issuer's or the local processor's"
+        )]
+        product: Option<kds::Product>,
+
+        #[structopt(flatten)]
+        arks: appraisal::TrustedArks,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SnpConfig {
+    #[structopt(about = "Set the reported TCB version and whether the chip ID is masked")]
+    Set {
+        #[structopt(
+            long,
+            help = "TCB version to report: current, a number as in attestation reports, \
+                    or bootloader=N,tee=N,snp=N,microcode=N"
+        )]
+        reported_tcb: Option<String>,
+
+        #[structopt(
+            long,
+            possible_values = &["true", "false"],
+            help = "Whether to zero the chip ID in attestation reports"
+        )]
+        mask_chip_id: Option<bool>,
+    },
+}
+
+pub fn cmd(snp: Snp) -> Result<()> {
+    match snp {
+        Snp::Measurement {
+            firmware,
+            kernel,
+            initrd,
+            cmdline,
+            vcpus,
+            out_form,
+        } => {
+            let boot = kernel.map(|kernel| DirectBoot {
+                kernel,
+                initrd,
+                cmdline,
+            });
+
+            let digest = launch_digest(&ovmf::Ovmf::load(&firmware)?, boot.as_ref(), &vcpus)?;
+
+            if out_form == encoding::Form::Raw {
+                return std::io::stdout()
+                    .write_all(&digest)
+                    .context("unable to write to stdout");
+            }
+
+            output::print(&schema::LaunchDigest {
+                launch_digest: encoding::encode(out_form, &digest),
+                measurement: None,
+            });
+            Ok(())
+        }
+
+        Snp::DerivedKey {
+            policy,
+            image_id,
+            family_id,
+            measurement,
+            guest_svn,
+            tcb,
+            vmrk,
+            vmpl,
+            destination,
+        } => {
+            let key = report::derived_key(report::KeySelect {
+                vmrk,
+                policy,
+                image_id,
+                family_id,
+                measurement,
+                guest_svn,
+                tcb: tcb.map(|tcb| parse_tcb(&tcb, None)).transpose()?,
+                vmpl,
+            })?;
+            session::write(&destination, &key, 0o600)
+        }
+
+        Snp::FetchVcek {
+            id,
+            bl,
+            tee,
+            snp,
+            ucode,
+            product,
+            pem,
+            destination,
+        } => {
+            let product = kds::Product::or_local(product)?;
+
+            let id = match id {
+                Some(id) => encoding::decode(
+                    encoding::Form::Hex,
+                    id.as_bytes(),
+                    kds::CHIP_ID_SIZE,
+                    "chip ID",
+                )?,
+                None => {
+                    let mut fw = firmware()?;
+                    issue("GET_ID", || fw.get_identifier()).context("error fetching identifier")?
+                }
+            };
+
+            // GET_ID reports both IDs of a two-socket platform; the VCEK is the first socket's.
+            if id.len() < kds::CHIP_ID_SIZE {
+                return Err(error::Context::new(
+                    &format!(
+                        "chip ID is {} bytes, expected {}",
+                        id.len(),
+                        kds::CHIP_ID_SIZE
+                    ),
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                ));
+            }
+            let id = &id[..kds::CHIP_ID_SIZE];
+
+            let tcb = match (bl, tee, snp, ucode) {
+                (Some(bootloader), Some(tee), Some(snp), Some(microcode)) => platform::TcbVersion {
+                    bootloader,
+                    tee,
+                    snp,
+                    microcode,
+                },
+                _ => {
+                    let reported = snp_status()?.reported_tcb;
+                    platform::TcbVersion {
+                        bootloader: bl.unwrap_or(reported.bootloader),
+                        tee: tee.unwrap_or(reported.tee),
+                        snp: snp.unwrap_or(reported.snp),
+                        microcode: ucode.unwrap_or(reported.microcode),
+                    }
+                }
+            };
+
+            let vcek = kds::vcek(product, id, tcb)?;
+            let bytes = match pem {
+                true => sandbox::run("VCEK", || {
+                    X509::from_der(&vcek)
+                        .and_then(|cert| cert.to_pem())
+                        .context("unable to encode the VCEK")
+                })?,
+                false => vcek,
+            };
+            session::write(&destination, &bytes, 0o644)
+        }
+
+        Snp::IdBlock {
+            digest,
+            policy,
+            family_id,
+            image_id,
+            guest_svn,
+            id_key,
+            author_key,
+            in_form,
+        } => {
+            let id = |value: Option<String>, what| match value {
+                Some(value) => encoding::arg(in_form, &value, what),
+                None => Ok([0u8; 16]),
+            };
+
+            let block = IdBlock {
+                digest: encoding::decode(in_form, digest.as_bytes(), 48, "launch digest")?,
+                family_id: id(family_id, "family ID")?,
+                image_id: id(image_id, "image ID")?,
+                guest_svn,
+                policy: policy::parse(&policy)?,
+            }
+            .encode();
+
+            let id_key = signing_key(&id_key)?;
+            let author_key = match author_key {
+                Some(path) => Some(signing_key(&path)?),
+                None => None,
+            };
+
+            let auth = id_auth(&block, &id_key, author_key.as_ref())?;
+
+            output::print(&schema::IdBlock {
+                id_block: base64::encode(&block[..]),
+                id_auth: base64::encode(&auth[..]),
+                author_key_enabled: author_key.is_some(),
+            });
+            Ok(())
+        }
+
+        Snp::Commit { yes } => commit(yes),
+
+        Snp::Config {
+            cmd:
+                SnpConfig::Set {
+                    reported_tcb,
+                    mask_chip_id,
+                },
+        } => set_config(reported_tcb.as_deref(), mask_chip_id),
+
+        Snp::Ok => ok::report(&ok::snp_checks()),
+
+        Snp::Policy { cmd } => policy::snp_cmd(cmd),
+
+        Snp::Report {
+            cmd: Some(SnpReport::Show { report }),
+            ..
+        } => {
+            tracing::debug!(path = %report.display(), "reading file");
+            let bytes =
+                std::fs::read(&report).context(format!("unable to read {}", report.display()))?;
+            output::print(&report::Details(report::Report::new(bytes)?.document()));
+            Ok(())
+        }
+
+        Snp::Report {
+            cmd:
+                Some(SnpReport::Verify {
+                    report,
+                    policy,
+                    report_data_sha512,
+                    nonce_id,
+                    vcek,
+                    chain,
+                    product,
+                    arks,
+                }),
+            ..
+        } => {
+            let appraisal = load_appraisal(policy.as_deref())?;
+            let arks = arks.load()?;
+            let expected = report_data_sha512
+                .as_deref()
+                .map(report::data_sha512)
+                .transpose()?;
+            let stored = nonce_id.as_deref().map(nonce::Stored::load).transpose()?;
+
+            tracing::debug!(path = %report.display(), "reading file");
+            let bytes =
+                std::fs::read(&report).context(format!("unable to read {}", report.display()))?;
+            let report = report::Report::new(bytes)?;
+            let fields = report.document();
+
+            let downloaded_vcek = |data| Encoded {
+                name: "downloaded VCEK".into(),
+                data,
+            };
+            let downloaded_chain = |data| Encoded {
+                name: "downloaded certificate chain".into(),
+                data,
+            };
+            let tcb = (&fields.reported_tcb).into();
+            let (vcek, chain) = match (vcek, chain) {
+                (None, _) if fields.chip_id.iter().all(|b| *b == 0) => {
+                    return Err(error::Context::new(
+                        "the chip ID is masked in the report",
+                        Box::new(Error::new(ErrorKind::InvalidInput, "give --vcek")),
+                    ))
+                }
+                // Neither depends on the other, so both are downloaded at once.
+                (None, None) => {
+                    let product = kds::Product::or_local(product)?;
+                    let (vcek, chain) = kds::vcek_and_chain(product, &fields.chip_id, tcb)?;
+                    (downloaded_vcek(vcek), downloaded_chain(chain))
+                }
+                (None, Some(chain)) => {
+                    let product = kds::Product::or_local(product)?;
+                    (
+                        downloaded_vcek(kds::vcek(product, &fields.chip_id, tcb)?),
+                        Encoded::read(&chain)?,
+                    )
+                }
+                (Some(vcek), Some(chain)) => (Encoded::read(&vcek)?, Encoded::read(&chain)?),
+                (Some(vcek), None) => {
+                    let vcek = Encoded::read(&vcek)?;
+
+                    // The chain of the ASK that issued the VCEK, which is not the
+                    // local generation's when verifying another host's report.
+                    let product = match (product, kds::Product::issuer(&vcek.data)?) {
+                        (Some(given), Some(issuer)) if given != issuer => {
+                            return Err(error::Context::new(
+                                &format!("{} was issued for {}, not {}", vcek.name, issuer, given),
+                                Box::new(Error::new(
+                                    ErrorKind::InvalidInput,
+                                    format!("give --product {} or omit it", issuer),
+                                )),
+                            ))
+                        }
+                        (given, issuer) => kds::Product::or_local(given.or(issuer))?,
+                    };
+                    let chain = downloaded_chain(kds::cert_chain(product)?);
+                    (vcek, chain)
+                }
+            };
+
+            appraise(
+                &report,
+                vcek,
+                vec![chain],
+                &arks,
+                &appraisal,
+                expected.as_ref(),
+                stored,
+            )
+        }
+
+        Snp::Report {
+            cmd: None,
+            data,
+            report_data_sha512,
+            random,
+            vmpl,
+            certs,
+            verify,
+            policy,
+            arks,
+            destination,
+        } => {
+            let destination = destination.ok_or_else(|| {
+                error::Context::new(
+                    "an output file is required",
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                )
+            })?;
+
+            let appraisal = load_appraisal(policy.as_deref())?;
+            // The host supplies the certificates, so its chain must end in a trusted ARK.
+            let arks = match verify {
+                true => arks.load()?,
+                false => Vec::new(),
+            };
+
+            let mut report_data = [0u8; 64];
+            match (data, &report_data_sha512) {
+                (Some(path), _) => {
+                    tracing::debug!(path = %path.display(), "reading file");
+                    let bytes = std::fs::read(&path)
+                        .context(format!("unable to read {}", path.display()))?;
+                    report_data = report::data(&bytes)?;
+                }
+                (None, Some(path)) => report_data = report::data_sha512(path)?,
+                (None, None) if random => openssl::rand::rand_bytes(&mut report_data)
+                    .context("unable to generate report data")?,
+                (None, None) => (),
+            }
+
+            if certs.is_none() && !verify {
+                let report = report::request_device(&report_data, vmpl)?;
+                session::write(&destination, report.as_bytes(), 0o644)?;
+                output::print(&report.document());
+                return Ok(());
+            }
+
+            let (report, table) = report::request_extended(&report_data, vmpl)?;
+            session::write(&destination, report.as_bytes(), 0o644)?;
+
+            let mut found = std::collections::HashMap::new();
+            for (guid, cert) in &table {
+                let name = report::cert_name(guid).map_or_else(|| guid.to_string(), String::from);
+                if let Some(dir) = &certs {
+                    session::write(&dir.join(format!("{}.der", name)), cert, 0o644)?;
+                }
+                found.insert(name, cert);
+            }
+
+            if !verify {
+                output::print(&report.document());
+                return Ok(());
+            }
+
+            let cert = |name: &str| match found.get(name) {
+                Some(der) => Ok(Encoded {
+                    name: format!("{} in the table", name),
+                    data: der.to_vec(),
+                }),
+                None => Err(error::Context::new(
+                    &format!("the host provided no {} certificate", name.to_uppercase()),
+                    Box::<Error>::new(ErrorKind::NotFound.into()),
+                )),
+            };
+            appraise(
+                &report,
+                cert("vcek")?,
+                vec![cert("ask")?, cert("ark")?],
+                &arks,
+                &appraisal,
+                report_data_sha512.as_ref().map(|_| &report_data),
+                None,
+            )
+        }
+
+        Snp::Rmp => {
+            output::print(&rmp()?);
+            Ok(())
+        }
+
+        Snp::Show => {
+            output::print(&schema::SnpPlatformStatus::from(snp_status()?));
+            Ok(())
+        }
+
+        Snp::Tcb { report } => {
+            output::print(&tcb_status(report.as_deref())?);
+            Ok(())
+        }
+
+        Snp::TcbCheck {
+            minimums,
+            report,
+            product,
+        } => {
+            let product = kds::Product::or_local(product)?;
+            let status = tcb_status(report.as_deref())?;
+            let minimums = minimums.or(config::get().tcb_minimums);
+            tcb::check(minimums.as_deref(), product, &status)
+        }
+    }
+}
+
+/// The size of the RMP's bookkeeping area, before the entries.
+const RMP_BOOKKEEPING: u64 = 0x4000;
+
+/// The size of an RMP entry, each describing a 4 KiB page.
+const RMP_ENTRY: u64 = 16;
+
+/// The RMP's location from the RMP_BASE and RMP_END MSRs, and the memory it covers.
+fn rmp() -> Result<schema::Rmp> {
+    let (base, end) = platform::rmp_range()
+        .context("unable to read the RMP MSRs (is the msr module loaded, and are you root?)")?;
+    if base == 0 && end == 0 {
+        return Err(error::Context::new(
+            "no RMP is reserved",
+            Box::new(Error::new(
+                ErrorKind::NotFound,
+                "check the SEV-SNP and RMP settings of the BIOS",
+            )),
+        ));
+    }
+
+    let size = end + 1 - base;
+    let entries = size.saturating_sub(RMP_BOOKKEEPING) / RMP_ENTRY;
+    let covered = entries * 4096;
+    let system_ram = platform::system_ram_end();
+
+    Ok(schema::Rmp {
+        base,
+        end,
+        size,
+        entries,
+        covered,
+        system_ram,
+        covers_system_ram: system_ram.map(|ram| covered >= ram),
+    })
+}
+
+impl output::Document for schema::Rmp {
+    fn table(&self) -> String {
+        let mut out = format!(
+            "rmp: {:#x}-{:#x}\nsize: {} MiB\nentries: {}\ncovered memory: {} MiB\n",
+            self.base,
+            self.end,
+            self.size >> 20,
+            self.entries,
+            self.covered >> 20
+        );
+        if let Some(ram) = self.system_ram {
+            out += &format!("system ram: {} MiB\n", ram >> 20);
+        }
+        if self.covers_system_ram == Some(false) {
+            out +=
+                "warning: the RMP does not cover all system RAM, so the kernel disables SEV-SNP\n";
+        }
+        out
+    }
+}
+
+/// Compares the TCB versions of the platform or, as the platform status does not
+/// include the committed ones, of an attestation report.
+fn tcb_status(report: Option<&Path>) -> Result<schema::TcbStatus> {
+    let (current, reported, committed, current_version, committed_version) = match report {
+        Some(path) => {
+            tracing::debug!(path = %path.display(), "reading file");
+            let bytes =
+                std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+            let r = report::Report::new(bytes)?.document();
+            (
+                (&r.current_tcb).into(),
+                (&r.reported_tcb).into(),
+                Some((&r.committed_tcb).into()),
+                format!(
+                    "{}.{}.{}",
+                    r.current_major, r.current_minor, r.current_build
+                ),
+                Some(format!(
+                    "{}.{}.{}",
+                    r.committed_major, r.committed_minor, r.committed_build
+                )),
+            )
+        }
+        None => {
+            let status = snp_status()?;
+            (
+                status.current_tcb,
+                status.reported_tcb,
+                None,
+                format!("{}.{}.{}", status.api_major, status.api_minor, status.build),
+                None,
+            )
+        }
+    };
+
+    let mut warnings = Vec::new();
+    let understated = tcb_below(reported, current);
+    if !understated.is_empty() {
+        warnings.push(format!(
+            "the reported TCB understates the installed firmware ({}), so reports are \
+             signed as if older firmware were running (see snp config set)",
+            understated.join(", ")
+        ));
+    }
+    if let Some(committed) = committed {
+        let uncommitted = tcb_below(committed, current);
+        if !uncommitted.is_empty() || committed_version.as_ref() != Some(&current_version) {
+            warnings.push(format!(
+                "the installed firmware {} is not committed ({} committed), so it can be \
+                 rolled back and an update not in the flash is lost on reset (see snp commit)",
+                current_version,
+                committed_version.as_deref().unwrap_or_default()
+            ));
+        }
+    }
+
+    Ok(schema::TcbStatus {
+        current_tcb: current.into(),
+        reported_tcb: reported.into(),
+        committed_tcb: committed.map(Into::into),
+        current_version,
+        committed_version,
+        warnings,
+    })
+}
+
+/// The components of `tcb` below those of `other`, e.g. `snp 8 < 14`.
+fn tcb_below(tcb: platform::TcbVersion, other: platform::TcbVersion) -> Vec<String> {
+    [
+        ("bootloader", tcb.bootloader, other.bootloader),
+        ("tee", tcb.tee, other.tee),
+        ("snp", tcb.snp, other.snp),
+        ("microcode", tcb.microcode, other.microcode),
+    ]
+    .iter()
+    .filter(|(_, svn, min)| svn < min)
+    .map(|(name, svn, min)| format!("{} {} < {}", name, svn, min))
+    .collect()
+}
+
+/// Commits the installed firmware and the reported TCB, once confirmed.
+fn commit(yes: bool) -> Result<()> {
+    eprintln!(
+        "{} SNP_COMMIT makes the installed firmware and the reported TCB the oldest this \
+         platform accepts; older firmware can never be installed again",
+        "warning:".red()
+    );
+    if !yes {
+        return Err(error::Context::new(
+            "refusing to commit without --yes",
+            Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                "the commit cannot be undone",
+            )),
+        ));
+    }
+
+    let mut fw = firmware()?;
+    issue("SNP_COMMIT", || fw.snp_commit()).context("unable to commit the SNP firmware")
+}
+
+/// Checks the report's signature and the VCEK's chain, then applies the
+/// appraisal policy, printing the verdicts. A stored nonce is used up if the
+/// report binds it.
+fn appraise(
+    report: &report::Report,
+    vcek: Encoded,
+    chain: Vec<Encoded>,
+    arks: &[X509],
+    appraisal: &appraisal::Appraisal,
+    report_data: Option<&[u8; 64]>,
+    nonce: Option<nonce::Stored>,
+) -> Result<()> {
+    let fields = report.document();
+    let mut results = sandbox::run("certificates", || {
+        let vcek = vcek.decode()?.remove(0);
+        let mut certs = Vec::new();
+        for encoded in &chain {
+            certs.extend(encoded.decode()?);
+        }
+        Ok(appraisal::signature(report, &vcek, &certs, arks))
+    })?;
+    results.extend(report_data.map(|data| appraisal::report_data(&fields, data)));
+    if let Some(nonce) = nonce {
+        let check = nonce.check(&fields.report_data);
+        nonce.consume(&check)?;
+        results.push(check);
+    }
+    results.extend(appraisal.appraise(&fields));
+    appraisal::report(results)
+}
+
+/// Loads the appraisal policy, if any.
+fn load_appraisal(policy: Option<&Path>) -> Result<appraisal::Appraisal> {
+    match policy {
+        Some(path) => appraisal::Appraisal::load(path),
+        None => Ok(appraisal::Appraisal::default()),
+    }
+}
+
+/// Certificates in PEM or DER format, decoded only in the sandbox.
+struct Encoded {
+    /// What they are, for errors.
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Encoded {
+    fn read(path: &Path) -> Result<Self> {
+        tracing::debug!(path = %path.display(), "reading file");
+        Ok(Self {
+            name: path.display().to_string(),
+            data: std::fs::read(path).context(format!("unable to read {}", path.display()))?,
+        })
+    }
+
+    /// The certificates, at least one.
+    fn decode(&self) -> Result<Vec<X509>> {
+        match X509::stack_from_pem(&self.data) {
+            Ok(certs) if !certs.is_empty() => Ok(certs),
+            _ => X509::from_der(&self.data).map(|cert| vec![cert]),
+        }
+        .context(format!("unable to decode {}", self.name))
+    }
+}
+
+/// Sets the SEV-SNP configuration, keeping the settings not given, and prints it.
+fn set_config(reported_tcb: Option<&str>, mask_chip_id: Option<bool>) -> Result<()> {
+    if reported_tcb.is_none() && mask_chip_id.is_none() {
+        return Err(error::Context::new(
+            "nothing to set",
+            Box::new(Error::new(
+                ErrorKind::InvalidInput,
+                "give --reported-tcb, --mask-chip-id or both",
+            )),
+        ));
+    }
+
+    let status = snp_status()?;
+    let tcb = match reported_tcb {
+        Some(tcb) => parse_tcb(tcb, Some(status.current_tcb))?,
+        None => status.reported_tcb,
+    };
+
+    // The firmware only signs reports with a TCB it is at least as new as.
+    let current = status.current_tcb;
+    for (name, requested, installed) in &[
+        ("bootloader", tcb.bootloader, current.bootloader),
+        ("tee", tcb.tee, current.tee),
+        ("snp", tcb.snp, current.snp),
+        ("microcode", tcb.microcode, current.microcode),
+    ] {
+        if requested > installed {
+            return Err(error::Context::new(
+                "the reported TCB may not be above the installed TCB",
+                Box::new(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "{} SVN {} is above the installed {}",
+                        name, requested, installed
+                    ),
+                )),
+            ));
+        }
+    }
+
+    let mask_chip_id = mask_chip_id.unwrap_or(status.mask_chip_id);
+    let mut fw = firmware()?;
+    issue("SNP_SET_CONFIG", || fw.snp_set_config(tcb, mask_chip_id))
+        .context("unable to set the SNP configuration")?;
+
+    let status = snp_status()?;
+    output::print(&schema::SnpConfig {
+        reported_tcb: status.reported_tcb.into(),
+        mask_chip_id: status.mask_chip_id,
+    });
+    Ok(())
+}
+
+/// Parses a TCB version: `current` if known, the u64 of attestation reports, or its
+/// components as `bootloader=N,tee=N,snp=N,microcode=N`.
+fn parse_tcb(tcb: &str, current: Option<platform::TcbVersion>) -> Result<platform::TcbVersion> {
+    let invalid = || {
+        error::Context::new(
+            &format!("invalid TCB version {}", tcb),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        )
+    };
+
+    if tcb == "current" {
+        return current.ok_or_else(invalid);
+    }
+    if let Ok(raw) = parse_u64(tcb) {
+        return Ok(raw.into());
+    }
+
+    let mut parsed = platform::TcbVersion::default();
+    let mut seen = Vec::new();
+    for component in tcb.split(',') {
+        let (name, svn) = component.split_once('=').ok_or_else(invalid)?;
+        let (name, svn) = (name.trim(), svn.trim().parse().map_err(|_| invalid())?);
+        match name {
+            "bootloader" => parsed.bootloader = svn,
+            "tee" => parsed.tee = svn,
+            "snp" => parsed.snp = svn,
+            "microcode" => parsed.microcode = svn,
+            _ => return Err(invalid()),
+        }
+        if seen.contains(&name) {
+            return Err(invalid());
+        }
+        seen.push(name);
+    }
+
+    match seen.len() {
+        4 => Ok(parsed),
+        _ => Err(invalid()),
+    }
+}
+
+impl output::Document for schema::SnpConfig {
+    fn table(&self) -> String {
+        format!(
+            "reported tcb: bootloader {}, tee {}, snp {}, microcode {}\nmask chip id: {}\n",
+            self.reported_tcb.bootloader,
+            self.reported_tcb.tee,
+            self.reported_tcb.snp,
+            self.reported_tcb.microcode,
+            self.mask_chip_id
+        )
+    }
+}
+
+impl From<platform::TcbVersion> for schema::TcbVersion {
+    fn from(tcb: platform::TcbVersion) -> Self {
+        Self {
+            bootloader: tcb.bootloader,
+            tee: tcb.tee,
+            snp: tcb.snp,
+            microcode: tcb.microcode,
+        }
+    }
+}
+
+impl From<platform::SnpStatus> for schema::SnpPlatformStatus {
+    fn from(status: platform::SnpStatus) -> Self {
+        Self {
+            api_major: status.api_major,
+            api_minor: status.api_minor,
+            build_id: status.build,
+            state: match status.initialized {
+                true => "initialized",
+                false => "uninitialized",
+            }
+            .to_string(),
+            rmp_initialized: status.rmp_initialized,
+            mask_chip_id: status.mask_chip_id,
+            guests: status.guests,
+            current_tcb: status.current_tcb.into(),
+            reported_tcb: status.reported_tcb.into(),
+        }
+    }
+}
+
+/// A TCB version by component, e.g. `bootloader 3, tee 0, snp 14, microcode 209`.
+fn describe_tcb(tcb: &schema::TcbVersion) -> String {
+    format!(
+        "bootloader {}, tee {}, snp {}, microcode {}",
+        tcb.bootloader, tcb.tee, tcb.snp, tcb.microcode
+    )
+}
+
+impl output::Document for schema::TcbStatus {
+    fn table(&self) -> String {
+        let unknown = || "unknown (give --report)".to_string();
+        let mut out = format!(
+            "current tcb: {}\nreported tcb: {}\ncommitted tcb: {}\n\
+             current version: {}\ncommitted version: {}\n",
+            describe_tcb(&self.current_tcb),
+            describe_tcb(&self.reported_tcb),
+            self.committed_tcb
+                .as_ref()
+                .map_or_else(unknown, describe_tcb),
+            self.current_version,
+            self.committed_version.clone().unwrap_or_else(unknown)
+        );
+        for warning in &self.warnings {
+            out += &format!("warning: {}\n", warning);
+        }
+        out
+    }
+}
+
+impl output::Document for schema::SnpPlatformStatus {
+    fn table(&self) -> String {
+        format!(
+            "api version: {}.{}\nbuild: {}\nstate: {}\nrmp initialized: {}\n\
+             mask chip id: {}\nguests: {}\ncurrent tcb: {}\nreported tcb: {}\n",
+            self.api_major,
+            self.api_minor,
+            self.build_id,
+            self.state,
+            self.rmp_initialized,
+            self.mask_chip_id,
+            self.guests,
+            describe_tcb(&self.current_tcb),
+            describe_tcb(&self.reported_tcb)
+        )
+    }
+}
+
+impl output::Document for schema::IdBlock {
+    fn table(&self) -> String {
+        format!(
+            "id-block: {}\nid-auth: {}\nauthor-key-enabled: {}\n",
+            self.id_block,
+            self.id_auth,
+            if self.author_key_enabled { "on" } else { "off" }
+        )
+    }
+}
+
+/// The ID block, binding the launch digest to the identity of the guest image.
+struct IdBlock {
+    digest: Vec<u8>,
+    family_id: [u8; 16],
+    image_id: [u8; 16],
+    guest_svn: u32,
+    policy: u64,
+}
+
+/// The only defined version of the ID block.
+const ID_BLOCK_VERSION: u32 = 1;
+
+impl IdBlock {
+    fn encode(&self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(96);
+        block.extend_from_slice(&self.digest);
+        block.extend_from_slice(&self.family_id);
+        block.extend_from_slice(&self.image_id);
+        block.extend_from_slice(&ID_BLOCK_VERSION.to_le_bytes());
+        block.extend_from_slice(&self.guest_svn.to_le_bytes());
+        block.extend_from_slice(&self.policy.to_le_bytes());
+        block
+    }
+}
+
+/// ECDSA P-384 with SHA-384, the only algorithm defined for ID and author keys.
+const ALGO_ECDSA_P384_SHA384: u32 = 1;
+
+/// The curve identifier of P-384 in SNP public keys.
+const CURVE_P384: u32 = 2;
+
+/// The sizes of the signature and public key fields of the ID authentication information.
+const SIGNATURE_SIZE: usize = 0x200;
+const PUBLIC_KEY_SIZE: usize = 0x404;
+
+/// Components are stored little-endian in 72-byte fields.
+const COMPONENT_SIZE: usize = 72;
+
+/// Loads a PEM private key and checks it is on P-384.
+fn signing_key(path: &Path) -> Result<ec::EcKey<pkey::Private>> {
+    tracing::debug!(path = %path.display(), "reading file");
+    let pem = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+    let key = ec::EcKey::private_key_from_pem(&pem)
+        .context(format!("{} is not a PEM EC private key", path.display()))?;
+
+    if key.group().curve_name() != Some(nid::Nid::SECP384R1) {
+        return Err(error::Context::new(
+            &format!("{} is not a P-384 key", path.display()),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        ));
+    }
+
+    Ok(key)
+}
+
+fn component(n: &bn::BigNumRef) -> [u8; COMPONENT_SIZE] {
+    let mut out = [0u8; COMPONENT_SIZE];
+    for (o, b) in out.iter_mut().zip(n.to_vec().iter().rev()) {
+        *o = *b;
+    }
+    out
+}
+
+/// Encodes the public half of a key as an SNP public key structure.
+fn public_key(key: &ec::EcKey<pkey::Private>) -> Result<Vec<u8>> {
+    let mut ctx = bn::BigNumContext::new().context("unable to create BN context")?;
+    let mut x = bn::BigNum::new().context("unable to create BN")?;
+    let mut y = bn::BigNum::new().context("unable to create BN")?;
+    key.public_key()
+        .affine_coordinates_gfp(key.group(), &mut x, &mut y, &mut ctx)
+        .context("unable to get public key coordinates")?;
+
+    let mut out = Vec::with_capacity(PUBLIC_KEY_SIZE);
+    out.extend_from_slice(&CURVE_P384.to_le_bytes());
+    out.extend_from_slice(&component(&x));
+    out.extend_from_slice(&component(&y));
+    out.resize(PUBLIC_KEY_SIZE, 0);
+    Ok(out)
+}
+
+/// Signs a message as an SNP signature structure.
+fn sign(key: &ec::EcKey<pkey::Private>, msg: &[u8]) -> Result<Vec<u8>> {
+    let digest = sha384(msg)?;
+    let sig = ecdsa::EcdsaSig::sign(&digest, key).context("unable to sign")?;
+
+    let mut out = Vec::with_capacity(SIGNATURE_SIZE);
+    out.extend_from_slice(&component(sig.r()));
+    out.extend_from_slice(&component(sig.s()));
+    out.resize(SIGNATURE_SIZE, 0);
+    Ok(out)
+}
+
+/// Builds the 4 KiB ID authentication information structure.
+fn id_auth(
+    block: &[u8],
+    id_key: &ec::EcKey<pkey::Private>,
+    author_key: Option<&ec::EcKey<pkey::Private>>,
+) -> Result<Vec<u8>> {
+    let id_public = public_key(id_key)?;
+
+    let mut auth = Vec::with_capacity(4096);
+    auth.extend_from_slice(&ALGO_ECDSA_P384_SHA384.to_le_bytes());
+    auth.extend_from_slice(
+        &(if author_key.is_some() {
+            ALGO_ECDSA_P384_SHA384
+        } else {
+            0
+        })
+        .to_le_bytes(),
+    );
+    auth.resize(0x40, 0);
+    auth.extend_from_slice(&sign(id_key, block)?);
+    auth.extend_from_slice(&id_public);
+    auth.resize(0x680, 0);
+
+    if let Some(author_key) = author_key {
+        auth.extend_from_slice(&sign(author_key, &id_public)?);
+        auth.extend_from_slice(&public_key(author_key)?);
+    }
+
+    auth.resize(4096, 0);
+    Ok(auth)
+}
+
+/// The guest physical address the platform assigns to VMSA pages.
+const VMSA_GPA: u64 = 0xffff_ffff_f000;
+
+/// The page types of SNP_LAUNCH_UPDATE.
+const PAGE_TYPE_NORMAL: u8 = 1;
+const PAGE_TYPE_VMSA: u8 = 2;
+const PAGE_TYPE_ZERO: u8 = 3;
+const PAGE_TYPE_SECRETS: u8 = 5;
+const PAGE_TYPE_CPUID: u8 = 6;
+
+/// The SEV metadata section types OVMF asks the hypervisor to prepare.
+const SECTION_SNP_SEC_MEM: u32 = 1;
+const SECTION_SNP_SECRETS: u32 = 2;
+const SECTION_CPUID: u32 = 3;
+const SECTION_SNP_KERNEL_HASHES: u32 = 0x10;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The running launch digest.
+struct Gctx([u8; 48]);
+
+impl Gctx {
+    /// Extends the digest with the PAGE_INFO of a single page.
+    fn update(&mut self, page_type: u8, gpa: u64, contents: &[u8; 48]) -> Result<()> {
+        let mut info = Vec::with_capacity(0x70);
+        info.extend_from_slice(&self.0);
+        info.extend_from_slice(contents);
+        info.extend_from_slice(&0x70u16.to_le_bytes());
+        info.push(page_type);
+        info.extend_from_slice(&[0u8; 5]); // IMI page, VMPL permissions, reserved
+        info.extend_from_slice(&gpa.to_le_bytes());
+
+        self.0 = sha384(&info)?;
+        Ok(())
+    }
+
+    fn normal(&mut self, gpa: u64, data: &[u8]) -> Result<()> {
+        for (i, page) in data.chunks(PAGE_SIZE).enumerate() {
+            self.update(
+                PAGE_TYPE_NORMAL,
+                gpa + (i * PAGE_SIZE) as u64,
+                &sha384(page)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn empty(&mut self, page_type: u8, gpa: u64, size: u64) -> Result<()> {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            self.update(page_type, gpa + offset, &[0u8; 48])?;
+        }
+        Ok(())
+    }
+}
+
+fn sha384(data: &[u8]) -> Result<[u8; 48]> {
+    let mut out = [0u8; 48];
+    out.copy_from_slice(
+        &hash::hash(hash::MessageDigest::sha384(), data).context("unable to hash page")?,
+    );
+    Ok(out)
+}
+
+/// Computes the launch digest of an OVMF guest as QEMU launches it.
+fn launch_digest(
+    ovmf: &ovmf::Ovmf,
+    boot: Option<&DirectBoot>,
+    vcpus: &vcpu::VcpuArgs,
+) -> Result<[u8; 48]> {
+    let invalid =
+        |msg: &str| error::Context::new(msg, Box::<Error>::new(ErrorKind::InvalidInput.into()));
+
+    let sections = ovmf
+        .metadata()?
+        .ok_or_else(|| invalid("firmware does not support SEV-SNP (no SEV metadata)"))?;
+
+    // The kernel hashes table sits at its reserved offset within an otherwise empty page.
+    let hashes = match boot {
+        Some(boot) => {
+            let area = ovmf
+                .entry(ovmf::SEV_HASH_TABLE_RV)
+                .filter(|e| e.len() >= 4)
+                .ok_or_else(|| {
+                    invalid("firmware does not support measured direct boot (no hashes table area)")
+                })?;
+            let offset =
+                u32::from_le_bytes([area[0], area[1], area[2], area[3]]) as usize % PAGE_SIZE;
+
+            let table = boot.hashes_table()?;
+            if offset + table.len() > PAGE_SIZE {
+                return Err(invalid("kernel hashes table crosses a page boundary"));
+            }
+
+            let mut page = vec![0u8; PAGE_SIZE];
+            page[offset..offset + table.len()].copy_from_slice(&table);
+            Some(page)
+        }
+        None => None,
+    };
+
+    let mut gctx = Gctx([0u8; 48]);
+
+    // The firmware is mapped to end at 4 GiB.
+    let data = ovmf.data();
+    gctx.normal((1u64 << 32) - data.len() as u64, data)?;
+
+    let mut hashes_used = false;
+    for s in &sections {
+        let (gpa, size) = (s.gpa as u64, s.size as u64);
+        match s.kind {
+            SECTION_SNP_SEC_MEM => gctx.empty(PAGE_TYPE_ZERO, gpa, size)?,
+            SECTION_SNP_SECRETS => gctx.empty(PAGE_TYPE_SECRETS, gpa, size)?,
+            SECTION_CPUID => gctx.empty(PAGE_TYPE_CPUID, gpa, size)?,
+            SECTION_SNP_KERNEL_HASHES => match &hashes {
+                Some(page) => {
+                    gctx.normal(gpa, page)?;
+                    hashes_used = true;
+                }
+                None => gctx.empty(PAGE_TYPE_ZERO, gpa, size)?,
+            },
+            kind => {
+                return Err(invalid(&format!(
+                    "unknown SEV metadata section type {:#x}",
+                    kind
+                )))
+            }
+        }
+    }
+
+    if hashes.is_some() && !hashes_used {
+        return Err(invalid(
+            "firmware does not support measured direct boot (no kernel hashes section)",
+        ));
+    }
+
+    let features = parse_u64(&vcpus.sev_features)
+        .context(format!("invalid --sev-features: {}", vcpus.sev_features))?;
+
+    let mut vcpus = vcpus.clone();
+    vcpus.sev_features = format!("{:#x}", features | vmsa::SEV_FEATURES_SNP_ACTIVE);
+    if vcpus.ap_eip.is_none() {
+        vcpus.ap_eip = ovmf.ap_reset_eip().map(|eip| format!("{:#x}", eip));
+    }
+
+    for vmsa in vcpus.vmsas()? {
+        gctx.update(PAGE_TYPE_VMSA, VMSA_GPA, &sha384(vmsa.as_bytes())?)?;
+    }
+
+    Ok(gctx.0)
+}
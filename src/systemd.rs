@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration with systemd for the service modes: readiness notification and
+//! socket activation, both of which are no-ops when not run by systemd.
+
+use super::*;
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+
+/// The first file descriptor passed by socket activation.
+const LISTEN_FDS_START: libc::c_int = 3;
+
+/// Sends a state change (e.g. `READY=1`) to the service manager, if there is one.
+pub fn notify(state: &str) {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+
+    if let Err(e) = send(path.as_bytes(), state.as_bytes()) {
+        eprintln!("unable to notify systemd: {}", e);
+    }
+}
+
+fn send(path: &[u8], msg: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: sockaddr_un is plain data, for which all zeroes is valid.
+    let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    if path.is_empty() || path.len() >= addr.sun_path.len() {
+        return Err(Error::new(ErrorKind::InvalidInput, "invalid NOTIFY_SOCKET"));
+    }
+
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+
+    // A leading @ names a socket in the abstract namespace.
+    if path[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    let len = std::mem::size_of::<libc::sa_family_t>() + path.len();
+
+    // SAFETY: `addr` is a valid sockaddr_un of at least `len` bytes.
+    let ret = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            msg.as_ptr() as *const libc::c_void,
+            msg.len(),
+            libc::MSG_NOSIGNAL,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+
+    match ret {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Takes the listening socket passed by socket activation, if any.
+pub fn listener() -> Result<Option<TcpListener>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+
+    // The variables are meant for this process only, not for its children.
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    if pid.and_then(|p| p.parse().ok()) != Some(std::process::id()) {
+        return Ok(None);
+    }
+
+    match fds.as_deref() {
+        None | Some("0") => Ok(None),
+        Some("1") => {
+            // SAFETY: the fd is marked close-on-exec, which is valid for any fd.
+            unsafe { libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+            // SAFETY: systemd passed this socket to this process alone.
+            Ok(Some(unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) }))
+        }
+        Some(n) => Err(error::Context::new(
+            &format!("systemd passed {} sockets, expected one", n),
+            Box::<Error>::new(ErrorKind::InvalidInput.into()),
+        )),
+    }
+}
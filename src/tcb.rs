@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The TCB versions AMD recommends, compared with a platform's.
+//!
+//! AMD announces the TCB versions fixing the issues of each processor generation
+//! in its security bulletins and firmware releases. The table bundled when sevctl
+//! was built can be replaced with a newer one, from a file or a URL (e.g. of an
+//! internal mirror kept up to date).
+
+use super::*;
+use schema::CheckState;
+use serde::Deserialize;
+
+/// The minimums known when sevctl was built.
+const MINIMUMS: &str = include_str!("tcb-minimums.toml");
+
+/// A table of minimums.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Minimums {
+    #[serde(default)]
+    minimum: Vec<Minimum>,
+}
+
+/// The TCB version AMD recommends for a processor generation.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Minimum {
+    product: String,
+
+    /// The release or security bulletin it comes from.
+    source: String,
+
+    bootloader: u8,
+    tee: u8,
+    snp: u8,
+    microcode: u8,
+}
+
+impl Minimums {
+    /// Loads the table at `location`, a file or an HTTPS URL, or the bundled one.
+    fn load(location: Option<&str>) -> Result<Self> {
+        let (text, name) = match location {
+            Some(url) if url.starts_with("https://") || url.starts_with("http://") => {
+                let bytes = fetch(url, "TCB minimums")?;
+                let text = String::from_utf8(bytes).context(format!("invalid table {}", url))?;
+                (text, url.to_string())
+            }
+            Some(path) => {
+                tracing::debug!(path, "reading file");
+                let text =
+                    std::fs::read_to_string(path).context(format!("unable to read {}", path))?;
+                (text, path.to_string())
+            }
+            None => (MINIMUMS.to_string(), "bundled TCB minimums".to_string()),
+        };
+
+        toml::from_str(&text).context(format!("invalid TCB minimums {}", name))
+    }
+}
+
+/// Checks the versions of `status` against the minimum for `product`, printing a
+/// verdict per component and version, and failing if any is below.
+pub fn check(
+    location: Option<&str>,
+    product: kds::Product,
+    status: &schema::TcbStatus,
+) -> Result<()> {
+    let minimums = Minimums::load(location)?;
+    let product = product.to_string();
+    let minimum = minimums
+        .minimum
+        .iter()
+        .find(|m| m.product.eq_ignore_ascii_case(&product))
+        .ok_or_else(|| {
+            error::Context::new(
+                &format!("no TCB minimum is known for {}", product),
+                Box::new(Error::new(ErrorKind::NotFound, "give --minimums")),
+            )
+        })?;
+
+    let mut checks = Vec::new();
+    let versions = [
+        ("current", "Installed", Some(&status.current_tcb)),
+        ("reported", "Reported", Some(&status.reported_tcb)),
+        ("committed", "Committed", status.committed_tcb.as_ref()),
+    ];
+    for (id, which, tcb) in &versions {
+        let tcb = match tcb {
+            Some(tcb) => tcb,
+            None => continue,
+        };
+
+        for (component, svn, min) in &[
+            ("bootloader", tcb.bootloader, minimum.bootloader),
+            ("tee", tcb.tee, minimum.tee),
+            ("snp", tcb.snp, minimum.snp),
+            ("microcode", tcb.microcode, minimum.microcode),
+        ] {
+            checks.push(appraisal::result(
+                &format!("{}.{}", id, component),
+                &format!("{} {} SVN at least {}", which, component, min),
+                match svn >= min {
+                    true => Ok(svn.to_string()),
+                    false => Err(format!("{} is below {} ({})", svn, min, minimum.source)),
+                },
+            ));
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|c| c.state == CheckState::Fail)
+        .count();
+    let total = checks.len();
+    output::print(&schema::Checks { checks });
+
+    match failed {
+        0 => Ok(()),
+        n => Err(error::Context::new(
+            &format!(
+                "the TCB is below the minimum AMD recommends for {}",
+                product
+            ),
+            Box::new(Error::new(
+                ErrorKind::Other,
+                format!("{} of {} checks failed", n, total),
+            )),
+        )),
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `tracing` events and spans around firmware commands, file reads, network
+//! requests and verification steps: logged on stderr at the verbosity chosen with
+//! `-v`, and exported to an OpenTelemetry collector (OTLP over HTTP with JSON).
+//!
+//! Spans are collected in memory while the command runs and sent when it ends. A
+//! W3C `TRACEPARENT` in the environment makes them part of the caller's trace, so
+//! the steps of a pipeline spanning several hosts show up in one trace.
+
+use super::*;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The spans finished so far, if exporting is enabled.
+static FINISHED: Mutex<Option<Collected>> = Mutex::new(None);
+
+struct Collected {
+    endpoint: String,
+    spans: Vec<Value>,
+}
+
+/// Logs the events at `level` or more severe on stderr, and with an `endpoint`
+/// starts collecting spans for export to the OTLP collector there.
+///
+/// The events of dependencies (e.g. the HTTP client) are only logged at `TRACE`.
+pub fn init(level: Option<Level>, endpoint: Option<&str>) {
+    let log = level.map(|level| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_target(false)
+            .with_filter(filter_fn(move |metadata| {
+                *metadata.level() <= level && (level == Level::TRACE || ours(metadata.target()))
+            }))
+    });
+
+    let otlp = endpoint.map(|endpoint| {
+        let (trace_id, parent) = traceparent().unwrap_or_else(|| (random(), [0; 8]));
+
+        *FINISHED.lock().unwrap() = Some(Collected {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            spans: Vec::new(),
+        });
+
+        Otlp { trace_id, parent }
+    });
+
+    if log.is_none() && otlp.is_none() {
+        return;
+    }
+
+    let subscriber = tracing_subscriber::registry().with(log).with(otlp);
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        eprintln!("unable to install the tracing subscriber");
+    }
+}
+
+/// How many finished spans are held before they are sent, for the service modes.
+const MAX_PENDING: usize = 512;
+
+/// Sends the collected spans, if exporting is enabled.
+pub fn flush() {
+    if let Some(collected) = FINISHED.lock().unwrap().take() {
+        send(collected);
+    }
+}
+
+fn send(collected: Collected) {
+    if collected.spans.is_empty() {
+        return;
+    }
+
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", "sevctl"),
+                    attribute("service.version", VERSION),
+                    attribute("host.name", host.trim()),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "sevctl", "version": VERSION },
+                "spans": collected.spans,
+            }],
+        }],
+    });
+
+    let url = format!("{}/v1/traces", collected.endpoint);
+    if let Err(e) = post(&url, &body) {
+        eprintln!("unable to export traces to {}: {}", url, e);
+    }
+}
+
+#[cfg(feature = "network")]
+fn post(url: &str, body: &Value) -> std::result::Result<(), reqwest::Error> {
+    reqwest::blocking::Client::new()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .and_then(|r| r.error_for_status())
+        .map(|_| ())
+}
+
+#[cfg(not(feature = "network"))]
+fn post(_url: &str, _body: &Value) -> std::result::Result<(), &'static str> {
+    Err("sevctl was built without network support")
+}
+
+/// Reads the trace ID and parent span ID from a W3C `TRACEPARENT`.
+fn traceparent() -> Option<([u8; 16], [u8; 8])> {
+    let value = std::env::var("TRACEPARENT").ok()?;
+    let mut parts = value.trim().split('-');
+    let (_version, trace, parent) = (parts.next()?, parts.next()?, parts.next()?);
+
+    let mut ids = ([0u8; 16], [0u8; 8]);
+    ids.0
+        .copy_from_slice(&unhex(trace).ok().filter(|t| t.len() == 16)?);
+    ids.1
+        .copy_from_slice(&unhex(parent).ok().filter(|p| p.len() == 8)?);
+    Some(ids)
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    openssl::rand::rand_bytes(&mut buf).expect("unable to generate random bytes");
+    buf
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+/// A span being recorded.
+struct Span {
+    id: [u8; 8],
+    start: SystemTime,
+    attributes: Vec<Value>,
+    events: Vec<Value>,
+    failed: bool,
+}
+
+/// Collects spans in the OTLP JSON encoding.
+struct Otlp {
+    trace_id: [u8; 16],
+
+    /// The span the root spans are children of, all zeroes if none.
+    parent: [u8; 8],
+}
+
+impl<S> Layer<S> for Otlp
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // Spans of dependencies (e.g. the HTTP client's internals) are left out.
+        if !ours(attrs.metadata().target()) {
+            return;
+        }
+
+        let mut span = Span {
+            id: random(),
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            events: Vec::new(),
+            failed: false,
+        };
+        attrs.record(&mut Fields(&mut span.attributes));
+
+        if let Some(entry) = ctx.span(id) {
+            entry.extensions_mut().insert(span);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(entry) = ctx.span(id) {
+            if let Some(span) = entry.extensions_mut().get_mut::<Span>() {
+                values.record(&mut Fields(&mut span.attributes));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !ours(event.metadata().target()) {
+            return;
+        }
+
+        let entry = match ctx.event_span(event) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let mut extensions = entry.extensions_mut();
+        let span = match extensions.get_mut::<Span>() {
+            Some(span) => span,
+            None => return,
+        };
+
+        let mut attributes = Vec::new();
+        event.record(&mut Fields(&mut attributes));
+
+        // Events are named by their message, if they have one.
+        let level = *event.metadata().level();
+        let name = match attributes.iter().position(|a| a["key"] == "message") {
+            Some(i) => attributes.remove(i)["value"]["stringValue"].clone(),
+            None => Value::from(level.as_str().to_lowercase()),
+        };
+
+        span.failed |= level == Level::ERROR;
+        span.events.push(json!({
+            "timeUnixNano": nanos(SystemTime::now()),
+            "name": name,
+            "attributes": attributes,
+        }));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let entry = match ctx.span(&id) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let span = match entry.extensions_mut().remove::<Span>() {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = entry
+            .parent()
+            .and_then(|p| p.extensions().get::<Span>().map(|s| s.id))
+            .unwrap_or(self.parent);
+
+        let mut value = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&span.id),
+            "name": entry.name(),
+            "kind": 1,
+            "startTimeUnixNano": nanos(span.start),
+            "endTimeUnixNano": nanos(SystemTime::now()),
+            "attributes": span.attributes,
+            "events": span.events,
+            "status": { "code": if span.failed { 2 } else { 1 } },
+        });
+        if parent != [0; 8] {
+            value["parentSpanId"] = Value::from(hex(&parent));
+        }
+
+        let mut finished = FINISHED.lock().unwrap();
+        if let Some(collected) = finished.as_mut() {
+            collected.spans.push(value);
+
+            if collected.spans.len() >= MAX_PENDING {
+                let batch = Collected {
+                    endpoint: collected.endpoint.clone(),
+                    spans: std::mem::take(&mut collected.spans),
+                };
+                std::thread::spawn(move || send(batch));
+            }
+        }
+    }
+}
+
+fn ours(target: &str) -> bool {
+    target == "sevctl" || target.starts_with("sevctl::")
+}
+
+/// Records fields as OTLP attributes.
+struct Fields<'a>(&'a mut Vec<Value>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push(attribute(field.name(), value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .push(attribute(field.name(), &format!("{:?}", value)));
+    }
+}
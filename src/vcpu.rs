@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Known vCPU models and the initial register state derived from them.
+
+use super::*;
+
+/// A vCPU model as (names, family, model, stepping).
+///
+/// The names are the QEMU `-cpu` models followed by the AMD code names.
+const MODELS: &[(&[&str], u32, u32, u32)] = &[
+    (
+        &[
+            "EPYC",
+            "EPYC-v1",
+            "EPYC-v2",
+            "EPYC-v3",
+            "EPYC-v4",
+            "EPYC-IBPB",
+            "naples",
+        ],
+        23,
+        1,
+        2,
+    ),
+    (
+        &["EPYC-Rome", "EPYC-Rome-v1", "EPYC-Rome-v2", "rome"],
+        23,
+        49,
+        0,
+    ),
+    (
+        &["EPYC-Milan", "EPYC-Milan-v1", "EPYC-Milan-v2", "milan"],
+        25,
+        1,
+        1,
+    ),
+    (&["EPYC-Genoa", "EPYC-Genoa-v1", "genoa"], 25, 17, 0),
+];
+
+/// Encodes a CPUID function 1 EAX value, splitting out the extended family and model.
+pub fn signature(family: u32, model: u32, stepping: u32) -> u32 {
+    let (family_low, family_high) = if family > 0xf {
+        (0xf, family - 0xf)
+    } else {
+        (family, 0)
+    };
+
+    (family_high << 20) | ((model >> 4) << 16) | (family_low << 8) | ((model & 0xf) << 4) | stepping
+}
+
+/// The canonical names of the known models.
+pub fn types() -> impl Iterator<Item = &'static str> {
+    MODELS.iter().map(|(names, ..)| names[0])
+}
+
+/// Looks up a model by name, ignoring case.
+fn model(name: &str) -> Option<(u32, u32, u32)> {
+    MODELS
+        .iter()
+        .find(|(names, ..)| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+        .map(|(_, family, model, stepping)| (*family, *model, *stepping))
+}
+
+/// The vCPU configuration of an SEV-ES guest.
+#[derive(StructOpt, Clone)]
+pub struct VcpuArgs {
+    #[structopt(long, default_value = "1", help = "Number of vCPUs (SEV-ES)")]
+    pub num_cpus: u32,
+
+    #[structopt(
+        long,
+        help = "vCPU model (a QEMU -cpu model such as EPYC-Milan, or naples, rome, milan, genoa)"
+    )]
+    pub vcpu_type: Option<String>,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["vcpu-family", "vcpu-model", "vcpu-stepping"],
+        help = "vCPU signature (CPUID function 1 EAX), overriding --vcpu-type"
+    )]
+    pub vcpu_sig: Option<String>,
+
+    #[structopt(long, help = "vCPU family, overriding --vcpu-type")]
+    pub vcpu_family: Option<u32>,
+
+    #[structopt(long, help = "vCPU model number, overriding --vcpu-type")]
+    pub vcpu_model: Option<u32>,
+
+    #[structopt(long, help = "vCPU stepping, overriding --vcpu-type")]
+    pub vcpu_stepping: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Reset vector of the application processors (SEV-ES), defaults to the firmware's"
+    )]
+    pub ap_eip: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "0",
+        help = "SEV_FEATURES of the generated VMSAs (SEV-ES)"
+    )]
+    pub sev_features: String,
+}
+
+impl VcpuArgs {
+    /// Resolves the vCPU signature from the model and its overrides.
+    pub fn signature(&self) -> Result<u32> {
+        if let Some(sig) = &self.vcpu_sig {
+            let sig = parse_u64(sig).context(format!("invalid --vcpu-sig: {}", sig))?;
+            if sig > u32::MAX as u64 {
+                return Err(invalid("--vcpu-sig does not fit in 32 bits"));
+            }
+            return Ok(sig as u32);
+        }
+
+        let base = match &self.vcpu_type {
+            Some(name) => Some(model(name).ok_or_else(|| {
+                let known: Vec<&str> = MODELS
+                    .iter()
+                    .flat_map(|(n, ..)| n.iter().cloned())
+                    .collect();
+                invalid(&format!(
+                    "unknown vCPU type {} (known types: {})",
+                    name,
+                    known.join(", ")
+                ))
+            })?),
+            None => None,
+        };
+
+        let family = self.vcpu_family.or_else(|| base.map(|b| b.0));
+        let model = self.vcpu_model.or_else(|| base.map(|b| b.1));
+        let stepping = self.vcpu_stepping.or_else(|| base.map(|b| b.2));
+
+        match (family, model, stepping) {
+            (Some(family), Some(model), Some(stepping)) => {
+                if family > 0xff + 0xf || model > 0xff || stepping > 0xf {
+                    return Err(invalid("vCPU family, model or stepping out of range"));
+                }
+                Ok(signature(family, model, stepping))
+            }
+            _ => Err(invalid(
+                "one of --vcpu-type, --vcpu-sig or --vcpu-family/--vcpu-model/--vcpu-stepping is required",
+            )),
+        }
+    }
+
+    /// Generates the initial VMSA of every vCPU, bootstrap processor first.
+    pub fn vmsas(&self) -> Result<Vec<vmsa::Vmsa>> {
+        let vcpu_sig = self.signature()?;
+        let sev_features = parse_u64(&self.sev_features)
+            .context(format!("invalid --sev-features: {}", self.sev_features))?;
+
+        let mut vmsas = vec![vmsa::Vmsa::reset(vmsa::BSP_EIP, vcpu_sig, sev_features)];
+        if self.num_cpus > 1 {
+            let ap_eip = match &self.ap_eip {
+                Some(eip) => parse_u64(eip).context(format!("invalid --ap-eip: {}", eip))? as u32,
+                None => {
+                    return Err(invalid(
+                        "--ap-eip is required for SEV-ES guests with more than one vCPU \
+                         when the firmware declares no AP reset vector",
+                    ))
+                }
+            };
+
+            for _ in 1..self.num_cpus {
+                vmsas.push(vmsa::Vmsa::reset(ap_eip, vcpu_sig, sev_features));
+            }
+        }
+
+        Ok(vmsas)
+    }
+}
+
+fn invalid(msg: &str) -> error::Context {
+    error::Context::new(msg, Box::<Error>::new(ErrorKind::InvalidInput.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(argv: &[&str]) -> VcpuArgs {
+        VcpuArgs::from_iter_safe(std::iter::once("vcpu").chain(argv.iter().cloned())).unwrap()
+    }
+
+    #[test]
+    fn signatures() {
+        for (name, sig) in &[
+            ("EPYC", 0x0080_0f12),
+            ("naples", 0x0080_0f12),
+            ("EPYC-Rome", 0x0083_0f10),
+            ("rome", 0x0083_0f10),
+            ("EPYC-Milan-v2", 0x00a0_0f11),
+            ("Milan", 0x00a0_0f11),
+            ("EPYC-Genoa", 0x00a1_0f10),
+            ("GENOA", 0x00a1_0f10),
+        ] {
+            let signature = args(&["--vcpu-type", name]).signature().unwrap();
+            assert_eq!(signature, *sig, "{}", name);
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        let sig = args(&["--vcpu-type", "milan", "--vcpu-stepping", "0"]).signature();
+        assert_eq!(sig.unwrap(), 0x00a0_0f10);
+
+        // Without a model to fill them in, all three numbers are needed.
+        let sig = args(&["--vcpu-family", "6", "--vcpu-model", "42"]).signature();
+        assert!(sig.is_err());
+
+        let sig = args(&[
+            "--vcpu-family",
+            "6",
+            "--vcpu-model",
+            "42",
+            "--vcpu-stepping",
+            "7",
+        ]);
+        assert_eq!(sig.signature().unwrap(), 0x0002_06a7);
+
+        let sig = args(&["--vcpu-sig", "0x800f12"]).signature();
+        assert_eq!(sig.unwrap(), 0x0080_0f12);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(args(&["--vcpu-type", "EPYC-Turin"]).signature().is_err());
+        assert!(args(&["--vcpu-type", "rome", "--vcpu-stepping", "16"])
+            .signature()
+            .is_err());
+        assert!(args(&["--vcpu-sig", "0x100000000"]).signature().is_err());
+        assert!(args(&[]).signature().is_err());
+    }
+}
@@ -0,0 +1,743 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The VM save area (VMSA) holding an SEV-ES/SEV-SNP vCPU's encrypted register state.
+
+use super::*;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// The VMSA occupies a single page.
+pub const VMSA_SIZE: usize = 4096;
+
+/// The reset vector of the bootstrap processor.
+pub const BSP_EIP: u32 = 0xffff_fff0;
+
+/// The SEV_FEATURES bit marking a VMSA as belonging to an SNP guest.
+pub const SEV_FEATURES_SNP_ACTIVE: u64 = 1;
+
+#[derive(StructOpt)]
+pub enum VmsaCmd {
+    #[structopt(about = "Build the initial VMSA of a vCPU")]
+    Build {
+        #[structopt(long, help = "Build the VMSA of an SEV-SNP guest")]
+        snp: bool,
+
+        #[structopt(
+            long,
+            help = "Build the VMSA of an application processor rather than the bootstrap processor"
+        )]
+        ap: bool,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "OVMF firmware image declaring the application processors' reset vector"
+        )]
+        firmware: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "TOML profile of register values overriding the reset state"
+        )]
+        profile: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with_all = &["snp", "ap", "firmware", "profile"],
+            help = "Build the VMSA from the fields in a JSON file (as written by vmsa show --format json)"
+        )]
+        from_json: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        vcpus: vcpu::VcpuArgs,
+
+        #[structopt(parse(from_os_str), help = "Path to write the VMSA to")]
+        output: PathBuf,
+    },
+
+    #[structopt(about = "Build the initial VMSAs of all vCPUs, in launch order")]
+    BuildSet {
+        #[structopt(long, help = "Build the VMSAs of an SEV-SNP guest")]
+        snp: bool,
+
+        #[structopt(
+            long,
+            parse(from_os_str),
+            help = "OVMF firmware image declaring the application processors' reset vector"
+        )]
+        firmware: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        vcpus: vcpu::VcpuArgs,
+
+        #[structopt(parse(from_os_str), help = "Directory to write the VMSAs to")]
+        dir: PathBuf,
+    },
+
+    #[structopt(about = "Change individual fields of a VMSA")]
+    Update {
+        #[structopt(
+            long = "field",
+            number_of_values = 1,
+            required = true,
+            help = "Field to set, as <name>=<value> (e.g. rip=0xfff0 or cs.base=0xffff0000)"
+        )]
+        fields: Vec<String>,
+
+        #[structopt(parse(from_os_str), help = "Path to the VMSA, updated in place")]
+        file: PathBuf,
+    },
+
+    #[structopt(about = "List the fields in which two VMSAs differ")]
+    Diff {
+        #[structopt(parse(from_os_str), help = "Path to the first VMSA")]
+        a: PathBuf,
+
+        #[structopt(parse(from_os_str), help = "Path to the second VMSA")]
+        b: PathBuf,
+    },
+
+    #[structopt(about = "Display the fields of a VMSA")]
+    Show {
+        #[structopt(
+            long,
+            default_value = "text",
+            possible_values = &["text", "json"],
+            help = "Output format: text or json"
+        )]
+        format: Format,
+
+        #[structopt(parse(from_os_str), help = "Path to the VMSA")]
+        file: PathBuf,
+    },
+}
+
+/// The output format of `vmsa show`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        Ok(match s {
+            "text" => Format::Text,
+            "json" => Format::Json,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown format {} (expected text or json)", s),
+                ))
+            }
+        })
+    }
+}
+
+pub fn cmd(cmd: VmsaCmd) -> Result<()> {
+    match cmd {
+        VmsaCmd::Build {
+            snp,
+            ap,
+            firmware,
+            profile,
+            from_json,
+            mut vcpus,
+            output,
+        } => {
+            if let Some(json) = from_json {
+                return Vmsa::from_json(&json)?.store(&output);
+            }
+
+            prepare(snp, firmware.as_deref(), &mut vcpus)?;
+            vcpus.num_cpus = if ap { 2 } else { 1 };
+            let mut vmsa = vcpus.vmsas()?.pop().unwrap();
+            if let Some(profile) = profile {
+                vmsa.apply_profile(&profile)?;
+            }
+
+            vmsa.store(&output)
+        }
+
+        VmsaCmd::BuildSet {
+            snp,
+            firmware,
+            mut vcpus,
+            dir,
+        } => {
+            prepare(snp, firmware.as_deref(), &mut vcpus)?;
+            let vmsas = vcpus.vmsas()?;
+
+            std::fs::create_dir_all(&dir).context(format!("unable to create {}", dir.display()))?;
+
+            // Pad the index so that the files sort in launch order.
+            let width = (vmsas.len() - 1).to_string().len();
+            let mut files = Vec::new();
+            for (i, vmsa) in vmsas.iter().enumerate() {
+                let path = dir.join(format!("vcpu{:0width$}.vmsa", i, width = width));
+                vmsa.store(&path)?;
+                files.push(path);
+            }
+
+            output::print(&schema::VmsaBuildSet { files });
+            Ok(())
+        }
+
+        VmsaCmd::Update { fields, file } => {
+            let mut vmsa = Vmsa::load(&file)?;
+            for field in &fields {
+                vmsa.assign(field)?;
+            }
+            vmsa.store(&file)
+        }
+
+        VmsaCmd::Diff { a, b } => {
+            let (a, b) = (Vmsa::load(&a)?, Vmsa::load(&b)?);
+            let mut differences = Vec::new();
+
+            for (name, off, size) in fields() {
+                let (x, y) = (a.get(&name).unwrap(), b.get(&name).unwrap());
+                if x != y {
+                    let hex = |v| format!("{:#0width$x}", v, width = size * 2 + 2);
+                    differences.push(schema::VmsaDifference {
+                        field: name,
+                        offset: off,
+                        a: hex(x),
+                        b: hex(y),
+                    });
+                }
+            }
+
+            // Bytes outside the named fields are reserved, but still measured.
+            let named = fields();
+            for (off, (x, y)) in a.0.iter().zip(b.0.iter()).enumerate() {
+                if x != y && !named.iter().any(|(_, o, s)| (*o..o + s).contains(&off)) {
+                    differences.push(schema::VmsaDifference {
+                        field: "reserved".to_string(),
+                        offset: off,
+                        a: format!("{:#04x}", x),
+                        b: format!("{:#04x}", y),
+                    });
+                }
+            }
+
+            let differ = !differences.is_empty();
+            output::print(&schema::VmsaDiff { differences });
+            if differ {
+                return Err(error::Context::new(
+                    "VMSAs differ",
+                    Box::<Error>::new(ErrorKind::InvalidData.into()),
+                ));
+            }
+
+            Ok(())
+        }
+
+        VmsaCmd::Show { format, file } => {
+            // --format json predates --output, and is kept as its alias.
+            if format == Format::Json {
+                output::init(output::Format::Json);
+            }
+
+            output::print(&Vmsa::load(&file)?.document());
+            Ok(())
+        }
+    }
+}
+
+impl output::Document for schema::Vmsa {
+    fn table(&self) -> String {
+        // The fields are those of document(), so they are all present and in hex.
+        let get = |name: &str| u64::from_str_radix(&self.fields[name][2..], 16).unwrap();
+        let mut out = String::new();
+
+        for (seg, _) in SEGMENTS {
+            let get = |sub: &str| get(&format!("{}.{}", seg, sub));
+            out += &format!(
+                "{:<6} selector {:#06x} attrib {:#06x} limit {:#010x} base {:#018x}\n",
+                seg,
+                get("selector"),
+                get("attrib"),
+                get("limit"),
+                get("base")
+            );
+        }
+
+        for (name, _, size) in REGISTERS {
+            out += &format!(
+                "{:<20} {:#0width$x}\n",
+                name,
+                get(name),
+                width = size * 2 + 2
+            );
+        }
+
+        let features = get("sev_features");
+        for (bit, name) in SEV_FEATURES {
+            if features & (1 << bit) != 0 {
+                out += &format!("sev_features: {}\n", name);
+            }
+        }
+
+        let known = SEV_FEATURES.iter().fold(0u64, |m, (bit, _)| m | 1 << bit);
+        if features & !known != 0 {
+            out += &format!("sev_features: unknown bits {:#x}\n", features & !known);
+        }
+
+        out
+    }
+}
+
+impl output::Document for schema::VmsaDiff {
+    fn table(&self) -> String {
+        if self.differences.is_empty() {
+            return "VMSAs are identical\n".to_string();
+        }
+
+        let mut out = String::new();
+        for d in &self.differences {
+            out += &format!(
+                "{:<20} {} != {} (offset {:#05x})\n",
+                d.field, d.a, d.b, d.offset
+            );
+        }
+        out
+    }
+
+    fn csv(&self) -> String {
+        let rows = self
+            .differences
+            .iter()
+            .map(|d| {
+                vec![
+                    d.field.clone(),
+                    d.offset.to_string(),
+                    d.a.clone(),
+                    d.b.clone(),
+                ]
+            })
+            .collect();
+        output::csv(&["field", "offset", "a", "b"], rows)
+    }
+}
+
+impl output::Document for schema::VmsaBuildSet {
+    fn table(&self) -> String {
+        self.files
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect()
+    }
+
+    fn csv(&self) -> String {
+        let rows = self
+            .files
+            .iter()
+            .map(|path| vec![path.display().to_string()])
+            .collect();
+        output::csv(&["file"], rows)
+    }
+}
+
+/// Applies the options shared by the build commands to the vCPU configuration.
+fn prepare(snp: bool, firmware: Option<&Path>, vcpus: &mut vcpu::VcpuArgs) -> Result<()> {
+    if snp {
+        let features = parse_u64(&vcpus.sev_features)
+            .context(format!("invalid --sev-features: {}", vcpus.sev_features))?;
+        vcpus.sev_features = format!("{:#x}", features | SEV_FEATURES_SNP_ACTIVE);
+    }
+
+    if vcpus.ap_eip.is_none() {
+        if let Some(firmware) = firmware {
+            let eip = ovmf::Ovmf::load(firmware)?.ap_reset_eip();
+            vcpus.ap_eip = eip.map(|eip| format!("{:#x}", eip));
+        }
+    }
+
+    Ok(())
+}
+
+/// The bits of SEV_FEATURES.
+const SEV_FEATURES: &[(u32, &str)] = &[
+    (0, "SNPActive"),
+    (1, "vTOM"),
+    (2, "ReflectVC"),
+    (3, "RestrictedInjection"),
+    (4, "AlternateInjection"),
+    (5, "DebugSwap"),
+    (6, "PreventHostIBS"),
+    (7, "BTBIsolation"),
+    (8, "VmplSSS"),
+    (9, "SecureTSC"),
+    (10, "VmgexitParameter"),
+    (12, "IbsVirtualization"),
+    (14, "VmsaRegProt"),
+    (15, "SmtProtection"),
+];
+
+/// The segment registers, each a selector, attributes, limit and base.
+const SEGMENTS: &[(&str, usize)] = &[
+    ("es", 0x000),
+    ("cs", 0x010),
+    ("ss", 0x020),
+    ("ds", 0x030),
+    ("fs", 0x040),
+    ("gs", 0x050),
+    ("gdtr", 0x060),
+    ("ldtr", 0x070),
+    ("idtr", 0x080),
+    ("tr", 0x090),
+];
+
+/// The layout of a segment register.
+const SEGMENT_FIELDS: &[(&str, usize, usize)] = &[
+    ("selector", 0x0, 2),
+    ("attrib", 0x2, 2),
+    ("limit", 0x4, 4),
+    ("base", 0x8, 8),
+];
+
+/// The remaining scalar fields as (name, offset, size).
+const REGISTERS: &[(&str, usize, usize)] = &[
+    ("vmpl0_ssp", 0x0a0, 8),
+    ("vmpl1_ssp", 0x0a8, 8),
+    ("vmpl2_ssp", 0x0b0, 8),
+    ("vmpl3_ssp", 0x0b8, 8),
+    ("u_cet", 0x0c0, 8),
+    ("vmpl", 0x0ca, 1),
+    ("cpl", 0x0cb, 1),
+    ("efer", 0x0d0, 8),
+    ("xss", 0x140, 8),
+    ("cr4", 0x148, 8),
+    ("cr3", 0x150, 8),
+    ("cr0", 0x158, 8),
+    ("dr7", 0x160, 8),
+    ("dr6", 0x168, 8),
+    ("rflags", 0x170, 8),
+    ("rip", 0x178, 8),
+    ("dr0", 0x180, 8),
+    ("dr1", 0x188, 8),
+    ("dr2", 0x190, 8),
+    ("dr3", 0x198, 8),
+    ("dr0_addr_mask", 0x1a0, 8),
+    ("dr1_addr_mask", 0x1a8, 8),
+    ("dr2_addr_mask", 0x1b0, 8),
+    ("dr3_addr_mask", 0x1b8, 8),
+    ("rsp", 0x1d8, 8),
+    ("s_cet", 0x1e0, 8),
+    ("ssp", 0x1e8, 8),
+    ("isst_addr", 0x1f0, 8),
+    ("rax", 0x1f8, 8),
+    ("star", 0x200, 8),
+    ("lstar", 0x208, 8),
+    ("cstar", 0x210, 8),
+    ("sfmask", 0x218, 8),
+    ("kernel_gs_base", 0x220, 8),
+    ("sysenter_cs", 0x228, 8),
+    ("sysenter_esp", 0x230, 8),
+    ("sysenter_eip", 0x238, 8),
+    ("cr2", 0x240, 8),
+    ("g_pat", 0x268, 8),
+    ("dbgctl", 0x270, 8),
+    ("br_from", 0x278, 8),
+    ("br_to", 0x280, 8),
+    ("last_excp_from", 0x288, 8),
+    ("last_excp_to", 0x290, 8),
+    ("pkru", 0x2e8, 4),
+    ("tsc_aux", 0x2ec, 4),
+    ("rcx", 0x308, 8),
+    ("rdx", 0x310, 8),
+    ("rbx", 0x318, 8),
+    ("rbp", 0x328, 8),
+    ("rsi", 0x330, 8),
+    ("rdi", 0x338, 8),
+    ("r8", 0x340, 8),
+    ("r9", 0x348, 8),
+    ("r10", 0x350, 8),
+    ("r11", 0x358, 8),
+    ("r12", 0x360, 8),
+    ("r13", 0x368, 8),
+    ("r14", 0x370, 8),
+    ("r15", 0x378, 8),
+    ("guest_exit_info_1", 0x390, 8),
+    ("guest_exit_info_2", 0x398, 8),
+    ("guest_exit_int_info", 0x3a0, 8),
+    ("guest_nrip", 0x3a8, 8),
+    ("sev_features", 0x3b0, 8),
+    ("vintr_ctrl", 0x3b8, 8),
+    ("guest_exit_code", 0x3c0, 8),
+    ("virtual_tom", 0x3c8, 8),
+    ("tlb_id", 0x3d0, 8),
+    ("pcpu_id", 0x3d8, 8),
+    ("event_inj", 0x3e0, 8),
+    ("xcr0", 0x3e8, 8),
+    ("x87_dp", 0x400, 8),
+    ("mxcsr", 0x408, 4),
+    ("x87_ftw", 0x40c, 2),
+    ("x87_fsw", 0x40e, 2),
+    ("x87_fcw", 0x410, 2),
+    ("x87_fop", 0x412, 2),
+    ("x87_ds", 0x414, 2),
+    ("x87_cs", 0x416, 2),
+    ("x87_rip", 0x418, 8),
+];
+
+/// Looks up the offset and size of a named field (e.g. `rip` or `cs.base`).
+fn field(name: &str) -> Option<(usize, usize)> {
+    if let Some(dot) = name.find('.') {
+        let (seg, sub) = (&name[..dot], &name[dot + 1..]);
+        let base = SEGMENTS.iter().find(|(n, _)| *n == seg)?.1;
+        let (_, off, size) = SEGMENT_FIELDS.iter().find(|(n, _, _)| *n == sub)?;
+        return Some((base + off, *size));
+    }
+
+    REGISTERS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, off, size)| (*off, *size))
+}
+
+/// Every named field as (name, offset, size), in layout order.
+fn fields() -> Vec<(String, usize, usize)> {
+    let segments = SEGMENTS.iter().flat_map(|(seg, base)| {
+        SEGMENT_FIELDS
+            .iter()
+            .map(move |(sub, off, size)| (format!("{}.{}", seg, sub), base + off, *size))
+    });
+
+    let registers = REGISTERS
+        .iter()
+        .map(|(name, off, size)| (name.to_string(), *off, *size));
+
+    segments.chain(registers).collect()
+}
+
+/// Collects the leaves of a TOML table under their dotted names.
+fn flatten<'a>(prefix: String, value: &'a toml::Value, out: &mut Vec<(String, &'a toml::Value)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let name = match prefix.as_str() {
+                    "" => key.clone(),
+                    _ => format!("{}.{}", prefix, key),
+                };
+                flatten(name, value, out);
+            }
+        }
+        _ => out.push((prefix, value)),
+    }
+}
+
+/// A VMSA page.
+pub struct Vmsa(Vec<u8>);
+
+impl Vmsa {
+    /// The reset state QEMU/KVM gives a vCPU starting at `eip`.
+    pub fn reset(eip: u32, vcpu_sig: u32, sev_features: u64) -> Self {
+        let mut vmsa = Self(vec![0u8; VMSA_SIZE]);
+
+        for (seg, attrib) in &[
+            ("es", 0x93),
+            ("cs", 0x9b),
+            ("ss", 0x93),
+            ("ds", 0x93),
+            ("fs", 0x93),
+            ("gs", 0x93),
+            ("gdtr", 0x00),
+            ("ldtr", 0x82),
+            ("idtr", 0x00),
+            ("tr", 0x8b),
+        ] {
+            vmsa.put(&format!("{}.attrib", seg), *attrib);
+            vmsa.put(&format!("{}.limit", seg), 0xffff);
+        }
+
+        vmsa.put("cs.selector", 0xf000);
+        vmsa.put("cs.base", (eip & 0xffff_0000) as u64);
+        vmsa.put("rip", (eip & 0xffff) as u64);
+
+        vmsa.put("efer", 0x1000); // EFER.SVME
+        vmsa.put("cr4", 0x40); // CR4.MCE
+        vmsa.put("cr0", 0x10);
+        vmsa.put("dr7", 0x400);
+        vmsa.put("dr6", 0xffff_0ff0);
+        vmsa.put("rflags", 0x2);
+        vmsa.put("g_pat", 0x0007_0406_0007_0406);
+        vmsa.put("rdx", vcpu_sig as u64);
+        vmsa.put("sev_features", sev_features);
+        vmsa.put("xcr0", 0x1);
+        vmsa.put("mxcsr", 0x1f80);
+        vmsa.put("x87_fcw", 0x37f);
+
+        vmsa
+    }
+
+    /// Reads a VMSA page from a file.
+    pub fn load(path: &Path) -> Result<Self> {
+        tracing::debug!(path = %path.display(), "reading file");
+        let data = std::fs::read(path).context(format!("unable to read {}", path.display()))?;
+        if data.len() != VMSA_SIZE {
+            return Err(error::Context::new(
+                &format!(
+                    "{} is {} bytes, expected a {}-byte VMSA",
+                    path.display(),
+                    data.len(),
+                    VMSA_SIZE
+                ),
+                Box::<Error>::new(ErrorKind::InvalidData.into()),
+            ));
+        }
+
+        Ok(Self(data))
+    }
+
+    /// Writes the VMSA page to a file.
+    pub fn store(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, &self.0).context(format!("unable to write {}", path.display()))
+    }
+
+    /// Applies a `<name>=<value>` assignment.
+    pub fn assign(&mut self, assignment: &str) -> Result<()> {
+        let eq = assignment.find('=').ok_or_else(|| {
+            error::Context::new(
+                &format!("invalid field {}, expected <name>=<value>", assignment),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            )
+        })?;
+
+        let (name, value) = (assignment[..eq].trim(), assignment[eq + 1..].trim());
+        let value = parse_u64(value).context(format!("invalid value for {}: {}", name, value))?;
+        self.set(name, value)
+    }
+
+    /// Applies a TOML profile of field values, e.g.
+    ///
+    /// ```toml
+    /// rip = 0x1000
+    /// cs.base = 0
+    /// rsi = "0xffff_ffff_0000_0000"
+    /// ```
+    ///
+    /// Values beyond the range of TOML integers can be given as strings.
+    pub fn apply_profile(&mut self, path: &Path) -> Result<()> {
+        let text =
+            std::fs::read_to_string(path).context(format!("unable to read {}", path.display()))?;
+        let profile: toml::Value = text
+            .parse()
+            .context(format!("invalid profile {}", path.display()))?;
+
+        let mut fields = Vec::new();
+        flatten(String::new(), &profile, &mut fields);
+
+        for (name, value) in fields {
+            let value = match value {
+                toml::Value::Integer(v) if *v >= 0 => *v as u64,
+                toml::Value::String(v) => {
+                    parse_u64(v).context(format!("invalid value for {}: {}", name, v))?
+                }
+                v => {
+                    return Err(error::Context::new(
+                        &format!("invalid value for {}: {}", name, v),
+                        Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                    ))
+                }
+            };
+
+            self.set(&name, value)
+                .context(format!("invalid profile {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds a VMSA from a JSON object mapping field names to values, leaving the
+    /// remaining fields zero. Values can be numbers or strings such as `"0xfff0"`, and
+    /// the `schema_version` of `vmsa show` output is ignored.
+    pub fn from_json(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).context(format!("unable to read {}", path.display()))?;
+        let fields: Map<String, Value> =
+            serde_json::from_str(&text).context(format!("invalid JSON VMSA {}", path.display()))?;
+
+        let mut vmsa = Self(vec![0u8; VMSA_SIZE]);
+        for (name, value) in &fields {
+            if name == "schema_version" {
+                continue;
+            }
+
+            let value = match value {
+                Value::Number(v) => v.as_u64(),
+                Value::String(v) => parse_u64(v).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                error::Context::new(
+                    &format!("invalid value for {}: {}", name, value),
+                    Box::<Error>::new(ErrorKind::InvalidInput.into()),
+                )
+            })?;
+
+            vmsa.set(name, value)
+                .context(format!("invalid JSON VMSA {}", path.display()))?;
+        }
+
+        Ok(vmsa)
+    }
+
+    /// Every named field as a hex string, which keeps 64-bit values exact.
+    pub fn document(&self) -> schema::Vmsa {
+        schema::Vmsa {
+            fields: fields()
+                .into_iter()
+                .map(|(name, _, _)| {
+                    let value = format!("{:#x}", self.get(&name).unwrap());
+                    (name, value)
+                })
+                .collect(),
+        }
+    }
+
+    /// The raw page contents.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Reads a named field.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        let (off, size) = field(name)?;
+        let mut value = [0u8; 8];
+        value[..size].copy_from_slice(&self.0[off..off + size]);
+        Some(u64::from_le_bytes(value))
+    }
+
+    /// Writes a named field, rejecting unknown fields and values that do not fit.
+    pub fn set(&mut self, name: &str, value: u64) -> Result<()> {
+        let (off, size) = field(name).ok_or_else(|| {
+            error::Context::new(
+                &format!("unknown VMSA field: {}", name),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            )
+        })?;
+
+        if size < 8 && value >> (size * 8) != 0 {
+            return Err(error::Context::new(
+                &format!(
+                    "value {:#x} does not fit in {}-byte field {}",
+                    value, size, name
+                ),
+                Box::<Error>::new(ErrorKind::InvalidInput.into()),
+            ));
+        }
+
+        self.0[off..off + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    fn put(&mut self, name: &str, value: u64) {
+        self.set(name, value).unwrap()
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Stream sockets over virtio-vsock, which connect a guest and its host without networking.
+
+use super::*;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+/// A listening vsock socket.
+pub struct Listener(File);
+
+/// Listens on `port` for connections from any CID.
+pub fn listen(port: u32) -> Result<Listener> {
+    let fd = socket()?;
+    let addr = address(libc::VMADDR_CID_ANY, port);
+
+    // SAFETY: `addr` is a valid sockaddr_vm and the length matches it.
+    let ret = unsafe {
+        libc::bind(
+            fd.0,
+            &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    };
+    check(ret).context(format!("unable to bind vsock port {}", port))?;
+
+    // SAFETY: `fd` is a bound socket.
+    check(unsafe { libc::listen(fd.0, 16) }).context("unable to listen on vsock")?;
+
+    Ok(Listener(fd.into_file()))
+}
+
+impl Listener {
+    /// Accepts a connection, returning it with the peer's CID.
+    pub fn accept(&self) -> Result<(File, u32)> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: sockaddr_vm is plain data, for which all zeroes is valid.
+        let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+
+        // SAFETY: `addr` and `len` describe a writable sockaddr_vm.
+        let fd = unsafe {
+            libc::accept(
+                self.0.as_raw_fd(),
+                &mut addr as *mut libc::sockaddr_vm as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        check(fd).context("unable to accept vsock connection")?;
+
+        // SAFETY: accept returned a new socket that nothing else owns.
+        Ok((unsafe { File::from_raw_fd(fd) }, addr.svm_cid))
+    }
+}
+
+/// Connects to `port` of the VM with the given CID.
+pub fn connect(cid: u32, port: u32) -> Result<File> {
+    let fd = socket()?;
+    let addr = address(cid, port);
+
+    // SAFETY: `addr` is a valid sockaddr_vm and the length matches it.
+    let ret = unsafe {
+        libc::connect(
+            fd.0,
+            &addr as *const libc::sockaddr_vm as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    };
+    check(ret).context(format!("unable to connect to vsock {}:{}", cid, port))?;
+
+    Ok(fd.into_file())
+}
+
+/// Closes the socket unless it is handed over to a `File`.
+struct Fd(RawFd);
+
+impl Fd {
+    fn into_file(self) -> File {
+        let fd = self.0;
+        std::mem::forget(self);
+
+        // SAFETY: ownership of the socket moves to the file.
+        unsafe { File::from_raw_fd(fd) }
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        // SAFETY: the socket is owned by this value.
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn socket() -> Result<Fd> {
+    // SAFETY: plain socket creation.
+    let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    check(fd).context("unable to create vsock socket (is the vsock driver loaded?)")?;
+    Ok(Fd(fd))
+}
+
+fn address(cid: u32, port: u32) -> libc::sockaddr_vm {
+    // SAFETY: sockaddr_vm is plain data, for which all zeroes is valid.
+    let mut addr: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+    addr.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    addr.svm_cid = cid;
+    addr.svm_port = port;
+    addr
+}
+
+fn check(ret: libc::c_int) -> std::io::Result<()> {
+    match ret {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
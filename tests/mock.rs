@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs sevctl against the fake firmware of the `mock` feature.
+
+#![cfg(feature = "mock")]
+
+use serde_json::Value;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Runs sevctl with the mock backend, ignoring the host's configuration.
+fn sevctl(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sevctl"))
+        .args(["--backend", "mock"])
+        .args(args)
+        .env("SEVCTL_CONFIG", "/dev/null")
+        .env_remove("SEVCTL_MOCK_FAIL")
+        .output()
+        .expect("unable to run sevctl")
+}
+
+/// Runs sevctl with `--output json`, returning its exit status and document.
+fn json(args: &[&str]) -> (bool, Value) {
+    let output = sevctl(&[&["--output", "json"], args].concat());
+    let doc = serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        panic!(
+            "sevctl {} printed no JSON ({}): {}",
+            args.join(" "),
+            e,
+            String::from_utf8_lossy(&output.stderr)
+        )
+    });
+    (output.status.success(), doc)
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sevctl-mock-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn show() {
+    let (ok, version) = json(&["show", "version"]);
+    assert!(ok);
+    assert_eq!(version["schema_version"], 1);
+    assert_eq!(version["api-major"], 1);
+    assert_eq!(version["api-minor"], 55);
+    assert_eq!(version["build-id"], 21);
+
+    let (ok, guests) = json(&["show", "guests"]);
+    assert!(ok);
+    assert_eq!(guests["guests"], 0);
+
+    let (ok, flags) = json(&["show", "flags"]);
+    assert!(ok);
+    assert_eq!(flags["owned"], false);
+}
+
+#[test]
+fn ok() {
+    // The CPU and KVM checks look at the host, so only the firmware's are certain.
+    let (_, doc) = json(&["ok"]);
+    let checks = doc["checks"].as_array().unwrap();
+    let state = |id: &str| {
+        checks
+            .iter()
+            .find(|c| c["id"] == id)
+            .unwrap_or_else(|| panic!("no {} check", id))["state"]
+            .clone()
+    };
+
+    assert_eq!(state("dev.sev"), "pass");
+    assert_eq!(state("fw.platform-status"), "pass");
+}
+
+#[test]
+fn export_and_verify() {
+    let chain = temp("chain");
+    let output = sevctl(&["export", chain.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(std::fs::metadata(&chain).unwrap().len() > 0);
+
+    // The chain verifies against the built-in AMD certificates.
+    let (ok, doc) = json(&["verify", "--sev", chain.to_str().unwrap()]);
+    std::fs::remove_file(&chain).unwrap();
+    assert!(ok);
+    assert_eq!(doc["valid"], true);
+    let signatures = doc["signatures"].as_array().unwrap();
+    assert!(!signatures.is_empty());
+    assert!(signatures.iter().all(|s| s["valid"] == true));
+
+    // Without --sev, the chain is exported from the firmware.
+    let (ok, doc) = json(&["verify"]);
+    assert!(ok);
+    assert_eq!(doc["valid"], true);
+}
+
+#[test]
+fn scripted_failure() {
+    let output = Command::new(env!("CARGO_BIN_EXE_sevctl"))
+        .args(["--backend", "mock", "--output", "json", "show", "version"])
+        .env("SEVCTL_CONFIG", "/dev/null")
+        .env("SEVCTL_MOCK_FAIL", "PLATFORM_STATUS=INVALID_PLATFORM_STATE")
+        .output()
+        .expect("unable to run sevctl");
+
+    assert!(!output.status.success());
+    let doc: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(doc["kind"], "firmware");
+    assert_eq!(doc["firmware-status"], 1);
+}